@@ -38,8 +38,8 @@ fn compute_normals_corner() {
 
     let latitude = to_latitude(dimensions.y - 1, terrain_uniforms_lt);
 
-    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0;
-    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0 * cos(radians(latitude));
+    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0 * cos(radians(latitude));
+    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0;
 
     let coords_top_left = vec2i(dimensions.x - 1, dimensions.y - 1);
     let coords_top_right = vec2i(0, dimensions.y - 1);
@@ -43,8 +43,8 @@ fn compute_normals_left_right(
 
     let latitude = to_latitude(coords.x, terrain_uniforms_lt);
 
-    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0;
-    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0 * cos(radians(latitude));
+    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0 * cos(radians(latitude));
+    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0;
 
     let coords_left = vec2i(dimensions.x - 1, coords.x);
     let coords_right = vec2i(0, coords.x);
@@ -84,8 +84,8 @@ fn compute_normals_top_bottom(
 
     let latitude = to_latitude(dimensions.y - 1, terrain_uniforms_lt);
 
-    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0;
-    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0 * cos(radians(latitude));
+    let x = radians(abs(terrain_uniforms_lt.pixel_scale.x)) * R0 * cos(radians(latitude));
+    let y = radians(abs(terrain_uniforms_lt.pixel_scale.y)) * R0;
 
     let coords_top= vec2i(coords.x, dimensions.y - 1);
     let coords_bottom= vec2i(coords.x, 0);
@@ -34,8 +34,8 @@ fn compute_normals(
 
     let latitude = to_latitude(coords.y, terrain_uniforms);
 
-    let x = radians(terrain_uniforms.pixel_scale.x) * R0;
-    let y = radians(terrain_uniforms.pixel_scale.y) * R0 * cos(radians(latitude));
+    let x = radians(terrain_uniforms.pixel_scale.x) * R0 * cos(radians(latitude));
+    let y = radians(terrain_uniforms.pixel_scale.y) * R0;
 
     let top = vec3f(0, y, textureLoad(terrain_heightmap, coords.xy + vec2i(0, -1), 0).r);
     let left = vec3f(-x, 0, textureLoad(terrain_heightmap, coords.xy + vec2i(-1, 0), 0).r);
@@ -0,0 +1,168 @@
+const R0 = 6371000.0;
+// the march step grows with the distance, as the precision needed gets lower
+const STEP_GROWTH = 0.02;
+
+struct TerrainUniforms {
+    raster_point: vec2f,
+    model_point: vec2f,
+    pixel_scale: vec2f,
+    size: vec2f,
+    normal_to_world_rotation: mat4x4f,
+}
+
+struct ShadowUniforms {
+    sun_direction: vec3f,
+    max_distance: f32,
+    penumbra: f32,
+    // bit for each of the loaded neighbours, in the order of their bindings
+    neighbours: u32,
+}
+
+@group(0) @binding(0) var terrain_heightmap: texture_2d<f32>;
+@group(0) @binding(1) var sun_visibility: texture_storage_2d<r32float, write>;
+@group(0) @binding(2) var<uniform> terrain_uniforms: TerrainUniforms;
+@group(0) @binding(3) var<uniform> shadow_uniforms: ShadowUniforms;
+// the tile's own height map and uniforms are bound instead of the neighbours that aren't loaded
+@group(0) @binding(4) var terrain_heightmap_top_left: texture_2d<f32>;
+@group(0) @binding(5) var terrain_heightmap_top: texture_2d<f32>;
+@group(0) @binding(6) var terrain_heightmap_top_right: texture_2d<f32>;
+@group(0) @binding(7) var terrain_heightmap_left: texture_2d<f32>;
+@group(0) @binding(8) var terrain_heightmap_right: texture_2d<f32>;
+@group(0) @binding(9) var terrain_heightmap_bottom_left: texture_2d<f32>;
+@group(0) @binding(10) var terrain_heightmap_bottom: texture_2d<f32>;
+@group(0) @binding(11) var terrain_heightmap_bottom_right: texture_2d<f32>;
+@group(0) @binding(12) var<uniform> terrain_uniforms_top_left: TerrainUniforms;
+@group(0) @binding(13) var<uniform> terrain_uniforms_top: TerrainUniforms;
+@group(0) @binding(14) var<uniform> terrain_uniforms_top_right: TerrainUniforms;
+@group(0) @binding(15) var<uniform> terrain_uniforms_left: TerrainUniforms;
+@group(0) @binding(16) var<uniform> terrain_uniforms_right: TerrainUniforms;
+@group(0) @binding(17) var<uniform> terrain_uniforms_bottom_left: TerrainUniforms;
+@group(0) @binding(18) var<uniform> terrain_uniforms_bottom: TerrainUniforms;
+@group(0) @binding(19) var<uniform> terrain_uniforms_bottom_right: TerrainUniforms;
+
+struct HeightSample {
+    height: f32,
+    found: bool,
+}
+
+fn to_model(coords: vec2f, transform: TerrainUniforms) -> vec2f {
+    return vec2f(
+        (coords.x - transform.raster_point.x) * transform.pixel_scale.x + transform.model_point.x,
+        (coords.y - transform.raster_point.y) * -transform.pixel_scale.y + transform.model_point.y,
+    );
+}
+
+fn to_raster(model: vec2f, transform: TerrainUniforms) -> vec2f {
+    return vec2f(
+        (model.x - transform.model_point.x) / transform.pixel_scale.x + transform.raster_point.x,
+        (model.y - transform.model_point.y) / -transform.pixel_scale.y + transform.raster_point.y,
+    );
+}
+
+// Height at the model coordinates, which the height map may not cover
+fn load_height(heightmap: texture_2d<f32>, transform: TerrainUniforms, model: vec2f) -> HeightSample {
+    let coords = vec2i(round(to_raster(model, transform)));
+    if (any(coords < vec2i(0)) || any(coords >= vec2i(textureDimensions(heightmap)))) {
+        return HeightSample(0.0, false);
+    }
+    return HeightSample(textureLoad(heightmap, coords, 0).r, true);
+}
+
+// The neighbours differ in their width further north and south, so each of
+// them gets sampled in its own raster
+fn load_neighbour_height(neighbour: u32, model: vec2f) -> HeightSample {
+    switch neighbour {
+        case 0u: { return load_height(terrain_heightmap_top_left, terrain_uniforms_top_left, model); }
+        case 1u: { return load_height(terrain_heightmap_top, terrain_uniforms_top, model); }
+        case 2u: { return load_height(terrain_heightmap_top_right, terrain_uniforms_top_right, model); }
+        case 3u: { return load_height(terrain_heightmap_left, terrain_uniforms_left, model); }
+        case 4u: { return load_height(terrain_heightmap_right, terrain_uniforms_right, model); }
+        case 5u: { return load_height(terrain_heightmap_bottom_left, terrain_uniforms_bottom_left, model); }
+        case 6u: { return load_height(terrain_heightmap_bottom, terrain_uniforms_bottom, model); }
+        default: { return load_height(terrain_heightmap_bottom_right, terrain_uniforms_bottom_right, model); }
+    }
+}
+
+// Marches from each point of the height map towards the sun and finds the highest
+// angle the terrain rises to above its horizon, the sun is visible above it.
+// Past the tile's edges the march continues on the loaded neighbours
+@compute
+@workgroup_size(16, 16)
+fn compute_shadows(
+    @builtin(global_invocation_id) global_id: vec3u,
+) {
+    let dimensions = vec2i(textureDimensions(terrain_heightmap));
+    let coords = vec2i(global_id.xy);
+
+    if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let model = radians(to_model(vec2f(coords), terrain_uniforms));
+    let longitude = model.x;
+    let latitude = model.y;
+    let up = vec3f(cos(latitude) * cos(longitude), cos(latitude) * sin(longitude), sin(latitude));
+    let east = vec3f(-sin(longitude), cos(longitude), 0.0);
+    let north = cross(up, east);
+
+    let sun = shadow_uniforms.sun_direction;
+    let sun_altitude = asin(clamp(dot(sun, up), -1.0, 1.0));
+    let sun_horizontal = vec2f(dot(sun, east), dot(sun, north));
+
+    var horizon = -radians(90.0);
+    if (length(sun_horizontal) > 1e-4) {
+        // meters per texel, the raster's y axis points south
+        let texel_size = vec2f(
+            radians(terrain_uniforms.pixel_scale.x) * R0 * cos(latitude),
+            -radians(terrain_uniforms.pixel_scale.y) * R0,
+        );
+        let direction = normalize(sun_horizontal) / texel_size;
+        // meters to move by for the larger of the texel coordinates to change by one
+        let base_step = 1.0 / max(abs(direction.x), abs(direction.y));
+
+        let height = textureLoad(terrain_heightmap, coords, 0).r;
+        var max_slope = -1e9;
+        var distance = base_step;
+        while (distance <= shadow_uniforms.max_distance) {
+            let position = vec2f(coords) + direction * distance;
+            let sample_coords = vec2i(round(position));
+            // -1, 0 or 1 tiles to the east and to the south
+            let tile = select(vec2i(0), vec2i(-1), sample_coords < vec2i(0))
+                + select(vec2i(0), vec2i(1), sample_coords >= dimensions);
+            var sample_height: f32;
+            if (all(tile == vec2i(0))) {
+                sample_height = textureLoad(terrain_heightmap, sample_coords, 0).r;
+            } else {
+                // the bindings skip the tile itself in the middle
+                var neighbour = u32((tile.y + 1) * 3 + tile.x + 1);
+                if (neighbour > 4u) {
+                    neighbour -= 1u;
+                }
+                if ((shadow_uniforms.neighbours & (1u << neighbour)) == 0u) {
+                    break;
+                }
+                let sample = load_neighbour_height(
+                    neighbour,
+                    to_model(position, terrain_uniforms),
+                );
+                if (!sample.found) {
+                    break;
+                }
+                sample_height = sample.height;
+            }
+            // the earth's curvature lowers the distant terrain
+            let rise = sample_height - height
+                - distance * distance / (2.0 * R0);
+            max_slope = max(max_slope, rise / distance);
+            distance += max(base_step, distance * STEP_GROWTH);
+        }
+        horizon = atan(max_slope);
+    }
+
+    let visibility = smoothstep(
+        -shadow_uniforms.penumbra,
+        shadow_uniforms.penumbra,
+        sun_altitude - horizon,
+    );
+    textureStore(sun_visibility, coords, vec4f(visibility, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,21 @@
+var<private> positions: array<vec2f, 3> = array<vec2f, 3>(
+    vec2f(-1.0, -1.0),
+    vec2f(3.0, -1.0),
+    vec2f(-1.0, 3.0)
+);
+
+@group(0) @binding(0)
+var t_depth: texture_2d<f32>;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) v_index: u32
+) -> @builtin(position) vec4f {
+    return vec4f(positions[v_index], 0.0, 1.0);
+}
+
+// the bits are kept intact so the buffer can be read the same way as a copied depth texture
+@fragment
+fn fs_main(@builtin(position) position: vec4f) -> @location(0) u32 {
+    return bitcast<u32>(textureLoad(t_depth, vec2i(position.xy), 0).r);
+}
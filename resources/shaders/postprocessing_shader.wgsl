@@ -18,11 +18,58 @@ var<private> uvs: array<vec2f, 6> = array<vec2f, 6>(
 
 const NEAR = 50.0;
 const FAR = 500000.0;
+const PI = 3.14159265;
+
+// heights (in meters) over which the density of the air and of the aerosols drops e times
+const RAYLEIGH_SCALE_HEIGHT = 8000.0;
+const MIE_SCALE_HEIGHT = 1200.0;
+// how much the aerosols scatter forwards, giving the glow around the sun
+const MIE_ASYMMETRY = 0.76;
+const SUN_INTENSITY = 12.0;
+// light scattered into the haze from the whole sky
+const SKY_AMBIENT = vec3f(0.3, 0.4, 0.6);
+
+const R0 = 6371000.0;
+// the render shader flags the water with the alpha, the land has 1
+const SEA_ALPHA_MAX = 0.25;
+const LAKE_ALPHA_MAX = 0.75;
+// reflectance of the water seen from straight above
+const WATER_F0 = 0.02;
+// the sun's reflection, sharper on the calm lakes than on the sea
+const SUN_GLINT = 4.0;
+const SEA_GLINT_EXPONENT = 400.0;
+const LAKE_GLINT_EXPONENT = 4000.0;
+// steps along the reflected rays, growing with the distance, and the ones finding the hit
+const REFLECTION_STEPS = 48;
+const REFLECTION_REFINEMENTS = 6;
+// meters
+const REFLECTION_MAX_DISTANCE = 50000.0;
+// part of the screen along its edges the terrain's reflections fade out in
+const REFLECTION_EDGE_FADE = 0.05;
 
 struct PostprocessingUniforms {
-    viewport: vec2f,
+    inverse_view_rotation_proj: mat4x4f,
+    camera_direction: vec3f,
+    camera_altitude: f32,
+    camera_up: vec3f,
     pixelize_n: f32,
-    _padding: f32,
+    sun_direction: vec3f,
+    mie: f32,
+    rayleigh: vec3f,
+    turbidity: f32,
+    viewport: vec2f,
+    _padding_viewport: vec2f,
+    view_rotation_proj: mat4x4f,
+    // linear
+    water_color: vec3f,
+    // 1 shows the sea on the tiles of the sea mask
+    sea: u32,
+    // refraction coefficient, the ratio of the curvature of the light rays to the earth's
+    refraction: f32,
+    // 1 mirrors the terrain in the lakes
+    water_reflections: u32,
+    // only the default view mode (0) gets the haze and the water
+    view_mode: i32,
 }
 
 @group(0) @binding(0)
@@ -33,8 +80,16 @@ var s_render: sampler;
 var t_depth: texture_2d<f32>;
 @group(0) @binding(3)
 var s_depth: sampler;
+@group(0) @binding(4)
+var t_sky_view: texture_2d<f32>;
+@group(0) @binding(5)
+var s_sky_view: sampler;
 @group(1) @binding(0)
 var<uniform> uniforms: PostprocessingUniforms;
+// the loaded tiles the backend has no terrain for, one texel per tile by the longitude
+// and the latitude from -180 and -90 degrees
+@group(1) @binding(1)
+var t_sea_mask: texture_2d<f32>;
 
 struct VertexInput {
     @location(0) position: vec3f,
@@ -53,6 +108,194 @@ fn dist_from_depth(depth: f32) -> f32 {
     return FAR * NEAR / (FAR - depth * (FAR - NEAR));
 }
 
+// Unit vector the pixel is viewed along
+fn view_direction(uv: vec2f) -> vec3f {
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.5, 1.0);
+    let point = uniforms.inverse_view_rotation_proj * ndc;
+    return normalize(point.xyz / point.w);
+}
+
+// Mean density (relative to sea level) along a straight ray between the altitudes
+fn mean_density(altitude_from: f32, altitude_to: f32, scale_height: f32) -> f32 {
+    let density_from = exp(-max(altitude_from, 0.0) / scale_height);
+    if abs(altitude_to - altitude_from) < 1.0 {
+        return density_from;
+    }
+    let density_to = exp(-max(altitude_to, 0.0) / scale_height);
+    return scale_height * (density_from - density_to) / (altitude_to - altitude_from);
+}
+
+fn rayleigh_phase(cos_angle: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + cos_angle * cos_angle);
+}
+
+fn mie_phase(cos_angle: f32) -> f32 {
+    let g = MIE_ASYMMETRY;
+    return (1.0 - g * g) / (4.0 * PI * pow(1.0 + g * g - 2.0 * g * cos_angle, 1.5));
+}
+
+// Sunlight left after crossing the atmosphere, reddened when the sun is low
+fn sunlight(sun_sine: f32) -> vec3f {
+    let air_mass = 1.0 / max(sun_sine + 0.03, 0.03);
+    let optical_depth = uniforms.rayleigh * RAYLEIGH_SCALE_HEIGHT + uniforms.mie * MIE_SCALE_HEIGHT;
+    return exp(-optical_depth * air_mass) * smoothstep(-0.05, 0.05, sun_sine);
+}
+
+// Aerial perspective, the light the air and the aerosols scatter towards the camera
+// and take away over the distance (in meters) to the terrain
+fn apply_haze(color: vec3f, direction: vec3f, distance: f32) -> vec3f {
+    let target_altitude = uniforms.camera_altitude + dot(direction, uniforms.camera_up) * distance;
+    let rayleigh = uniforms.rayleigh
+        * mean_density(uniforms.camera_altitude, target_altitude, RAYLEIGH_SCALE_HEIGHT);
+    let mie = uniforms.mie * mean_density(uniforms.camera_altitude, target_altitude, MIE_SCALE_HEIGHT);
+    let extinction = rayleigh + mie;
+    if all(extinction <= vec3f(0.0)) {
+        return color;
+    }
+    let transmittance = exp(-extinction * distance);
+
+    let cos_angle = dot(direction, uniforms.sun_direction);
+    let sun_sine = dot(uniforms.sun_direction, uniforms.camera_up);
+    let scattering = (rayleigh * rayleigh_phase(cos_angle) + mie * mie_phase(cos_angle))
+        / max(extinction, vec3f(1e-12));
+    let in_scattering = SUN_INTENSITY * sunlight(sun_sine) * scattering
+        + SKY_AMBIENT * smoothstep(-0.2, 0.1, sun_sine);
+
+    return color * transmittance + in_scattering * (1.0 - transmittance);
+}
+
+// Local frame at the camera, east, north and up, the same as in the sky shader
+fn camera_frame() -> mat3x3f {
+    let up = uniforms.camera_up;
+    var east = cross(vec3f(0.0, 0.0, 1.0), up);
+    if length(east) < 1e-6 {
+        east = vec3f(0.0, 1.0, 0.0);
+    }
+    east = normalize(east);
+    return mat3x3f(east, cross(up, east), up);
+}
+
+// Where the sky shader put the direction in the sky view, the inverse of its `sky_view_direction`
+fn sky_view_uv(direction: vec3f) -> vec2f {
+    let local = transpose(camera_frame()) * direction;
+    let azimuth = atan2(local.x, local.y);
+    let elevation = asin(clamp(local.z, 0.0, 1.0));
+    return vec2f(fract(azimuth / (2.0 * PI)), sqrt(elevation / (0.5 * PI)));
+}
+
+// Distance along the ray to the sea level, which the refraction lifts in the distance the
+// same way as the terrain, negative if the ray doesn't get down to it
+fn sea_distance(direction: vec3f) -> f32 {
+    let mu = dot(direction, uniforms.camera_up);
+    let altitude = max(uniforms.camera_altitude, 0.0);
+    // the drop of the sea level below the tangent plane per squared meter
+    let curvature = (1.0 - uniforms.refraction) / (2.0 * R0);
+    if mu >= 0.0 {
+        return -1.0;
+    }
+    if curvature <= 0.0 {
+        return altitude / -mu;
+    }
+    let discriminant = mu * mu - 4.0 * curvature * altitude;
+    if discriminant < 0.0 {
+        return -1.0;
+    }
+    return (-mu - sqrt(discriminant)) / (2.0 * curvature);
+}
+
+// Whether the point (relative to the camera) is on a tile of the sea mask
+fn on_sea_tile(point: vec3f) -> bool {
+    let position = uniforms.camera_up * (R0 + uniforms.camera_altitude) + point;
+    let latitude = degrees(asin(clamp(position.z / length(position), -1.0, 1.0)));
+    let longitude = degrees(atan2(position.y, position.x));
+    let size = vec2i(textureDimensions(t_sea_mask));
+    let texel = clamp(vec2i(floor(vec2f(longitude + 180.0, latitude + 90.0))), vec2i(0), size - 1);
+    return textureLoad(t_sea_mask, texel, 0).r > 0.5;
+}
+
+fn load_texel(uv: vec2f) -> vec2i {
+    return clamp(vec2i(uv * uniforms.viewport), vec2i(0), vec2i(uniforms.viewport) - 1);
+}
+
+// Screen position and view depth of the point relative to the camera
+fn project(point: vec3f) -> vec3f {
+    let clip = uniforms.view_rotation_proj * vec4f(point, 1.0);
+    let ndc = clip.xy / max(clip.w, 1e-6);
+    return vec3f(0.5 + 0.5 * ndc.x, 0.5 - 0.5 * ndc.y, dot(point, uniforms.camera_direction));
+}
+
+// The terrain the ray from the point (relative to the camera) hits, as the color seen there
+// in the render and how much of it shows, 0 if it leaves the screen or hits only the sky
+fn trace_reflection(start: vec3f, ray: vec3f) -> vec4f {
+    var near = 0.0;
+    var far = -1.0;
+    for (var i = 1; i <= REFLECTION_STEPS; i++) {
+        let step = f32(i) / f32(REFLECTION_STEPS);
+        let distance = REFLECTION_MAX_DISTANCE * step * step;
+        let screen = project(start + ray * distance);
+        if screen.z <= NEAR || any(screen.xy < vec2f(0.0)) || any(screen.xy > vec2f(1.0)) {
+            break;
+        }
+        let depth = textureLoad(t_depth, load_texel(screen.xy), 0).r;
+        let scene = dist_from_depth(depth);
+        // behind the terrain there, but not so far that it passed behind it
+        if depth < 1.0 && screen.z > scene && screen.z - scene < max(distance - near, 0.05 * scene) {
+            far = distance;
+            break;
+        }
+        near = distance;
+    }
+    if far < 0.0 {
+        return vec4f(0.0);
+    }
+
+    for (var i = 0; i < REFLECTION_REFINEMENTS; i++) {
+        let middle = 0.5 * (near + far);
+        let screen = project(start + ray * middle);
+        let scene = dist_from_depth(textureLoad(t_depth, load_texel(screen.xy), 0).r);
+        if screen.z > scene {
+            far = middle;
+        } else {
+            near = middle;
+        }
+    }
+
+    let hit = start + ray * far;
+    let screen = project(hit);
+    let texel = textureLoad(t_render, load_texel(screen.xy), 0);
+    // the water doesn't mirror the other water's flags
+    if texel.a < LAKE_ALPHA_MAX {
+        return vec4f(0.0);
+    }
+    let edges = min(screen.xy, 1.0 - screen.xy);
+    let fade = smoothstep(0.0, REFLECTION_EDGE_FADE, min(edges.x, edges.y));
+    return vec4f(apply_haze(texel.rgb, normalize(hit), length(hit)), fade);
+}
+
+// Color of the water seen along the direction at the distance, the light from within
+// and the reflections of the sky and the sun, and on the calm lakes of the terrain,
+// weighted with the Fresnel term
+fn water_color(direction: vec3f, distance: f32, calm: bool) -> vec3f {
+    let position = uniforms.camera_up * (R0 + uniforms.camera_altitude) + direction * distance;
+    let normal = normalize(position);
+    let reflected = reflect(direction, normal);
+    let sun_sine = dot(uniforms.sun_direction, normal);
+
+    var reflection = textureSampleLevel(t_sky_view, s_sky_view, sky_view_uv(reflected), 0.0).rgb;
+    let glint_exponent = select(SEA_GLINT_EXPONENT, LAKE_GLINT_EXPONENT, calm);
+    reflection += SUN_GLINT * sunlight(sun_sine)
+        * pow(max(dot(reflected, uniforms.sun_direction), 0.0), glint_exponent);
+    if calm && uniforms.water_reflections != 0u {
+        let terrain = trace_reflection(direction * distance, reflected);
+        reflection = mix(reflection, terrain.rgb, terrain.a);
+    }
+
+    let cos_incidence = clamp(-dot(direction, normal), 0.0, 1.0);
+    let fresnel = WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - cos_incidence, 5.0);
+    let body = uniforms.water_color * (0.01 + 0.7 * max(sun_sine, 0.0));
+    return mix(body, reflection, fresnel);
+}
+
 @vertex
 fn vs_main(
     @builtin(vertex_index) v_index: u32
@@ -90,7 +333,32 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
         }
     }
 
-    let contour_color = vec4f(0.0, 0.0, 0.0, 1.0);
+    let contour_color = vec3f(0.0, 0.0, 0.0);
+    let outline = smoothstep(0.05, 0.15, contour/center_linear);
+    let direction = view_direction(center_sample_uv);
 
-    return mix(render_color, contour_color, smoothstep(0.05, 0.15, contour/center_linear));
+    // the debug and analysis view modes show the terrain's colors as they are
+    let shaded = uniforms.view_mode == 0;
+
+    if center_depth >= 1.0 {
+        // the sea on the tiles without terrain, otherwise the sky is left as it is
+        let distance = sea_distance(direction);
+        if !shaded || uniforms.sea == 0u || distance < 0.0 || !on_sea_tile(direction * distance) {
+            return vec4f(mix(render_color.rgb, contour_color, outline), 1.0);
+        }
+        let color = mix(water_color(direction, distance, false), contour_color, outline);
+        return vec4f(clamp(apply_haze(color, direction, distance), vec3f(0.0), vec3f(1.0)), 1.0);
+    }
+
+    if !shaded {
+        return vec4f(mix(render_color.rgb, contour_color, outline), 1.0);
+    }
+
+    let distance = center_linear / max(dot(direction, uniforms.camera_direction), 1e-3);
+    var surface = render_color.rgb;
+    if render_color.a < LAKE_ALPHA_MAX {
+        surface = water_color(direction, distance, render_color.a >= SEA_ALPHA_MAX);
+    }
+    let color = mix(surface, contour_color, outline);
+    return vec4f(clamp(apply_haze(color, direction, distance), vec3f(0.0), vec3f(1.0)), 1.0);
 }
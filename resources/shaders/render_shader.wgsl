@@ -1,4 +1,22 @@
 const R0 = 6371000.0;
+const MAX_ELEVATION_STOPS = 8;
+// the sizes of `SLOPE_CLASSES` and `ASPECT_COLORS` in terrain_coloring.rs
+const SLOPE_CLASS_COUNT = 4;
+const ASPECT_COUNT = 8;
+// the cartographic convention, light from the north-west, 45° above the horizon
+const HILLSHADE_AZIMUTH = 315.0;
+const HILLSHADE_ALTITUDE = 45.0;
+// pixels, denser contours fade out instead of merging into a solid color
+const CONTOUR_MIN_SPACING = 4.0;
+// the postprocessing shades the water, told apart from the land (1) by the alpha
+const SEA_ALPHA = 0.0;
+const LAKE_ALPHA = 0.5;
+// meters, the ground this close to sea level is part of the sea along the shores
+const SHORE_HEIGHT = 0.5;
+// meters the lakes are raised by above the terrain around them, at least and per meter of distance
+// as the depth buffer's precision drops
+const LAKE_OFFSET = 1.0;
+const LAKE_OFFSET_PER_METER = 2e-5;
 
 struct Uniforms {
     projection: mat4x4f,
@@ -6,6 +24,31 @@ struct Uniforms {
     camera_pos: vec4f,
     sun_direction: vec3f,
     view_mode: i32,
+    // refraction coefficient, the ratio of the curvature of the light rays to the earth's
+    refraction: f32,
+    elevation_stop_count: u32,
+    // 0 lights the draped imagery like the untextured terrain, 1 shows it unlit
+    imagery_blend: f32,
+    // 1 covers the ground below sea level with the sea
+    sea: u32,
+    // linear colors with the elevation (in meters) in w, in ascending order
+    elevation_stops: array<vec4f, MAX_ELEVATION_STOPS>,
+    // linear
+    contour_color: vec3f,
+    // meters, 0 without contours
+    contour_interval: f32,
+    // pixels
+    contour_width: f32,
+    contour_index_width: f32,
+    contour_index_every: u32,
+    // linear colors of the slope classes, with their lowest slope (in degrees) in w
+    slope_classes: array<vec4f, SLOPE_CLASS_COUNT>,
+    // linear colors of the directions the slopes face, clockwise from north
+    aspect_colors: array<vec4f, ASPECT_COUNT>,
+    // linear color of the ground too flat to face any direction
+    flat_color: vec3f,
+    // degrees, gentler slopes don't face any direction in the aspect view mode
+    flat_slope: f32,
 }
 
 struct TerrainUniforms {
@@ -14,12 +57,20 @@ struct TerrainUniforms {
     pixel_scale: vec2f,
     size: vec2f,
     normal_to_world_rotation: mat4x4f,
+    // georeferencing of the draped imagery, its size is 0 without any
+    imagery_raster_point: vec2f,
+    imagery_model_point: vec2f,
+    imagery_pixel_scale: vec2f,
+    imagery_size: vec2f,
 }
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
 @group(1) @binding(0) var terrain_heightmap: texture_2d<f32>;
 @group(1) @binding(1) var terrain_normals: texture_2d<f32>;
 @group(1) @binding(2) var<uniform> terrain_uniforms: TerrainUniforms;
+@group(1) @binding(3) var terrain_sun_visibility: texture_2d<f32>;
+@group(1) @binding(4) var terrain_imagery: texture_2d<f32>;
+@group(1) @binding(5) var terrain_imagery_sampler: sampler;
 
 struct VertexInput {
     @location(0) position: vec2u,
@@ -30,6 +81,12 @@ struct VertexOutput {
     @location(0) color: vec3f,
     @location(1) world_position: vec3f,
     @location(2) world_normal: vec3f,
+    // 0 in the shadow of the terrain, 1 in full sunlight
+    @location(3) sun_visibility: f32,
+    // meters above sea level
+    @location(4) elevation: f32,
+    // texture coordinates of the draped imagery
+    @location(5) imagery_uv: vec2f,
 }
 
 fn to_model(
@@ -44,29 +101,48 @@ fn to_model(
     return model;
 }
 
+fn to_imagery_uv(
+    model: vec2f,
+    transform: TerrainUniforms,
+) -> vec2f {
+    let raster = (model - transform.imagery_model_point) / transform.imagery_pixel_scale * vec2f(1.0, -1.0) + transform.imagery_raster_point;
+    return raster / max(transform.imagery_size, vec2f(1.0));
+}
+
 @vertex
 fn vs_main(
     raster: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
 
-    let height = textureLoad(terrain_heightmap, raster.position, 0).r;
+    var height = textureLoad(terrain_heightmap, raster.position, 0).r;
+    // the sea covers the ground below its level and the holes without any data
+    if uniforms.sea != 0u && !(height > 0.0) {
+        height = 0.0;
+    }
     let model_position = to_model(raster, terrain_uniforms);
     let longitude = radians(model_position.x);
     let latitude = radians(model_position.y);
 
     let R = R0 + height;
 
-    let position = vec3f(
+    let true_position = vec3f(
         R * cos(latitude) * cos(longitude),
         R * cos(latitude) * sin(longitude),
         R * sin(latitude),
     );
+    // the refracted rays curve along with the earth, which makes the distant terrain appear raised
+    let distance = length(true_position - uniforms.camera_pos.xyz);
+    let lift = uniforms.refraction * distance * distance / (2.0 * R0);
+    let position = true_position * (1.0 + lift / R);
 
     let normal = 2.0 * textureLoad(terrain_normals, raster.position, 0).rgb - vec3f(1);
     out.color = vec3f(1.0, 1.0, 1.0);
     out.world_position = position;
     out.world_normal = (terrain_uniforms.normal_to_world_rotation * vec4f(normal, 0.0)).xyz;
+    out.sun_visibility = textureLoad(terrain_sun_visibility, raster.position, 0).r;
+    out.elevation = height;
+    out.imagery_uv = to_imagery_uv(model_position, terrain_uniforms);
 
     out.clip_position = uniforms.projection * vec4f(position, 1.0);
     return out;
@@ -93,19 +169,122 @@ fn lin2srgb(color: vec3f) -> vec3f {
     return mix( color_lo, color_hi, s );
 }
 
+fn elevation_color(elevation: f32) -> vec3f {
+    var color = uniforms.elevation_stops[0].rgb;
+    for (var i = 1u; i < uniforms.elevation_stop_count; i++) {
+        let low = uniforms.elevation_stops[i - 1u];
+        let high = uniforms.elevation_stops[i];
+        if elevation >= low.w {
+            color = mix(low.rgb, high.rgb, clamp((elevation - low.w) / (high.w - low.w), 0.0, 1.0));
+        }
+    }
+    return color;
+}
+
+// Local (north, east, up) frame at the position
+fn local_frame(position: vec3f) -> mat3x3f {
+    let up = normalize(position);
+    let east = normalize(vec3f(-position.y, position.x, 0.0));
+    return mat3x3f(cross(up, east), east, up);
+}
+
+// Slope angle in degrees
+fn slope_angle(normal: vec3f, up: vec3f) -> f32 {
+    return degrees(acos(clamp(dot(normal, up), -1.0, 1.0)));
+}
+
+fn slope_color(normal: vec3f, position: vec3f) -> vec3f {
+    let slope = slope_angle(normal, normalize(position));
+    var color = uniforms.slope_classes[0].rgb;
+    for (var i = 1; i < SLOPE_CLASS_COUNT; i++) {
+        if slope >= uniforms.slope_classes[i].w {
+            color = uniforms.slope_classes[i].rgb;
+        }
+    }
+    return color;
+}
+
+fn aspect_color(normal: vec3f, position: vec3f) -> vec3f {
+    let frame = local_frame(position);
+    if slope_angle(normal, frame[2]) < uniforms.flat_slope {
+        return uniforms.flat_color;
+    }
+    // the downhill direction is where the normal leans
+    let aspect = degrees(atan2(dot(normal, frame[1]), dot(normal, frame[0])));
+    let sector = u32(round(((aspect + 360.0) % 360.0) / 45.0)) % u32(ASPECT_COUNT);
+    return uniforms.aspect_colors[sector].rgb;
+}
+
+fn hillshade(normal: vec3f, position: vec3f) -> f32 {
+    let frame = local_frame(position);
+    let azimuth = radians(HILLSHADE_AZIMUTH);
+    let altitude = radians(HILLSHADE_ALTITUDE);
+    let light = frame * vec3f(cos(altitude) * cos(azimuth), cos(altitude) * sin(azimuth), sin(altitude));
+    return max(dot(normal, light), 0.0);
+}
+
+// Coverage of a pixel by the lines at multiples of the interval, `pixel_height` is
+// the elevation change across the pixel, which keeps the lines' width constant on screen
+fn contour_line(elevation: f32, pixel_height: f32, interval: f32, width: f32) -> f32 {
+    let spacing = interval / pixel_height;
+    let distance = abs(fract(elevation / interval + 0.5) - 0.5) * spacing;
+    let line = 1.0 - smoothstep(0.5 * width - 0.5, 0.5 * width + 0.5, distance);
+    return line * smoothstep(CONTOUR_MIN_SPACING, 2.0 * CONTOUR_MIN_SPACING, spacing);
+}
+
+fn contour_coverage(elevation: f32, pixel_height: f32) -> f32 {
+    // flat ground (e.g. the sea) right at a contour's elevation isn't a line
+    if uniforms.contour_interval <= 0.0 || pixel_height < 1e-3 {
+        return 0.0;
+    }
+    let index_interval = uniforms.contour_interval * f32(uniforms.contour_index_every);
+    return max(
+        contour_line(elevation, pixel_height, uniforms.contour_interval, uniforms.contour_width),
+        contour_line(elevation, pixel_height, index_interval, uniforms.contour_index_width),
+    );
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let color = shaded_color(in);
+    if uniforms.view_mode == 2 {
+        return color;
+    }
+    // the derivatives are taken before any branching on the fragment's values
+    let coverage = contour_coverage(in.elevation, fwidth(in.elevation));
+    let alpha = select(color.a, SEA_ALPHA, uniforms.sea != 0u && in.elevation < SHORE_HEIGHT);
+    return vec4f(mix(color.rgb, uniforms.contour_color, coverage), alpha);
+}
+
+fn shaded_color(in: VertexOutput) -> vec4f {
     let ambient_strength = 0.01;
     let light_color = vec3f(1.0, 1.0, 1.0);
 
-    let diffuse_strength = 0.7 * max(dot(normalize(in.world_normal), uniforms.sun_direction), 0.0);
+    let diffuse_strength = 0.7 * in.sun_visibility * max(dot(normalize(in.world_normal), uniforms.sun_direction), 0.0);
     let diffuse_color = light_color * diffuse_strength;
 
     let ambient_color = light_color * ambient_strength;
-    let result_lin = (ambient_color + diffuse_color) * in.color;
+
+    // sampled outside of any branch, as the mip level needs uniform control flow
+    let imagery = textureSample(terrain_imagery, terrain_imagery_sampler, in.imagery_uv);
+    let uv_inside = all(in.imagery_uv >= vec2f(0.0)) && all(in.imagery_uv <= vec2f(1.0));
+    let draped = select(0.0, imagery.a, terrain_uniforms.imagery_size.x > 0.0 && uv_inside);
+    let color = mix(in.color, imagery.rgb, draped);
+    // the photos have their own shading, so they're partly shown as they are
+    let lighting = mix(ambient_color + diffuse_color, light_color, uniforms.imagery_blend * draped);
+    let result_lin = lighting * color;
     let result = ditherRGB(result_lin, in.clip_position.xy + uniforms.camera_pos.xy - in.world_position.xy);
 
-    if uniforms.view_mode == 2 {
+    let normal = normalize(in.world_normal);
+    if uniforms.view_mode == 6 {
+        return vec4f(elevation_color(in.elevation) * (0.2 + 0.8 * hillshade(normal, in.world_position)), 1.0);
+    } else if uniforms.view_mode == 5 {
+        return vec4f(aspect_color(normal, in.world_position), 1.0);
+    } else if uniforms.view_mode == 4 {
+        return vec4f(slope_color(normal, in.world_position), 1.0);
+    } else if uniforms.view_mode == 3 {
+        return vec4f(elevation_color(in.elevation), 1.0);
+    } else if uniforms.view_mode == 2 {
         return vec4f(0.5 * (in.world_normal + vec3f(1)), 1.0);
     } else if uniforms.view_mode == 1 {
         return vec4f(result_lin, 1.0);
@@ -113,3 +292,29 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
         return vec4f(result, 1.0);
     }
 }
+
+struct LakeInput {
+    @location(0) position: vec3f,
+    // meters above sea level
+    @location(1) elevation: f32,
+}
+
+@vertex
+fn vs_lake(in: LakeInput) -> @builtin(position) vec4f {
+    let distance = length(in.position - uniforms.camera_pos.xyz);
+    let lift = uniforms.refraction * distance * distance / (2.0 * R0)
+        + max(LAKE_OFFSET, LAKE_OFFSET_PER_METER * distance);
+    let position = in.position * (1.0 + lift / (R0 + in.elevation));
+    return uniforms.projection * vec4f(position, 1.0);
+}
+
+@fragment
+fn fs_lake() -> @location(0) vec4f {
+    return vec4f(0.0, 0.0, 0.0, LAKE_ALPHA);
+}
+
+// Exact world space normals for exporting, written to a float texture
+@fragment
+fn fs_normals(in: VertexOutput) -> @location(0) vec4f {
+    return vec4f(normalize(in.world_normal), 1.0);
+}
@@ -0,0 +1,246 @@
+var<private> positions: array<vec2f, 6> = array<vec2f, 6>(
+    vec2f(-1.0, 1.0),
+    vec2f(-1.0, -1.0),
+    vec2f(1.0, -1.0),
+    vec2f(-1.0, 1.0),
+    vec2f(1.0, 1.0),
+    vec2f(1.0, -1.0)
+);
+
+const PI = 3.14159265;
+const R0 = 6371000.0;
+const ATMOSPHERE_HEIGHT = 60000.0;
+
+// scattering coefficients (per meter) of the clean air at sea level
+const RAYLEIGH = vec3f(5.8e-6, 13.5e-6, 33.1e-6);
+const RAYLEIGH_SCALE_HEIGHT = 8000.0;
+const MIE_SCALE_HEIGHT = 1200.0;
+const MIE_ASYMMETRY = 0.76;
+const SUN_INTENSITY = 28.0;
+// radians
+const SUN_ANGULAR_RADIUS = 0.00465;
+const SUN_DISK_INTENSITY = 40.0;
+// stands in for the multiple scattering that keeps the sky from going black at dusk
+const NIGHT_SKY = vec3f(0.002, 0.004, 0.01);
+
+const VIEW_STEPS = 16;
+const LIGHT_STEPS = 4;
+
+// texels of the sky view, the same as `SKY_VIEW_SIZE` in terrain_renderer.rs
+const SKY_VIEW_SIZE = vec2f(256.0, 64.0);
+
+struct PostprocessingUniforms {
+    inverse_view_rotation_proj: mat4x4f,
+    camera_direction: vec3f,
+    camera_altitude: f32,
+    camera_up: vec3f,
+    pixelize_n: f32,
+    sun_direction: vec3f,
+    mie: f32,
+    rayleigh: vec3f,
+    turbidity: f32,
+    viewport: vec2f,
+    _padding_viewport: vec2f,
+    view_rotation_proj: mat4x4f,
+    water_color: vec3f,
+    sea: u32,
+    refraction: f32,
+    water_reflections: u32,
+    view_mode: i32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: PostprocessingUniforms;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) v_index: u32
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4f(positions[v_index], 1.0, 1.0);
+    return out;
+}
+
+fn hash12n(seed: vec2f) -> f32 {
+	var p  = fract(seed * vec2f(5.3987, 5.4421));
+    p += dot(p.yx, p.xy + vec2f(21.5351, 14.3137));
+	return fract(p.x * p.y * 95.4307);
+}
+
+fn hash42n(p: vec2f) -> vec3f {
+    return vec3f(hash12n(p), hash12n(p + 0.07), hash12n(p + 0.11));
+}
+
+fn ditherRGB(color: vec3f, p: vec2f) -> vec3f {
+    return color + 1.0 * (hash42n(p) + hash42n(p + 0.13) - 1.0) / 255.0;
+}
+
+fn lin2srgb(color: vec3f) -> vec3f {
+    let color_lo = 12.92 * color;
+    let color_hi = 1.055 * pow(color,vec3f(0.41666)) - 0.055;
+    let s = step( vec3f(0.0031308), color);
+    return mix( color_lo, color_hi, s );
+}
+
+fn srgb2lin(color: vec3f) -> vec3f {
+    let color_lo = color / 12.92;
+    let color_hi = pow((color + 0.055) / 1.055, vec3f(2.4));
+    let s = step(vec3f(0.04045), color);
+    return mix(color_lo, color_hi, s);
+}
+
+// Unit vector the pixel is viewed along
+fn view_direction(pixel_pos: vec2f) -> vec3f {
+    let uv = pixel_pos / uniforms.viewport;
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.5, 1.0);
+    let point = uniforms.inverse_view_rotation_proj * ndc;
+    return normalize(point.xyz / point.w);
+}
+
+// Distance along the ray from `radius` meters off the earth's center with the cosine
+// `mu` to the vertical to where it leaves the sphere, negative if it misses it
+fn exit_distance(radius: f32, mu: f32, sphere_radius: f32) -> f32 {
+    let discriminant = radius * radius * (mu * mu - 1.0) + sphere_radius * sphere_radius;
+    if discriminant < 0.0 {
+        return -1.0;
+    }
+    return -radius * mu + sqrt(discriminant);
+}
+
+// Distance to where the ray hits the ground, negative if it doesn't
+fn ground_distance(radius: f32, mu: f32) -> f32 {
+    let discriminant = radius * radius * (mu * mu - 1.0) + R0 * R0;
+    if mu > 0.0 || discriminant < 0.0 {
+        return -1.0;
+    }
+    return -radius * mu - sqrt(discriminant);
+}
+
+// Local frame at the camera, east, north and up
+fn camera_frame() -> mat3x3f {
+    let up = uniforms.camera_up;
+    var east = cross(vec3f(0.0, 0.0, 1.0), up);
+    if length(east) < 1e-6 {
+        east = vec3f(0.0, 1.0, 0.0);
+    }
+    east = normalize(east);
+    return mat3x3f(east, cross(up, east), up);
+}
+
+// Direction a texel of the sky view stands for, clockwise from north along u and from
+// the horizon up along v, with more texels towards the horizon
+fn sky_view_direction(uv: vec2f) -> vec3f {
+    let azimuth = 2.0 * PI * uv.x;
+    let elevation = 0.5 * PI * uv.y * uv.y;
+    let local = vec3f(cos(elevation) * sin(azimuth), cos(elevation) * cos(azimuth), sin(elevation));
+    return camera_frame() * local;
+}
+
+fn altitude_at(radius: f32, mu: f32, distance: f32) -> f32 {
+    return sqrt(radius * radius + distance * distance + 2.0 * radius * mu * distance) - R0;
+}
+
+fn rayleigh_phase(cos_angle: f32) -> f32 {
+    return 3.0 / (16.0 * PI) * (1.0 + cos_angle * cos_angle);
+}
+
+fn mie_phase(cos_angle: f32) -> f32 {
+    let g = MIE_ASYMMETRY;
+    return (1.0 - g * g) / (4.0 * PI * pow(1.0 + g * g - 2.0 * g * cos_angle, 1.5));
+}
+
+fn mie_coefficient() -> f32 {
+    // the turbidity is the ratio of all the scattering to that of the clean air
+    return RAYLEIGH.g * max(uniforms.turbidity - 1.0, 0.0);
+}
+
+// Optical depths (Rayleigh, Mie) of the air and aerosols from the point to the sun,
+// negative if the earth is in the way
+fn sun_optical_depth(radius: f32, mu: f32) -> vec2f {
+    if ground_distance(radius, mu) > 0.0 {
+        return vec2f(-1.0);
+    }
+    let ray_length = exit_distance(radius, mu, R0 + ATMOSPHERE_HEIGHT);
+    let step = ray_length / f32(LIGHT_STEPS);
+    var depth = vec2f(0.0);
+    for (var i = 0; i < LIGHT_STEPS; i++) {
+        let altitude = altitude_at(radius, mu, (f32(i) + 0.5) * step);
+        depth += exp(-altitude / vec2f(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT)) * step;
+    }
+    return depth;
+}
+
+// Single scattering of the sunlight towards the camera, integrated along the view ray
+fn sky_color(direction: vec3f) -> vec3f {
+    let up = uniforms.camera_up;
+    let sun = uniforms.sun_direction;
+    let mie = mie_coefficient();
+    let radius = R0 + clamp(uniforms.camera_altitude, 0.0, ATMOSPHERE_HEIGHT - 1.0);
+    let mu = dot(direction, up);
+
+    var ray_length = exit_distance(radius, mu, R0 + ATMOSPHERE_HEIGHT);
+    let ground = ground_distance(radius, mu);
+    if ground > 0.0 {
+        ray_length = ground;
+    }
+
+    var view_depth = vec2f(0.0);
+    var rayleigh_sum = vec3f(0.0);
+    var mie_sum = vec3f(0.0);
+    for (var i = 0; i < VIEW_STEPS; i++) {
+        // the steps get longer with the distance, as the air thins out along most rays
+        let start = f32(i) / f32(VIEW_STEPS);
+        let end = f32(i + 1) / f32(VIEW_STEPS);
+        let step = ray_length * (end * end - start * start);
+        let middle = 0.5 * (start + end);
+        let distance = ray_length * middle * middle;
+        let altitude = altitude_at(radius, mu, distance);
+        let density = exp(-altitude / vec2f(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT)) * step;
+        view_depth += density;
+
+        let point = up * radius + direction * distance;
+        let point_radius = length(point);
+        let sun_depth = sun_optical_depth(point_radius, dot(point / point_radius, sun));
+        if sun_depth.x < 0.0 {
+            continue;
+        }
+        let depth = view_depth + sun_depth;
+        let attenuation = exp(-(RAYLEIGH * depth.x + 1.1 * mie * depth.y));
+        rayleigh_sum += density.x * attenuation;
+        mie_sum += density.y * attenuation;
+    }
+
+    let cos_angle = dot(direction, sun);
+    var color = SUN_INTENSITY
+        * (RAYLEIGH * rayleigh_phase(cos_angle) * rayleigh_sum + mie * mie_phase(cos_angle) * mie_sum);
+
+    // the sun disk, dimmed and reddened by the air in front of it, with a soft limb
+    if ground < 0.0 {
+        let angle = acos(clamp(cos_angle, -1.0, 1.0));
+        let disk = 1.0 - smoothstep(0.8 * SUN_ANGULAR_RADIUS, SUN_ANGULAR_RADIUS, angle);
+        let transmittance = exp(-(RAYLEIGH * view_depth.x + 1.1 * mie * view_depth.y));
+        color += SUN_DISK_INTENSITY * disk * transmittance;
+    }
+
+    return color + NIGHT_SKY;
+}
+
+// The sky above the camera's horizon by the azimuth and the elevation, for the reflections
+@fragment
+fn fs_sky_view(in: VertexOutput) -> @location(0) vec4f {
+    let color = sky_color(sky_view_direction(in.clip_position.xy / SKY_VIEW_SIZE));
+    return vec4f(1.0 - exp(-color), 1.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    let color = sky_color(view_direction(in.clip_position.xy));
+    // compresses the sun disk and the glow around it into the displayable range
+    let mapped = 1.0 - exp(-color);
+    // dithered in the target's sRGB steps, which are coarse in the dark night sky
+    return vec4f(srgb2lin(ditherRGB(lin2srgb(mapped), in.clip_position.xy)), 1.0);
+}
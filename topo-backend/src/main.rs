@@ -1,5 +1,5 @@
 use axum::body::Body;
-use axum::extract::{Query, State};
+use axum::extract::{Path as UrlPath, Query, State};
 use axum::response::IntoResponse;
 use axum::{Router, routing::get};
 use color_eyre::Result;
@@ -9,7 +9,7 @@ use serde::Deserialize;
 use std::path::Path;
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
-use topo_common::{GeoLocation, LatitudeDirection, LongitudeDirection};
+use topo_common::GeoLocation;
 use tower::ServiceBuilder;
 use tower_http::CompressionLevel;
 use tower_http::compression::CompressionLayer;
@@ -32,19 +32,7 @@ async fn get_peaks(
     State(state): State<AppState>,
     geo_location: Query<GeoLocation>,
 ) -> impl IntoResponse {
-    let file_name = Path::new(&state.data_dir).join(format!(
-        "peaks/peaks_{}{}_{}{}.csv",
-        match geo_location.latitude.direction {
-            LatitudeDirection::N => "",
-            LatitudeDirection::S => "-",
-        },
-        geo_location.latitude.degree.to_string(),
-        match geo_location.longitude.direction {
-            LongitudeDirection::E => "",
-            LongitudeDirection::W => "-",
-        },
-        geo_location.longitude.degree.to_string()
-    ));
+    let file_name = Path::new(&state.data_dir).join(geo_location.peaks_file_path());
 
     match File::open(file_name).await {
         Ok(file) => {
@@ -64,19 +52,7 @@ async fn get_dem(
     State(state): State<AppState>,
     geo_location: Query<GeoLocation>,
 ) -> impl IntoResponse {
-    let file_name = Path::new(&state.data_dir).join(format!(
-        "COP90/COP90_hh/Copernicus_DSM_30_{}{:02}_00_{}{:03}_00_DEM.tif",
-        match geo_location.latitude.direction {
-            LatitudeDirection::N => "N",
-            LatitudeDirection::S => "S",
-        },
-        geo_location.latitude.degree,
-        match geo_location.longitude.direction {
-            LongitudeDirection::E => "E",
-            LongitudeDirection::W => "W",
-        },
-        geo_location.longitude.degree
-    ));
+    let file_name = Path::new(&state.data_dir).join(geo_location.dem_file_path());
 
     match File::open(file_name).await {
         Ok(file) => {
@@ -92,6 +68,76 @@ async fn get_dem(
     }
 }
 
+async fn get_imagery(
+    State(state): State<AppState>,
+    geo_location: Query<GeoLocation>,
+) -> impl IntoResponse {
+    let file_name = Path::new(&state.data_dir).join(geo_location.imagery_file_path());
+
+    match File::open(file_name).await {
+        Ok(file) => {
+            let stream = ReaderStream::with_capacity(file, 10 * 1024 * 1024);
+            let body = Body::from_stream(stream);
+
+            ([(header::CONTENT_TYPE, "image/tiff")], body)
+        }
+        Err(_) => {
+            let body = Body::empty();
+            ([(header::CONTENT_TYPE, "text/html")], body)
+        }
+    }
+}
+
+async fn get_lakes(
+    State(state): State<AppState>,
+    geo_location: Query<GeoLocation>,
+) -> impl IntoResponse {
+    let file_name = Path::new(&state.data_dir).join(geo_location.lakes_file_path());
+
+    match File::open(file_name).await {
+        Ok(file) => {
+            let stream = ReaderStream::with_capacity(file, 256 * 1024);
+            let body = Body::from_stream(stream);
+
+            ([(header::CONTENT_TYPE, "application/geo+json")], body)
+        }
+        Err(_) => {
+            let body = Body::empty();
+            ([(header::CONTENT_TYPE, "text/html")], body)
+        }
+    }
+}
+
+/// XYZ imagery tiles from `imagery/tiles/{z}/{x}/{y}.{png,jpg}` in the data directory
+async fn get_imagery_tile(
+    State(state): State<AppState>,
+    UrlPath((z, x, file)): UrlPath<(u32, u32, String)>,
+) -> impl IntoResponse {
+    let content_type = match file.split_once('.') {
+        Some((y, "png")) if y.parse::<u32>().is_ok() => "image/png",
+        Some((y, "jpg" | "jpeg")) if y.parse::<u32>().is_ok() => "image/jpeg",
+        _ => return ([(header::CONTENT_TYPE, "text/html")], Body::empty()),
+    };
+    let file_name = Path::new(&state.data_dir)
+        .join("imagery/tiles")
+        .join(z.to_string())
+        .join(x.to_string())
+        .join(file);
+
+    match File::open(file_name).await {
+        Ok(file) => {
+            let stream = ReaderStream::with_capacity(file, 256 * 1024);
+            let body = Body::from_stream(stream);
+
+            ([(header::CONTENT_TYPE, content_type)], body)
+        }
+        Err(_) => {
+            let body = Body::empty();
+            ([(header::CONTENT_TYPE, "text/html")], body)
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -116,6 +162,7 @@ async fn main() -> Result<()> {
 
     let app = Router::new()
         .route("/peaks", get(get_peaks))
+        .route("/lakes", get(get_lakes))
         .layer(
             ServiceBuilder::new().layer(
                 CompressionLayer::new()
@@ -124,6 +171,8 @@ async fn main() -> Result<()> {
             ),
         )
         .route("/dem", get(get_dem))
+        .route("/imagery", get(get_imagery))
+        .route("/imagery/tiles/{z}/{x}/{y}", get(get_imagery_tile))
         .layer(cors)
         .with_state(state);
 
@@ -1,7 +1,7 @@
 use std::str::FromStr;
 
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use strum::{Display, EnumString};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumString, Display, Hash)]
@@ -36,7 +36,7 @@ pub struct GeoLocation {
     pub longitude: Longitude,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize, Serialize)]
 pub struct GeoCoord {
     pub latitude: f32,
     pub longitude: f32,
@@ -125,6 +125,74 @@ impl GeoLocation {
     pub fn to_numerical(&self) -> (f32, f32) {
         (self.latitude.into(), self.longitude.into())
     }
+
+    /// Path of the peaks csv file relative to the data directory
+    pub fn peaks_file_path(&self) -> String {
+        format!(
+            "peaks/peaks_{}{}_{}{}.csv",
+            match self.latitude.direction {
+                LatitudeDirection::N => "",
+                LatitudeDirection::S => "-",
+            },
+            self.latitude.degree,
+            match self.longitude.direction {
+                LongitudeDirection::E => "",
+                LongitudeDirection::W => "-",
+            },
+            self.longitude.degree
+        )
+    }
+
+    /// Path of the imagery GeoTIFF file draped on the terrain relative to the data directory
+    pub fn imagery_file_path(&self) -> String {
+        format!(
+            "imagery/imagery_{}{}_{}{}.tif",
+            match self.latitude.direction {
+                LatitudeDirection::N => "",
+                LatitudeDirection::S => "-",
+            },
+            self.latitude.degree,
+            match self.longitude.direction {
+                LongitudeDirection::E => "",
+                LongitudeDirection::W => "-",
+            },
+            self.longitude.degree
+        )
+    }
+
+    /// Path of the GeoJSON file with the lake polygons relative to the data directory
+    pub fn lakes_file_path(&self) -> String {
+        format!(
+            "lakes/lakes_{}{}_{}{}.geojson",
+            match self.latitude.direction {
+                LatitudeDirection::N => "",
+                LatitudeDirection::S => "-",
+            },
+            self.latitude.degree,
+            match self.longitude.direction {
+                LongitudeDirection::E => "",
+                LongitudeDirection::W => "-",
+            },
+            self.longitude.degree
+        )
+    }
+
+    /// Path of the DEM GeoTIFF file relative to the data directory
+    pub fn dem_file_path(&self) -> String {
+        format!(
+            "COP90/COP90_hh/Copernicus_DSM_30_{}{:02}_00_{}{:03}_00_DEM.tif",
+            match self.latitude.direction {
+                LatitudeDirection::N => "N",
+                LatitudeDirection::S => "S",
+            },
+            self.latitude.degree,
+            match self.longitude.direction {
+                LongitudeDirection::E => "E",
+                LongitudeDirection::W => "W",
+            },
+            self.longitude.degree
+        )
+    }
 }
 
 impl GeoCoord {
@@ -136,6 +204,68 @@ impl GeoCoord {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseGeoCoordError(String);
+
+impl std::fmt::Display for ParseGeoCoordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid coordinates: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseGeoCoordError {}
+
+/// Parses "latitude, longitude" with values either signed (`49.35, -20.21`)
+/// or followed by a direction (`49.35N 20.21W`), separated with a comma and/or whitespace
+impl FromStr for GeoCoord {
+    type Err = ParseGeoCoordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+
+        let &[latitude, longitude] = parts.as_slice() else {
+            return Err(ParseGeoCoordError(format!(
+                "expected latitude and longitude, got \"{s}\""
+            )));
+        };
+
+        let latitude = parse_signed_degree(latitude, ('N', 'S'))?;
+        let longitude = parse_signed_degree(longitude, ('E', 'W'))?;
+
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(ParseGeoCoordError(format!(
+                "latitude {latitude} out of range"
+            )));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(ParseGeoCoordError(format!(
+                "longitude {longitude} out of range"
+            )));
+        }
+
+        Ok(Self::new(latitude, longitude))
+    }
+}
+
+fn parse_signed_degree(
+    s: &str,
+    (positive, negative): (char, char),
+) -> Result<f32, ParseGeoCoordError> {
+    let (number, sign) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some(c) if c == positive => (&s[..s.len() - 1], 1.0),
+        Some(c) if c == negative => (&s[..s.len() - 1], -1.0),
+        _ => (s, 1.0),
+    };
+
+    number
+        .parse::<f32>()
+        .map(|value| sign * value)
+        .map_err(|err| ParseGeoCoordError(format!("\"{s}\": {err}")))
+}
+
 fn latitude_from_str<'de, D>(deserializer: D) -> Result<Latitude, D::Error>
 where
     D: Deserializer<'de>,
@@ -195,4 +325,35 @@ mod tests {
             },
         )
     }
+
+    #[test]
+    fn parse_geo_coord() {
+        assert_eq!(
+            "49.35135, 20.21139".parse::<GeoCoord>(),
+            Ok(GeoCoord::new(49.35135, 20.21139))
+        );
+        assert_eq!(
+            "-33.9 18.4".parse::<GeoCoord>(),
+            Ok(GeoCoord::new(-33.9, 18.4))
+        );
+        assert_eq!(
+            "46.5N,7.9w".parse::<GeoCoord>(),
+            Ok(GeoCoord::new(46.5, -7.9))
+        );
+        assert!("46.5".parse::<GeoCoord>().is_err());
+        assert!("91, 20".parse::<GeoCoord>().is_err());
+        assert!("46.5E 7.9N".parse::<GeoCoord>().is_err());
+    }
+
+    #[test]
+    fn data_file_paths() {
+        let location = GeoLocation::from_coord(-3, 37);
+        assert_eq!(location.peaks_file_path(), "peaks/peaks_-3_37.csv");
+        assert_eq!(location.imagery_file_path(), "imagery/imagery_-3_37.tif");
+        assert_eq!(location.lakes_file_path(), "lakes/lakes_-3_37.geojson");
+        assert_eq!(
+            location.dem_file_path(),
+            "COP90/COP90_hh/Copernicus_DSM_30_S03_00_E037_00_DEM.tif"
+        );
+    }
 }
@@ -1,23 +1,451 @@
-use color_eyre::{Report, Result};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use color_eyre::{
+    Report, Result,
+    eyre::{Context, eyre},
+};
+use config::{Config, ConfigBuilder, FileFormat, builder::DefaultState};
+use log::LevelFilter;
 use tokio::runtime::Runtime;
 use tokio_with_wasm::alias as tokio;
-use topo_renderer::{app::ApplicationRunner, control::background_runner::BackgroundNotification};
-use winit::window::Window;
+use topo_common::GeoCoord;
+use topo_renderer::{
+    app::{ApplicationEvent, ApplicationRunner},
+    control::background_runner::BackgroundNotification,
+    data::{camera::ViewMode, camera_path::CameraPath},
+    export::{
+        fly_through::{FlyThroughOptions, render_fly_through},
+        gbuffer::render_gbuffer,
+        panorama::{PanoramaOptions, PanoramaProjection, render_panorama},
+        peaks::{save_peaks, visible_peaks},
+        poster::{PosterOptions, render_poster},
+        vector::{VectorOptions, render_labeled_view},
+    },
+    headless::HeadlessRenderer,
+    settings::RendererSettings,
+};
+use winit::window::{Fullscreen, Window};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Renders vistas from any place in the world")]
+struct Args {
+    /// Settings file to load instead of `Settings.{toml,json,yaml,...}` in the working directory
+    #[arg(long, value_name = "FILE")]
+    settings: Option<PathBuf>,
+    /// TOML file remapping controls to keys, mouse buttons or modifier combinations,
+    /// e.g. `forward = ["KeyZ", "ArrowUp"]`, replacing the `key_bindings` setting
+    #[arg(long, value_name = "FILE")]
+    key_bindings: Option<PathBuf>,
+    /// Override a single setting, e.g. `--set fov=60` or `--set start_location.latitude=46.5`
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    /// Latitude of the start location (in degrees, negative for south)
+    #[arg(long, requires = "lon", allow_negative_numbers = true)]
+    lat: Option<f32>,
+    /// Longitude of the start location (in degrees, negative for west)
+    #[arg(long, requires = "lat", allow_negative_numbers = true)]
+    lon: Option<f32>,
+    /// Start location as a single string, e.g. "49.179, 20.088" or "49.179N 20.088E"
+    #[arg(long, value_name = "COORDINATES", conflicts_with_all = ["lat", "lon"])]
+    coords: Option<GeoCoord>,
+    /// Camera altitude in meters above sea level (50m above the ground by default)
+    #[arg(long, allow_negative_numbers = true)]
+    alt: Option<f32>,
+    /// Viewing direction in degrees clockwise from north
+    #[arg(long, allow_negative_numbers = true)]
+    yaw: Option<f32>,
+    /// Viewing angle in degrees above the horizon
+    #[arg(long, allow_negative_numbers = true)]
+    pitch: Option<f32>,
+    /// Vertical field of view in degrees
+    #[arg(long)]
+    fov: Option<f32>,
+    /// UTC date and time the terrain is lit for, e.g. "2024-06-21T18:30:00Z" (now by default)
+    #[arg(long, value_name = "TIMESTAMP")]
+    time: Option<String>,
+    /// Bending of the light rays: "standard" (k = 0.13, the default), "none",
+    /// "strong_inversion" (k = 0.5) or a refraction coefficient
+    #[arg(long, value_name = "REFRACTION")]
+    refraction: Option<String>,
+    /// How the terrain gets colored: default, normals, position, hypsometric, slope,
+    /// aspect or hillshade
+    #[arg(long)]
+    view_mode: Option<ViewMode>,
+    /// Meters between the contour lines drawn over the terrain
+    #[arg(long, value_name = "METERS")]
+    contour_interval: Option<f32>,
+    /// Window width in logical pixels
+    #[arg(long, default_value_t = 800)]
+    width: u32,
+    /// Window height in logical pixels
+    #[arg(long, default_value_t = 600)]
+    height: u32,
+    /// Start in borderless fullscreen mode
+    #[arg(long)]
+    fullscreen: bool,
+    /// Url of the backend serving the terrain data
+    #[arg(long, value_name = "URL")]
+    backend_url: Option<String>,
+    /// Read the terrain data from a local directory instead of the backend
+    #[arg(long, value_name = "DIR", conflicts_with = "backend_url")]
+    data_dir: Option<PathBuf>,
+    /// Log level, overrides `RUST_LOG`
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<LevelFilter>,
+    /// Render a single frame without opening a window, save it as PNG and exit
+    ///
+    /// The frame is `--width` by `--height` pixels
+    #[arg(long, value_name = "FILE")]
+    screenshot: Option<PathBuf>,
+    /// Render a 360° panorama around the start location without opening a window, save it as PNG and exit
+    ///
+    /// The panorama is centered on `--yaw`, equirectangular ones carry Photo Sphere (GPano) metadata
+    #[arg(long, value_name = "FILE", conflicts_with = "screenshot")]
+    panorama: Option<PathBuf>,
+    /// Width of the panorama in pixels
+    #[arg(long, default_value_t = PanoramaOptions::default().width)]
+    panorama_width: u32,
+    /// Projection of the panorama: equirectangular or cylindrical
+    #[arg(long, default_value_t = PanoramaProjection::default())]
+    panorama_projection: PanoramaProjection,
+    /// Elevation angle in degrees at the bottom edge of the panorama
+    #[arg(long, allow_negative_numbers = true, default_value_t = PanoramaOptions::default().min_elevation)]
+    panorama_min_elevation: f32,
+    /// Elevation angle in degrees at the top edge of the panorama
+    #[arg(long, allow_negative_numbers = true, default_value_t = PanoramaOptions::default().max_elevation)]
+    panorama_max_elevation: f32,
+    /// Leave out the peak labels from the panorama
+    #[arg(long)]
+    panorama_no_labels: bool,
+    /// Export the terrain silhouettes and the labeled peaks of the start view as SVG or PDF
+    /// (depending on the extension) without opening a window and exit
+    ///
+    /// The silhouettes are traced on a `--width` by `--height` pixels render
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["screenshot", "panorama"])]
+    vector: Option<PathBuf>,
+    /// Width of the vector export on paper in millimeters
+    #[arg(long, default_value_t = VectorOptions::default().width_mm)]
+    print_width: f32,
+    /// Render the start view at `--width` by `--height` pixels in tiles, which allows
+    /// resolutions beyond the limits of a single render, save it and exit
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["screenshot", "panorama", "vector"])]
+    poster: Option<PathBuf>,
+    /// Largest side of the tiles the poster gets rendered in
+    #[arg(long, default_value_t = PosterOptions::default().max_tile_size)]
+    tile_size: u32,
+    /// Play back a camera path recorded in the window (with the `record_camera_path` key),
+    /// save its frames at `--width` by `--height` pixels to the `--frames` directory and exit
+    #[arg(long, value_name = "FILE", requires = "frames", conflicts_with_all = ["screenshot", "panorama", "vector", "poster"])]
+    fly_through: Option<PathBuf>,
+    /// Directory for the numbered PNG frames of the fly-through
+    #[arg(long, value_name = "DIR", requires = "fly_through")]
+    frames: Option<PathBuf>,
+    /// Frames per second of the fly-through
+    #[arg(long, requires = "fly_through", default_value_t = FlyThroughOptions::default().frame_rate)]
+    frame_rate: f32,
+    /// Export the peaks visible from the start view as JSON, CSV or GeoJSON
+    /// (depending on the extension) without opening a window and exit
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["screenshot", "panorama", "vector", "poster", "fly_through"])]
+    peaks: Option<PathBuf>,
+    /// Export the distance, world normals and latitude, longitude and elevation of every pixel
+    /// of the start view as 32-bit float EXR or TIFF (depending on the extension) and exit
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["screenshot", "panorama", "vector", "poster", "fly_through", "peaks"])]
+    gbuffer: Option<PathBuf>,
+    /// Play back a recorded or scripted camera path (JSON keyframes) in the window once it opens
+    #[arg(long, value_name = "FILE")]
+    play: Option<PathBuf>,
+}
+
+/// Settings are layered with the later sources taking precedence:
+/// settings file, `TOPO_` prefixed environment variables, command line overrides
+fn load_settings(args: &Args) -> Result<RendererSettings> {
+    let file = match &args.settings {
+        Some(path) => config::File::from(path.as_path()),
+        None => config::File::with_name("Settings").required(false),
+    };
+
+    let mut builder = Config::builder().add_source(file).add_source(
+        config::Environment::with_prefix("TOPO")
+            .prefix_separator("_")
+            .separator("__"),
+    );
+
+    for setting_override in &args.overrides {
+        let (key, value) = setting_override
+            .split_once('=')
+            .ok_or_else(|| eyre!("Expected KEY=VALUE, got \"{setting_override}\""))?;
+        builder = builder.set_override(key.trim(), value.trim())?;
+    }
+
+    builder = apply_arg_overrides(builder, args)?;
+
+    let mut settings = RendererSettings::from_config(builder.build()?)?;
+    if let Some(path) = &args.key_bindings {
+        settings.key_bindings = Config::builder()
+            .add_source(config::File::from(path.as_path()).format(FileFormat::Toml))
+            .build()
+            .and_then(Config::try_deserialize)
+            .wrap_err_with(|| format!("Invalid key bindings in {}", path.display()))?;
+    }
+
+    Ok(settings)
+}
+
+/// Dedicated command line options take precedence over all the other settings sources
+///
+/// Floats are passed as `f64` since that's what `config` values are stored as
+fn apply_arg_overrides(
+    builder: ConfigBuilder<DefaultState>,
+    args: &Args,
+) -> Result<ConfigBuilder<DefaultState>> {
+    let start_location = args
+        .coords
+        .or_else(|| Some(GeoCoord::new(args.lat?, args.lon?)));
+
+    let builder = builder
+        .set_override_option(
+            "start_location.latitude",
+            start_location.map(|coord| f64::from(coord.latitude)),
+        )?
+        .set_override_option(
+            "start_location.longitude",
+            start_location.map(|coord| f64::from(coord.longitude)),
+        )?
+        .set_override_option("start_altitude", args.alt.map(f64::from))?
+        .set_override_option("start_yaw", args.yaw.map(f64::from))?
+        .set_override_option("start_pitch", args.pitch.map(f64::from))?
+        .set_override_option("fov", args.fov.map(f64::from))?
+        .set_override_option("sun.time", args.time.clone())?
+        .set_override_option("refraction", args.refraction.clone())?
+        .set_override_option("view_mode", args.view_mode.map(|mode| mode.to_string()))?
+        .set_override_option("contours.interval", args.contour_interval.map(f64::from))?
+        .set_override_option("backend_url", args.backend_url.clone())?
+        .set_override_option(
+            "data_dir",
+            args.data_dir
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        )?;
+
+    Ok(builder)
+}
+
+async fn render_screenshot(
+    settings: RendererSettings,
+    (width, height): (u32, u32),
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((width, height).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+
+    renderer
+        .render()
+        .await?
+        .save(path)
+        .wrap_err_with(|| format!("Unable to save screenshot to {}", path.display()))?;
+    log::info!("Screenshot saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn render_panorama_file(
+    settings: RendererSettings,
+    options: PanoramaOptions,
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((1, 1).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+
+    render_panorama(&mut renderer, &options)
+        .await?
+        .save_png(path)
+        .wrap_err_with(|| format!("Unable to save panorama to {}", path.display()))?;
+    log::info!("Panorama saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn render_vector_file(
+    settings: RendererSettings,
+    (width, height): (u32, u32),
+    options: VectorOptions,
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((width, height).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+
+    render_labeled_view(&mut renderer, &options)
+        .await?
+        .save(path, &options)?;
+    log::info!("Vector export saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn render_poster_file(
+    settings: RendererSettings,
+    options: PosterOptions,
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((1, 1).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+
+    render_poster(&mut renderer, &options)
+        .await?
+        .save(path)
+        .wrap_err_with(|| format!("Unable to save poster to {}", path.display()))?;
+    log::info!("Poster saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn render_fly_through_frames(
+    settings: RendererSettings,
+    (width, height): (u32, u32),
+    camera_path: &Path,
+    options: FlyThroughOptions,
+    output_dir: &Path,
+) -> Result<()> {
+    let path = CameraPath::load(camera_path)?;
+    let mut renderer = HeadlessRenderer::new((width, height).into(), settings).await?;
+
+    let frames = render_fly_through(&mut renderer, &path, &options, output_dir).await?;
+    log::info!("{frames} frames saved to {}", output_dir.display());
+
+    Ok(())
+}
+
+async fn render_peaks_file(
+    settings: RendererSettings,
+    (width, height): (u32, u32),
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((width, height).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+    // labels are laid out while rendering, which marks the visible peaks
+    renderer.render().await?;
+
+    save_peaks(
+        &visible_peaks(renderer.data(), (width as f32, height as f32).into()),
+        path,
+    )?;
+    log::info!("Visible peaks saved to {}", path.display());
+
+    Ok(())
+}
+
+async fn render_gbuffer_file(
+    settings: RendererSettings,
+    (width, height): (u32, u32),
+    path: &Path,
+) -> Result<()> {
+    let start_location = settings.start_location;
+    let mut renderer = HeadlessRenderer::new((width, height).into(), settings).await?;
+    renderer.load_location(start_location).await?;
+
+    render_gbuffer(&mut renderer).await?.save(path)?;
+    log::info!("G-buffer saved to {}", path.display());
+
+    Ok(())
+}
 
 pub fn main() -> Result<()> {
-    env_logger::init();
+    let args = Args::parse();
+
+    let mut logger_builder = env_logger::Builder::from_default_env();
+    if let Some(log_level) = args.log_level {
+        logger_builder.filter_level(log_level);
+    }
+    logger_builder.init();
+
+    let settings = load_settings(&args)?;
     use winit::dpi::LogicalSize;
     use winit::platform::x11::WindowAttributesExtX11;
 
-    let (width, height) = (800, 600);
+    let (width, height) = (args.width, args.height);
     let window_attributes = Window::default_attributes()
         .with_base_size(LogicalSize::new(width as f64, height as f64))
         .with_min_inner_size(LogicalSize::new(width as f64, height as f64))
-        .with_inner_size(LogicalSize::new(width as f64, height as f64));
+        .with_inner_size(LogicalSize::new(width as f64, height as f64))
+        .with_fullscreen(args.fullscreen.then_some(Fullscreen::Borderless(None)));
 
     let background_runtime = Runtime::new()?;
 
-    let mut app_runner = ApplicationRunner::new(window_attributes);
+    if let Some(path) = &args.screenshot {
+        return background_runtime.block_on(render_screenshot(settings, (width, height), path));
+    }
+
+    if let Some(path) = &args.panorama {
+        let options = PanoramaOptions {
+            width: args.panorama_width,
+            min_elevation: args.panorama_min_elevation,
+            max_elevation: args.panorama_max_elevation,
+            projection: args.panorama_projection,
+            labels: !args.panorama_no_labels,
+            ..Default::default()
+        };
+        return background_runtime.block_on(render_panorama_file(settings, options, path));
+    }
+
+    if let Some(path) = &args.vector {
+        let options = VectorOptions {
+            width_mm: args.print_width,
+            ..Default::default()
+        };
+        return background_runtime.block_on(render_vector_file(
+            settings,
+            (width, height),
+            options,
+            path,
+        ));
+    }
+
+    if let Some(path) = &args.poster {
+        let options = PosterOptions {
+            size: (width, height).into(),
+            max_tile_size: args.tile_size,
+            ..Default::default()
+        };
+        return background_runtime.block_on(render_poster_file(settings, options, path));
+    }
+
+    if let (Some(camera_path), Some(output_dir)) = (&args.fly_through, &args.frames) {
+        let options = FlyThroughOptions {
+            frame_rate: args.frame_rate,
+        };
+        return background_runtime.block_on(render_fly_through_frames(
+            settings,
+            (width, height),
+            camera_path,
+            options,
+            output_dir,
+        ));
+    }
+
+    if let Some(path) = &args.peaks {
+        return background_runtime.block_on(render_peaks_file(settings, (width, height), path));
+    }
+
+    if let Some(path) = &args.gbuffer {
+        return background_runtime.block_on(render_gbuffer_file(settings, (width, height), path));
+    }
+
+    let camera_path = args.play.as_deref().map(CameraPath::load).transpose()?;
+
+    let mut app_runner = ApplicationRunner::new(window_attributes, settings);
+
+    if let Some(path) = camera_path {
+        app_runner
+            .get_event_loop_proxy()
+            .send_event(ApplicationEvent::PlayCameraPath(path))
+            .map_err(|err| eyre!("{err}"))?;
+    }
 
     if let Err(err) = app_runner.configure_background_runner(|f| background_runtime.spawn(f)) {
         log::error!("{err:?}");
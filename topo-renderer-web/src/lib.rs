@@ -2,10 +2,16 @@ mod js;
 
 use std::cell::OnceCell;
 
+use config::Config;
+use futures::channel::oneshot;
+use jiff::Timestamp;
 use topo_common::GeoCoord;
 use topo_renderer::{
     app::{ApplicationEvent, ApplicationRunner},
-    control::background_runner::BackgroundNotification,
+    control::{background_runner::BackgroundNotification, key_bindings::KeyBindings},
+    data::{camera::ViewMode, camera_path::CameraPath, refraction::Refraction},
+    export::peaks::PeaksFormat,
+    settings::RendererSettings,
 };
 
 use color_eyre::{
@@ -35,11 +41,164 @@ pub fn set_location(latitude: f32, longitude: f32) {
     })
 }
 
-#[tokio::main(flavor = "multi_thread")]
-pub async fn async_start() -> Result<()> {
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-    console_log::init_with_level(log::Level::Info).expect("could not initialize logger");
+fn send_event(event: ApplicationEvent) -> Result<()> {
+    EVENT_LOOP_PROXY.with(|cell| {
+        cell.get()
+            .ok_or_eyre("The renderer is not running")?
+            .send_event(event)
+            .map_err(|err| eyre!("{err}"))
+    })
+}
+
+/// Flies the camera to the location along a great circle arc, the altitude
+/// (in meters) stays the same and the duration (in seconds) follows from
+/// the distance unless given
+#[wasm_bindgen]
+pub fn fly_to(
+    latitude: f32,
+    longitude: f32,
+    altitude: Option<f32>,
+    duration: Option<f32>,
+) -> Result<(), JsError> {
+    send_event(ApplicationEvent::FlyTo {
+        location: GeoCoord::new(latitude, longitude),
+        altitude,
+        duration,
+    })
+    .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Plays back a camera path given as JSON, i.e. keyframes with their `time`,
+/// `location`, `altitude`, `yaw`, `pitch`, `fov` and optionally `easing` and `lift`
+#[wasm_bindgen]
+pub fn play_camera_path(path: &str) -> Result<(), JsError> {
+    let path = serde_json::from_str::<CameraPath>(path)
+        .map_err(|err| JsError::new(&format!("Invalid camera path: {err}")))?;
+    send_event(ApplicationEvent::PlayCameraPath(path)).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Remaps controls to keys, mouse buttons or modifier combinations given as JSON,
+/// e.g. `{"forward": ["KeyZ", "ArrowUp"], "look": ["Ctrl+MouseLeft"]}`, the controls
+/// that aren't present get their default bindings
+#[wasm_bindgen]
+pub fn set_key_bindings(key_bindings: &str) -> Result<(), JsError> {
+    let key_bindings = serde_json::from_str::<KeyBindings>(key_bindings)
+        .map_err(|err| JsError::new(&format!("Invalid key bindings: {err}")))?;
+    send_event(ApplicationEvent::SetKeyBindings(key_bindings))
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Lights the terrain for the given date and time, an RFC 3339 timestamp
+/// with an offset, e.g. "2024-06-21T18:30:00Z" or "2024-06-21T20:30:00+02:00"
+#[wasm_bindgen]
+pub fn set_time(time: &str) -> Result<(), JsError> {
+    let time = time
+        .parse::<Timestamp>()
+        .map_err(|err| JsError::new(&format!("Invalid time: {err}")))?;
+    send_event(ApplicationEvent::SetSunTime(time)).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Starts advancing the sun's clock by the given number of seconds per real second,
+/// or stops the time-lapse when not given
+#[wasm_bindgen]
+pub fn set_time_lapse(speed: Option<f64>) -> Result<(), JsError> {
+    send_event(ApplicationEvent::SetTimeLapse(speed)).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Bends the light rays from the terrain with a preset, "standard", "none" or
+/// "strong_inversion", or with the given refraction coefficient, e.g. "0.2"
+#[wasm_bindgen]
+pub fn set_refraction(refraction: &str) -> Result<(), JsError> {
+    let refraction = refraction
+        .parse::<Refraction>()
+        .map_err(|err| JsError::new(&err.to_string()))?;
+    send_event(ApplicationEvent::SetRefraction(refraction))
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Colors the terrain with a view mode: "default", "normals", "position",
+/// "hypsometric", "slope", "aspect" or "hillshade"
+#[wasm_bindgen]
+pub fn set_view_mode(view_mode: &str) -> Result<(), JsError> {
+    let view_mode = view_mode
+        .parse::<ViewMode>()
+        .map_err(|_| JsError::new(&format!("Unknown view mode {view_mode}")))?;
+    send_event(ApplicationEvent::SetViewMode(view_mode))
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Draws contour lines every given number of meters over the terrain, 0 hides them
+#[wasm_bindgen]
+pub fn set_contour_interval(interval: f32) -> Result<(), JsError> {
+    send_event(ApplicationEvent::SetContourInterval(interval))
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Resolves with the legend of the current view mode as JSON, a `title` and
+/// `entries` with their sRGB `color` and `label`, or `null` for the modes
+/// whose colors don't stand for anything
+#[wasm_bindgen]
+pub async fn get_legend() -> Result<String, JsError> {
+    let (sender, receiver) = oneshot::channel();
+    send_event(ApplicationEvent::GetLegend(sender))
+        .map_err(|err| JsError::new(&err.to_string()))?;
+
+    let legend = receiver
+        .await
+        .map_err(|_| JsError::new("The renderer stopped before sending the legend"))?;
+    serde_json::to_string(&legend).map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Resolves with the currently visible peaks serialized to
+/// the given format: "json", "csv" or "geojson"
+#[wasm_bindgen]
+pub async fn export_visible_peaks(format: String) -> Result<String, JsError> {
+    let format = format
+        .parse::<PeaksFormat>()
+        .map_err(|_| JsError::new(&format!("Unknown peaks format {format}")))?;
+    let (sender, receiver) = oneshot::channel();
+    send_event(ApplicationEvent::ExportPeaks(format, sender))
+        .map_err(|err| JsError::new(&err.to_string()))?;
+
+    receiver
+        .await
+        .map_err(|_| eyre!("The renderer stopped before exporting the peaks"))
+        .and_then(|result| result)
+        .map_err(|err| JsError::new(&err.to_string()))
+}
+
+/// Settings are layered with the later sources taking precedence:
+/// the object passed from javascript, url query parameters
+fn load_settings(settings: JsValue) -> Result<RendererSettings> {
+    let mut builder = Config::builder();
+
+    if !settings.is_undefined() && !settings.is_null() {
+        let settings_json = js_sys::JSON::stringify(&settings)
+            .map_err(|_| eyre!("Unable to serialize settings object"))?;
+        builder = builder.add_source(config::File::from_str(
+            &String::from(settings_json),
+            config::FileFormat::Json,
+        ));
+    }
+
+    let query = wgpu::web_sys::window()
+        .ok_or_eyre("Unable to get window")?
+        .location()
+        .search()
+        .map_err(|_| eyre!("Unable to get url query"))?;
+
+    for pair in query.trim_start_matches('?').split('&') {
+        if let Some((key, value)) = pair.split_once('=') {
+            let value = js_sys::decode_uri_component(value)
+                .map_err(|_| eyre!("Unable to decode url query parameter {key}"))?;
+            builder = builder.set_override(key, String::from(value))?;
+        }
+    }
 
+    RendererSettings::from_config(builder.build()?)
+}
+
+pub async fn async_start(settings: RendererSettings) -> Result<()> {
     use wasm_bindgen::JsCast;
     use winit::platform::web::WindowAttributesExtWebSys;
     match wgpu::web_sys::window()
@@ -53,7 +212,7 @@ pub async fn async_start() -> Result<()> {
     {
         Ok::<_, Report>(canvas) => {
             let window_attributes = Window::default_attributes().with_canvas(Some(canvas));
-            let mut app_runner = ApplicationRunner::new(window_attributes);
+            let mut app_runner = ApplicationRunner::new(window_attributes, settings);
             EVENT_LOOP_PROXY.with(|cell| cell.set(app_runner.get_event_loop_proxy()).ok());
             if let Err(err) = app_runner.configure_background_runner(|f| tokio::spawn(f)) {
                 log::error!("{err:?}");
@@ -134,7 +293,18 @@ pub async fn async_start() -> Result<()> {
     }
 }
 
-#[wasm_bindgen(start)]
-pub fn start() {
-    async_start();
+#[wasm_bindgen]
+pub fn start(settings: JsValue) {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    console_log::init_with_level(log::Level::Info).expect("could not initialize logger");
+
+    match load_settings(settings) {
+        Ok(settings) => tokio::spawn_local(async move {
+            let _ = async_start(settings).await;
+        }),
+        Err(err) => {
+            log::error!("{err:?}");
+            push_notification(format!("Error loading settings: {err}"));
+        }
+    }
 }
@@ -1,7 +1,9 @@
-use std::{pin::Pin, sync::Arc};
+use std::{path::PathBuf, pin::Pin, sync::Arc};
 
 use color_eyre::Report;
 use futures::channel::oneshot;
+use glam::Vec3;
+use jiff::Timestamp;
 use tokio::{sync::broadcast::Receiver, task::JoinHandle};
 use tokio_with_wasm::alias as tokio;
 use topo_common::{GeoCoord, GeoLocation};
@@ -11,23 +13,54 @@ use winit::{
     error::EventLoopError,
     event::WindowEvent,
     event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy},
-    window::WindowAttributes,
+    window::{CursorGrabMode, WindowAttributes},
 };
 
 use crate::{
     control::{
         application_controllers::ApplicationControllers, background_runner::BackgroundNotification,
+        camera_controller::Control, key_bindings::KeyBindings,
     },
-    data::application_data::{ApplicationData, PeakLabel},
+    data::{
+        application_data::{ApplicationData, PeakLabel},
+        camera::ViewMode,
+        camera_path::{CameraPath, CameraPathPoint},
+        contours::ContourAnchor,
+        imagery::ImagerySettings,
+        refraction::Refraction,
+        terrain_coloring::Legend,
+        water::WaterSettings,
+    },
+    export::peaks::{PeaksFormat, VisiblePeak, export_peaks, visible_peaks},
     render::{
         data::PeakInstance,
+        geometry::inverse_transform,
         render_engine::{RenderEngine, RenderEvent},
     },
+    settings::{ContourSettings, RendererSettings},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct ApplicationSettings {
     pub backend_url: String,
+    /// Directory laid out the same way as the backend's `data_dir`, when set
+    /// the data is read from it directly instead of being fetched from the backend
+    /// (not supported on wasm)
+    pub data_dir: Option<PathBuf>,
+    pub imagery: ImagerySettings,
+    pub water: WaterSettings,
+}
+
+impl Default for ApplicationSettings {
+    fn default() -> Self {
+        Self {
+            backend_url: "http://localhost:3333".to_string(),
+            data_dir: None,
+            imagery: ImagerySettings::default(),
+            water: WaterSettings::default(),
+        }
+    }
 }
 
 pub enum ApplicationEvent {
@@ -35,13 +68,46 @@ pub enum ApplicationEvent {
     ChangeLocation(GeoCoord),
     PeaksReady((GeoLocation, Vec<PeakInstance>)),
     PeakLabelsReady((GeoLocation, Vec<PeakLabel>)),
+    /// Where the index contours of the loaded tile can be labeled, found for the given
+    /// label interval
+    ContourAnchorsReady((GeoLocation, f32, Vec<ContourAnchor>)),
+    /// Background processing of a requested location finished (successfully or not)
+    LocationLoaded(GeoLocation),
     RenderEvent(RenderEvent),
+    /// Serializes the currently visible peaks and sends them back
+    ExportPeaks(PeaksFormat, oneshot::Sender<Result<String, Report>>),
+    /// Animates the camera to the location along a great circle arc, keeping
+    /// its altitude unless given, see [`CameraPath::fly_to`]
+    FlyTo {
+        location: GeoCoord,
+        altitude: Option<f32>,
+        /// Seconds
+        duration: Option<f32>,
+    },
+    /// Plays back a recorded or scripted camera path
+    PlayCameraPath(CameraPath),
+    /// Remaps the controls
+    SetKeyBindings(KeyBindings),
+    /// Lights the terrain for the given UTC time
+    SetSunTime(Timestamp),
+    /// Starts the time-lapse with the given speed (in simulated seconds per real second),
+    /// or stops it when `None`
+    SetTimeLapse(Option<f64>),
+    /// Bends the light rays from the terrain with the given refraction
+    SetRefraction(Refraction),
+    /// Colors the terrain with the given view mode
+    SetViewMode(ViewMode),
+    /// Sends back the legend of the current view mode, see [`Legend::new`]
+    GetLegend(oneshot::Sender<Option<Legend>>),
+    /// Draws contour lines with the given interval (in meters), 0 hides them
+    SetContourInterval(f32),
 }
 
 pub struct Application {
     engine: Option<RenderEngine>,
     controllers: ApplicationControllers,
     data: ApplicationData,
+    settings: RendererSettings,
     window_attributes: WindowAttributes,
     event_loop_proxy: EventLoopProxy<ApplicationEvent>,
     surface_configured: bool,
@@ -54,24 +120,21 @@ impl Application {
     pub fn new(
         window_attributes: WindowAttributes,
         event_loop_proxy: EventLoopProxy<ApplicationEvent>,
+        settings: RendererSettings,
     ) -> Self {
-        let settings = Arc::new(ApplicationSettings {
-            backend_url: env!("TOPO_backend_url").to_string(),
-        });
-
-        let controllers =
-            ApplicationControllers::new(event_loop_proxy.clone(), Arc::clone(&settings));
+        let controllers = ApplicationControllers::new(event_loop_proxy.clone(), &settings);
 
         let bounds = window_attributes
             .inner_size
             .map(|s| s.to_physical(1.0).into())
             .unwrap_or((800.0, 600.0).into());
-        let data = ApplicationData::new(bounds);
+        let data = ApplicationData::new(bounds, &settings);
 
         Self {
             engine: None,
             controllers,
             data,
+            settings,
             window_attributes,
             event_loop_proxy,
             surface_configured: false,
@@ -80,6 +143,190 @@ impl Application {
             resized: None,
         }
     }
+
+    /// Peaks visible in the last rendered frame
+    fn get_visible_peaks(&self) -> Vec<VisiblePeak> {
+        let size = self
+            .engine
+            .as_ref()
+            .map(|engine| engine.size())
+            .unwrap_or_default();
+        visible_peaks(&self.data, (size.width as f32, size.height as f32).into())
+    }
+
+    fn export_peaks_to_file(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = &self.settings.peaks_export_file;
+            match crate::export::peaks::save_peaks(&self.get_visible_peaks(), path) {
+                Ok(()) => log::info!("Visible peaks saved to {}", path.display()),
+                Err(err) => log::error!("{err:?}"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        log::warn!("Saving peaks to a file is not supported on wasm, use export_visible_peaks");
+    }
+
+    /// Point on the label or the terrain at the position (in pixels)
+    fn pick_target(&self, position: (f32, f32)) -> Option<Vec3> {
+        if let Some(peak) = self.pick_peak(position) {
+            return Some(peak.position);
+        }
+        self.pick_terrain(position)
+    }
+
+    /// Peak whose label is at the position
+    fn pick_peak(&self, position: (f32, f32)) -> Option<&PeakInstance> {
+        let (location, id) = self.engine.as_ref()?.get_label_at(position)?;
+        self.data.peaks.get(&location)?.get(id.0 as usize)
+    }
+
+    fn pick_terrain(&self, position: (f32, f32)) -> Option<Vec3> {
+        let size = self.engine.as_ref()?.size();
+        let direction =
+            self.data
+                .camera
+                .screen_ray(position, size.width as f32, size.height as f32);
+        self.data
+            .pick_terrain(self.data.camera.eye, direction, self.settings.view_distance)
+    }
+
+    /// Flies the camera halfway to the target, ending up facing it
+    fn fly_towards(&mut self, target: Vec3) {
+        let camera = self.data.camera;
+        let mut destination = camera;
+        destination.set_eye(camera.eye.lerp(target, 0.5));
+        destination.look_at(target);
+
+        let from = CameraPathPoint::from_camera(&camera, 0.0);
+        let to = CameraPathPoint::from_camera(&destination, 0.0);
+        let mut path = CameraPath::fly_to(from, to.location, to.altitude, None);
+        if let Some(last) = path.points.last_mut() {
+            last.yaw = to.yaw;
+            last.pitch = to.pitch;
+        }
+        self.controllers.play(path);
+    }
+
+    /// Logs the peak or terrain at the position
+    fn identify(&self, position: (f32, f32)) {
+        if let Some(peak) = self.pick_peak(position) {
+            log::info!(
+                "{} ({:.0}m) at {:.5}, {:.5}",
+                peak.name,
+                peak.elevation,
+                peak.coord.latitude,
+                peak.coord.longitude
+            );
+        } else if let Some(point) = self.pick_terrain(position) {
+            let (elevation, coord) = inverse_transform(point);
+            log::info!(
+                "Terrain at {:.5}, {:.5}, {elevation:.0}m above sea level",
+                coord.latitude,
+                coord.longitude
+            );
+        } else {
+            log::info!("No terrain there");
+        }
+    }
+
+    /// Orbits around the peak or terrain under the cursor (or in the middle of the view)
+    fn toggle_orbit(&mut self) {
+        let camera_controller = &self.controllers.camera_controller;
+        if camera_controller.get_orbit_target().is_some() {
+            log::info!("Orbit mode off");
+            self.controllers.camera_controller.stop_orbit();
+            return;
+        }
+
+        let size = self
+            .engine
+            .as_ref()
+            .map(|engine| engine.size())
+            .unwrap_or_default();
+        let center = (0.5 * size.width as f32, 0.5 * size.height as f32);
+        match self
+            .pick_target(camera_controller.get_cursor_position())
+            .or_else(|| self.pick_target(center))
+        {
+            Some(target) => {
+                log::info!("Orbit mode on");
+                self.controllers
+                    .camera_controller
+                    .start_orbit(&mut self.data.camera, target);
+                self.require_render = true;
+            }
+            None => log::warn!("No terrain to orbit around in view"),
+        }
+    }
+
+    fn toggle_time_lapse(&mut self) {
+        let time_lapse = !self.data.sun.is_time_lapse();
+        self.data.sun.set_time_lapse(time_lapse);
+        log::info!("Time-lapse {}", if time_lapse { "on" } else { "off" });
+        self.log_sun_position();
+    }
+
+    /// Logs the time and where the sun is in the sky from the camera's location
+    fn log_sun_position(&self) {
+        let (_, location) = inverse_transform(self.data.camera.eye);
+        let position = self.data.sun.position_at(location);
+        log::info!(
+            "Sun at {}: azimuth {:.1}°, altitude {:.1}°",
+            self.data.sun.get_time(),
+            position.azimuth,
+            position.altitude
+        );
+    }
+
+    /// Grabs and hides the cursor so that moving the mouse turns the view, or releases it
+    fn set_pointer_lock(&mut self, locked: bool) {
+        if let Some(window) = self.engine.as_ref().and_then(|engine| engine.window()) {
+            let grab = if locked {
+                // not every platform supports locking the cursor in place
+                window
+                    .set_cursor_grab(CursorGrabMode::Locked)
+                    .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+            } else {
+                window.set_cursor_grab(CursorGrabMode::None)
+            };
+            if let Err(err) = grab {
+                log::warn!("Unable to change the cursor grab: {err}");
+            }
+            window.set_cursor_visible(!locked);
+        }
+        log::info!("Pointer lock {}", if locked { "on" } else { "off" });
+        self.controllers.camera_controller.set_pointer_lock(locked);
+    }
+
+    fn toggle_camera_path_recording(&mut self) {
+        if !self.controllers.is_recording() {
+            log::info!("Recording the camera path");
+            self.controllers.start_recording();
+            return;
+        }
+
+        let Some(path) = self.controllers.stop_recording() else {
+            return;
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let file = &self.settings.camera_path_file;
+            match path.save(file) {
+                Ok(()) => log::info!(
+                    "Camera path of {:.1}s saved to {}",
+                    path.duration(),
+                    file.display()
+                ),
+                Err(err) => log::error!("{err:?}"),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        log::warn!(
+            "Saving the camera path of {:.1}s is not supported on wasm",
+            path.duration()
+        );
+    }
 }
 
 pub struct ApplicationRunner {
@@ -88,12 +335,12 @@ pub struct ApplicationRunner {
 }
 
 impl ApplicationRunner {
-    pub fn new(window_attributes: WindowAttributes) -> Self {
+    pub fn new(window_attributes: WindowAttributes, settings: RendererSettings) -> Self {
         let mut event_loop = EventLoop::<ApplicationEvent>::with_user_event();
         let event_loop = event_loop.build().unwrap();
         let event_loop_proxy = event_loop.create_proxy();
 
-        let app = Application::new(window_attributes, event_loop_proxy);
+        let app = Application::new(window_attributes, event_loop_proxy, settings);
 
         Self { app, event_loop }
     }
@@ -188,13 +435,13 @@ impl ApplicationHandler<ApplicationEvent> for Application {
                     Ok(Some(mut engine)) => {
                         if let Some(physical_size) = self.resized.take() {
                             self.surface_configured = engine.resize(physical_size, &mut self.data);
-                            engine.window().request_redraw();
+                            engine.request_redraw();
                         }
                         self.engine = Some(engine);
                         self.require_render = true;
                         if let Some(engine) = self.engine.as_mut() {
                             if let Err(err) = self.controllers.ui_controller.change_location(
-                                GeoCoord::new(49.35135, 20.21139),
+                                self.settings.start_location,
                                 &mut self.data,
                                 engine,
                             ) {
@@ -219,21 +466,31 @@ impl ApplicationHandler<ApplicationEvent> for Application {
                     self.surface_configured = engine.resize(physical_size, &mut self.data);
                     self.require_render = true;
                     // On macos the window needs to be redrawn manually after resizing
-                    engine.window().request_redraw();
+                    engine.request_redraw();
                 }
                 WindowEvent::RedrawRequested => {
-                    engine.window().request_redraw();
+                    engine.request_redraw();
 
                     if !self.surface_configured {
                         return;
                     }
 
                     let _ = engine.poll();
-                    if self.controllers.update(
+                    let changed = self.controllers.update(
                         self.require_render,
                         &mut self.data,
                         engine.size().into(),
-                    ) {
+                    );
+                    if let Some(location) = self.controllers.take_animated_location()
+                        && let Err(err) = self.controllers.ui_controller.follow_camera(
+                            location,
+                            &mut self.data,
+                            engine,
+                        )
+                    {
+                        log::error!("{err:?}");
+                    }
+                    if changed {
                         engine.update(&mut self.data);
                         match engine.render(&self.data) {
                             Ok(_) => {}
@@ -254,12 +511,52 @@ impl ApplicationHandler<ApplicationEvent> for Application {
                             }
                         }
                     }
-                    self.require_render = false;
+                    // keep rendering until the shadows follow the sun
+                    self.require_render = engine.has_stale_shadows(&self.data);
                 }
                 WindowEvent::CloseRequested => event_loop.exit(),
                 _ => {}
             }
         }
+
+        if matches!(event, WindowEvent::Focused(false))
+            && self.controllers.camera_controller.is_pointer_locked()
+        {
+            self.set_pointer_lock(false);
+        }
+
+        for control in self.controllers.take_requested_controls() {
+            match control {
+                Control::ExportPeaks => self.export_peaks_to_file(),
+                Control::RecordCameraPath => self.toggle_camera_path_recording(),
+                Control::ToggleOrbit => self.toggle_orbit(),
+                Control::TogglePointerLock => {
+                    let locked = self.controllers.camera_controller.is_pointer_locked();
+                    self.set_pointer_lock(!locked);
+                }
+                Control::ToggleTimeLapse => self.toggle_time_lapse(),
+                _ => {}
+            }
+        }
+
+        if let Some(position) = self.controllers.camera_controller.take_fly_request()
+            && let Some(target) = self.pick_target(position)
+        {
+            self.fly_towards(target);
+            self.require_render = true;
+        }
+        if let Some(position) = self.controllers.camera_controller.take_identify_request() {
+            self.identify(position);
+        }
+
+        if let Some(position) = self.controllers.camera_controller.take_pick_request()
+            && let Some(target) = self.pick_target(position)
+        {
+            self.controllers
+                .camera_controller
+                .start_orbit(&mut self.data.camera, target);
+            self.require_render = true;
+        }
     }
 
     fn device_event(
@@ -301,10 +598,111 @@ impl ApplicationHandler<ApplicationEvent> for Application {
             }
             ApplicationEvent::PeaksReady((location, peaks)) => {
                 self.data.peaks.insert(location, peaks);
+                self.data.scene_changed();
                 true
             }
             ApplicationEvent::PeakLabelsReady((location, labels)) => {
                 self.data.peak_labels.insert(location, labels);
+                self.data.scene_changed();
+                true
+            }
+            ApplicationEvent::ContourAnchorsReady((location, interval, anchors)) => {
+                if self.data.set_contour_anchors(location, interval, anchors) {
+                    self.data.scene_changed();
+                    true
+                } else {
+                    // the interval changed while they were being found
+                    if let Err(err) = self
+                        .controllers
+                        .ui_controller
+                        .request_contour_anchors(location, &self.data)
+                    {
+                        log::error!("{err:?}");
+                    }
+                    false
+                }
+            }
+            ApplicationEvent::LocationLoaded(location) => {
+                self.data.pending_locations.remove(&location);
+                true
+            }
+            ApplicationEvent::ExportPeaks(format, sender) => {
+                if sender
+                    .send(export_peaks(&self.get_visible_peaks(), format))
+                    .is_err()
+                {
+                    log::warn!("Peaks export receiver dropped");
+                }
+                false
+            }
+            ApplicationEvent::FlyTo {
+                location,
+                altitude,
+                duration,
+            } => {
+                let from = CameraPathPoint::from_camera(&self.data.camera, 0.0);
+                let altitude = altitude.unwrap_or(from.altitude);
+                self.controllers
+                    .play(CameraPath::fly_to(from, location, altitude, duration));
+                true
+            }
+            ApplicationEvent::PlayCameraPath(path) => {
+                log::info!("Playing a camera path of {:.1}s", path.duration());
+                self.controllers.play(path);
+                true
+            }
+            ApplicationEvent::SetKeyBindings(key_bindings) => {
+                self.controllers.set_key_bindings(&key_bindings);
+                self.settings.key_bindings = key_bindings;
+                false
+            }
+            ApplicationEvent::SetSunTime(time) => {
+                self.data.sun.set_time(time);
+                self.log_sun_position();
+                true
+            }
+            ApplicationEvent::SetTimeLapse(speed) => {
+                if let Some(speed) = speed {
+                    self.data.sun.set_time_lapse_speed(speed);
+                }
+                self.data.sun.set_time_lapse(speed.is_some());
+                false
+            }
+            ApplicationEvent::SetRefraction(refraction) => {
+                self.data.camera.refraction = refraction.coefficient();
+                true
+            }
+            ApplicationEvent::SetViewMode(view_mode) => {
+                self.data.camera.view_mode = view_mode;
+                true
+            }
+            ApplicationEvent::GetLegend(sender) => {
+                let legend = Legend::new(
+                    self.data.camera.view_mode,
+                    &self.data.coloring.elevation_stops,
+                );
+                if sender.send(legend).is_err() {
+                    log::warn!("Legend receiver dropped");
+                }
+                false
+            }
+            ApplicationEvent::SetContourInterval(interval) => {
+                let label_interval = self.data.contours.label_interval();
+                self.data.set_contours(ContourSettings {
+                    interval,
+                    ..self.data.contours.clone()
+                });
+                if self.data.contours.label_interval() != label_interval {
+                    for location in self.data.height_maps.keys() {
+                        if let Err(err) = self
+                            .controllers
+                            .ui_controller
+                            .request_contour_anchors(*location, &self.data)
+                        {
+                            log::error!("{err:?}");
+                        }
+                    }
+                }
                 true
             }
         };
@@ -1,5 +1,11 @@
+use std::io::{Read, Seek};
+
 use thiserror::Error;
-use tiff::decoder::DecodingResult;
+use tiff::{
+    TiffError,
+    decoder::{Decoder, DecodingResult},
+    tags::Tag,
+};
 
 #[derive(Error, Debug)]
 pub enum CoordinateTransformError {
@@ -11,8 +17,11 @@ pub enum CoordinateTransformError {
         "Incorrect geo tag data: ModelPixelScaleTag should have 3 and ModelTiepointTag should have 6 values"
     )]
     IncorrectGeoTagData,
+    #[error(transparent)]
+    Tiff(#[from] TiffError),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct CoordinateTransform {
     pub raster_point: (f32, f32),
     pub model_point: (f32, f32),
@@ -54,6 +63,26 @@ impl CoordinateTransform {
         }
     }
 
+    /// Reads the geo tags of a GeoTIFF, see [`Self::from_geo_tag_data`]
+    pub fn from_decoder<R: Read + Seek>(
+        decoder: &mut Decoder<R>,
+    ) -> Result<Self, CoordinateTransformError> {
+        let pixel_scale_data = decoder
+            .find_tag(Tag::ModelPixelScaleTag)?
+            .map(|value| value.into_f64_vec())
+            .transpose()?;
+        let tie_points_data = decoder
+            .find_tag(Tag::ModelTiepointTag)?
+            .map(|value| value.into_f64_vec())
+            .transpose()?;
+        let model_transformation_data = decoder
+            .find_tag(Tag::ModelTransformationTag)?
+            .map(|value| value.into_f64_vec())
+            .transpose()?;
+
+        Self::from_geo_tag_data(pixel_scale_data, tie_points_data, model_transformation_data)
+    }
+
     pub fn to_model(&self, coord: (f32, f32)) -> (f32, f32) {
         (
             (coord.0 - self.raster_point.0) * self.pixel_scale.0 + self.model_point.0,
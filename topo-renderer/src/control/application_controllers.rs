@@ -1,27 +1,48 @@
-#[cfg(not(target_arch = "wasm32"))]
-use std::time::Instant;
 use std::{pin::Pin, sync::Arc};
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(target_arch = "wasm32")]
+use web_time::Duration;
+
 use color_eyre::{Report, Result};
+use jiff::SignedDuration;
 use tokio::{sync::broadcast::Receiver, task::JoinHandle};
 use tokio_with_wasm::alias as tokio;
-#[cfg(target_arch = "wasm32")]
-use web_time::Instant;
+use topo_common::{GeoCoord, GeoLocation};
 use winit::{
-    event::{DeviceEvent, WindowEvent},
+    event::{DeviceEvent, ElementState, WindowEvent},
     event_loop::EventLoopProxy,
 };
 
 use crate::{
-    app::{ApplicationEvent, ApplicationSettings},
+    app::ApplicationEvent,
     control::{
         background_runner::{BackgroundEvent, BackgroundNotification, BackgroundRunner},
-        camera_controller::CameraController,
+        camera_animation::CameraAnimation,
+        camera_controller::{CameraController, Control},
+        clock::Clock,
+        key_bindings::{Input, InputMap, KeyBindings},
         ui_controller::UiController,
     },
-    data::application_data::ApplicationData,
+    data::{application_data::ApplicationData, camera_path::CameraPath},
+    render::geometry::{R0, inverse_transform},
+    settings::{GroundSettings, RendererSettings},
 };
 
+/// Controls handled by the application rather than the camera controller
+const APPLICATION_CONTROLS: [Control; 5] = [
+    Control::ExportPeaks,
+    Control::RecordCameraPath,
+    Control::ToggleOrbit,
+    Control::TogglePointerLock,
+    Control::ToggleTimeLapse,
+];
+/// Minutes the sun clock moves by per pixel of horizontal mouse movement while moving the light,
+/// vertical movement moves it by days
+const LIGHT_MINUTES_PER_PIXEL: f32 = 1.0;
+const LIGHT_DAYS_PER_PIXEL: f32 = 1.0;
+
 pub enum ControllerEvent {
     VisibleLabelsUpdate(),
 }
@@ -39,27 +60,51 @@ pub struct ApplicationControllers {
     event_sender: tokio::sync::mpsc::Sender<BackgroundEvent>,
     pub ui_controller: UiController,
     pub camera_controller: CameraController,
-    previous_instant: Instant,
+    clock: Clock,
+    input_map: InputMap,
+    requested_controls: Vec<Control>,
+    recording: Option<CameraPathRecording>,
+    animation: Option<CameraAnimation>,
+    /// Location the animation moved the camera to, when it is on another tile than the current location
+    animated_location: Option<GeoCoord>,
+    ground: GroundSettings,
+}
+
+/// Camera path being recorded, see [`ApplicationControllers::start_recording`]
+struct CameraPathRecording {
+    path: CameraPath,
+    /// Seconds since the recording started
+    time: f32,
 }
 
 impl ApplicationControllers {
     pub fn new(
         render_event_loopback: EventLoopProxy<ApplicationEvent>,
-        settings: Arc<ApplicationSettings>,
+        settings: &RendererSettings,
     ) -> Self {
         let (event_sender, event_receiver) = tokio::sync::mpsc::channel(128);
 
-        let runner = BackgroundRunner::new(event_receiver, render_event_loopback, settings);
+        let runner = BackgroundRunner::new(
+            event_receiver,
+            render_event_loopback,
+            Arc::new(settings.application.clone()),
+        );
 
-        let ui_controller = UiController::new(event_sender.clone());
-        let camera_controller = CameraController::new(0.01);
+        let ui_controller = UiController::new(event_sender.clone(), settings.view_distance);
+        let camera_controller = CameraController::new(settings);
 
         ApplicationControllers {
             runner_state: Some(RunnerState::Initialized(runner)),
             event_sender,
             ui_controller,
             camera_controller,
-            previous_instant: Instant::now(),
+            clock: Clock::real_time(),
+            input_map: settings.key_bindings.to_input_map(),
+            requested_controls: vec![],
+            recording: None,
+            animation: None,
+            animated_location: None,
+            ground: settings.ground.clone(),
         }
     }
 
@@ -99,9 +144,69 @@ impl ApplicationControllers {
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        if let Some((input, state)) = Input::from_event(event)
+            && let Some(control) = self
+                .input_map
+                .get_control(input, self.camera_controller.get_modifiers())
+            && APPLICATION_CONTROLS.contains(&control)
+        {
+            if state == ElementState::Pressed {
+                self.requested_controls.push(control);
+            }
+            return true;
+        }
         self.camera_controller.process_events(event)
     }
 
+    /// Replaces the bindings of both the application and camera controls
+    pub fn set_key_bindings(&mut self, key_bindings: &KeyBindings) {
+        self.input_map = key_bindings.to_input_map();
+        self.camera_controller.set_key_bindings(key_bindings);
+    }
+
+    /// Application controls pressed since the last call, e.g. [`Control::ExportPeaks`]
+    pub fn take_requested_controls(&mut self) -> Vec<Control> {
+        std::mem::take(&mut self.requested_controls)
+    }
+
+    /// Replaces the clock that drives the camera movement, e.g. with a fixed step one
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording the camera's pose on every update
+    pub fn start_recording(&mut self) {
+        self.recording = Some(CameraPathRecording {
+            path: CameraPath::default(),
+            time: 0.0,
+        });
+    }
+
+    /// Returns the path recorded since [`Self::start_recording`]
+    pub fn stop_recording(&mut self) -> Option<CameraPath> {
+        self.recording.take().map(|recording| recording.path)
+    }
+
+    /// Plays the path back from its start, replacing a running animation, until
+    /// it ends or any input moves the camera
+    pub fn play(&mut self, path: CameraPath) {
+        self.camera_controller.stop_movement();
+        self.animation = Some(CameraAnimation::new(path));
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    /// Location to load the terrain around, after the animation moved the camera to another tile
+    pub fn take_animated_location(&mut self) -> Option<GeoCoord> {
+        self.animated_location.take()
+    }
+
     pub fn device_input(&mut self, event: &DeviceEvent) {
         self.camera_controller.process_device_events(event)
     }
@@ -112,15 +217,90 @@ impl ApplicationControllers {
         data: &mut ApplicationData,
         size: (u32, u32),
     ) -> bool {
-        let current_instant = Instant::now();
-        let time_delta = current_instant - self.previous_instant;
-        self.previous_instant = current_instant;
+        let time_delta = self.clock.tick();
+
+        let (altitude, location) = inverse_transform(data.camera.eye);
+        let height_above_ground = altitude - data.get_ground_height(location).unwrap_or_default();
+        let mut camera_changed = self.camera_controller.update_camera(
+            &mut data.camera,
+            size,
+            time_delta,
+            height_above_ground,
+        );
+
+        if let Some(mut animation) = self.animation.take()
+            // any input takes over the camera
+            && !camera_changed
+        {
+            if let Some(pose) = animation.advance(time_delta) {
+                pose.apply(&mut data.camera);
+                camera_changed = true;
+                if data.current_location.map(GeoLocation::from)
+                    != Some(GeoLocation::from(pose.location))
+                {
+                    self.animated_location = Some(pose.location);
+                }
+            }
+            if !animation.is_finished() {
+                self.animation = Some(animation);
+            }
+        }
+
+        camera_changed |= self.keep_above_ground(data);
+        camera_changed |= self.update_sun(data, time_delta);
+
+        if let Some(recording) = &mut self.recording {
+            recording.time += time_delta.as_secs_f32();
+            recording.path.record(&data.camera, recording.time);
+        }
 
-        let camera_changed =
-            self.camera_controller
-                .update_camera(&mut data.camera, size, time_delta);
         require_render || camera_changed
     }
+
+    /// Advances the sun's clock and moves it by the mouse movement while moving the light.
+    /// Returns whether the lighting changed
+    fn update_sun(&mut self, data: &mut ApplicationData, time_delta: Duration) -> bool {
+        let mut changed = data.sun.advance(time_delta);
+        if let Some((dx, dy)) = self.camera_controller.take_light_delta() {
+            let minutes = dx * LIGHT_MINUTES_PER_PIXEL + dy * LIGHT_DAYS_PER_PIXEL * 24.0 * 60.0;
+            data.sun
+                .shift(SignedDuration::from_secs_f32(minutes * 60.0));
+            changed = true;
+        }
+        changed
+    }
+
+    /// Puts the camera at the walking height above the ground in walk mode (unless orbiting),
+    /// otherwise lifts it to the minimum height when it's below (if collision is enabled).
+    /// Returns whether it moved
+    fn keep_above_ground(&self, data: &mut ApplicationData) -> bool {
+        let (altitude, location) = inverse_transform(data.camera.eye);
+        let Some(ground_height) = data.get_ground_height(location) else {
+            return false;
+        };
+
+        let orbit_target = self.camera_controller.get_orbit_target();
+        let target = if self.camera_controller.is_walking() && orbit_target.is_none() {
+            ground_height + self.ground.walk_height
+        } else if self.ground.collision {
+            altitude.max(ground_height + self.ground.min_height)
+        } else {
+            altitude
+        };
+        // this far from the center of the earth the eye's coordinates
+        // are only precise to about half a meter
+        if (target - altitude).abs() < 1.0 {
+            return false;
+        }
+
+        // along the vertical, converting back from the coordinates would shift the camera
+        data.camera
+            .set_eye(data.camera.eye.normalize() * (R0 + target));
+        if let Some(orbit_target) = orbit_target {
+            data.camera.look_at(orbit_target);
+        }
+        true
+    }
 }
 
 impl Drop for ApplicationControllers {
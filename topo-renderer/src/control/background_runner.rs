@@ -1,15 +1,13 @@
-use std::{fmt::Display, io::Cursor, sync::Arc};
+use std::{collections::HashMap, fmt::Display, io::Cursor, sync::Arc};
 
 use bytes::{Buf, Bytes};
 use color_eyre::{
     Result,
-    eyre::{Context, ContextCompat, OptionExt},
+    eyre::{Context, OptionExt},
 };
+use futures::future::join_all;
 use itertools::Itertools;
-use tiff::{
-    decoder::{Decoder, DecodingResult},
-    tags::Tag,
-};
+use tiff::decoder::{Decoder, DecodingResult};
 use tokio::{
     join, select,
     sync::broadcast,
@@ -23,7 +21,13 @@ use winit::event_loop::EventLoopProxy;
 use crate::{
     app::{ApplicationEvent, ApplicationSettings},
     common::coordinate_transform::{CoordinateTransform, get_height_value_at},
-    data::peak::Peak,
+    data::{
+        contours::contour_anchors,
+        height_map::HeightMap,
+        imagery::{Imagery, ImagerySource, xyz_tiles_covering},
+        peak::Peak,
+        water::{Lake, LakeMesh},
+    },
     render::{
         data::PeakInstance, geometry::transform, render_engine::RenderEvent,
         text_renderer::TextRenderer,
@@ -35,6 +39,18 @@ pub enum BackgroundEvent {
     DataRequested {
         requested: GeoLocation,
         current_location: GeoCoord,
+        /// Whether to put the camera on the ground at the current location once its tile is loaded
+        reset_camera: bool,
+        /// Elevation difference (in meters) between the labeled contours, see
+        /// [`crate::settings::ContourSettings::label_interval`]
+        contour_interval: f32,
+    },
+    /// Finds where the index contours of the loaded tile can be labeled again, e.g. after
+    /// the contour interval changed
+    ContourAnchorsRequested {
+        location: GeoLocation,
+        height_map: Arc<HeightMap>,
+        contour_interval: f32,
     },
 }
 
@@ -53,11 +69,21 @@ impl Display for BackgroundEvent {
             BackgroundEvent::DataRequested {
                 requested,
                 current_location,
+                ..
             } => write!(
                 f,
                 "Data requested for location {:?}, current location: {:?}",
                 requested, current_location
             ),
+            BackgroundEvent::ContourAnchorsRequested {
+                location,
+                contour_interval,
+                ..
+            } => write!(
+                f,
+                "Contour anchors requested for location {:?}, interval: {}",
+                location, contour_interval
+            ),
         }
     }
 }
@@ -96,41 +122,21 @@ pub struct BackgroundRunner {
     running_tasks: JoinSet<(String, Result<()>)>,
 }
 
+/// Peaks and heights of the location, `None` if the backend has no terrain for it, i.e. it's the sea
 pub async fn fetch_terrain(
     location: GeoLocation,
     settings: &ApplicationSettings,
-) -> Result<(
-    Vec<PeakInstance>,
-    (DecodingResult, CoordinateTransform, (u32, u32)),
-)> {
-    let (tiff_bytes, peaks_bytes) = join!(
-        get_tiff_from_http(settings.backend_url.as_str(), location),
-        get_peaks_from_http(settings.backend_url.as_str(), location),
-    );
+) -> Result<Option<(Vec<PeakInstance>, HeightMap)>> {
+    let (tiff_bytes, peaks_bytes) =
+        join!(get_tiff(location, settings), get_peaks(location, settings));
 
     let mut height_map_decoding_result = DecodingResult::F32(vec![]);
 
-    let mut decoder = Decoder::new(Cursor::new(
-        tiff_bytes?.wrap_err("Empty terrain map for location")?,
-    ))?;
-    let pixel_scale_data = decoder
-        .find_tag(Tag::ModelPixelScaleTag)?
-        .map(|value| value.into_f64_vec())
-        .transpose()?;
-    let tie_points_data = decoder
-        .find_tag(Tag::ModelTiepointTag)?
-        .map(|value| value.into_f64_vec())
-        .transpose()?;
-    let model_transformation_data = decoder
-        .find_tag(Tag::ModelTransformationTag)?
-        .map(|value| value.into_f64_vec())
-        .transpose()?;
-
-    let coordinate_transform = CoordinateTransform::from_geo_tag_data(
-        pixel_scale_data,
-        tie_points_data,
-        model_transformation_data,
-    )?;
+    let Some(tiff_bytes) = tiff_bytes? else {
+        return Ok(None);
+    };
+    let mut decoder = Decoder::new(Cursor::new(tiff_bytes))?;
+    let coordinate_transform = CoordinateTransform::from_decoder(&mut decoder)?;
 
     let _ = decoder.read_image_to_buffer(&mut height_map_decoding_result);
     let size = decoder.dimensions()?;
@@ -155,36 +161,204 @@ pub async fn fetch_terrain(
                     p.latitude as f64,
                 )
                 .map(|h: f32| {
-                    PeakInstance::new(transform(h + 10.0, p.longitude, p.latitude), p.name)
+                    PeakInstance::new(
+                        transform(h + 10.0, p.longitude, p.latitude),
+                        GeoCoord::new(p.latitude, p.longitude),
+                        p.elevation,
+                        p.name,
+                    )
                 })
             })
             .collect::<Vec<_>>()
     });
 
-    Ok((
+    Ok(Some((
         peaks.unwrap_or(vec![]),
-        (height_map_decoding_result, coordinate_transform, size),
-    ))
+        HeightMap::new(height_map_decoding_result, coordinate_transform, size),
+    )))
 }
 
-async fn get_tiff_from_http(backend_url: &str, location: GeoLocation) -> Result<Option<Bytes>> {
-    let url = format!("{backend_url}/dem?{}", location.to_request_params());
-    let response = reqwest::get(&url)
-        .await
-        .wrap_err_with(|| format!("Error trying to fetch from {}", &url))?
-        .bytes()
-        .await
-        .wrap_err_with(|| format!("Error decoding response from {}", &url))?;
-    if response.len() > 0 {
-        Ok(Some(response))
-    } else {
-        Ok(None)
+async fn get_tiff(location: GeoLocation, settings: &ApplicationSettings) -> Result<Option<Bytes>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(data_dir) = &settings.data_dir {
+        return get_from_data_dir(data_dir.join(location.dem_file_path())).await;
     }
+
+    get_tiff_from_http(settings.backend_url.as_str(), location).await
+}
+
+async fn get_peaks(location: GeoLocation, settings: &ApplicationSettings) -> Result<Option<Bytes>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(data_dir) = &settings.data_dir {
+        return get_from_data_dir(data_dir.join(location.peaks_file_path())).await;
+    }
+
+    get_peaks_from_http(settings.backend_url.as_str(), location).await
+}
+
+/// Missing files are treated the same way as empty backend responses
+#[cfg(not(target_arch = "wasm32"))]
+async fn get_from_data_dir(path: std::path::PathBuf) -> Result<Option<Bytes>> {
+    match tokio::fs::read(&path).await {
+        Ok(bytes) if !bytes.is_empty() => Ok(Some(Bytes::from(bytes))),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("Error reading {}", path.display())),
+    }
+}
+
+async fn get_tiff_from_http(backend_url: &str, location: GeoLocation) -> Result<Option<Bytes>> {
+    get_from_http(&format!(
+        "{backend_url}/dem?{}",
+        location.to_request_params()
+    ))
+    .await
 }
 
 async fn get_peaks_from_http(backend_url: &str, location: GeoLocation) -> Result<Option<Bytes>> {
-    let url = format!("{backend_url}/peaks?{}", location.to_request_params());
-    let response = reqwest::get(&url)
+    get_from_http(&format!(
+        "{backend_url}/peaks?{}",
+        location.to_request_params()
+    ))
+    .await
+}
+
+/// Imagery draped on the location's terrain, `None` if there's none for it
+pub async fn fetch_imagery(
+    location: GeoLocation,
+    settings: &ApplicationSettings,
+) -> Result<Option<Imagery>> {
+    let imagery = &settings.imagery;
+    match imagery.source {
+        ImagerySource::None => Ok(None),
+        ImagerySource::GeoTiff => get_imagery_tiff(location, settings)
+            .await?
+            .map(|bytes| Imagery::from_geotiff(&bytes))
+            .transpose()
+            .wrap_err_with(|| format!("Error decoding the imagery of {location:?}")),
+        ImagerySource::Xyz => {
+            let tiles = xyz_tiles_covering(location, imagery.zoom);
+            let responses =
+                join_all(tiles.iter().map(|tile| get_imagery_tile(*tile, settings))).await;
+
+            let images = tiles
+                .into_iter()
+                .zip(responses)
+                .filter_map(|(tile, response)| {
+                    let decoded = response.and_then(|bytes| {
+                        bytes
+                            .map(|bytes| image::load_from_memory(&bytes))
+                            .transpose()
+                            .wrap_err_with(|| format!("Error decoding the imagery tile {tile:?}"))
+                    });
+                    match decoded {
+                        Ok(image) => image.map(|image| (tile, image.to_rgba8())),
+                        Err(err) => {
+                            log::warn!("{err:?}");
+                            None
+                        }
+                    }
+                })
+                .collect::<HashMap<_, _>>();
+
+            Ok((!images.is_empty()).then(|| {
+                Imagery::from_xyz_tiles(location, imagery.zoom, imagery.resolution, &images)
+            }))
+        }
+    }
+}
+
+/// Lakes of the location triangulated at their elevations, empty if there are none
+pub async fn fetch_lakes(
+    location: GeoLocation,
+    settings: &ApplicationSettings,
+) -> Result<LakeMesh> {
+    if !settings.water.lakes {
+        return Ok(LakeMesh::default());
+    }
+
+    let Some(bytes) = get_lakes(location, settings).await? else {
+        return Ok(LakeMesh::default());
+    };
+    let lakes = Lake::read_geojson(bytes.reader())
+        .wrap_err_with(|| format!("Error reading the lakes of {location:?}"))?;
+
+    Ok(LakeMesh::new(&lakes))
+}
+
+/// Events that drape the fetched imagery and lakes over the location's terrain, the terrain is
+/// usable without them so failing to get them is only logged
+pub fn imagery_and_lake_events(
+    location: GeoLocation,
+    imagery: Result<Option<Imagery>>,
+    lakes: Result<LakeMesh>,
+) -> Vec<RenderEvent> {
+    let mut events = Vec::new();
+    match imagery {
+        Ok(Some(imagery)) => events.push(RenderEvent::ImageryReady(location, imagery)),
+        Ok(None) => {}
+        Err(err) => log::warn!("Skipping the imagery of {location:?}: {err:?}"),
+    }
+    match lakes {
+        Ok(lakes) if !lakes.is_empty() => events.push(RenderEvent::LakesReady(location, lakes)),
+        Ok(_) => {}
+        Err(err) => log::warn!("Skipping the lakes of {location:?}: {err:?}"),
+    }
+    events
+}
+
+async fn get_lakes(location: GeoLocation, settings: &ApplicationSettings) -> Result<Option<Bytes>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(data_dir) = &settings.data_dir {
+        return get_from_data_dir(data_dir.join(location.lakes_file_path())).await;
+    }
+
+    get_from_http(&format!(
+        "{}/lakes?{}",
+        settings.backend_url,
+        location.to_request_params()
+    ))
+    .await
+}
+
+async fn get_imagery_tiff(
+    location: GeoLocation,
+    settings: &ApplicationSettings,
+) -> Result<Option<Bytes>> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(data_dir) = &settings.data_dir {
+        return get_from_data_dir(data_dir.join(location.imagery_file_path())).await;
+    }
+
+    get_from_http(&format!(
+        "{}/imagery?{}",
+        settings.backend_url,
+        location.to_request_params()
+    ))
+    .await
+}
+
+/// Tiles with a full url are fetched from there, the rest from the data directory or the backend
+async fn get_imagery_tile(
+    tile: (u32, u32),
+    settings: &ApplicationSettings,
+) -> Result<Option<Bytes>> {
+    let path = settings.imagery.get_tile_url(tile);
+    if path.contains("://") {
+        return get_from_http(&path).await;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(data_dir) = &settings.data_dir {
+        return get_from_data_dir(data_dir.join(path)).await;
+    }
+
+    get_from_http(&format!("{}/{path}", settings.backend_url)).await
+}
+
+/// Empty responses are treated as missing data
+async fn get_from_http(url: &str) -> Result<Option<Bytes>> {
+    let response = reqwest::get(url)
         .await
         .wrap_err_with(|| format!("Error trying to fetch from {}", &url))?
         .bytes()
@@ -225,52 +399,133 @@ impl BackgroundRunner {
             DataRequested {
                 requested,
                 current_location,
+                reset_camera,
+                contour_interval,
             } => {
-                let (peaks, (terrain, coordinate_transform, size)) =
-                    fetch_terrain(requested, &settings).await?;
-
-                if GeoLocation::from(current_location) == requested {
-                    let height = get_height_value_at(
-                        &terrain,
-                        &coordinate_transform,
-                        size,
-                        current_location.longitude as f64,
-                        current_location.latitude as f64,
-                    )
-                    .ok_or_eyre("Unable to get current location's height from the height map")?;
-
-                    let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(
-                        RenderEvent::ResetCamera(current_location, height),
-                    ));
-                }
-
-                let _ = render_event_loopback
-                    .send_event(ApplicationEvent::PeaksReady((requested, peaks.clone())));
-
-                let peak_names_iter = peaks.iter().map(|peak| peak.name.as_str());
+                let result = Self::load_location(
+                    render_event_loopback.clone(),
+                    requested,
+                    reset_camera.then_some(current_location),
+                    contour_interval,
+                    &settings,
+                )
+                .await;
 
+                // sent regardless of the result so that the application
+                // doesn't keep waiting for data that will never arrive
                 let _ =
-                    TextRenderer::load_additional_fonts(TextRenderer::get_scripts(peak_names_iter))
-                        .await?;
-
-                let process_peaks = {
-                    let render_event_loopback = render_event_loopback.clone();
-                    move || {
-                        let labels = TextRenderer::prepare_peak_labels(&peaks);
-                        let _ = render_event_loopback
-                            .send_event(ApplicationEvent::PeakLabelsReady((requested, labels)));
-                    }
-                };
+                    render_event_loopback.send_event(ApplicationEvent::LocationLoaded(requested));
 
-                let _ = spawn_blocking(process_peaks).await;
+                result
+            }
+            ContourAnchorsRequested {
+                location,
+                height_map,
+                contour_interval,
+            } => {
+                Self::find_contour_anchors(
+                    render_event_loopback,
+                    location,
+                    height_map,
+                    contour_interval,
+                )
+                .await;
+                Ok(())
+            }
+        }
+    }
 
+    /// Scans the whole height map, so it's done off the event loop
+    async fn find_contour_anchors(
+        render_event_loopback: EventLoopProxy<ApplicationEvent>,
+        location: GeoLocation,
+        height_map: Arc<HeightMap>,
+        contour_interval: f32,
+    ) {
+        let _ = spawn_blocking(move || {
+            let anchors = contour_anchors(&height_map, contour_interval);
+            let _ = render_event_loopback.send_event(ApplicationEvent::ContourAnchorsReady((
+                location,
+                contour_interval,
+                anchors,
+            )));
+        })
+        .await;
+    }
+
+    async fn load_location(
+        render_event_loopback: EventLoopProxy<ApplicationEvent>,
+        requested: GeoLocation,
+        camera_location: Option<GeoCoord>,
+        contour_interval: f32,
+        settings: &ApplicationSettings,
+    ) -> Result<()> {
+        let Some((peaks, height_map)) = fetch_terrain(requested, settings).await? else {
+            let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(
+                RenderEvent::NoTerrain(requested),
+            ));
+            // the camera is put on the sea there
+            if let Some(current_location) = camera_location
+                && GeoLocation::from(current_location) == requested
+            {
                 let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(
-                    RenderEvent::TerrainReady(requested, terrain, coordinate_transform, size),
+                    RenderEvent::ResetCamera(current_location, 0.0),
                 ));
+            }
+            return Ok(());
+        };
+        let height_map = Arc::new(height_map);
+
+        if let Some(current_location) = camera_location
+            && GeoLocation::from(current_location) == requested
+        {
+            let height = height_map
+                .get_height(current_location)
+                .ok_or_eyre("Unable to get current location's height from the height map")?;
+
+            let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(
+                RenderEvent::ResetCamera(current_location, height),
+            ));
+        }
 
-                Ok(())
+        let _ = render_event_loopback
+            .send_event(ApplicationEvent::PeaksReady((requested, peaks.clone())));
+
+        let peak_names_iter = peaks.iter().map(|peak| peak.name.as_str());
+
+        TextRenderer::load_additional_fonts(TextRenderer::get_scripts(peak_names_iter)).await?;
+
+        let process_peaks = {
+            let render_event_loopback = render_event_loopback.clone();
+            move || {
+                let labels = TextRenderer::prepare_peak_labels(&peaks);
+                let _ = render_event_loopback
+                    .send_event(ApplicationEvent::PeakLabelsReady((requested, labels)));
             }
+        };
+
+        let _ = spawn_blocking(process_peaks).await;
+
+        let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(
+            RenderEvent::TerrainReady(requested, Arc::clone(&height_map)),
+        ));
+        Self::find_contour_anchors(
+            render_event_loopback.clone(),
+            requested,
+            height_map,
+            contour_interval,
+        )
+        .await;
+
+        let (imagery, lakes) = join!(
+            fetch_imagery(requested, settings),
+            fetch_lakes(requested, settings)
+        );
+        for event in imagery_and_lake_events(requested, imagery, lakes) {
+            let _ = render_event_loopback.send_event(ApplicationEvent::RenderEvent(event));
         }
+
+        Ok(())
     }
 
     pub async fn run(&mut self) {
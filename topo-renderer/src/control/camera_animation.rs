@@ -0,0 +1,81 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(target_arch = "wasm32")]
+use web_time::Duration;
+
+use crate::data::camera_path::{CameraPath, CameraPathPoint};
+
+/// Playback of a [`CameraPath`] driven by the updates' time steps
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraAnimation {
+    path: CameraPath,
+    /// Seconds since the start, `None` before the first step
+    time: Option<f32>,
+}
+
+impl CameraAnimation {
+    pub fn new(path: CameraPath) -> Self {
+        Self { path, time: None }
+    }
+
+    /// Advances the playback and returns the pose to apply, the first step starts at
+    /// the beginning of the path regardless of its length, as the time since the
+    /// previous update usually includes loading the path
+    pub fn advance(&mut self, time_delta: Duration) -> Option<CameraPathPoint> {
+        let time = match self.time {
+            Some(time) => time + time_delta.as_secs_f32(),
+            None => 0.0,
+        };
+        self.time = Some(time);
+        self.path.sample(time)
+    }
+
+    /// Whether the last pose of the path has been returned
+    pub fn is_finished(&self) -> bool {
+        self.time
+            .is_some_and(|time| time >= self.path.duration() || self.path.points.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use topo_common::GeoCoord;
+
+    use crate::data::camera_path::Easing;
+
+    use super::*;
+
+    #[test]
+    fn plays_the_path_until_its_end() {
+        let point = |time: f32| CameraPathPoint {
+            time,
+            location: GeoCoord::new(49.0, 20.0),
+            altitude: 100.0 * time,
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 45.0,
+            easing: Easing::Linear,
+            lift: 0.0,
+        };
+        let mut animation = CameraAnimation::new(CameraPath {
+            points: vec![point(2.0), point(3.0)],
+        });
+
+        assert!(!animation.is_finished());
+        let step = Duration::from_millis(600);
+        assert_eq!(animation.advance(Duration::from_secs(10)), Some(point(2.0)));
+        assert_abs_diff_eq!(
+            animation.advance(step).unwrap().altitude,
+            260.0,
+            epsilon = 1e-3
+        );
+        assert!(!animation.is_finished());
+        assert_eq!(animation.advance(step), Some(point(3.0)));
+        assert!(animation.is_finished());
+
+        let mut empty = CameraAnimation::new(CameraPath::default());
+        assert_eq!(empty.advance(step), None);
+        assert!(empty.is_finished());
+    }
+}
@@ -1,39 +1,110 @@
+use glam::Vec3;
 use itertools::Itertools;
-use std::collections::{BTreeMap, VecDeque};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::Duration;
-use strum::{EnumIter, IntoEnumIterator};
+use strum::{Display, EnumIter, IntoEnumIterator};
 #[cfg(target_arch = "wasm32")]
 use web_time::Duration;
 
 use winit::{
     dpi::PhysicalPosition,
-    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, Touch, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
+    event::{
+        DeviceEvent, ElementState, MouseButton, MouseScrollDelta, Touch, TouchPhase, WindowEvent,
+    },
+    keyboard::ModifiersState,
+};
+
+use crate::{
+    control::{
+        key_bindings::{Input, InputMap, KeyBindings},
+        movement::{Movement, target_speed},
+        orbit::Orbit,
+    },
+    data::camera::Camera,
+    settings::{MovementSettings, RendererSettings},
 };
 
-use crate::data::camera::Camera;
+/// Radians per pixel the mouse turns the camera by
+const MOUSE_LOOK_SPEED: f32 = 0.01;
+/// Radians per second the zoom keys change the field of view by
+const FOV_KEY_SPEED: f32 = 1.0;
+/// Field of view change per wheel line
+const WHEEL_FOV_ZOOM: f32 = 0.9;
+
+/// Degrees per pixel the mouse or a finger moves in orbit mode
+const ORBIT_DRAG_SPEED: f32 = 0.2;
+/// Degrees per second the keys rotate by in orbit mode
+const ORBIT_KEY_SPEED: f32 = 45.0;
+/// Distance change per second the keys zoom by in orbit mode
+const ORBIT_KEY_ZOOM: f32 = 0.5;
+/// Distance change per wheel line in orbit mode
+const ORBIT_WHEEL_ZOOM: f32 = 0.9;
+/// Wheel pixel deltas (e.g. from touchpads) per line
+const PIXELS_PER_LINE: f32 = 40.0;
+/// Pixels the cursor may move between pressing and releasing a button for a click
+const CLICK_TOLERANCE: f64 = 4.0;
+/// Pixels a finger may move while touching for a tap or long press
+const TAP_TOLERANCE: f64 = 10.0;
+/// Seconds
+const MAX_TAP_DURATION: f32 = 0.3;
+/// Seconds between the end of two taps to make them a double tap
+const DOUBLE_TAP_INTERVAL: f32 = 0.4;
+/// Pixels between the two taps of a double tap
+const DOUBLE_TAP_DISTANCE: f64 = 40.0;
+/// Seconds a finger has to rest for a long press
+const LONG_PRESS_DURATION: f32 = 0.6;
+/// Fraction of the height above the ground a three finger drag of a pixel moves up or down by
+const TOUCH_ALTITUDE_SPEED: f32 = 0.005;
+/// Meters a three finger drag of a pixel moves up or down by at least
+const MIN_TOUCH_ALTITUDE_STEP: f32 = 0.5;
 
 enum CameraControllerEvent {
     ToggleViewMode,
+    ToggleWalkMode,
     UpdateCameraOrientation {
         start_position: StoredMultiPosition,
         end_position: StoredMultiPosition,
     },
 }
 
-#[derive(Copy, Clone, Debug, EnumIter, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    EnumIter,
+    Display,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 pub enum Control {
-    Up,
-    Down,
+    Forward,
+    Backward,
     Left,
     Right,
-    E,
-    Q,
-    Ctrl,
-    Space,
-    Shift,
-    MouseRight,
+    ZoomIn,
+    ZoomOut,
+    MoveLight,
+    Ascend,
+    Descend,
+    Sprint,
+    Look,
+    ToggleViewMode,
+    ExportPeaks,
+    RecordCameraPath,
+    ToggleWalkMode,
+    ToggleOrbit,
+    TogglePointerLock,
+    ResetView,
+    ToggleTimeLapse,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
@@ -42,6 +113,15 @@ struct TouchPosition {
     location: PhysicalPosition<f64>,
 }
 
+/// Finger that may turn into a tap or a long press, until it moves
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PendingTap {
+    id: u64,
+    location: PhysicalPosition<f64>,
+    /// Seconds of touch time, see [`CameraController::touch_time`]
+    time: f32,
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 struct MultiTouchPositions {
     position1: TouchPosition,
@@ -86,128 +166,252 @@ impl StoredMultiPosition {
 }
 
 pub struct CameraController {
-    speed: f32,
+    /// Radians, restored by [`Control::ResetView`]
+    default_fov: f32,
+    movement_settings: MovementSettings,
+    movement: Movement,
+    input_map: InputMap,
+    /// Controls the currently held inputs were pressed for, to release them
+    /// regardless of the modifiers held at the time
+    active_inputs: HashMap<Input, Control>,
+    modifiers: ModifiersState,
     is_pressed_map: BTreeMap<Control, bool>,
     mouse_view_delta: (f32, f32),
     mouse_ctrl_delta: (f32, f32),
     touch_state: TouchState,
     touch_single_delta: (f64, f64),
     touch_multi_delta: Option<StoredMultiPosition>,
+    /// Pixels the fingers of a three finger drag moved down by
+    touch_altitude_delta: f64,
+    /// Seconds of updates, timing the taps and long presses
+    touch_time: f32,
+    pending_tap: Option<PendingTap>,
+    /// Last single tap, which a second one turns into a double tap
+    last_tap: Option<PendingTap>,
+    /// Position of the last double tap, the camera should fly towards
+    fly_request: Option<(f32, f32)>,
+    /// Position of the last long press, whatever is there should be identified
+    identify_request: Option<(f32, f32)>,
     events_to_process: VecDeque<CameraControllerEvent>,
+    /// Moving along the ground rather than flying, see [`Self::is_walking`]
+    walking: bool,
+    orbit: Option<Orbit>,
+    cursor_position: PhysicalPosition<f64>,
+    /// Cursor position where the left button got pressed
+    click_start: Option<PhysicalPosition<f64>>,
+    /// Cursor position of the last click in orbit mode, to pick a new target at
+    pick_request: Option<(f32, f32)>,
+    /// Lines scrolled with the mouse wheel
+    wheel_delta: f32,
+    /// Mouse motion turns the view without holding [`Control::Look`]
+    pointer_locked: bool,
+    reset_view_requested: bool,
 }
 
 impl CameraController {
-    pub fn new(speed: f32) -> Self {
+    pub fn new(settings: &RendererSettings) -> Self {
         let mut is_pressed = BTreeMap::new();
         for control in Control::iter() {
             is_pressed.insert(control, false);
         }
         Self {
-            speed,
+            default_fov: settings.fov.to_radians(),
+            movement_settings: settings.movement.clone(),
+            movement: Movement::default(),
+            input_map: settings.key_bindings.to_input_map(),
+            active_inputs: HashMap::new(),
+            modifiers: ModifiersState::empty(),
             is_pressed_map: is_pressed,
             mouse_view_delta: (0.0, 0.0),
             mouse_ctrl_delta: (0.0, 0.0),
             touch_state: TouchState::Off,
             touch_single_delta: (0.0, 0.0),
             touch_multi_delta: None,
+            touch_altitude_delta: 0.0,
+            touch_time: 0.0,
+            pending_tap: None,
+            last_tap: None,
+            fly_request: None,
+            identify_request: None,
             events_to_process: VecDeque::default(),
+            walking: false,
+            orbit: None,
+            cursor_position: PhysicalPosition::default(),
+            click_start: None,
+            pick_request: None,
+            wheel_delta: 0.0,
+            pointer_locked: false,
+            reset_view_requested: false,
         }
     }
 
+    /// Starts pivoting the camera around the target, from where it is now
+    pub fn start_orbit(&mut self, camera: &mut Camera, target: Vec3) {
+        let orbit = Orbit::from_camera(camera, target);
+        orbit.apply(camera);
+        self.movement.stop();
+        self.orbit = Some(orbit);
+    }
+
+    pub fn stop_orbit(&mut self) {
+        self.orbit = None;
+    }
+
+    /// Point the camera pivots around in orbit mode
+    pub fn get_orbit_target(&self) -> Option<Vec3> {
+        self.orbit.map(|orbit| orbit.target)
+    }
+
+    /// Last known position of the cursor (in pixels)
+    pub fn get_cursor_position(&self) -> (f32, f32) {
+        (self.cursor_position.x as f32, self.cursor_position.y as f32)
+    }
+
+    /// Position clicked in orbit mode since the last call, where the target should be picked
+    pub fn take_pick_request(&mut self) -> Option<(f32, f32)> {
+        self.pick_request.take()
+    }
+
+    /// Position double tapped since the last call, the camera should fly towards
+    pub fn take_fly_request(&mut self) -> Option<(f32, f32)> {
+        self.fly_request.take()
+    }
+
+    /// Position long pressed since the last call, the peak or terrain there should be identified
+    pub fn take_identify_request(&mut self) -> Option<(f32, f32)> {
+        self.identify_request.take()
+    }
+
+    /// Mouse movement (in pixels) while [`Control::MoveLight`] was held since the last call
+    pub fn take_light_delta(&mut self) -> Option<(f32, f32)> {
+        Some(std::mem::take(&mut self.mouse_ctrl_delta)).filter(|delta| *delta != (0.0, 0.0))
+    }
+
+    /// In walk mode the camera moves horizontally and can't ascend or descend,
+    /// its height above the ground is kept by the application controllers
+    pub fn is_walking(&self) -> bool {
+        self.walking
+    }
+
+    /// Replaces the bindings, e.g. after the user remapped them
+    pub fn set_key_bindings(&mut self, key_bindings: &KeyBindings) {
+        self.input_map = key_bindings.to_input_map();
+        self.release_all();
+    }
+
+    /// Lets the mouse motion turn the view without a button held, the cursor
+    /// should be grabbed and hidden meanwhile
+    pub fn set_pointer_lock(&mut self, locked: bool) {
+        self.pointer_locked = locked;
+    }
+
+    pub fn is_pointer_locked(&self) -> bool {
+        self.pointer_locked
+    }
+
+    /// Keyboard modifiers currently held
+    pub fn get_modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    /// Stops the camera's inertial movement, e.g. when an animation takes it over
+    pub fn stop_movement(&mut self) {
+        self.movement.stop();
+    }
+
     fn is_pressed(&self, control: Control) -> bool {
         *self.is_pressed_map.get(&control).unwrap_or(&false)
     }
 
-    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match *event {
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state,
-                        physical_key: PhysicalKey::Code(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                let is_pressed = state == ElementState::Pressed;
-                match keycode {
-                    KeyCode::KeyW | KeyCode::ArrowUp => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Up)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::KeyS | KeyCode::ArrowDown => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Down)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
+    fn release_all(&mut self) {
+        self.active_inputs.clear();
+        self.is_pressed_map
+            .iter_mut()
+            .for_each(|(_, pressed)| *pressed = false);
+    }
 
-                    KeyCode::KeyA | KeyCode::ArrowLeft => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Left)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::KeyD | KeyCode::ArrowRight => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Right)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::KeyQ => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Q)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::KeyE => {
-                        self.is_pressed_map
-                            .get_mut(&Control::E)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::Space => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Space)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::ShiftLeft => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Shift)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::ControlLeft => {
-                        self.is_pressed_map
-                            .get_mut(&Control::Ctrl)
-                            .map(|pressed| *pressed = is_pressed);
-                        true
-                    }
-                    KeyCode::KeyF if is_pressed => {
-                        self.events_to_process
-                            .push_back(CameraControllerEvent::ToggleViewMode);
-                        true
-                    }
-                    _ => false,
+    /// Presses or releases the control bound to the input, returns whether there is one
+    fn process_input(&mut self, input: Input, state: ElementState) -> bool {
+        let control = match state {
+            ElementState::Pressed => self.input_map.get_control(input, self.modifiers),
+            ElementState::Released => self.active_inputs.remove(&input),
+        };
+        let Some(control) = control else {
+            return false;
+        };
+
+        let is_pressed = state == ElementState::Pressed;
+        if is_pressed {
+            self.active_inputs.insert(input, control);
+        }
+        match control {
+            Control::ToggleViewMode => {
+                if is_pressed {
+                    self.events_to_process
+                        .push_back(CameraControllerEvent::ToggleViewMode);
                 }
             }
+            Control::ToggleWalkMode => {
+                if is_pressed {
+                    self.events_to_process
+                        .push_back(CameraControllerEvent::ToggleWalkMode);
+                }
+            }
+            Control::ResetView => self.reset_view_requested |= is_pressed,
+            control => {
+                if let Some(pressed) = self.is_pressed_map.get_mut(&control) {
+                    *pressed = is_pressed;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match *event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                false
+            }
             WindowEvent::CursorLeft { device_id: _ } => {
-                self.is_pressed_map
-                    .iter_mut()
-                    .for_each(|(_, pressed)| *pressed = false);
+                self.release_all();
                 false
             }
+            // in orbit mode dragging with the left button rotates too and clicking picks a new target
             WindowEvent::MouseInput {
                 device_id: _,
                 state,
-                button,
-            } if button == MouseButton::Right => {
-                self.is_pressed_map
-                    .get_mut(&Control::MouseRight)
-                    .map(|pressed| *pressed = state.is_pressed());
+                button: MouseButton::Left,
+            } if self.orbit.is_some() => {
+                if let Some(pressed) = self.is_pressed_map.get_mut(&Control::Look) {
+                    *pressed = state.is_pressed();
+                }
+                match state {
+                    ElementState::Pressed => self.click_start = Some(self.cursor_position),
+                    ElementState::Released => {
+                        if let Some(start) = self.click_start.take()
+                            && (start.x - self.cursor_position.x).abs() < CLICK_TOLERANCE
+                            && (start.y - self.cursor_position.y).abs() < CLICK_TOLERANCE
+                        {
+                            self.pick_request = Some(self.get_cursor_position());
+                        }
+                    }
+                }
+                true
+            }
+            WindowEvent::KeyboardInput { .. } | WindowEvent::MouseInput { .. } => {
+                Input::from_event(event)
+                    .is_some_and(|(input, state)| self.process_input(input, state))
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = position;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
                 true
             }
             WindowEvent::Touch(Touch {
@@ -216,6 +420,7 @@ impl CameraController {
                 id,
                 ..
             }) => {
+                self.track_tap(phase, id, location);
                 if let Some(new_state) = match (phase, &mut self.touch_state) {
                     (winit::event::TouchPhase::Started, TouchState::Off) => {
                         Some(TouchState::Single(TouchPosition { id, location }))
@@ -275,6 +480,10 @@ impl CameraController {
                         }),
                     ) => {
                         if id == position1.id {
+                            // with three or more fingers dragging changes the altitude
+                            if !others.is_empty() {
+                                self.touch_altitude_delta += location.y - position1.location.y;
+                            }
                             position1.location = location;
                         } else if id == position2.id {
                             position2.location = location;
@@ -299,7 +508,9 @@ impl CameraController {
                             } else {
                                 prev.position1
                             };
-                            if let Some(start_position) = self.touch_multi_delta.take() {
+                            if let Some(start_position) = self.touch_multi_delta.take()
+                                && prev.others.is_empty()
+                            {
                                 self.events_to_process.push_back(
                                     CameraControllerEvent::UpdateCameraOrientation {
                                         start_position,
@@ -340,13 +551,90 @@ impl CameraController {
         }
     }
 
+    /// Turns short touches that don't move into taps and double taps,
+    /// called before the touch state gets updated
+    fn track_tap(&mut self, phase: TouchPhase, id: u64, location: PhysicalPosition<f64>) {
+        let moved_from = |start: PhysicalPosition<f64>, tolerance: f64| {
+            (start.x - location.x).hypot(start.y - location.y) > tolerance
+        };
+        match phase {
+            TouchPhase::Started => {
+                // more fingers make it a gesture
+                self.pending_tap = (self.touch_state == TouchState::Off).then_some(PendingTap {
+                    id,
+                    location,
+                    time: self.touch_time,
+                });
+            }
+            TouchPhase::Moved => {
+                if self
+                    .pending_tap
+                    .is_some_and(|tap| tap.id == id && moved_from(tap.location, TAP_TOLERANCE))
+                {
+                    self.pending_tap = None;
+                }
+            }
+            TouchPhase::Ended => {
+                let time = self.touch_time;
+                if let Some(tap) = self.pending_tap.take_if(|tap| tap.id == id)
+                    && time - tap.time < MAX_TAP_DURATION
+                {
+                    match self.last_tap.take() {
+                        Some(last_tap)
+                            if time - last_tap.time < DOUBLE_TAP_INTERVAL
+                                && !moved_from(last_tap.location, DOUBLE_TAP_DISTANCE) =>
+                        {
+                            self.fly_request = Some((location.x as f32, location.y as f32));
+                        }
+                        _ => self.last_tap = Some(PendingTap { time, ..tap }),
+                    }
+                }
+            }
+            TouchPhase::Cancelled => self.pending_tap = None,
+        }
+    }
+
+    /// Advances the time of the touch gestures, turning a finger resting long enough into a long press
+    fn update_touch_time(&mut self, seconds: f32) {
+        self.touch_time += seconds;
+        let time = self.touch_time;
+        if let Some(tap) = self
+            .pending_tap
+            .take_if(|tap| time - tap.time >= LONG_PRESS_DURATION)
+        {
+            self.identify_request = Some((tap.location.x as f32, tap.location.y as f32));
+        }
+    }
+
+    /// Turns, tilts and zooms by how two fingers moved: rotating them turns,
+    /// dragging them up or down tilts and spreading or pinching them zooms
+    fn apply_two_finger_gesture(
+        &mut self,
+        start_position: StoredMultiPosition,
+        end_position: StoredMultiPosition,
+        camera: &mut Camera,
+        size: (u32, u32),
+    ) -> bool {
+        let fov = camera.get_fovy();
+        let (rotation_change, new_fov) =
+            get_rotation_and_fov_change(start_position, end_position, fov, size);
+        let pitch_change = get_pitch_change(start_position, end_position, fov, size);
+
+        if rotation_change != 0.0 || new_fov != 0.0 || pitch_change != 0.0 {
+            self.movement.rotate(-rotation_change, pitch_change);
+            camera.set_fovy(new_fov);
+            return true;
+        }
+        false
+    }
+
     pub fn process_device_events(&mut self, event: &DeviceEvent) {
         match event {
             DeviceEvent::MouseMotion { delta } => {
-                if self.is_pressed(Control::Ctrl) {
+                if self.is_pressed(Control::MoveLight) {
                     self.mouse_ctrl_delta.0 += delta.0 as f32;
                     self.mouse_ctrl_delta.1 += delta.1 as f32;
-                } else if self.is_pressed(Control::MouseRight) {
+                } else if self.is_pressed(Control::Look) || self.pointer_locked {
                     self.mouse_view_delta.0 += delta.0 as f32;
                     self.mouse_view_delta.1 += delta.1 as f32;
                 }
@@ -355,59 +643,97 @@ impl CameraController {
         }
     }
 
+    /// Moves the camera by the controls pressed since the last update, the speed
+    /// scales with the camera's height above the ground (in meters)
     pub fn update_camera(
         &mut self,
         camera: &mut Camera,
         size: (u32, u32),
         time_delta: Duration,
+        height_above_ground: f32,
     ) -> bool {
-        let mut changed = false;
-        let increment = self.speed * 0.1 * time_delta.as_micros() as f32;
-        if self.is_pressed(Control::Q) {
-            camera.set_fovy(camera.fov_y() - 0.001 * increment);
-            changed = true;
+        self.update_touch_time(time_delta.as_secs_f32());
+        let reset = std::mem::take(&mut self.reset_view_requested);
+        if reset {
+            self.reset_view(camera);
         }
-        if self.is_pressed(Control::E) {
-            camera.set_fovy(camera.fov_y() + 0.001 * increment);
-            changed = true;
+        if let Some(orbit) = self.orbit {
+            return self.update_orbit(orbit, camera, size, time_delta);
         }
-        if self.is_pressed(Control::Up) {
-            camera.set_eye(camera.eye + camera.direction() * increment);
-            changed = true;
-        }
-        if self.is_pressed(Control::Down) {
-            camera.set_eye(camera.eye - camera.direction() * increment);
+
+        let mut changed = reset;
+        let seconds = time_delta.as_secs_f32();
+        let wheel_lines = std::mem::take(&mut self.wheel_delta);
+        if wheel_lines != 0.0 {
+            let (width, height) = (size.0 as f32, size.1 as f32);
+            // with the pointer locked the cursor stays at the center
+            let position = if self.pointer_locked {
+                (0.5 * width, 0.5 * height)
+            } else {
+                self.get_cursor_position()
+            };
+            let fov = camera.get_fovy() * WHEEL_FOV_ZOOM.powf(wheel_lines);
+            camera.zoom_at(position, width, height, fov);
             changed = true;
         }
-        if self.is_pressed(Control::Right) {
-            camera.set_eye(camera.eye + camera.direction_right() * increment);
+        if self.is_pressed(Control::ZoomIn) {
+            camera.set_fovy(camera.fov_y() - FOV_KEY_SPEED * seconds);
             changed = true;
         }
-        if self.is_pressed(Control::Left) {
-            camera.set_eye(camera.eye - camera.direction_right() * increment);
+        if self.is_pressed(Control::ZoomOut) {
+            camera.set_fovy(camera.fov_y() + FOV_KEY_SPEED * seconds);
             changed = true;
         }
-        if self.is_pressed(Control::Shift) {
-            camera.set_eye(camera.eye - camera.up() * increment);
-            changed = true;
+        let (forward, right) = if self.walking {
+            let forward = camera.direction_horizontal();
+            (forward, forward.cross(camera.up()))
+        } else {
+            (camera.direction(), camera.direction_right())
+        };
+        let mut direction = Vec3::ZERO;
+        for (control, control_direction) in [
+            (Control::Forward, forward),
+            (Control::Backward, -forward),
+            (Control::Right, right),
+            (Control::Left, -right),
+        ] {
+            if self.is_pressed(control) {
+                direction += control_direction;
+            }
         }
-        if self.is_pressed(Control::Space) {
-            camera.set_eye(camera.eye + camera.up() * increment);
-            changed = true;
+        if !self.walking {
+            if self.is_pressed(Control::Ascend) {
+                direction += camera.up();
+            }
+            if self.is_pressed(Control::Descend) {
+                direction -= camera.up();
+            }
         }
-        camera.sun_angle.theta += self.mouse_ctrl_delta.0;
-        camera.sun_angle.phi += self.mouse_ctrl_delta.1;
-
-        if self.mouse_view_delta != (0.0, 0.0) {
-            camera.rotate_yaw(-self.mouse_view_delta.0 * 0.01);
-            camera.rotate_pitch(self.mouse_view_delta.1 * 0.01);
+        let speed = target_speed(
+            &self.movement_settings,
+            height_above_ground,
+            self.is_pressed(Control::Sprint),
+        );
+        let displacement = self.movement.advance(
+            direction.normalize_or_zero() * speed,
+            &self.movement_settings,
+            seconds,
+        );
+        if displacement != Vec3::ZERO {
+            camera.set_eye(camera.eye + displacement);
             changed = true;
-            self.mouse_view_delta = (0.0, 0.0);
         }
 
-        if self.mouse_ctrl_delta != (0.0, 0.0) {
+        let (dx, dy) = std::mem::take(&mut self.mouse_view_delta);
+        self.movement
+            .rotate(-dx * MOUSE_LOOK_SPEED, dy * MOUSE_LOOK_SPEED);
+        if let Some(rotation) = self
+            .movement
+            .take_rotation(&self.movement_settings, seconds)
+        {
+            camera.rotate_yaw(rotation.x);
+            camera.rotate_pitch(rotation.y);
             changed = true;
-            self.mouse_ctrl_delta = (0.0, 0.0);
         }
 
         if self.touch_single_delta != (0.0, 0.0) {
@@ -420,53 +746,172 @@ impl CameraController {
             changed = true;
         }
 
-        self.events_to_process
-            .drain(..)
-            .for_each(|event| match event {
+        let altitude_pixels = std::mem::take(&mut self.touch_altitude_delta) as f32;
+        if altitude_pixels != 0.0 && !self.walking {
+            // dragging up rises, faster the higher the camera is
+            let meters_per_pixel =
+                (height_above_ground * TOUCH_ALTITUDE_SPEED).max(MIN_TOUCH_ALTITUDE_STEP);
+            camera.set_eye(camera.eye - camera.up() * altitude_pixels * meters_per_pixel);
+            changed = true;
+        }
+
+        for event in std::mem::take(&mut self.events_to_process) {
+            match event {
                 CameraControllerEvent::ToggleViewMode => {
                     camera.view_mode = camera.view_mode.toggle();
                     changed = true;
                 }
+                CameraControllerEvent::ToggleWalkMode => {
+                    self.walking = !self.walking;
+                    log::info!("Walk mode {}", if self.walking { "on" } else { "off" });
+                    changed = true;
+                }
                 CameraControllerEvent::UpdateCameraOrientation {
                     start_position,
                     end_position,
                 } => {
-                    let (rotation_change, new_fov) = get_rotation_and_fov_change(
-                        start_position,
-                        end_position,
-                        camera.get_fovy(),
-                        size,
-                    );
-
-                    if rotation_change != 0.0 || new_fov != 0.0 {
-                        camera.rotate_yaw(-rotation_change);
-                        camera.set_fovy(new_fov);
-                        changed = true;
-                    }
+                    changed |=
+                        self.apply_two_finger_gesture(start_position, end_position, camera, size);
                 }
-            });
+            }
+        }
 
         if let (Some(delta), TouchState::Multi(positions)) =
             (self.touch_multi_delta.take(), &self.touch_state)
         {
-            let (rotation_change, new_fov) = get_rotation_and_fov_change(
-                delta,
-                StoredMultiPosition::from_multi_positions(positions),
-                camera.get_fovy(),
-                size,
-            );
+            let end_position = StoredMultiPosition::from_multi_positions(positions);
+            // three or more fingers only change the altitude
+            if positions.others.is_empty() {
+                changed |= self.apply_two_finger_gesture(delta, end_position, camera, size);
+            }
+
+            self.touch_multi_delta = StoredMultiPosition::from_touch_state(&self.touch_state);
+        }
+
+        changed
+    }
 
-            if rotation_change != 0.0 || new_fov != 0.0 {
-                camera.rotate_yaw(-rotation_change);
-                camera.set_fovy(new_fov);
+    /// Drags, the arrow keys and single finger moves rotate around the target,
+    /// the wheel, forward/backward keys and pinching zoom the distance
+    fn update_orbit(
+        &mut self,
+        mut orbit: Orbit,
+        camera: &mut Camera,
+        size: (u32, u32),
+        time_delta: Duration,
+    ) -> bool {
+        let mut changed = false;
+        let seconds = time_delta.as_secs_f32();
+        let mut rotate = |orbit: &mut Orbit, azimuth_change: f32, elevation_change: f32| {
+            if azimuth_change != 0.0 || elevation_change != 0.0 {
+                orbit.rotate(azimuth_change, elevation_change);
                 changed = true;
             }
+        };
 
-            self.touch_multi_delta = StoredMultiPosition::from_touch_state(&self.touch_state);
+        let key_rotation = ORBIT_KEY_SPEED * seconds;
+        let key_axis = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => key_rotation,
+            (false, true) => -key_rotation,
+            _ => 0.0,
+        };
+        rotate(
+            &mut orbit,
+            key_axis(
+                self.is_pressed(Control::Left),
+                self.is_pressed(Control::Right),
+            ),
+            key_axis(
+                self.is_pressed(Control::Ascend),
+                self.is_pressed(Control::Descend),
+            ),
+        );
+
+        let (dx, dy) = std::mem::take(&mut self.mouse_view_delta);
+        rotate(&mut orbit, -dx * ORBIT_DRAG_SPEED, dy * ORBIT_DRAG_SPEED);
+        let (dx, dy) = std::mem::take(&mut self.touch_single_delta);
+        rotate(
+            &mut orbit,
+            -dx as f32 * ORBIT_DRAG_SPEED,
+            dy as f32 * ORBIT_DRAG_SPEED,
+        );
+
+        let mut zoom = ORBIT_WHEEL_ZOOM.powf(std::mem::take(&mut self.wheel_delta));
+        if self.is_pressed(Control::Forward) {
+            zoom *= 1.0 - ORBIT_KEY_ZOOM * seconds;
+        }
+        if self.is_pressed(Control::Backward) {
+            zoom /= 1.0 - ORBIT_KEY_ZOOM * seconds;
+        }
+
+        self.touch_altitude_delta = 0.0;
+        if let (Some(delta), TouchState::Multi(positions)) =
+            (self.touch_multi_delta.take(), &self.touch_state)
+            && positions.others.is_empty()
+        {
+            let fov = camera.get_fovy();
+            let end_position = StoredMultiPosition::from_multi_positions(positions);
+            let (rotation_change, new_fov) =
+                get_rotation_and_fov_change(delta, end_position, fov, size);
+            let pitch_change = get_pitch_change(delta, end_position, fov, size);
+            rotate(
+                &mut orbit,
+                rotation_change.to_degrees(),
+                pitch_change.to_degrees(),
+            );
+            // pinching out, which narrows the field of view otherwise, moves closer
+            zoom *= new_fov / fov;
         }
+        self.touch_multi_delta = StoredMultiPosition::from_touch_state(&self.touch_state);
 
+        if zoom != 1.0 {
+            orbit.zoom(zoom);
+            changed = true;
+        }
+
+        self.events_to_process
+            .drain(..)
+            .for_each(|event| match event {
+                CameraControllerEvent::ToggleViewMode => {
+                    camera.view_mode = camera.view_mode.toggle();
+                    changed = true;
+                }
+                CameraControllerEvent::ToggleWalkMode => {
+                    self.walking = !self.walking;
+                    log::info!("Walk mode {}", if self.walking { "on" } else { "off" });
+                }
+                CameraControllerEvent::UpdateCameraOrientation { .. } => {}
+            });
+
+        if changed {
+            orbit.apply(camera);
+            self.orbit = Some(orbit);
+        }
         changed
     }
+
+    /// Leaves orbit mode, stops, levels the camera and restores the field of view
+    fn reset_view(&mut self, camera: &mut Camera) {
+        self.orbit = None;
+        self.movement.stop();
+        camera.set_pitch(0.0);
+        camera.set_fovy(self.default_fov);
+    }
+}
+
+/// Radians to tilt the view down by, dragging two fingers across the whole
+/// height of the view tilts it by the field of view
+fn get_pitch_change(
+    start_position: StoredMultiPosition,
+    end_position: StoredMultiPosition,
+    fov: f32,
+    size: (u32, u32),
+) -> f32 {
+    let vertical_move = 0.5
+        * ((end_position.position1.y - start_position.position1.y)
+            + (end_position.position2.y - start_position.position2.y)) as f32;
+    // like dragging the scenery, moving the fingers down tilts the view up
+    -vertical_move * fov / size.1 as f32
 }
 
 fn get_rotation_and_fov_change(
@@ -495,3 +940,127 @@ fn get_rotation_and_fov_change(
 
     (angle_change, fov_p)
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use topo_common::GeoCoord;
+    use winit::event::DeviceId;
+
+    use crate::{render::geometry::inverse_transform, settings::MovementSettings};
+
+    use super::*;
+
+    const SIZE: (u32, u32) = (800, 600);
+
+    fn touch(phase: TouchPhase, id: u64, x: f64, y: f64) -> WindowEvent {
+        WindowEvent::Touch(Touch {
+            device_id: DeviceId::dummy(),
+            phase,
+            location: PhysicalPosition::new(x, y),
+            force: None,
+            id,
+        })
+    }
+
+    fn setup() -> (CameraController, Camera) {
+        let settings = RendererSettings {
+            movement: MovementSettings {
+                rotation_smoothing: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        (CameraController::new(&settings), camera)
+    }
+
+    fn step(controller: &mut CameraController, camera: &mut Camera, millis: u64) -> bool {
+        controller.update_camera(camera, SIZE, Duration::from_millis(millis), 1000.0)
+    }
+
+    fn tap(controller: &mut CameraController, camera: &mut Camera, x: f64, y: f64) {
+        controller.process_events(&touch(TouchPhase::Started, 1, x, y));
+        step(controller, camera, 100);
+        controller.process_events(&touch(TouchPhase::Ended, 1, x, y));
+        step(controller, camera, 100);
+    }
+
+    #[test]
+    fn two_finger_vertical_drag_tilts_the_view() {
+        let (mut controller, mut camera) = setup();
+        controller.process_events(&touch(TouchPhase::Started, 1, 300.0, 300.0));
+        controller.process_events(&touch(TouchPhase::Started, 2, 500.0, 300.0));
+        step(&mut controller, &mut camera, 16);
+
+        controller.process_events(&touch(TouchPhase::Moved, 1, 300.0, 150.0));
+        controller.process_events(&touch(TouchPhase::Moved, 2, 500.0, 150.0));
+        step(&mut controller, &mut camera, 16);
+        // the turn is applied in the next update
+        step(&mut controller, &mut camera, 16);
+
+        // dragging up by a quarter of the view tilts it down by a quarter of the field of view
+        assert_abs_diff_eq!(camera.pitch, 0.25 * camera.fov_y(), epsilon = 1e-4);
+        assert_abs_diff_eq!(camera.yaw, 0.0, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn double_tap_requests_a_flight() {
+        let (mut controller, mut camera) = setup();
+        tap(&mut controller, &mut camera, 200.0, 400.0);
+        assert_eq!(controller.take_fly_request(), None);
+        tap(&mut controller, &mut camera, 205.0, 398.0);
+        assert_eq!(controller.take_fly_request(), Some((205.0, 398.0)));
+
+        // taps too far apart in time or space
+        tap(&mut controller, &mut camera, 200.0, 400.0);
+        step(&mut controller, &mut camera, 500);
+        tap(&mut controller, &mut camera, 200.0, 400.0);
+        tap(&mut controller, &mut camera, 400.0, 400.0);
+        assert_eq!(controller.take_fly_request(), None);
+    }
+
+    #[test]
+    fn long_press_requests_identification() {
+        let (mut controller, mut camera) = setup();
+        controller.process_events(&touch(TouchPhase::Started, 1, 100.0, 100.0));
+        controller.process_events(&touch(TouchPhase::Moved, 1, 103.0, 102.0));
+        step(&mut controller, &mut camera, 300);
+        assert_eq!(controller.take_identify_request(), None);
+        step(&mut controller, &mut camera, 400);
+        assert_eq!(controller.take_identify_request(), Some((100.0, 100.0)));
+
+        // releasing a long press isn't a tap
+        controller.process_events(&touch(TouchPhase::Ended, 1, 103.0, 102.0));
+        tap(&mut controller, &mut camera, 100.0, 100.0);
+        assert_eq!(controller.take_fly_request(), None);
+
+        // a finger that moves pans instead
+        controller.process_events(&touch(TouchPhase::Started, 1, 100.0, 100.0));
+        controller.process_events(&touch(TouchPhase::Moved, 1, 150.0, 100.0));
+        step(&mut controller, &mut camera, 1000);
+        assert_eq!(controller.take_identify_request(), None);
+    }
+
+    #[test]
+    fn three_finger_drag_changes_the_altitude() {
+        let (mut controller, mut camera) = setup();
+        for (id, x) in [(1, 300.0), (2, 400.0), (3, 500.0)] {
+            controller.process_events(&touch(TouchPhase::Started, id, x, 300.0));
+        }
+        step(&mut controller, &mut camera, 16);
+        let (pitch, fov) = (camera.pitch, camera.fov_y());
+
+        for (id, x) in [(1, 300.0), (2, 400.0), (3, 500.0)] {
+            controller.process_events(&touch(TouchPhase::Moved, id, x, 200.0));
+        }
+        assert!(step(&mut controller, &mut camera, 16));
+        step(&mut controller, &mut camera, 16);
+
+        // 100 pixels up at 1000m above the ground
+        let (altitude, _) = inverse_transform(camera.eye);
+        assert_abs_diff_eq!(altitude, 2500.0, epsilon = 2.0);
+        assert_eq!((camera.pitch, camera.fov_y()), (pitch, fov));
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(target_arch = "wasm32")]
+use web_time::{Duration, Instant};
+
+/// Source of the time steps driving the camera movement
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Clock {
+    /// Follows the wall-clock time between the updates
+    RealTime { previous: Instant },
+    /// Advances by the same step on every update regardless of how long it
+    /// actually took, e.g. when rendering frames for a video
+    Fixed { step: Duration },
+}
+
+impl Clock {
+    pub fn real_time() -> Self {
+        Clock::RealTime {
+            previous: Instant::now(),
+        }
+    }
+
+    pub fn fixed(frame_rate: f32) -> Self {
+        Clock::Fixed {
+            step: Duration::from_secs_f64(1.0 / frame_rate as f64),
+        }
+    }
+
+    /// Time passed since the previous tick
+    pub fn tick(&mut self) -> Duration {
+        match self {
+            Clock::RealTime { previous } => {
+                let now = Instant::now();
+                let delta = now - *previous;
+                *previous = now;
+                delta
+            }
+            Clock::Fixed { step } => *step,
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, de::IntoDeserializer};
+use thiserror::Error;
+use winit::{
+    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+};
+
+use crate::control::camera_controller::Control;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KeyBindingsError {
+    #[error("Unknown modifier {0}, expected Ctrl, Shift, Alt or Super")]
+    UnknownModifier(String),
+    #[error("Unknown key or mouse button {0}")]
+    UnknownInput(String),
+    #[error("{binding} is bound to both {first} and {second}")]
+    Conflict {
+        binding: Binding,
+        first: Control,
+        second: Control,
+    },
+}
+
+const MODIFIER_NAMES: [(ModifiersState, &str); 4] = [
+    (ModifiersState::CONTROL, "Ctrl"),
+    (ModifiersState::SHIFT, "Shift"),
+    (ModifiersState::ALT, "Alt"),
+    (ModifiersState::SUPER, "Super"),
+];
+
+const MOUSE_BUTTON_NAMES: [(MouseButton, &str); 5] = [
+    (MouseButton::Left, "MouseLeft"),
+    (MouseButton::Right, "MouseRight"),
+    (MouseButton::Middle, "MouseMiddle"),
+    (MouseButton::Back, "MouseBack"),
+    (MouseButton::Forward, "MouseForward"),
+];
+
+/// Physical key or mouse button
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Input {
+    /// Input of a keyboard or mouse button event and whether it got pressed or released
+    pub fn from_event(event: &WindowEvent) -> Option<(Self, ElementState)> {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(keycode),
+                        state,
+                        ..
+                    },
+                ..
+            } => Some((Self::Key(*keycode), *state)),
+            WindowEvent::MouseInput { state, button, .. } => Some((Self::Mouse(*button), *state)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(keycode) => write!(f, "{keycode:?}"),
+            Self::Mouse(MouseButton::Other(id)) => write!(f, "Mouse{id}"),
+            Self::Mouse(button) => {
+                let (_, name) = MOUSE_BUTTON_NAMES
+                    .iter()
+                    .find(|(named_button, _)| named_button == button)
+                    .expect("All the other mouse buttons are named");
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+impl FromStr for Input {
+    type Err = KeyBindingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((button, _)) = MOUSE_BUTTON_NAMES.iter().find(|(_, name)| *name == s) {
+            return Ok(Self::Mouse(*button));
+        }
+        if let Some(id) = s.strip_prefix("Mouse").and_then(|id| id.parse().ok()) {
+            return Ok(Self::Mouse(MouseButton::Other(id)));
+        }
+        KeyCode::deserialize(s.into_deserializer())
+            .map(Self::Key)
+            .map_err(|_: serde::de::value::Error| KeyBindingsError::UnknownInput(s.to_owned()))
+    }
+}
+
+/// Input together with the modifiers that have to be held for it,
+/// written as e.g. `KeyW`, `Ctrl+KeyS` or `MouseRight`
+///
+/// Key names follow [`KeyCode`], mouse buttons are `MouseLeft`, `MouseRight`,
+/// `MouseMiddle`, `MouseBack`, `MouseForward` or `Mouse` followed by the button's number
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Binding {
+    pub modifiers: ModifiersState,
+    pub input: Input,
+}
+
+impl From<KeyCode> for Binding {
+    fn from(keycode: KeyCode) -> Self {
+        Self {
+            modifiers: ModifiersState::empty(),
+            input: Input::Key(keycode),
+        }
+    }
+}
+
+impl From<MouseButton> for Binding {
+    fn from(button: MouseButton) -> Self {
+        Self {
+            modifiers: ModifiersState::empty(),
+            input: Input::Mouse(button),
+        }
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in MODIFIER_NAMES {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", self.input)
+    }
+}
+
+impl FromStr for Binding {
+    type Err = KeyBindingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (modifier_names, input) = match s.rsplit_once('+') {
+            Some((modifier_names, input)) => (Some(modifier_names), input),
+            None => (None, s),
+        };
+        let mut modifiers = ModifiersState::empty();
+        for modifier_name in modifier_names
+            .into_iter()
+            .flat_map(|names| names.split('+'))
+        {
+            let (modifier, _) = MODIFIER_NAMES
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(modifier_name))
+                .ok_or_else(|| KeyBindingsError::UnknownModifier(modifier_name.to_owned()))?;
+            modifiers |= *modifier;
+        }
+
+        Ok(Self {
+            modifiers,
+            input: input.parse()?,
+        })
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = KeyBindingsError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+/// Keys, mouse buttons and modifier combinations assigned to each control
+///
+/// When deserialized, only the controls that are present get overridden,
+/// the rest keep their default bindings. The result may not bind the same
+/// combination to several controls
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyBindings(BTreeMap<Control, Vec<Binding>>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use Control::*;
+
+        let keys = |keycodes: &[KeyCode]| keycodes.iter().map(|&keycode| keycode.into()).collect();
+        Self(BTreeMap::from([
+            (Forward, keys(&[KeyCode::KeyW, KeyCode::ArrowUp])),
+            (Backward, keys(&[KeyCode::KeyS, KeyCode::ArrowDown])),
+            (Left, keys(&[KeyCode::KeyA, KeyCode::ArrowLeft])),
+            (Right, keys(&[KeyCode::KeyD, KeyCode::ArrowRight])),
+            (ZoomIn, keys(&[KeyCode::KeyQ])),
+            (ZoomOut, keys(&[KeyCode::KeyE])),
+            (Ascend, keys(&[KeyCode::Space])),
+            (Descend, keys(&[KeyCode::ShiftLeft])),
+            (MoveLight, keys(&[KeyCode::ControlLeft])),
+            (Sprint, keys(&[KeyCode::ShiftRight])),
+            (Look, vec![MouseButton::Right.into()]),
+            (ToggleViewMode, keys(&[KeyCode::KeyF])),
+            (ExportPeaks, keys(&[KeyCode::KeyP])),
+            (RecordCameraPath, keys(&[KeyCode::KeyR])),
+            (ToggleWalkMode, keys(&[KeyCode::KeyG])),
+            (ToggleOrbit, keys(&[KeyCode::KeyO])),
+            (TogglePointerLock, keys(&[KeyCode::KeyL])),
+            (ResetView, keys(&[KeyCode::Home, KeyCode::KeyH])),
+            (ToggleTimeLapse, keys(&[KeyCode::KeyT])),
+        ]))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let overrides = BTreeMap::<Control, Vec<Binding>>::deserialize(deserializer)?;
+        let mut key_bindings = Self::default();
+        key_bindings.0.extend(overrides);
+        key_bindings.validate().map_err(serde::de::Error::custom)?;
+
+        Ok(key_bindings)
+    }
+}
+
+impl KeyBindings {
+    pub fn get_bindings(&self, control: Control) -> &[Binding] {
+        self.0.get(&control).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Fails on the first combination that is bound to more than one control
+    pub fn validate(&self) -> Result<(), KeyBindingsError> {
+        let mut bound_controls = HashMap::new();
+        for (control, bindings) in &self.0 {
+            for binding in bindings {
+                if let Some(first) = bound_controls.insert(*binding, *control)
+                    && first != *control
+                {
+                    return Err(KeyBindingsError::Conflict {
+                        binding: *binding,
+                        first,
+                        second: *control,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reverse lookup map used for handling keyboard and mouse events
+    pub fn to_input_map(&self) -> InputMap {
+        let mut input_map = InputMap::default();
+        for (control, bindings) in &self.0 {
+            for binding in bindings {
+                input_map
+                    .0
+                    .entry(binding.input)
+                    .or_default()
+                    .push((binding.modifiers, *control));
+            }
+        }
+        input_map
+    }
+}
+
+/// Controls by the input that triggers them, see [`KeyBindings::to_input_map`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputMap(HashMap<Input, Vec<(ModifiersState, Control)>>);
+
+impl InputMap {
+    /// Control bound to the input with the most of the held modifiers, bindings
+    /// with modifiers that aren't held don't match while those without any always do
+    pub fn get_control(&self, input: Input, modifiers: ModifiersState) -> Option<Control> {
+        self.0
+            .get(&input)?
+            .iter()
+            .filter(|(binding_modifiers, _)| modifiers.contains(*binding_modifiers))
+            .max_by_key(|(binding_modifiers, _)| binding_modifiers.bits().count_ones())
+            .map(|(_, control)| *control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("KeyW", Binding::from(KeyCode::KeyW))]
+    #[case("MouseRight", Binding::from(MouseButton::Right))]
+    #[case("Mouse8", Binding::from(MouseButton::Other(8)))]
+    #[case("Ctrl+Shift+KeyS", Binding {
+        modifiers: ModifiersState::CONTROL | ModifiersState::SHIFT,
+        input: Input::Key(KeyCode::KeyS),
+    })]
+    fn bindings_round_trip(#[case] text: &str, #[case] binding: Binding) {
+        assert_eq!(text.parse::<Binding>().unwrap(), binding);
+        assert_eq!(binding.to_string(), text);
+    }
+
+    #[rstest]
+    #[case("Hyper+KeyW", KeyBindingsError::UnknownModifier("Hyper".to_owned()))]
+    #[case("Ctrl+KeyÜ", KeyBindingsError::UnknownInput("KeyÜ".to_owned()))]
+    fn invalid_bindings_are_rejected(#[case] text: &str, #[case] error: KeyBindingsError) {
+        assert_eq!(text.parse::<Binding>(), Err(error));
+    }
+
+    #[test]
+    fn conflicting_overrides_are_rejected() {
+        assert_eq!(KeyBindings::default().validate(), Ok(()));
+
+        let result = serde_json::from_str::<KeyBindings>(r#"{"left": ["KeyQ"]}"#);
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("KeyQ is bound to both"), "{error}");
+
+        let key_bindings =
+            serde_json::from_str::<KeyBindings>(r#"{"left": ["KeyQ"], "zoom_in": ["Ctrl+KeyQ"]}"#)
+                .unwrap();
+        assert_eq!(
+            key_bindings.get_bindings(Control::Look),
+            &[Binding::from(MouseButton::Right)]
+        );
+    }
+
+    #[test]
+    fn held_modifiers_select_the_most_specific_binding() {
+        let key_bindings = serde_json::from_str::<KeyBindings>(
+            r#"{"export_peaks": ["Ctrl+KeyW"], "look": ["Alt+MouseLeft"]}"#,
+        )
+        .unwrap();
+        let input_map = key_bindings.to_input_map();
+        let w = Input::Key(KeyCode::KeyW);
+
+        assert_eq!(
+            input_map.get_control(w, ModifiersState::empty()),
+            Some(Control::Forward)
+        );
+        assert_eq!(
+            input_map.get_control(w, ModifiersState::SHIFT),
+            Some(Control::Forward)
+        );
+        assert_eq!(
+            input_map.get_control(w, ModifiersState::CONTROL | ModifiersState::SHIFT),
+            Some(Control::ExportPeaks)
+        );
+        assert_eq!(
+            input_map.get_control(Input::Mouse(MouseButton::Left), ModifiersState::empty()),
+            None
+        );
+        assert_eq!(
+            input_map.get_control(Input::Mouse(MouseButton::Left), ModifiersState::ALT),
+            Some(Control::Look)
+        );
+    }
+}
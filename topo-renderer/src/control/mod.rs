@@ -1,4 +1,9 @@
 pub mod application_controllers;
 pub mod background_runner;
+pub mod camera_animation;
 pub mod camera_controller;
+pub mod clock;
+pub mod key_bindings;
+pub mod movement;
+pub mod orbit;
 pub mod ui_controller;
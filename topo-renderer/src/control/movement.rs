@@ -0,0 +1,154 @@
+use glam::{Vec2, Vec3};
+
+use crate::settings::MovementSettings;
+
+/// Meters per second below which the camera is considered to have stopped
+const MIN_VELOCITY: f32 = 0.01;
+/// Radians of pending rotation below which it's applied at once
+const MIN_ROTATION: f32 = 1e-4;
+
+/// Fraction of the remaining difference an exponential approach with the given
+/// time constant covers within the time step
+fn approach_factor(time_constant: f32, seconds: f32) -> f32 {
+    if time_constant <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-seconds / time_constant).exp()
+    }
+}
+
+/// Speed the camera moves at (in meters per second), proportional to its height above the ground
+pub fn target_speed(settings: &MovementSettings, height_above_ground: f32, sprint: bool) -> f32 {
+    let speed = (height_above_ground * settings.speed_per_height)
+        .clamp(settings.min_speed, settings.max_speed);
+    if sprint {
+        speed * settings.sprint_multiplier
+    } else {
+        speed
+    }
+}
+
+/// Velocity of the free moving camera and the rotation it still has to turn by
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Movement {
+    /// Meters per second in world space
+    pub velocity: Vec3,
+    /// Yaw and pitch (in radians) requested by the mouse or touch but not applied yet
+    pending_rotation: Vec2,
+}
+
+impl Movement {
+    /// Accelerates towards the target velocity, or slows down when it's zero,
+    /// and returns the distance to move the camera by
+    pub fn advance(
+        &mut self,
+        target_velocity: Vec3,
+        settings: &MovementSettings,
+        seconds: f32,
+    ) -> Vec3 {
+        let time_constant = if target_velocity == Vec3::ZERO {
+            settings.damping_time
+        } else {
+            settings.acceleration_time
+        };
+        self.velocity +=
+            (target_velocity - self.velocity) * approach_factor(time_constant, seconds);
+        if self.velocity.length() < MIN_VELOCITY {
+            self.velocity = Vec3::ZERO;
+        }
+        self.velocity * seconds
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.velocity != Vec3::ZERO
+    }
+
+    /// Stops the camera at once, e.g. when something else takes it over
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Queues a yaw and pitch change (in radians) to be applied smoothly
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        self.pending_rotation += Vec2::new(yaw, pitch);
+    }
+
+    /// Part of the pending rotation to apply within the time step
+    pub fn take_rotation(&mut self, settings: &MovementSettings, seconds: f32) -> Option<Vec2> {
+        if self.pending_rotation == Vec2::ZERO {
+            return None;
+        }
+        let rotation = if self.pending_rotation.abs().max_element() < MIN_ROTATION {
+            self.pending_rotation
+        } else {
+            self.pending_rotation * approach_factor(settings.rotation_smoothing, seconds)
+        };
+        self.pending_rotation -= rotation;
+        Some(rotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn speed_follows_the_height_above_ground() {
+        let settings = MovementSettings::default();
+
+        assert_eq!(target_speed(&settings, 0.0, false), settings.min_speed);
+        assert_eq!(target_speed(&settings, 1e9, false), settings.max_speed);
+        assert_eq!(
+            target_speed(&settings, 1000.0, true),
+            1000.0 * settings.speed_per_height * settings.sprint_multiplier
+        );
+    }
+
+    #[test]
+    fn camera_accelerates_and_coasts_to_a_stop() {
+        let settings = MovementSettings::default();
+        let mut movement = Movement::default();
+        let target = Vec3::X * 100.0;
+
+        let first_step = movement.advance(target, &settings, 0.1);
+        assert!(first_step.x > 0.0 && first_step.x < 10.0);
+        for _ in 0..100 {
+            movement.advance(target, &settings, 0.1);
+        }
+        assert_abs_diff_eq!(movement.velocity.x, 100.0, epsilon = 0.01);
+
+        // keeps moving after the key is released
+        assert!(movement.advance(Vec3::ZERO, &settings, 0.1).x > 0.0);
+        for _ in 0..100 {
+            movement.advance(Vec3::ZERO, &settings, 0.1);
+        }
+        assert!(!movement.is_moving());
+    }
+
+    #[test]
+    fn rotation_is_spread_over_several_steps() {
+        let settings = MovementSettings::default();
+        let mut movement = Movement::default();
+        movement.rotate(0.5, -0.2);
+
+        let first = movement.take_rotation(&settings, 0.016).unwrap();
+        assert!(first.x > 0.0 && first.x < 0.5);
+        let mut total = first;
+        while let Some(rotation) = movement.take_rotation(&settings, 0.016) {
+            total += rotation;
+        }
+        assert!(total.abs_diff_eq(Vec2::new(0.5, -0.2), 1e-6));
+
+        let instant = MovementSettings {
+            rotation_smoothing: 0.0,
+            ..Default::default()
+        };
+        movement.rotate(0.5, 0.0);
+        assert_eq!(
+            movement.take_rotation(&instant, 0.016),
+            Some(Vec2::new(0.5, 0.0))
+        );
+    }
+}
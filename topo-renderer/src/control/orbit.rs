@@ -0,0 +1,110 @@
+use glam::Vec3;
+
+use crate::{data::camera::Camera, render::geometry::north_east};
+
+/// Meters
+const MIN_DISTANCE: f32 = 100.0;
+/// Meters
+const MAX_DISTANCE: f32 = 200_000.0;
+/// Degrees, keeps the camera from looking along the ground or flipping over the target
+const MIN_ELEVATION: f32 = 2.0;
+const MAX_ELEVATION: f32 = 89.0;
+
+/// Camera position around a target it keeps looking at
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Orbit {
+    pub target: Vec3,
+    /// Meters between the camera and the target
+    pub distance: f32,
+    /// Direction from the target to the camera (in degrees clockwise from north)
+    pub azimuth: f32,
+    /// Angle of the camera above the target's horizon (in degrees)
+    pub elevation: f32,
+}
+
+impl Orbit {
+    /// Orbit around the target passing through the camera's current position
+    pub fn from_camera(camera: &Camera, target: Vec3) -> Self {
+        let offset = camera.eye - target;
+        let (north, east) = north_east(target);
+        let distance = offset.length();
+        let offset = offset / distance;
+
+        Self {
+            target,
+            distance: distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            azimuth: offset
+                .dot(east)
+                .atan2(offset.dot(north))
+                .to_degrees()
+                .rem_euclid(360.0),
+            elevation: offset
+                .dot(target.normalize())
+                .clamp(-1.0, 1.0)
+                .asin()
+                .to_degrees()
+                .clamp(MIN_ELEVATION, MAX_ELEVATION),
+        }
+    }
+
+    /// Moves around the target (by degrees clockwise from above and upwards)
+    pub fn rotate(&mut self, azimuth_change: f32, elevation_change: f32) {
+        self.azimuth = (self.azimuth + azimuth_change).rem_euclid(360.0);
+        self.elevation = (self.elevation + elevation_change).clamp(MIN_ELEVATION, MAX_ELEVATION);
+    }
+
+    /// Scales the distance to the target, factors below 1 move closer
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance * factor).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        let (north, east) = north_east(self.target);
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        let horizontal = north * azimuth.cos() + east * azimuth.sin();
+        let offset = horizontal * elevation.cos() + self.target.normalize() * elevation.sin();
+
+        camera.set_eye(self.target + offset * self.distance);
+        camera.look_at(self.target);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use topo_common::GeoCoord;
+
+    use crate::render::geometry::transform;
+
+    use super::*;
+
+    #[test]
+    fn camera_circles_around_the_target_facing_it() {
+        let target = transform(2655.0, 20.088, 49.179);
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.15, 20.088), 4000.0);
+
+        let mut orbit = Orbit::from_camera(&camera, target);
+        // south of the target, looking north
+        assert_abs_diff_eq!(orbit.azimuth, 180.0, epsilon = 0.1);
+        assert!(orbit.elevation > MIN_ELEVATION);
+        orbit.apply(&mut camera);
+        assert!(
+            camera
+                .eye
+                .abs_diff_eq(transform(4000.0, 20.088, 49.15), 2.0)
+        );
+        assert_abs_diff_eq!(camera.azimuth(), 0.0, epsilon = 0.1);
+
+        orbit.rotate(90.0, 100.0);
+        orbit.zoom(0.5);
+        orbit.apply(&mut camera);
+        assert_eq!(orbit.elevation, MAX_ELEVATION);
+        assert_abs_diff_eq!(camera.eye.distance(target), orbit.distance, epsilon = 2.0);
+        let direction = (target - camera.eye).normalize();
+        assert!(camera.direction().abs_diff_eq(direction, 1e-3));
+
+        orbit.zoom(1e-6);
+        assert_eq!(orbit.distance, MIN_DISTANCE);
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 use color_eyre::Result;
 use itertools::Itertools;
@@ -14,28 +14,53 @@ use crate::{
 
 pub struct UiController {
     sender: Sender<BackgroundEvent>,
+    view_distance: f32,
 }
 
 impl UiController {
-    pub fn new(sender: Sender<BackgroundEvent>) -> Self {
-        Self { sender }
+    pub fn new(sender: Sender<BackgroundEvent>, view_distance: f32) -> Self {
+        Self {
+            sender,
+            view_distance,
+        }
     }
+    /// Loads the terrain around the location and puts the camera on the ground there
     pub fn change_location(
         &mut self,
         location: GeoCoord,
         data: &mut ApplicationData,
         engine: &mut RenderEngine,
+    ) -> Result<()> {
+        self.load_locations(location, true, data, engine)
+    }
+
+    /// Loads the terrain around the location, leaving the camera where it is (e.g. while it is animated)
+    pub fn follow_camera(
+        &mut self,
+        location: GeoCoord,
+        data: &mut ApplicationData,
+        engine: &mut RenderEngine,
+    ) -> Result<()> {
+        self.load_locations(location, false, data, engine)
+    }
+
+    fn load_locations(
+        &mut self,
+        location: GeoCoord,
+        reset_camera: bool,
+        data: &mut ApplicationData,
+        engine: &mut RenderEngine,
     ) -> Result<()> {
         data.current_location = Some(location);
-        let mut new_locations: HashSet<_> = Self::get_locations_range(location, 100_000.0)
+        let mut new_locations: HashSet<_> = Self::get_locations_range(location, self.view_distance)
             .into_iter()
             .collect();
         let mut to_unload = vec![];
 
         for location in &data.loaded_locations {
-            let is_current_in_new = new_locations.contains(&location);
+            let is_current_in_new = new_locations.contains(location);
             if is_current_in_new {
-                new_locations.remove(&location);
+                new_locations.remove(location);
             } else {
                 to_unload.push(*location);
             }
@@ -45,23 +70,50 @@ impl UiController {
             //self.text_state.remove_labels(location);
             // self.peaks.remove(&location);
             data.loaded_locations.remove(&location);
+            data.height_maps.remove(&location);
+            data.contour_anchors.remove(&location);
             engine.renderers_mut().terrain.unload_terrain(&location);
+            data.scene_changed();
         }
 
+        new_locations.retain(|location| !data.pending_locations.contains(location));
+
         for requested in new_locations.into_iter() {
+            data.pending_locations.insert(requested);
             self.sender.blocking_send(BackgroundEvent::DataRequested {
                 requested,
                 current_location: location,
+                reset_camera,
+                contour_interval: data.contours.label_interval(),
             })?;
         }
 
         Ok(())
     }
 
-    fn get_locations_range(location: GeoCoord, range_dist: f32) -> Vec<GeoLocation> {
+    /// Finds where the index contours of the loaded tile can be labeled with the current
+    /// contour settings, in the background
+    pub fn request_contour_anchors(
+        &self,
+        location: GeoLocation,
+        data: &ApplicationData,
+    ) -> Result<()> {
+        if let Some(height_map) = data.height_maps.get(&location) {
+            self.sender
+                .blocking_send(BackgroundEvent::ContourAnchorsRequested {
+                    location,
+                    height_map: Arc::clone(height_map),
+                    contour_interval: data.contours.label_interval(),
+                })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_locations_range(location: GeoCoord, range_dist: f32) -> Vec<GeoLocation> {
         // TODO: handle projection edges (90NS/180EW deg)
         let center = (
-            (location.latitude.floor() as i32).min(-90).max(89),
+            (location.latitude.floor() as i32).clamp(-90, 89),
             ((location.longitude.floor() + 540.0) as i32) % 360 - 180,
         );
         let lat_cos = (location.latitude.to_radians()).cos();
@@ -82,3 +134,24 @@ impl UiController {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(GeoCoord::new(49.9, 20.1), GeoLocation::from_coord(49, 20))]
+    #[case(GeoCoord::new(-33.1, 18.4), GeoLocation::from_coord(-34, 18))]
+    #[case(GeoCoord::new(89.5, -0.5), GeoLocation::from_coord(89, -1))]
+    fn loads_the_tile_under_the_location_first(
+        #[case] location: GeoCoord,
+        #[case] expected: GeoLocation,
+    ) {
+        let locations = UiController::get_locations_range(location, 100_000.0);
+
+        assert_eq!(locations[0], expected);
+        assert!(locations.len() > 1);
+    }
+}
@@ -1,13 +1,33 @@
-use std::collections::{BTreeMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
 use glam::Vec3;
 use topo_common::{GeoCoord, GeoLocation};
 
 use crate::{
-    data::{Size, camera::Camera},
-    render::data::{PeakInstance, PostprocessingUniforms, Uniforms},
+    data::{
+        Size,
+        camera::{Camera, StartView},
+        contours::ContourAnchor,
+        height_map::HeightMap,
+        refraction::refraction_lift,
+        sun::Sun,
+    },
+    render::{
+        data::{PeakInstance, PostprocessingUniforms, Uniforms},
+        geometry::inverse_transform,
+        text_renderer::TextRenderer,
+    },
+    settings::{ColoringSettings, ContourSettings, RendererSettings, ShadowSettings},
 };
 
+/// Meters
+const PICK_MIN_STEP: f32 = 10.0;
+const PICK_STEP_RATIO: f32 = 0.01;
+const PICK_REFINEMENTS: usize = 8;
+
 pub struct PeakLabel {
     pub buffer: glyphon::Buffer,
     pub width: f32,
@@ -16,30 +36,242 @@ pub struct PeakLabel {
 pub struct ApplicationData {
     pub current_location: Option<GeoCoord>,
     pub loaded_locations: HashSet<GeoLocation>,
+    /// Locations requested from the background runner that haven't been processed yet
+    pub pending_locations: HashSet<GeoLocation>,
+    /// Incremented whenever terrain or peaks are added or removed, so that
+    /// the depth dependent state (like label visibility) can be refreshed
+    pub scene_version: u64,
+    pub start_view: Option<StartView>,
     pub camera: Camera,
+    pub sun: Sun,
+    pub shadows: ShadowSettings,
+    pub coloring: ColoringSettings,
+    pub contours: ContourSettings,
     pub uniforms: Uniforms,
     pub postprocessing_uniforms: PostprocessingUniforms,
     pub peaks: BTreeMap<GeoLocation, Vec<PeakInstance>>,
     pub peak_labels: BTreeMap<GeoLocation, Vec<PeakLabel>>,
+    pub height_maps: HashMap<GeoLocation, Arc<HeightMap>>,
+    /// Where the index contours of each tile can be labeled, empty without contour labels
+    pub contour_anchors: HashMap<GeoLocation, Vec<ContourAnchor>>,
+    /// Contour labels by their elevation
+    pub contour_labels: BTreeMap<i32, PeakLabel>,
 }
 
 impl ApplicationData {
-    pub fn new(bounds: Size<f32>) -> Self {
+    pub fn new(bounds: Size<f32>, settings: &RendererSettings) -> Self {
         let mut camera = Camera::default();
         camera.set_eye(Vec3::new(0.0, 0.0, 0.0));
+        camera.set_fovy(settings.fov.to_radians());
+        camera.refraction = settings.refraction.coefficient();
+        camera.view_mode = settings.view_mode;
+
+        let sun = Sun::new(&settings.sun);
+        let uniforms = Uniforms::new(
+            &camera,
+            sun.direction(),
+            bounds,
+            &settings.coloring.elevation_stops,
+            settings.application.imagery.blend,
+            &settings.contours,
+            &settings.application.water,
+        );
+        let postprocessing_uniforms = PostprocessingUniforms::new(
+            bounds,
+            &camera,
+            sun.direction(),
+            &settings.postprocessing,
+            &settings.atmosphere,
+            &settings.application.water,
+        );
 
-        let pixelize_n = 100.0;
-        let uniforms = Uniforms::new(&camera, bounds);
-        let postprocessing_uniforms = PostprocessingUniforms::new(bounds, pixelize_n);
+        let start_view = StartView {
+            altitude: settings.start_altitude,
+            yaw: settings.start_yaw,
+            pitch: settings.start_pitch,
+        };
 
         Self {
             current_location: None,
             loaded_locations: HashSet::new(),
+            pending_locations: HashSet::new(),
+            scene_version: 0,
+            start_view: Some(start_view),
             camera,
+            sun,
+            shadows: settings.shadows.clone(),
+            coloring: settings.coloring.clone(),
+            contours: settings.contours.clone(),
             uniforms,
             postprocessing_uniforms,
             peaks: BTreeMap::new(),
             peak_labels: BTreeMap::new(),
+            height_maps: HashMap::new(),
+            contour_anchors: HashMap::new(),
+            contour_labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn scene_changed(&mut self) {
+        self.scene_version += 1;
+    }
+
+    /// Keeps the anchors of the loaded tile and prepares their labels, unless they were found
+    /// for another label interval than the current one. Returns whether they were kept
+    pub fn set_contour_anchors(
+        &mut self,
+        location: GeoLocation,
+        interval: f32,
+        anchors: Vec<ContourAnchor>,
+    ) -> bool {
+        if interval != self.contours.label_interval() || !self.height_maps.contains_key(&location) {
+            return false;
+        }
+
+        for anchor in &anchors {
+            let elevation = anchor.elevation.round() as i32;
+            self.contour_labels
+                .entry(elevation)
+                .or_insert_with(|| TextRenderer::prepare_label(&elevation.to_string()));
         }
+        self.contour_anchors.insert(location, anchors);
+        true
+    }
+
+    /// Drops the anchors found for the previous settings, the caller requests new ones
+    pub fn set_contours(&mut self, contours: ContourSettings) {
+        self.uniforms = self.uniforms.with_contours(&contours);
+        if contours.label_interval() != self.contours.label_interval() {
+            self.contour_anchors.clear();
+        }
+        self.contours = contours;
+        self.scene_changed();
+    }
+
+    /// Height of the terrain (in meters above sea level), `None` if its tile isn't loaded
+    pub fn get_ground_height(&self, coord: GeoCoord) -> Option<f32> {
+        self.height_maps
+            .get(&GeoLocation::from(coord))?
+            .get_height(coord)
+    }
+
+    /// First point where the ray hits the loaded terrain within the distance (in meters)
+    ///
+    /// The ray hits the terrain where it appears with the camera's refraction,
+    /// the returned point is where it really is
+    pub fn pick_terrain(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<Vec3> {
+        let refraction = self.camera.refraction;
+        let height_above_ground = |distance: f32| {
+            let (altitude, coord) = inverse_transform(origin + direction * distance);
+            self.get_ground_height(coord).map(|ground_height| {
+                altitude - ground_height - refraction_lift(distance, refraction)
+            })
+        };
+
+        // march with steps growing with the distance, as the precision needed does too
+        let (mut above, mut below) = (0.0, None);
+        let mut distance = PICK_MIN_STEP;
+        while distance <= max_distance {
+            if height_above_ground(distance).is_some_and(|height| height <= 0.0) {
+                below = Some(distance);
+                break;
+            }
+            above = distance;
+            distance += (distance * PICK_STEP_RATIO).max(PICK_MIN_STEP);
+        }
+
+        let mut below = below?;
+        for _ in 0..PICK_REFINEMENTS {
+            let middle = 0.5 * (above + below);
+            if height_above_ground(middle).is_some_and(|height| height <= 0.0) {
+                below = middle;
+            } else {
+                above = middle;
+            }
+        }
+        let point = origin + direction * below;
+        Some(point - point.normalize() * refraction_lift(below, refraction))
+    }
+
+    /// Whether all the requested terrain and peak data got loaded
+    pub fn is_scene_loaded(&self) -> bool {
+        self.current_location.is_some() && self.pending_locations.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use tiff::decoder::DecodingResult;
+
+    use crate::{
+        common::coordinate_transform::CoordinateTransform,
+        render::geometry::{R0, transform},
+    };
+
+    use super::*;
+
+    #[test]
+    fn rays_hit_the_loaded_terrain() {
+        let mut data = ApplicationData::new((800.0, 600.0).into(), &RendererSettings::default());
+        data.height_maps.insert(
+            GeoLocation::from(GeoCoord::new(49.5, 20.5)),
+            Arc::new(HeightMap::new(
+                DecodingResult::F32(vec![500.0; 100]),
+                CoordinateTransform {
+                    raster_point: (0.0, 0.0),
+                    model_point: (20.0, 50.0),
+                    pixel_scale: (0.1, 0.1),
+                },
+                (10, 10),
+            )),
+        );
+        let eye = transform(3000.0, 20.5, 49.5);
+        let down = -eye.normalize();
+
+        let hit = data.pick_terrain(eye, down, 10_000.0).unwrap();
+        assert_abs_diff_eq!(hit.length() - R0, 500.0, epsilon = 1.0);
+
+        // the ground is out of reach or behind
+        assert_eq!(data.pick_terrain(eye, down, 2000.0), None);
+        assert_eq!(data.pick_terrain(eye, -down, 10_000.0), None);
+    }
+
+    #[test]
+    fn contour_anchors_of_another_interval_are_dropped() {
+        let mut settings = RendererSettings::default();
+        settings.contours.interval = 50.0;
+        settings.contours.labels = true;
+        let mut data = ApplicationData::new((800.0, 600.0).into(), &settings);
+        let location = GeoLocation::from(GeoCoord::new(49.5, 20.5));
+        data.height_maps.insert(
+            location,
+            Arc::new(HeightMap::new(
+                DecodingResult::F32(vec![500.0; 4]),
+                CoordinateTransform {
+                    raster_point: (0.0, 0.0),
+                    model_point: (20.0, 50.0),
+                    pixel_scale: (0.5, 0.5),
+                },
+                (2, 2),
+            )),
+        );
+        let anchors = vec![ContourAnchor {
+            position: transform(500.0, 20.5, 49.5),
+            elevation: 500.0,
+        }];
+        let interval = data.contours.label_interval();
+
+        data.set_contours(ContourSettings {
+            interval: 20.0,
+            ..data.contours.clone()
+        });
+        assert!(!data.set_contour_anchors(location, interval, anchors.clone()));
+        assert!(data.contour_anchors.is_empty());
+
+        let interval = data.contours.label_interval();
+        assert!(data.set_contour_anchors(location, interval, anchors.clone()));
+        assert_eq!(data.contour_anchors[&location], anchors);
+        assert!(data.contour_labels.contains_key(&500));
     }
 }
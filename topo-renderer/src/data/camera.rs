@@ -1,24 +1,48 @@
-use glam::{Vec3, vec3};
-use std::f32::consts::PI;
+use glam::{Mat4, Vec2, Vec3, Vec4, vec3};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_PI_2, PI};
+use strum::{Display, EnumString};
 use topo_common::GeoCoord;
 
-use crate::render::geometry::transform;
+use crate::{
+    data::refraction::apparent_position,
+    render::geometry::{north_east, transform},
+};
 
 pub const NEAR: f32 = 50.0;
 pub const FAR: f32 = 500000.0;
-const MIN_FOV: f32 = 10.0_f32.to_radians();
-const MAX_FOV: f32 = 160.0_f32.to_radians();
+pub const MIN_FOV: f32 = 10.0_f32.to_radians();
+pub const MAX_FOV: f32 = 160.0_f32.to_radians();
 
 pub fn dist_from_depth(depth: f32) -> f32 {
     FAR * NEAR / (FAR - depth * (FAR - NEAR))
 }
 
-#[derive(Copy, Clone, Debug, Default, PartialEq)]
+/// How the terrain gets colored, the discriminants are passed to the render shader
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, EnumString, Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
 pub enum ViewMode {
     #[default]
     Default = 0,
     Normals = 1,
     Position = 2,
+    /// Elevation color ramp, see [`ElevationRamp`]
+    ///
+    /// [`ElevationRamp`]: crate::data::terrain_coloring::ElevationRamp
+    Hypsometric = 3,
+    /// Slope angle classes, see [`SLOPE_CLASSES`]
+    ///
+    /// [`SLOPE_CLASSES`]: crate::data::terrain_coloring::SLOPE_CLASSES
+    Slope = 4,
+    /// Direction the slopes face, see [`ASPECT_COLORS`]
+    ///
+    /// [`ASPECT_COLORS`]: crate::data::terrain_coloring::ASPECT_COLORS
+    Aspect = 5,
+    /// Elevation color ramp shaded by a light from the north-west
+    Hillshade = 6,
 }
 
 impl ViewMode {
@@ -27,28 +51,48 @@ impl ViewMode {
         match self {
             Default => Normals,
             Normals => Position,
-            Position => Default,
+            Position => Hypsometric,
+            Hypsometric => Slope,
+            Slope => Aspect,
+            Aspect => Hillshade,
+            Hillshade => Default,
         }
     }
 }
 
-// degrees
+/// Viewpoint overrides applied when the camera gets placed at the start location
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
-pub struct LightAngle {
-    // 0 is down, around X
-    pub theta: f32,
-    // 0 is in direction of x, around Y
-    pub phi: f32,
+pub struct StartView {
+    /// Altitude above sea level in meters, by default the camera is placed 50m above the ground
+    pub altitude: Option<f32>,
+    /// Degrees clockwise from north
+    pub yaw: Option<f32>,
+    /// Degrees above the horizon
+    pub pitch: Option<f32>,
+}
+
+/// Part of a larger view, rendered on its own with an off-center projection
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewTile {
+    /// Aspect ratio of the whole view
+    pub aspect_ratio: f32,
+    /// Bottom left corner of the tile in normalized device coordinates of the whole view
+    pub min: Vec2,
+    /// Top right corner of the tile in normalized device coordinates of the whole view
+    pub max: Vec2,
 }
 
-impl LightAngle {
-    pub fn to_vec3(&self) -> glam::Vec3 {
-        glam::Mat3::from_euler(
-            glam::EulerRot::XYZEx,
-            0.0,
-            (90.0 - self.phi).to_radians(),
-            self.theta.to_radians(),
-        ) * glam::Vec3::Z
+impl ViewTile {
+    /// Maps the tile's part of the clip space onto the whole of it
+    fn crop_matrix(&self) -> Mat4 {
+        let scale = 2.0 / (self.max - self.min);
+        let offset = -(self.max + self.min) / (self.max - self.min);
+        Mat4::from_cols(
+            Vec4::new(scale.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, scale.y, 0.0, 0.0),
+            Vec4::Z,
+            Vec4::new(offset.x, offset.y, 0.0, 1.0),
+        )
     }
 }
 
@@ -61,7 +105,12 @@ pub struct Camera {
     near: f32,
     far: f32,
     pub view_mode: ViewMode,
-    pub sun_angle: LightAngle,
+    /// When set, only this part of the view gets rendered, stretched over the whole target
+    pub tile: Option<ViewTile>,
+    /// Refraction coefficient of the rays reaching the eye, see [`Refraction`]
+    ///
+    /// [`Refraction`]: crate::data::refraction::Refraction
+    pub refraction: f32,
 }
 
 impl Default for Camera {
@@ -74,11 +123,8 @@ impl Default for Camera {
             near: NEAR,
             far: FAR,
             view_mode: ViewMode::default(),
-            // TODO: Move elsewhere as it's not a part of the camera
-            sun_angle: LightAngle {
-                theta: 45.0,
-                phi: 0.0,
-            },
+            tile: None,
+            refraction: 0.0,
         }
     }
 }
@@ -88,9 +134,14 @@ impl Camera {
 
     pub fn reset(&mut self, coord: GeoCoord, height: f32) {
         self.eye = transform(height, coord.longitude, coord.latitude);
-        self.sun_angle = LightAngle {
-            theta: coord.longitude,
-            phi: coord.latitude,
+    }
+
+    pub fn apply_start_view(&mut self, start_view: StartView) {
+        if let Some(yaw) = start_view.yaw {
+            self.set_azimuth(yaw);
+        }
+        if let Some(pitch) = start_view.pitch {
+            self.set_pitch(-pitch.to_radians());
         }
     }
 
@@ -98,13 +149,93 @@ impl Camera {
         self.eye.normalize()
     }
 
+    fn glob_rotation(&self) -> glam::Quat {
+        glam::Quat::from_rotation_arc(Vec3::new(0.0, -1.0, 0.0), self.up())
+    }
+
+    /// Unit vectors pointing north and east in the plane tangent to the sphere below the eye
+    pub fn north_east(&self) -> (Vec3, Vec3) {
+        north_east(self.eye)
+    }
+
+    /// Turns the camera towards the point
+    pub fn look_at(&mut self, target: Vec3) {
+        let direction = (target - self.eye).normalize();
+        let (north, east) = self.north_east();
+        self.set_azimuth(direction.dot(east).atan2(direction.dot(north)).to_degrees());
+        self.set_pitch(-direction.dot(self.up()).clamp(-1.0, 1.0).asin());
+    }
+
+    /// Direction of the ray from the eye through the position (in pixels) of a view of the given size
+    pub fn screen_ray(&self, (x, y): (f32, f32), width: f32, height: f32) -> Vec3 {
+        let ndc = Vec3::new(2.0 * x / width - 1.0, 1.0 - 2.0 * y / height, 1.0);
+        let view_direction = self
+            .build_proj_matrix(width, height)
+            .inverse()
+            .project_point3(ndc);
+        Mat4::look_to_rh(Vec3::ZERO, self.direction(), self.up())
+            .inverse()
+            .transform_vector3(view_direction)
+            .normalize()
+    }
+
+    /// Changes the field of view, turning the camera so that whatever is at
+    /// the position (in pixels) of a view of the given size stays there
+    pub fn zoom_at(&mut self, position: (f32, f32), width: f32, height: f32, fov: f32) {
+        let before = self.screen_ray(position, width, height);
+        self.set_fovy(fov);
+        let after = self.screen_ray(position, width, height);
+
+        let (north, east) = self.north_east();
+        let up = self.up();
+        // azimuth and elevation (in radians)
+        let angles = |ray: Vec3| {
+            (
+                ray.dot(east).atan2(ray.dot(north)),
+                ray.dot(up).clamp(-1.0, 1.0).asin(),
+            )
+        };
+        let (azimuth_before, elevation_before) = angles(before);
+        let (azimuth_after, elevation_after) = angles(after);
+        let (azimuth, elevation) = angles(self.direction());
+
+        self.set_azimuth((azimuth + azimuth_before - azimuth_after).to_degrees());
+        self.set_pitch(
+            -(elevation + elevation_before - elevation_after).clamp(-FRAC_PI_2, FRAC_PI_2),
+        );
+    }
+
     pub fn direction(&self) -> Vec3 {
-        let glob_rotation = glam::Quat::from_rotation_arc(Vec3::new(0.0, -1.0, 0.0), self.up());
         let x = self.yaw.cos() * self.pitch.cos();
         let y = self.pitch.sin();
         let z = self.yaw.sin() * self.pitch.cos();
-        let direction = glob_rotation * Vec3::new(x, y, z);
+        self.glob_rotation() * Vec3::new(x, y, z)
+    }
+
+    /// Viewing direction in degrees clockwise from north
+    pub fn azimuth(&self) -> f32 {
+        let (north, east) = self.north_east();
+        let direction = self.direction();
         direction
+            .dot(east)
+            .atan2(direction.dot(north))
+            .to_degrees()
+            .rem_euclid(360.0)
+    }
+
+    /// Turns the camera horizontally to face the given direction (degrees clockwise from north)
+    pub fn set_azimuth(&mut self, azimuth: f32) {
+        let (north, east) = self.north_east();
+        let azimuth = azimuth.to_radians();
+        let local = self.glob_rotation().inverse() * (north * azimuth.cos() + east * azimuth.sin());
+        self.yaw = local.z.atan2(local.x);
+    }
+
+    /// Viewing direction projected onto the plane tangent to the sphere below the eye
+    pub fn direction_horizontal(&self) -> Vec3 {
+        let up = self.up();
+        let direction = self.direction();
+        (direction - up * direction.dot(up)).normalize_or_zero()
     }
 
     pub fn direction_right(&self) -> Vec3 {
@@ -119,12 +250,22 @@ impl Camera {
         glam::Mat4::look_to_rh(self.eye, self.direction(), self.up())
     }
 
-    pub fn build_view_proj_matrix(&self, width: f32, height: f32) -> glam::Mat4 {
-        let aspect_ratio = width / height;
+    pub fn build_proj_matrix(&self, width: f32, height: f32) -> glam::Mat4 {
+        let (aspect_ratio, crop) = match self.tile {
+            Some(tile) => (tile.aspect_ratio, tile.crop_matrix()),
+            None => (width / height, Mat4::IDENTITY),
+        };
 
-        let proj = glam::Mat4::perspective_rh(self.fov_y, aspect_ratio, self.near, self.far);
+        crop * glam::Mat4::perspective_rh(self.fov_y, aspect_ratio, self.near, self.far)
+    }
 
-        proj * self.get_view()
+    /// Where the point appears to be from the eye, as the light rays bend with the refraction
+    pub fn apparent_position(&self, point: Vec3) -> Vec3 {
+        apparent_position(point, self.eye, self.refraction)
+    }
+
+    pub fn build_view_proj_matrix(&self, width: f32, height: f32) -> glam::Mat4 {
+        self.build_proj_matrix(width, height) * self.get_view()
     }
 
     pub fn build_view_normal_matrix(&self) -> glam::Mat4 {
@@ -170,3 +311,120 @@ impl Camera {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn toggle_cycles_through_every_view_mode() {
+        let modes = std::iter::successors(Some(ViewMode::Default), |mode| {
+            Some(mode.toggle()).filter(|mode| *mode != ViewMode::Default)
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(modes.len(), 7);
+        assert_eq!(modes.last().unwrap().toggle(), ViewMode::Default);
+        assert_eq!("hillshade".parse(), Ok(ViewMode::Hillshade));
+    }
+
+    #[rstest]
+    #[case(0.0)]
+    #[case(90.0)]
+    #[case(135.0)]
+    #[case(270.0)]
+    fn set_azimuth_faces_given_direction(#[case] azimuth: f32) {
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        camera.set_azimuth(azimuth);
+
+        let (north, east) = camera.north_east();
+        let expected = north * azimuth.to_radians().cos() + east * azimuth.to_radians().sin();
+
+        assert!(camera.direction().abs_diff_eq(expected, 1e-4));
+        assert_abs_diff_eq!(
+            (camera.azimuth() - azimuth + 180.0).rem_euclid(360.0),
+            180.0,
+            epsilon = 1e-2
+        );
+
+        // looking down doesn't change the horizontal direction
+        camera.set_pitch(0.3);
+        assert!(camera.direction_horizontal().abs_diff_eq(expected, 1e-4));
+    }
+
+    #[test]
+    fn screen_ray_and_look_at_agree() {
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        camera.set_azimuth(150.0);
+        camera.set_pitch(0.2);
+
+        let center = camera.screen_ray((400.0, 300.0), 800.0, 600.0);
+        assert!(center.abs_diff_eq(camera.direction(), 1e-4));
+        // the top of the view is half the field of view above its center
+        let top = camera.screen_ray((400.0, 0.0), 800.0, 600.0);
+        assert_abs_diff_eq!(
+            top.angle_between(center),
+            0.5 * camera.fov_y(),
+            epsilon = 1e-4
+        );
+        assert!(top.dot(camera.up()) > center.dot(camera.up()));
+
+        let target = camera.eye + top * 1000.0;
+        camera.look_at(target);
+        assert!(camera.direction().abs_diff_eq(top, 1e-3));
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        camera.set_azimuth(30.0);
+        camera.set_pitch(0.1);
+        let cursor = (650.0, 180.0);
+
+        let before = camera.screen_ray(cursor, 800.0, 600.0);
+        camera.zoom_at(cursor, 800.0, 600.0, 30f32.to_radians());
+        assert_abs_diff_eq!(camera.fov_y(), 30f32.to_radians());
+        let after = camera.screen_ray(cursor, 800.0, 600.0);
+        assert!(
+            after.angle_between(before) < 2e-3,
+            "{}",
+            after.angle_between(before)
+        );
+
+        // at the center it only changes the field of view
+        let direction = camera.direction();
+        camera.zoom_at((400.0, 300.0), 800.0, 600.0, 60f32.to_radians());
+        assert!(camera.direction().abs_diff_eq(direction, 1e-4));
+    }
+
+    #[test]
+    fn tile_projection_is_part_of_full_view() {
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        let point = camera.eye + 1000.0 * camera.direction() + 200.0 * camera.direction_right()
+            - 100.0 * camera.direction_down();
+        let full = camera
+            .build_view_proj_matrix(2000.0, 1000.0)
+            .project_point3(point);
+
+        camera.tile = Some(ViewTile {
+            aspect_ratio: 2.0,
+            min: Vec2::new(0.0, 0.0),
+            max: Vec2::new(0.5, 1.0),
+        });
+        let tiled = camera
+            .build_view_proj_matrix(500.0, 500.0)
+            .project_point3(point);
+
+        // positions far from the origin leave little precision in f32
+        assert_abs_diff_eq!(tiled.x, 4.0 * full.x - 1.0, epsilon = 1e-2);
+        assert_abs_diff_eq!(tiled.y, 2.0 * full.y - 1.0, epsilon = 1e-2);
+        assert_abs_diff_eq!(tiled.z, full.z, epsilon = 1e-4);
+    }
+}
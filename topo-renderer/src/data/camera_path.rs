@@ -0,0 +1,300 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+#[cfg(not(target_arch = "wasm32"))]
+use color_eyre::{Result, eyre::Context};
+use serde::{Deserialize, Serialize};
+use topo_common::GeoCoord;
+
+use crate::{
+    data::camera::Camera,
+    render::geometry::{great_circle_point, inverse_transform, surface_distance, transform},
+};
+
+/// Fly-to transitions rise by this fraction of the distance they cover
+const FLY_TO_LIFT_RATIO: f32 = 0.2;
+/// Meters
+const MAX_FLY_TO_LIFT: f32 = 50_000.0;
+/// Meters covered per second by default, within the duration limits below
+const FLY_TO_SPEED: f32 = 50_000.0;
+const MIN_FLY_TO_DURATION: f32 = 2.0;
+const MAX_FLY_TO_DURATION: f32 = 10.0;
+
+/// Progress of the transition between two points over time
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the fraction of the elapsed time to the fraction of the transition
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Camera pose at a point in time of a [`CameraPath`], i.e. a keyframe
+///
+/// The location moves along the great circle from the previous point, `easing`
+/// and `lift` shape the transition to this point
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraPathPoint {
+    /// Seconds since the start of the path
+    pub time: f32,
+    pub location: GeoCoord,
+    /// Meters above sea level
+    pub altitude: f32,
+    /// Degrees clockwise from north
+    pub yaw: f32,
+    /// Degrees above the horizon
+    pub pitch: f32,
+    /// Vertical field of view (in degrees)
+    pub fov: f32,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Meters the camera rises above the straight transition at its middle
+    #[serde(default)]
+    pub lift: f32,
+}
+
+impl CameraPathPoint {
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        let (altitude, location) = inverse_transform(camera.eye);
+        Self {
+            time,
+            location,
+            altitude,
+            yaw: camera.azimuth(),
+            pitch: -camera.pitch.to_degrees(),
+            fov: camera.fov_y().to_degrees(),
+            easing: Easing::default(),
+            lift: 0.0,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_eye(transform(
+            self.altitude,
+            self.location.longitude,
+            self.location.latitude,
+        ));
+        camera.set_azimuth(self.yaw);
+        camera.set_pitch(-self.pitch.to_radians());
+        camera.set_fovy(self.fov.to_radians());
+    }
+
+    /// Pose at the fraction `t` of the time between the points
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let time = self.time + (other.time - self.time) * t;
+        let t = other.easing.apply(t);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        // turn the shorter way around
+        let yaw_change = (other.yaw - self.yaw + 180.0).rem_euclid(360.0) - 180.0;
+
+        Self {
+            time,
+            location: great_circle_point(self.location, other.location, t),
+            altitude: lerp(self.altitude, other.altitude) + other.lift * 4.0 * t * (1.0 - t),
+            yaw: (self.yaw + yaw_change * t).rem_euclid(360.0),
+            pitch: lerp(self.pitch, other.pitch),
+            fov: lerp(self.fov, other.fov),
+            easing: other.easing,
+            lift: other.lift,
+        }
+    }
+}
+
+/// Recorded or scripted camera movement, which can be played back at any frame rate
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// Sorted by time
+    pub points: Vec<CameraPathPoint>,
+}
+
+impl CameraPath {
+    /// Flight from the pose to the location (keeping the viewing direction), easing in and out
+    /// and arcing above the terrain in between
+    ///
+    /// Without a duration (in seconds) it follows from the distance
+    pub fn fly_to(
+        from: CameraPathPoint,
+        location: GeoCoord,
+        altitude: f32,
+        duration: Option<f32>,
+    ) -> Self {
+        let from = CameraPathPoint { time: 0.0, ..from };
+        let distance = surface_distance(from.location, location);
+        let duration = duration
+            .unwrap_or((distance / FLY_TO_SPEED).clamp(MIN_FLY_TO_DURATION, MAX_FLY_TO_DURATION));
+
+        Self {
+            points: vec![
+                from,
+                CameraPathPoint {
+                    time: duration,
+                    location,
+                    altitude,
+                    easing: Easing::EaseInOut,
+                    lift: (FLY_TO_LIFT_RATIO * distance).min(MAX_FLY_TO_LIFT),
+                    ..from
+                },
+            ],
+        }
+    }
+
+    /// Adds the camera's pose at the given time (in seconds), which must not precede the last one
+    pub fn record(&mut self, camera: &Camera, time: f32) {
+        let point = CameraPathPoint::from_camera(camera, time);
+        let same_pose = |other: &CameraPathPoint| CameraPathPoint { time, ..*other } == point;
+        // while the camera stands still, only the first and the last point are needed
+        if let [.., before_last, last] = self.points.as_slice()
+            && same_pose(before_last)
+            && same_pose(last)
+        {
+            self.points.pop();
+        }
+        self.points.push(point);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Invalid camera path in {}", path.display()))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Unable to save camera path to {}", path.display()))
+    }
+
+    /// Seconds between the first and the last point
+    pub fn duration(&self) -> f32 {
+        match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// Pose at the given number of seconds after the first point, interpolated
+    /// between the keyframes, `None` for an empty path
+    pub fn sample(&self, time: f32) -> Option<CameraPathPoint> {
+        let first = self.points.first()?;
+        let time = first.time + time;
+        let next = self.points.partition_point(|point| point.time <= time);
+
+        Some(match next {
+            0 => *first,
+            next if next == self.points.len() => self.points[next - 1],
+            next => {
+                let (from, to) = (&self.points[next - 1], &self.points[next]);
+                from.lerp(to, (time - from.time) / (to.time - from.time))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    fn point(time: f32, yaw: f32) -> CameraPathPoint {
+        CameraPathPoint {
+            time,
+            location: GeoCoord::new(49.0 + time, 20.0),
+            altitude: 1000.0 * time,
+            yaw,
+            pitch: 0.0,
+            fov: 45.0,
+            easing: Easing::Linear,
+            lift: 0.0,
+        }
+    }
+
+    #[test]
+    fn samples_interpolate_between_points() {
+        let path = CameraPath {
+            points: vec![point(1.0, 350.0), point(2.0, 10.0), point(4.0, 90.0)],
+        };
+
+        assert_eq!(path.duration(), 3.0);
+        assert_eq!(path.sample(-1.0), Some(point(1.0, 350.0)));
+        assert_eq!(path.sample(5.0), Some(point(4.0, 90.0)));
+
+        let sample = path.sample(0.5).unwrap();
+        assert_abs_diff_eq!(sample.time, 1.5);
+        assert_abs_diff_eq!(sample.altitude, 1500.0);
+        assert_abs_diff_eq!(sample.location.latitude, 50.5);
+        // through north rather than all the way around
+        assert_abs_diff_eq!(sample.yaw, 0.0, epsilon = 1e-3);
+
+        assert_abs_diff_eq!(path.sample(2.0).unwrap().yaw, 50.0, epsilon = 1e-3);
+        assert_eq!(CameraPath::default().sample(0.0), None);
+    }
+
+    #[test]
+    fn recorded_pose_round_trips_through_camera() {
+        let mut camera = Camera::default();
+        camera.reset(GeoCoord::new(49.179, 20.088), 2000.0);
+        camera.set_azimuth(150.0);
+        camera.set_pitch(-5f32.to_radians());
+
+        let mut path = CameraPath::default();
+        path.record(&camera, 0.0);
+        let mut replayed = Camera::default();
+        path.sample(0.0).unwrap().apply(&mut replayed);
+
+        assert!(replayed.eye.abs_diff_eq(camera.eye, 1.0));
+        assert!(replayed.direction().abs_diff_eq(camera.direction(), 1e-3));
+        assert_abs_diff_eq!(replayed.fov_y(), camera.fov_y());
+    }
+
+    #[test]
+    fn eased_transition_arcs_between_points() {
+        let from = point(0.0, 0.0);
+        let path = CameraPath::fly_to(from, GeoCoord::new(49.0, 30.0), 1000.0, None);
+        let [_, to] = path.points.as_slice() else {
+            panic!("Expected two points");
+        };
+
+        assert_abs_diff_eq!(path.duration(), MAX_FLY_TO_DURATION);
+        assert_eq!(to.easing, Easing::EaseInOut);
+        assert_abs_diff_eq!(to.lift, MAX_FLY_TO_LIFT);
+
+        // slow at the start, at the top of the arc halfway through
+        let start = path.sample(1.0).unwrap();
+        assert!(start.location.longitude - 20.0 < 0.5);
+        let middle = path.sample(5.0).unwrap();
+        assert_abs_diff_eq!(middle.altitude, 500.0 + MAX_FLY_TO_LIFT, epsilon = 1e-1);
+        assert_abs_diff_eq!(middle.location.longitude, 25.0, epsilon = 1e-3);
+        assert!(middle.location.latitude > 49.0);
+        assert_eq!(
+            path.sample(10.0).unwrap().location,
+            GeoCoord::new(49.0, 30.0)
+        );
+
+        let json = serde_json::to_string(&path).unwrap();
+        assert_eq!(serde_json::from_str::<CameraPath>(&json).unwrap(), path);
+        // easing and lift are optional
+        let point: CameraPathPoint = serde_json::from_str(
+            r#"{"time":0,"location":{"latitude":49,"longitude":20},"altitude":0,"yaw":0,"pitch":0,"fov":45}"#,
+        )
+        .unwrap();
+        assert_eq!((point.easing, point.lift), (Easing::Linear, 0.0));
+    }
+}
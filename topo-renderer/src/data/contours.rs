@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use glam::Vec3;
+
+use crate::{
+    data::height_map::HeightMap,
+    render::{
+        geometry::transform,
+        text_renderer::{LINE_HEIGHT, LabelLayout},
+    },
+};
+
+/// Height map cells along each side of the squares that get at most one anchor per contour
+pub const ANCHOR_SPACING: u32 = 40;
+/// Least distance (in pixels) between the centers of two contour labels
+pub const LABEL_SPACING: f32 = 150.0;
+pub const MAX_LABELS: usize = 32;
+
+/// Point on an index contour where its elevation can be labeled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ContourAnchor {
+    pub position: Vec3,
+    /// Meters above sea level
+    pub elevation: f32,
+}
+
+/// Elevation label of a contour on the screen, centered on its anchor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ContourLabelLayout {
+    /// Meters above sea level
+    pub elevation: i32,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+}
+
+impl ContourLabelLayout {
+    pub fn get_top_left(&self) -> (f32, f32) {
+        (self.x - 0.5 * self.width, self.y - 0.5 * LINE_HEIGHT)
+    }
+
+    fn overlaps(&self, label: &LabelLayout) -> bool {
+        let (left, top) = self.get_top_left();
+        left < label.label_x + label.label_width
+            && label.label_x < left + self.width
+            && top < label.label_y + LINE_HEIGHT
+            && label.label_y < top + LINE_HEIGHT
+    }
+}
+
+/// Points where the contours at multiples of the interval cross the edges between
+/// the height map's cells, at most one per contour within [`ANCHOR_SPACING`] cells for
+/// each of the four directions the slope can face, so that some of them are in view
+pub fn contour_anchors(height_map: &HeightMap, interval: f32) -> Vec<ContourAnchor> {
+    if interval <= 0.0 {
+        return vec![];
+    }
+
+    let (width, height) = height_map.get_size();
+    let coordinate_transform = height_map.get_coordinate_transform();
+    let finite_height = |position| {
+        height_map
+            .get_raster_height(position)
+            .filter(|height| height.is_finite())
+    };
+
+    let mut anchored = HashSet::new();
+    let mut anchors = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let Some(start) = finite_height((x, y)) else {
+                continue;
+            };
+            for (dx, dy) in [(1, 0), (0, 1)] {
+                let Some(end) = finite_height((x + dx, y + dy)) else {
+                    continue;
+                };
+                // the levels above the lower end up to the higher one
+                let mut level = (start.min(end) / interval).floor() * interval + interval;
+                while level <= start.max(end) {
+                    let contour = (level / interval).round() as i64;
+                    let facing = (dx, end > start);
+                    if anchored.insert((x / ANCHOR_SPACING, y / ANCHOR_SPACING, contour, facing)) {
+                        let t = (level - start) / (end - start);
+                        let (longitude, latitude) = coordinate_transform
+                            .to_model((x as f32 + t * dx as f32, y as f32 + t * dy as f32));
+                        anchors.push(ContourAnchor {
+                            position: transform(level, longitude, latitude),
+                            elevation: level,
+                        });
+                    }
+                    level += interval;
+                }
+            }
+        }
+    }
+
+    anchors
+}
+
+/// Picks the labels to show out of the ones at the visible anchors, in the order of preference,
+/// keeping them apart from each other and off the peak labels
+pub fn layout_contour_labels(
+    candidates: impl IntoIterator<Item = ContourLabelLayout>,
+    peak_labels: &[LabelLayout],
+) -> Vec<ContourLabelLayout> {
+    let mut laid_out: Vec<ContourLabelLayout> = vec![];
+    for candidate in candidates {
+        if laid_out.len() >= MAX_LABELS {
+            break;
+        }
+
+        let crowded = laid_out
+            .iter()
+            .any(|label| (label.x - candidate.x).hypot(label.y - candidate.y) < LABEL_SPACING);
+        if !crowded && !peak_labels.iter().any(|label| candidate.overlaps(label)) {
+            laid_out.push(candidate);
+        }
+    }
+
+    laid_out
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use tiff::decoder::DecodingResult;
+    use topo_common::GeoLocation;
+
+    use crate::{
+        common::coordinate_transform::CoordinateTransform,
+        data::camera::ViewMode,
+        headless::tests::{gbuffer_pixels, render_start_view, test_settings, with_test_renderer},
+        render::{geometry::inverse_transform, text_renderer::LabelId},
+        settings::{ContourSettings, RendererSettings},
+    };
+
+    use super::*;
+
+    #[test]
+    fn anchors_follow_the_contours() {
+        // rising by 100m per cell to the east, 80 cells wide and tall
+        let size = 80;
+        let heights = (0..size * size)
+            .map(|i| (i % size) as f32 * 100.0)
+            .collect();
+        let height_map = HeightMap::new(
+            DecodingResult::F32(heights),
+            CoordinateTransform {
+                raster_point: (0.0, 0.0),
+                model_point: (20.0, 50.0),
+                pixel_scale: (0.01, 0.01),
+            },
+            (size, size),
+        );
+
+        let anchors = contour_anchors(&height_map, 250.0);
+
+        // 31 contours between 250m and 7750m, each in both squares along its course,
+        // all of them facing west
+        assert_eq!(anchors.len(), 31 * 2);
+        let (elevation, coord) = inverse_transform(anchors[0].position);
+        assert_eq!(anchors[0].elevation, 250.0);
+        assert_abs_diff_eq!(elevation, 250.0, epsilon = 0.1);
+        assert_abs_diff_eq!(coord.longitude, 20.025, epsilon = 1e-4);
+        assert_abs_diff_eq!(coord.latitude, 50.0, epsilon = 1e-4);
+        assert!(contour_anchors(&height_map, 0.0).is_empty());
+    }
+
+    fn label(elevation: i32, (x, y): (f32, f32)) -> ContourLabelLayout {
+        ContourLabelLayout {
+            elevation,
+            x,
+            y,
+            width: 30.0,
+        }
+    }
+
+    #[test]
+    fn labels_keep_apart() {
+        let peak_label = LabelLayout {
+            location: GeoLocation::from_coord(49, 20),
+            id: LabelId(0),
+            label_x: 500.0,
+            label_y: 10.0,
+            label_width: 80.0,
+            peak_x: 520.0,
+            peak_y: 300.0,
+        };
+        let candidates = [
+            label(1500, (100.0, 400.0)),
+            label(2000, (150.0, 420.0)),
+            label(2000, (400.0, 400.0)),
+            label(1500, (530.0, 20.0)),
+        ];
+
+        let laid_out = layout_contour_labels(candidates, &[peak_label]);
+
+        assert_eq!(laid_out, [candidates[0], candidates[2]]);
+    }
+
+    #[test]
+    fn contour_lines_cross_the_slopes_with_labels() {
+        let settings = RendererSettings {
+            start_pitch: Some(-25.0),
+            view_mode: ViewMode::Slope,
+            // the plain at 500m is between the contours, the sides are crossed by them
+            contours: ContourSettings {
+                interval: 300.0,
+                color: [0, 0, 255],
+                ..Default::default()
+            },
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, _| {
+            let (image, gbuffer) = render_start_view(renderer).await;
+            let labels = renderer.engine_mut().get_contour_labels().to_vec();
+
+            let blue_pixels = |elevations: std::ops::Range<f32>| {
+                gbuffer_pixels(&image, &gbuffer)
+                    .filter(|pixel| {
+                        elevations.contains(&pixel.elevation)
+                            && pixel.color[2] as i32 > pixel.color[0] as i32 + 50
+                    })
+                    .count()
+            };
+            assert_eq!(blue_pixels(480.0..520.0), 0);
+            assert!(blue_pixels(800.0..2200.0) > 0);
+
+            // the index contour at 1500m gets labeled on the block's side
+            assert!(!labels.is_empty());
+            assert!(labels.iter().all(|label| label.elevation == 1500));
+        });
+    }
+}
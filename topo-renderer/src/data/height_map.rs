@@ -0,0 +1,100 @@
+use tiff::decoder::DecodingResult;
+use topo_common::GeoCoord;
+
+use crate::common::coordinate_transform::{CoordinateTransform, get_height_value_at};
+
+/// Heights of a loaded tile, kept on the cpu to sample the terrain below the camera
+#[derive(Debug)]
+pub struct HeightMap {
+    heights: DecodingResult,
+    coordinate_transform: CoordinateTransform,
+    size: (u32, u32),
+}
+
+impl HeightMap {
+    pub fn new(
+        heights: DecodingResult,
+        coordinate_transform: CoordinateTransform,
+        size: (u32, u32),
+    ) -> Self {
+        Self {
+            heights,
+            coordinate_transform,
+            size,
+        }
+    }
+
+    /// Meters above sea level, `None` outside of the tile
+    pub fn get_height(&self, coord: GeoCoord) -> Option<f32> {
+        let (x, y) = self
+            .coordinate_transform
+            .to_raster((coord.longitude, coord.latitude));
+        if x < 0.0 || y < 0.0 || x >= self.size.0 as f32 || y >= self.size.1 as f32 {
+            return None;
+        }
+
+        get_height_value_at(
+            &self.heights,
+            &self.coordinate_transform,
+            self.size,
+            coord.longitude as f64,
+            coord.latitude as f64,
+        )
+    }
+
+    /// Meters above sea level at the raster position, `None` outside of the tile
+    pub fn get_raster_height(&self, (x, y): (u32, u32)) -> Option<f32> {
+        if x >= self.size.0 || y >= self.size.1 {
+            return None;
+        }
+
+        let index = y as usize * self.size.0 as usize + x as usize;
+        match &self.heights {
+            DecodingResult::F32(vec) => vec.get(index).copied(),
+            DecodingResult::F64(vec) => vec.get(index).map(|height| *height as f32),
+            _ => None,
+        }
+    }
+
+    /// Raw heights as uploaded to the gpu
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.heights {
+            DecodingResult::F32(vec) => bytemuck::cast_slice(vec),
+            DecodingResult::F64(vec) => bytemuck::cast_slice(vec),
+            _ => &[],
+        }
+    }
+
+    pub fn get_coordinate_transform(&self) -> &CoordinateTransform {
+        &self.coordinate_transform
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heights_are_sampled_within_the_tile() {
+        let coordinate_transform = CoordinateTransform {
+            raster_point: (0.0, 0.0),
+            model_point: (20.0, 50.0),
+            pixel_scale: (0.5, 0.5),
+        };
+        let height_map = HeightMap::new(
+            DecodingResult::F32(vec![1.0, 2.0, 3.0, 4.0]),
+            coordinate_transform,
+            (2, 2),
+        );
+
+        assert_eq!(height_map.get_height(GeoCoord::new(49.9, 20.1)), Some(1.0));
+        assert_eq!(height_map.get_height(GeoCoord::new(49.9, 20.6)), Some(2.0));
+        assert_eq!(height_map.get_height(GeoCoord::new(49.1, 20.9)), Some(4.0));
+        assert_eq!(height_map.get_height(GeoCoord::new(49.9, 19.9)), None);
+        assert_eq!(height_map.get_height(GeoCoord::new(48.9, 20.1)), None);
+    }
+}
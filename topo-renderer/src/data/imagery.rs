@@ -0,0 +1,377 @@
+use std::{collections::HashMap, f64::consts::PI, io::Cursor};
+
+use color_eyre::{Result, eyre::eyre};
+use image::{
+    Rgba, RgbaImage,
+    imageops::{FilterType, resize},
+};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tiff::{
+    ColorType,
+    decoder::{Decoder, DecodingResult},
+};
+use topo_common::{GeoCoord, GeoLocation};
+
+use crate::common::coordinate_transform::CoordinateTransform;
+
+/// Where the imagery draped on the terrain comes from
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, EnumString, Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ImagerySource {
+    /// Untextured terrain
+    #[default]
+    None,
+    /// A georeferenced RGB(A) GeoTIFF per tile, see [`GeoLocation::imagery_file_path`]
+    GeoTiff,
+    /// Web Mercator tiles, e.g. from a tile server, reprojected onto each tile
+    Xyz,
+}
+
+/// Orthophotos, satellite images or maps draped on the terrain
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ImagerySettings {
+    pub source: ImagerySource,
+    /// Zoom level of the XYZ tiles
+    pub zoom: u8,
+    /// Path of the XYZ tiles with `{z}`, `{x}` and `{y}` placeholders, relative to the data
+    /// directory or the backend unless it's a full url (e.g. of a tile server)
+    pub tile_url: String,
+    /// Texels along each side of a tile's reprojected XYZ imagery
+    pub resolution: u32,
+    /// 0 lights the imagery like the untextured terrain, 1 shows it as it is,
+    /// as the photos have their own shadows
+    pub blend: f32,
+}
+
+impl Default for ImagerySettings {
+    fn default() -> Self {
+        Self {
+            source: ImagerySource::None,
+            zoom: 11,
+            tile_url: "imagery/tiles/{z}/{x}/{y}.png".to_string(),
+            resolution: 2048,
+            blend: 0.5,
+        }
+    }
+}
+
+impl ImagerySettings {
+    pub fn get_tile_url(&self, (x, y): (u32, u32)) -> String {
+        self.tile_url
+            .replace("{z}", &self.zoom.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string())
+    }
+}
+
+/// Raster imagery with the same georeferencing as the height maps
+#[derive(Clone, Debug, PartialEq)]
+pub struct Imagery {
+    pub image: RgbaImage,
+    pub coordinate_transform: CoordinateTransform,
+}
+
+impl Imagery {
+    /// Decodes an 8 bit RGB or RGBA GeoTIFF with `ModelPixelScaleTag` and `ModelTiepointTag`
+    pub fn from_geotiff(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(Cursor::new(bytes))?;
+        let coordinate_transform = CoordinateTransform::from_decoder(&mut decoder)?;
+        let (width, height) = decoder.dimensions()?;
+        let color_type = decoder.colortype()?;
+        let DecodingResult::U8(data) = decoder.read_image()? else {
+            return Err(eyre!("Expected 8 bit imagery, got {color_type:?}"));
+        };
+
+        let pixels = match color_type {
+            ColorType::RGBA(8) => data,
+            ColorType::RGB(8) => data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            ColorType::Gray(8) => data.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            _ => return Err(eyre!("Unsupported imagery color type {color_type:?}")),
+        };
+        let image = RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| eyre!("Imagery data doesn't match its {width}x{height} size"))?;
+
+        Ok(Self {
+            image,
+            coordinate_transform,
+        })
+    }
+
+    /// The image and its halved versions down to a single texel, the first one scaled
+    /// down to fit within `max_size` texels
+    pub fn mip_chain(&self, max_size: u32) -> Vec<RgbaImage> {
+        let (width, height) = self.image.dimensions();
+        let scale = (max_size as f32 / width.max(height) as f32).min(1.0);
+        let first = if scale < 1.0 {
+            let size = |length: u32| ((length as f32 * scale) as u32).max(1);
+            resize(&self.image, size(width), size(height), FilterType::Triangle)
+        } else {
+            self.image.clone()
+        };
+
+        std::iter::successors(Some(first), |level| {
+            let (width, height) = level.dimensions();
+            (width > 1 || height > 1).then(|| {
+                resize(
+                    level,
+                    (width / 2).max(1),
+                    (height / 2).max(1),
+                    FilterType::Triangle,
+                )
+            })
+        })
+        .collect()
+    }
+
+    /// Resamples the Web Mercator tiles (by their x and y) covering the location onto its
+    /// latitude and longitude grid, the parts without a tile stay transparent
+    pub fn from_xyz_tiles(
+        location: GeoLocation,
+        zoom: u8,
+        resolution: u32,
+        tiles: &HashMap<(u32, u32), RgbaImage>,
+    ) -> Self {
+        let GeoCoord {
+            latitude: south,
+            longitude: west,
+        } = location.into();
+        let pixel_scale = 1.0 / resolution as f64;
+
+        let image = RgbaImage::from_fn(resolution, resolution, |i, j| {
+            let longitude = west as f64 + (i as f64 + 0.5) * pixel_scale;
+            let latitude = south as f64 + 1.0 - (j as f64 + 0.5) * pixel_scale;
+            let (x, y) = web_mercator_tile(latitude, longitude, zoom);
+            let tile = (x.floor() as u32, y.floor() as u32);
+            let Some(tile_image) = tiles.get(&tile) else {
+                return Rgba([0, 0, 0, 0]);
+            };
+            let (width, height) = tile_image.dimensions();
+            *tile_image.get_pixel(
+                ((x.fract() * width as f64) as u32).min(width - 1),
+                ((y.fract() * height as f64) as u32).min(height - 1),
+            )
+        });
+
+        Self {
+            image,
+            coordinate_transform: CoordinateTransform {
+                raster_point: (0.0, 0.0),
+                model_point: (west, south + 1.0),
+                pixel_scale: (pixel_scale as f32, pixel_scale as f32),
+            },
+        }
+    }
+}
+
+/// Fractional x and y of the Web Mercator tile at the zoom level the point is on
+pub fn web_mercator_tile(latitude: f64, longitude: f64, zoom: u8) -> (f64, f64) {
+    let tiles = (1u32 << zoom) as f64;
+    let latitude = latitude.to_radians();
+    (
+        (longitude + 180.0) / 360.0 * tiles,
+        (1.0 - latitude.tan().asinh() / PI) / 2.0 * tiles,
+    )
+}
+
+/// Web Mercator tiles (x and y) covering the location at the zoom level
+pub fn xyz_tiles_covering(location: GeoLocation, zoom: u8) -> Vec<(u32, u32)> {
+    let GeoCoord {
+        latitude: south,
+        longitude: west,
+    } = location.into();
+    let max_tile = (1u32 << zoom) - 1;
+    // the y axis points south
+    let (min_x, min_y) = web_mercator_tile(south as f64 + 1.0, west as f64, zoom);
+    let (max_x, max_y) = web_mercator_tile(south as f64, west as f64 + 1.0, zoom);
+    let xs = min_x.floor() as u32..=(max_x.ceil() as u32 - 1).min(max_tile);
+    let ys = min_y.floor() as u32..=(max_y.ceil() as u32 - 1).min(max_tile);
+    ys.flat_map(|y| xs.clone().map(move |x| (x, y))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use approx::assert_abs_diff_eq;
+    use itertools::Itertools;
+    use tiff::{encoder::TiffEncoder, encoder::colortype::RGB8, tags::Tag};
+
+    use crate::{
+        app::ApplicationSettings,
+        headless::tests::{gbuffer_pixels, render_start_view, test_settings, with_test_renderer},
+        settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[test]
+    fn finds_web_mercator_tiles() {
+        // the Tatras, on the OpenStreetMap tile 11/1138/701
+        let (x, y) = web_mercator_tile(49.179, 20.088, 11);
+        assert_eq!((x.floor(), y.floor()), (1138.0, 701.0));
+
+        let tiles = xyz_tiles_covering(GeoLocation::from_coord(49, 20), 11);
+        assert_eq!(tiles.first(), Some(&(1137, 694)));
+        assert_eq!(tiles.last(), Some(&(1143, 703)));
+        assert_eq!(tiles.len(), 7 * 10);
+    }
+
+    #[test]
+    fn reprojects_xyz_tiles_onto_the_location() {
+        let location = GeoLocation::from_coord(49, 20);
+        let tiles = xyz_tiles_covering(location, 8)
+            .into_iter()
+            .filter(|(_, y)| *y == 86)
+            .map(|(x, y)| {
+                let image = RgbaImage::from_pixel(4, 4, Rgba([x as u8, y as u8, 0, 255]));
+                ((x, y), image)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let imagery = Imagery::from_xyz_tiles(location, 8, 64, &tiles);
+
+        assert_eq!(imagery.image.dimensions(), (64, 64));
+        assert_eq!(
+            imagery.coordinate_transform.to_model((0.0, 0.0)),
+            (20.0, 50.0)
+        );
+        // the tile 8/142/86 spans 19.6875° to 21.09375° east and 49.838° to 50.737° north
+        assert_eq!(imagery.image.get_pixel(0, 0), &Rgba([142, 86, 0, 255]));
+        assert_eq!(imagery.image.get_pixel(63, 9), &Rgba([142, 86, 0, 255]));
+        assert_eq!(imagery.image.get_pixel(0, 10).0[3], 0);
+    }
+
+    #[test]
+    fn decodes_rgb_geotiff() {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+        let mut image = encoder.new_image::<RGB8>(2, 1).unwrap();
+        image
+            .encoder()
+            .write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.25, 0.0][..])
+            .unwrap();
+        image
+            .encoder()
+            .write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, 20.0, 50.0, 0.0][..])
+            .unwrap();
+        image.write_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+
+        let imagery = Imagery::from_geotiff(bytes.get_ref()).unwrap();
+
+        assert_eq!(imagery.image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(imagery.image.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+        let (longitude, latitude) = imagery.coordinate_transform.to_model((2.0, 1.0));
+        assert_abs_diff_eq!(longitude, 21.0);
+        assert_abs_diff_eq!(latitude, 49.75);
+    }
+
+    #[test]
+    fn halves_the_mip_levels_down_to_a_texel() {
+        let imagery = Imagery {
+            image: RgbaImage::from_pixel(300, 100, Rgba([10, 20, 30, 255])),
+            coordinate_transform: CoordinateTransform {
+                raster_point: (0.0, 0.0),
+                model_point: (20.0, 50.0),
+                pixel_scale: (1.0 / 300.0, 1.0 / 100.0),
+            },
+        };
+
+        let sizes = imagery
+            .mip_chain(256)
+            .iter()
+            .map(|level| level.dimensions())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sizes,
+            [
+                (256, 85),
+                (128, 42),
+                (64, 21),
+                (32, 10),
+                (16, 5),
+                (8, 2),
+                (4, 1),
+                (2, 1),
+                (1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_in_tile_urls() {
+        let settings = ImagerySettings {
+            tile_url: "https://tile.example.com/{z}/{x}/{y}.png".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.get_tile_url((1138, 706)),
+            "https://tile.example.com/11/1138/706.png"
+        );
+    }
+
+    #[test]
+    fn drapes_imagery_on_the_terrain() {
+        let settings = RendererSettings {
+            application: ApplicationSettings {
+                imagery: ImagerySettings {
+                    source: ImagerySource::GeoTiff,
+                    blend: 1.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            start_location: GeoCoord::new(49.42, 20.5),
+            view_distance: 15_000.0,
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, data_dir| {
+            // red imagery covering the northern half of the tile, beyond the block
+            let location = GeoLocation::from(GeoCoord::new(49.5, 20.5));
+            let imagery_path = data_dir.join(location.imagery_file_path());
+            fs::create_dir_all(imagery_path.parent().unwrap()).unwrap();
+            let mut encoder = TiffEncoder::new(fs::File::create(imagery_path).unwrap()).unwrap();
+            let mut image = encoder.new_image::<RGB8>(64, 32).unwrap();
+            image
+                .encoder()
+                .write_tag(Tag::ModelPixelScaleTag, &[1.0 / 64.0, 0.5 / 32.0, 0.0][..])
+                .unwrap();
+            image
+                .encoder()
+                .write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, 20.0, 50.0, 0.0][..])
+                .unwrap();
+            image.write_data(&[255, 0, 0].repeat(32 * 64)).unwrap();
+
+            let (image, gbuffer) = render_start_view(renderer).await;
+
+            let plain = |north: bool| {
+                gbuffer_pixels(&image, &gbuffer)
+                    .filter(|pixel| {
+                        (480.0..520.0).contains(&pixel.elevation)
+                            && (1_000.0..14_000.0).contains(&pixel.distance)
+                            && (pixel.latitude - 49.5).abs() > 0.005
+                            && (pixel.latitude > 49.5) == north
+                    })
+                    .map(|pixel| pixel.color)
+                    .collect_vec()
+            };
+
+            // the unlit imagery shows as it is, the rest of the tile stays untextured
+            let is_red = |pixel: &&[u8; 3]| pixel[0] >= 240 && pixel[1] <= 16 && pixel[2] <= 16;
+            let north = plain(true);
+            assert!(north.iter().filter(is_red).count() > north.len() * 9 / 10);
+            let south = plain(false);
+            assert!(!south.is_empty());
+            assert!(south.iter().all(|pixel| pixel[0] < pixel[1] + 32));
+        });
+    }
+}
@@ -1,6 +1,14 @@
 pub mod application_data;
 pub mod camera;
+pub mod camera_path;
+pub mod contours;
+pub mod height_map;
+pub mod imagery;
 pub mod peak;
+pub mod refraction;
+pub mod sun;
+pub mod terrain_coloring;
+pub mod water;
 
 use winit::dpi::{PhysicalSize, Pixel};
 
@@ -47,4 +55,5 @@ where
 pub struct DepthState {
     pub size: Size<u32>,
     pub camera: Camera,
+    pub scene_version: u64,
 }
@@ -0,0 +1,195 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::render::geometry::R0;
+
+#[derive(Error, Debug, PartialEq)]
+#[error(
+    "Unknown refraction {0}, expected standard, none, strong_inversion or a refraction coefficient"
+)]
+pub struct RefractionError(String);
+
+/// How much the air bends the light rays towards the ground, written as the name
+/// of a preset or as the refraction coefficient, i.e. the ratio of the curvature
+/// of the rays to that of the earth
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Refraction {
+    /// The standard atmosphere, k = 0.13
+    #[default]
+    Standard,
+    /// Straight rays
+    None,
+    /// Cold air near the ground, like above a snow cover or a lake, k = 0.5
+    StrongInversion,
+    Coefficient(f32),
+}
+
+impl Refraction {
+    const PRESETS: [(Refraction, &str); 3] = [
+        (Refraction::Standard, "standard"),
+        (Refraction::None, "none"),
+        (Refraction::StrongInversion, "strong_inversion"),
+    ];
+
+    pub fn coefficient(self) -> f32 {
+        match self {
+            Refraction::Standard => 0.13,
+            Refraction::None => 0.0,
+            Refraction::StrongInversion => 0.5,
+            Refraction::Coefficient(coefficient) => coefficient,
+        }
+    }
+}
+
+impl FromStr for Refraction {
+    type Err = RefractionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some((preset, _)) = Self::PRESETS.iter().find(|(_, name)| *name == s) {
+            return Ok(*preset);
+        }
+        s.parse()
+            .ok()
+            .filter(|coefficient: &f32| coefficient.is_finite())
+            .map(Refraction::Coefficient)
+            .ok_or_else(|| RefractionError(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for Refraction {
+    type Error = RefractionError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Refraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Self::PRESETS.iter().find(|(preset, _)| preset == self) {
+            Some((_, name)) => write!(f, "{name}"),
+            None => write!(f, "{}", self.coefficient()),
+        }
+    }
+}
+
+impl From<Refraction> for String {
+    fn from(refraction: Refraction) -> Self {
+        refraction.to_string()
+    }
+}
+
+/// Height (in meters) by which the terrain seen from the given distance (in meters)
+/// appears raised, as the refracted rays curve along with the earth
+pub fn refraction_lift(distance: f32, coefficient: f32) -> f32 {
+    coefficient * distance * distance / (2.0 * R0)
+}
+
+/// Where the point appears to be when looking from the eye, raised by [`refraction_lift`]
+pub fn apparent_position(point: Vec3, eye: Vec3, coefficient: f32) -> Vec3 {
+    point + point.normalize() * refraction_lift(point.distance(eye), coefficient)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use itertools::Itertools;
+    use rstest::rstest;
+
+    use crate::{
+        headless::tests::{gbuffer_pixels, render_start_view, test_settings, with_test_renderer},
+        render::geometry::{inverse_transform, transform},
+        settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[rstest]
+    #[case("standard", Refraction::Standard)]
+    #[case("none", Refraction::None)]
+    #[case("strong_inversion", Refraction::StrongInversion)]
+    #[case("0.25", Refraction::Coefficient(0.25))]
+    #[case(" -0.1 ", Refraction::Coefficient(-0.1))]
+    fn parses_presets_and_coefficients(#[case] input: &str, #[case] expected: Refraction) {
+        assert_eq!(input.parse(), Ok(expected));
+        assert_eq!(expected.to_string().parse(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case("inversion")]
+    #[case("NaN")]
+    #[case("")]
+    fn rejects_unknown_refraction(#[case] input: &str) {
+        assert_eq!(
+            input.parse::<Refraction>(),
+            Err(RefractionError(input.trim().to_owned()))
+        );
+    }
+
+    #[test]
+    fn raises_distant_points() {
+        // the Tatras seen from Budapest, 200 km away
+        let eye = transform(300.0, 19.04, 47.5);
+        let point = transform(2655.0, 20.21, 49.18);
+        let distance = point.distance(eye);
+        assert_abs_diff_eq!(distance, 206_000.0, epsilon = 1000.0);
+
+        let (altitude, coord) = inverse_transform(apparent_position(
+            point,
+            eye,
+            Refraction::Standard.coefficient(),
+        ));
+        // the earth's curvature hides 3330 m of the peak, the refraction brings back 13% of it
+        assert_abs_diff_eq!(altitude, 2655.0 + 433.0, epsilon = 5.0);
+        assert_abs_diff_eq!(coord.latitude, 49.18, epsilon = 1e-4);
+        assert_abs_diff_eq!(coord.longitude, 20.21, epsilon = 1e-4);
+
+        assert_eq!(
+            apparent_position(point, eye, Refraction::None.coefficient()),
+            point
+        );
+    }
+
+    #[test]
+    fn refraction_raises_terrain_and_labels_alike() {
+        let settings = RendererSettings {
+            // exaggerated, so that the peak 3.3 km away gets raised by 43 m
+            refraction: Refraction::Coefficient(50.0),
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, _| {
+            let (image, gbuffer) = render_start_view(renderer).await;
+
+            // the plain, raised by 35 to 98 m, is at its real elevation once the lift is taken away
+            let plain = gbuffer_pixels(&image, &gbuffer)
+                .filter(|pixel| {
+                    (3_000.0..5_000.0).contains(&pixel.distance)
+                        && (pixel.longitude - 20.5).abs() > 0.03
+                })
+                .map(|pixel| pixel.elevation)
+                .collect_vec();
+            assert!(!plain.is_empty());
+            for elevation in plain {
+                assert_abs_diff_eq!(elevation, 500.0, epsilon = 20.0);
+            }
+            // the label is tested against the raised peak
+            assert!(
+                renderer
+                    .data()
+                    .peaks
+                    .values()
+                    .flatten()
+                    .any(|peak| peak.visible && peak.name == "Test Peak")
+            );
+        });
+    }
+}
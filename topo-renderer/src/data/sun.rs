@@ -0,0 +1,296 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(target_arch = "wasm32")]
+use web_time::Duration;
+
+use glam::{DVec3, Vec3};
+use jiff::{SignedDuration, Timestamp};
+use topo_common::GeoCoord;
+
+use crate::{
+    render::geometry::{north_east, transform},
+    settings::SunSettings,
+};
+
+/// Seconds between the Unix epoch and the J2000.0 epoch (2000-01-01 12:00 TT)
+const J2000_UNIX_SECONDS: f64 = 946_728_000.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// Radians the sun has to move by before the lighting gets updated,
+/// the earth turns by that much in about 14 seconds
+const MIN_DIRECTION_CHANGE: f32 = 0.001;
+
+/// Unit vector pointing from the earth towards the sun in world space, i.e. with the
+/// z axis pointing to the north pole and the x axis to the prime meridian
+///
+/// Uses the low precision formulas of the Astronomical Almanac, which are accurate
+/// to about 0.01° between 1950 and 2050
+pub fn sun_direction(time: Timestamp) -> Vec3 {
+    let days = (time.as_millisecond() as f64 / 1000.0 - J2000_UNIX_SECONDS) / SECONDS_PER_DAY;
+
+    let mean_longitude = 280.460 + 0.985_647_4 * days;
+    let mean_anomaly = (357.528 + 0.985_600_3 * days).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.000_000_4 * days).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal_time = (280.460_618_37 + 360.985_647_366_29 * days).to_radians();
+
+    // longitude of the point the sun is right above
+    let longitude = right_ascension - sidereal_time;
+    DVec3::new(
+        declination.cos() * longitude.cos(),
+        declination.cos() * longitude.sin(),
+        declination.sin(),
+    )
+    .as_vec3()
+}
+
+/// Where the sun appears in the sky from a place on the earth
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SunPosition {
+    /// Degrees clockwise from north
+    pub azimuth: f32,
+    /// Degrees above the horizon, negative at night
+    pub altitude: f32,
+}
+
+impl SunPosition {
+    pub fn new(time: Timestamp, coord: GeoCoord) -> Self {
+        let direction = sun_direction(time);
+        let up = transform(0.0, coord.longitude, coord.latitude).normalize();
+        let (north, east) = north_east(up);
+
+        Self {
+            azimuth: direction
+                .dot(east)
+                .atan2(direction.dot(north))
+                .to_degrees()
+                .rem_euclid(360.0),
+            altitude: direction.dot(up).clamp(-1.0, 1.0).asin().to_degrees(),
+        }
+    }
+}
+
+/// Clock driving the sun, it follows the real time unless the time-lapse speeds it up
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sun {
+    time: Timestamp,
+    /// Simulated seconds per real second while the time-lapse runs
+    time_lapse_speed: f64,
+    time_lapse: bool,
+    /// Direction the lighting was last updated with
+    direction: Vec3,
+}
+
+impl Sun {
+    pub fn new(settings: &SunSettings) -> Self {
+        let time = settings.time.unwrap_or_else(Timestamp::now);
+        Self {
+            time,
+            time_lapse_speed: settings.time_lapse_speed,
+            time_lapse: false,
+            direction: sun_direction(time),
+        }
+    }
+
+    pub fn get_time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: Timestamp) {
+        self.time = time;
+        self.direction = sun_direction(time);
+    }
+
+    /// Moves the clock forwards or, with a negative duration, backwards
+    pub fn shift(&mut self, duration: SignedDuration) {
+        self.set_time(self.time.checked_add(duration).unwrap_or(self.time));
+    }
+
+    /// Unit vector pointing towards the sun in world space, see [`sun_direction`]
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn position_at(&self, coord: GeoCoord) -> SunPosition {
+        SunPosition::new(self.time, coord)
+    }
+
+    pub fn is_time_lapse(&self) -> bool {
+        self.time_lapse
+    }
+
+    pub fn set_time_lapse(&mut self, time_lapse: bool) {
+        self.time_lapse = time_lapse;
+    }
+
+    pub fn set_time_lapse_speed(&mut self, speed: f64) {
+        self.time_lapse_speed = speed;
+    }
+
+    /// Advances the clock by the real time passed (sped up in time-lapse mode) and
+    /// returns whether the sun moved enough for the lighting to be updated
+    pub fn advance(&mut self, time_delta: Duration) -> bool {
+        let speed = if self.time_lapse {
+            self.time_lapse_speed
+        } else {
+            1.0
+        };
+        let step = SignedDuration::from_secs_f64(time_delta.as_secs_f64() * speed);
+        self.time = self.time.checked_add(step).unwrap_or(self.time);
+
+        let direction = sun_direction(self.time);
+        if direction.angle_between(self.direction) < MIN_DIRECTION_CHANGE {
+            return false;
+        }
+        self.direction = direction;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use approx::assert_abs_diff_eq;
+    use image::RgbaImage;
+    use itertools::Itertools;
+    use rstest::rstest;
+    use topo_common::GeoLocation;
+
+    use crate::{
+        export::gbuffer::GBuffer,
+        headless::tests::{
+            TILE_SIZE, gbuffer_pixels, render_start_view, test_settings, with_test_renderer,
+            write_test_dem,
+        },
+        settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[rstest]
+    // summer solstice noon in Greenwich
+    #[case("2024-06-21T12:00:00Z", GeoCoord::new(51.4769, 0.0), 179.3, 61.9)]
+    // dawn in the Tatras at the winter solstice, 07:10 CET
+    #[case("2024-12-21T06:10:00Z", GeoCoord::new(49.18, 20.09), 122.2, -3.8)]
+    // midnight sun north of the arctic circle
+    #[case("2024-06-21T22:00:00Z", GeoCoord::new(69.65, 18.96), 349.4, 3.5)]
+    fn position_in_the_sky(
+        #[case] time: Timestamp,
+        #[case] coord: GeoCoord,
+        #[case] azimuth: f32,
+        #[case] altitude: f32,
+    ) {
+        let position = SunPosition::new(time, coord);
+        assert_abs_diff_eq!(position.azimuth, azimuth, epsilon = 1.0);
+        assert_abs_diff_eq!(position.altitude, altitude, epsilon = 1.0);
+    }
+
+    #[test]
+    fn time_lapse_speeds_up_the_clock() {
+        let start: Timestamp = "2024-06-21T12:00:00Z".parse().unwrap();
+        let mut sun = Sun::new(&SunSettings {
+            time: Some(start),
+            time_lapse_speed: 600.0,
+        });
+
+        // the sun barely moves within a second
+        assert!(!sun.advance(Duration::from_secs(1)));
+        assert_eq!(sun.get_time(), start + SignedDuration::from_secs(1));
+
+        sun.set_time_lapse(true);
+        let direction = sun.direction();
+        assert!(sun.advance(Duration::from_secs(1)));
+        assert_eq!(sun.get_time(), start + SignedDuration::from_secs(601));
+        // 10 minutes turn the earth by 2.5°, which moves the sun 23.4° north of the equator by less
+        assert_abs_diff_eq!(
+            sun.direction().angle_between(direction).to_degrees(),
+            2.5 * 23.44_f32.to_radians().cos(),
+            epsilon = 0.1
+        );
+
+        sun.shift(SignedDuration::from_hours(-12));
+        assert!(sun.position_at(GeoCoord::new(51.4769, 0.0)).altitude < 0.0);
+    }
+
+    /// Mean brightness of the plain within the latitudes, either right north of the
+    /// 3 texels wide block at 20.5° of longitude, or beside that
+    fn mean_brightness(
+        image: &RgbaImage,
+        gbuffer: &GBuffer,
+        latitudes: Range<f32>,
+        behind_the_block: bool,
+    ) -> f32 {
+        let brightness = gbuffer_pixels(image, gbuffer)
+            .filter(|pixel| {
+                latitudes.contains(&pixel.latitude)
+                    && ((pixel.longitude - 20.5).abs() < 0.004) == behind_the_block
+                    && (pixel.longitude - 20.5).abs() < 0.05
+            })
+            .map(|pixel| pixel.color[1] as f32)
+            .collect_vec();
+        assert!(!brightness.is_empty());
+        brightness.iter().sum::<f32>() / brightness.len() as f32
+    }
+
+    #[rstest]
+    // the block in the middle of the test tile
+    #[case(None, GeoCoord::new(49.47, 20.5), 49.525..49.55)]
+    // a block at the northern edge of a tile, the shadow falls on the tile north of it
+    #[case(Some((48, TILE_SIZE)), GeoCoord::new(48.95, 20.5), 49.005..49.03)]
+    // which is narrower north of 50°
+    #[case(Some((49, TILE_SIZE * 2 / 3)), GeoCoord::new(49.95, 20.5), 50.005..50.03)]
+    fn terrain_casts_shadows_away_from_the_sun(
+        // latitude of the tile with the block at its northern edge, width of the tile north of it
+        #[case] edge_block: Option<(i32, u32)>,
+        #[case] location: GeoCoord,
+        #[case] shadow: Range<f32>,
+    ) {
+        let settings = RendererSettings {
+            start_location: location,
+            start_altitude: Some(12_000.0),
+            start_pitch: Some(-50.0),
+            sun: SunSettings {
+                // winter noon, the sun is 17° above the horizon in the south
+                time: Some("2024-12-21T10:36:00Z".parse().unwrap()),
+                ..Default::default()
+            },
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (128, 96).into(), async |renderer, data_dir| {
+            if let Some((latitude, north_width)) = edge_block {
+                let center = TILE_SIZE / 2;
+                write_test_dem(
+                    data_dir,
+                    GeoLocation::from_coord(latitude, 20),
+                    TILE_SIZE,
+                    |x, y| {
+                        if x.abs_diff(center) <= 1 && (1..=3).contains(&y) {
+                            2500.0
+                        } else {
+                            500.0
+                        }
+                    },
+                );
+                write_test_dem(
+                    data_dir,
+                    GeoLocation::from_coord(latitude + 1, 20),
+                    north_width,
+                    |_, _| 500.0,
+                );
+            }
+            let (image, gbuffer) = render_start_view(renderer).await;
+
+            // the 2000m high block casts a 6.5km long shadow to the north
+            let in_shadow = mean_brightness(&image, &gbuffer, shadow.clone(), true);
+            let sunlight = mean_brightness(&image, &gbuffer, shadow, false);
+            assert!(in_shadow < 0.5 * sunlight);
+        });
+    }
+}
@@ -0,0 +1,324 @@
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::data::camera::ViewMode;
+
+/// Stops the elevation ramp can have at most, the size of the array in the shader
+pub const MAX_ELEVATION_STOPS: usize = 8;
+
+/// Slope classes used in avalanche terrain assessment, as (lowest slope in degrees,
+/// color, label), see [`gpu_slope_classes`]
+pub const SLOPE_CLASSES: [(f32, [u8; 3], &str); 4] = [
+    (0.0, [235, 235, 235], "< 30°"),
+    (30.0, [250, 225, 40], "30–35°"),
+    (35.0, [245, 140, 30], "35–40°"),
+    (40.0, [215, 35, 35], "≥ 40°"),
+];
+
+/// Colors of the directions the slopes face, clockwise from north, see [`gpu_aspect_colors`]
+pub const ASPECT_COLORS: [([u8; 3], &str); 8] = [
+    ([230, 50, 50], "N"),
+    ([240, 160, 40], "NE"),
+    ([240, 230, 60], "E"),
+    ([90, 200, 70], "SE"),
+    ([60, 200, 200], "S"),
+    ([60, 140, 230], "SW"),
+    ([70, 60, 200], "W"),
+    ([190, 60, 200], "NW"),
+];
+
+/// Color of the ground too flat to face any direction
+pub const FLAT_COLOR: [u8; 3] = [160, 160, 160];
+/// Degrees, gentler slopes don't face any direction in the aspect view mode
+pub const FLAT_SLOPE: f32 = 2.0;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ColoringError {
+    #[error("The elevation ramp needs between 1 and {MAX_ELEVATION_STOPS} stops, got {0}")]
+    StopCount(usize),
+    #[error("The elevation stops have to be in ascending order, {1}m follows {0}m")]
+    Unordered(f32, f32),
+}
+
+/// Color (sRGB) of the terrain at the given elevation (in meters above sea level)
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ColorStop {
+    pub elevation: f32,
+    pub color: [u8; 3],
+}
+
+/// Colors the terrain gets with its elevation in the hypsometric and hillshade view modes,
+/// interpolated between the stops and clamped beyond the first and the last one
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "Vec<ColorStop>", into = "Vec<ColorStop>")]
+pub struct ElevationRamp(Vec<ColorStop>);
+
+impl ElevationRamp {
+    pub fn get_stops(&self) -> &[ColorStop] {
+        &self.0
+    }
+
+    /// Stops in the layout of the shader, the linear color and the elevation in `w`
+    pub fn gpu_stops(&self) -> [Vec4; MAX_ELEVATION_STOPS] {
+        let mut stops = [Vec4::ZERO; MAX_ELEVATION_STOPS];
+        for (stop, gpu_stop) in self.0.iter().zip(stops.iter_mut()) {
+            *gpu_stop = srgb_to_linear(stop.color).extend(stop.elevation);
+        }
+        stops
+    }
+}
+
+impl Default for ElevationRamp {
+    fn default() -> Self {
+        let stops = [
+            (0.0, [70, 130, 80]),
+            (500.0, [140, 180, 100]),
+            (1000.0, [225, 215, 140]),
+            (1500.0, [200, 150, 90]),
+            (2000.0, [150, 105, 75]),
+            (2500.0, [175, 165, 160]),
+            (3000.0, [250, 250, 250]),
+        ];
+        Self(
+            stops
+                .into_iter()
+                .map(|(elevation, color)| ColorStop { elevation, color })
+                .collect(),
+        )
+    }
+}
+
+impl TryFrom<Vec<ColorStop>> for ElevationRamp {
+    type Error = ColoringError;
+
+    fn try_from(stops: Vec<ColorStop>) -> Result<Self, Self::Error> {
+        if stops.is_empty() || stops.len() > MAX_ELEVATION_STOPS {
+            return Err(ColoringError::StopCount(stops.len()));
+        }
+        if let Some(pair) = stops
+            .windows(2)
+            .find(|pair| pair[1].elevation <= pair[0].elevation)
+        {
+            return Err(ColoringError::Unordered(
+                pair[0].elevation,
+                pair[1].elevation,
+            ));
+        }
+        Ok(Self(stops))
+    }
+}
+
+impl From<ElevationRamp> for Vec<ColorStop> {
+    fn from(ramp: ElevationRamp) -> Self {
+        ramp.0
+    }
+}
+
+/// Slope classes in the layout of the shader, the linear color and the lowest slope in `w`
+pub fn gpu_slope_classes() -> [Vec4; SLOPE_CLASSES.len()] {
+    SLOPE_CLASSES.map(|(slope, color, _)| srgb_to_linear(color).extend(slope))
+}
+
+/// Aspect colors in the layout of the shader, linear
+pub fn gpu_aspect_colors() -> [Vec4; ASPECT_COLORS.len()] {
+    ASPECT_COLORS.map(|(color, _)| srgb_to_linear(color).extend(0.0))
+}
+
+pub(crate) fn srgb_to_linear(color: [u8; 3]) -> Vec3 {
+    Vec3::from_array(color.map(|channel| {
+        let channel = channel as f32 / 255.0;
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    }))
+}
+
+/// Colors of a view mode with their meaning, for showing a key next to the view
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Legend {
+    pub title: String,
+    pub entries: Vec<LegendEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LegendEntry {
+    /// sRGB
+    pub color: [u8; 3],
+    pub label: String,
+}
+
+impl LegendEntry {
+    fn new(color: [u8; 3], label: impl Into<String>) -> Self {
+        Self {
+            color,
+            label: label.into(),
+        }
+    }
+}
+
+impl Legend {
+    /// Legend of the view mode's colors, `None` for the modes that don't encode anything in them
+    pub fn new(view_mode: ViewMode, ramp: &ElevationRamp) -> Option<Self> {
+        let elevation_entries = || {
+            ramp.get_stops()
+                .iter()
+                .map(|stop| LegendEntry::new(stop.color, format!("{:.0} m", stop.elevation)))
+                .collect()
+        };
+        let (title, entries) = match view_mode {
+            ViewMode::Default | ViewMode::Normals | ViewMode::Position => return None,
+            ViewMode::Hypsometric => ("Elevation", elevation_entries()),
+            ViewMode::Hillshade => ("Elevation with hillshade", elevation_entries()),
+            ViewMode::Slope => (
+                "Slope",
+                SLOPE_CLASSES
+                    .iter()
+                    .map(|(_, color, label)| LegendEntry::new(*color, *label))
+                    .collect(),
+            ),
+            ViewMode::Aspect => (
+                "Aspect",
+                ASPECT_COLORS
+                    .iter()
+                    .map(|(color, label)| LegendEntry::new(*color, *label))
+                    .chain([LegendEntry::new(FLAT_COLOR, "Flat")])
+                    .collect(),
+            ),
+        };
+        Some(Self {
+            title: title.to_owned(),
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use image::RgbaImage;
+    use itertools::Itertools;
+    use rstest::rstest;
+
+    use crate::{
+        headless::tests::{gbuffer_pixels, render_start_view, test_settings, with_test_renderer},
+        settings::{AtmosphereSettings, RendererSettings},
+    };
+
+    use super::*;
+
+    fn stop(elevation: f32) -> ColorStop {
+        ColorStop {
+            elevation,
+            color: [255, 128, 0],
+        }
+    }
+
+    #[rstest]
+    #[case(vec![], ColoringError::StopCount(0))]
+    #[case(vec![stop(0.0); 9], ColoringError::StopCount(9))]
+    #[case(vec![stop(0.0), stop(1000.0), stop(500.0)], ColoringError::Unordered(1000.0, 500.0))]
+    #[case(vec![stop(0.0), stop(0.0)], ColoringError::Unordered(0.0, 0.0))]
+    fn rejects_invalid_ramps(#[case] stops: Vec<ColorStop>, #[case] expected: ColoringError) {
+        assert_eq!(ElevationRamp::try_from(stops), Err(expected));
+    }
+
+    #[test]
+    fn converts_stops_for_the_shader() {
+        let ramp = ElevationRamp::try_from(vec![stop(-100.0), stop(1500.0)]).unwrap();
+        let stops = ramp.gpu_stops();
+
+        assert_abs_diff_eq!(stops[0].x, 1.0);
+        assert_abs_diff_eq!(stops[0].y, 0.2158, epsilon = 1e-4);
+        assert_abs_diff_eq!(stops[0].z, 0.0);
+        assert_eq!(stops[0].w, -100.0);
+        assert_eq!(stops[1].w, 1500.0);
+        assert_eq!(stops[2], Vec4::ZERO);
+    }
+
+    #[test]
+    fn converts_legend_colors_for_the_shader() {
+        let classes = gpu_slope_classes();
+        assert_eq!(classes[0].truncate(), srgb_to_linear(SLOPE_CLASSES[0].1));
+        assert_eq!(classes[3].w, 40.0);
+        let aspects = gpu_aspect_colors();
+        assert_abs_diff_eq!(aspects[0].x, 0.791, epsilon = 1e-3);
+        assert_abs_diff_eq!(aspects[0].y, 0.032, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn legend_follows_the_view_mode() {
+        let ramp = ElevationRamp::default();
+
+        assert_eq!(Legend::new(ViewMode::Default, &ramp), None);
+        let hypsometric = Legend::new(ViewMode::Hypsometric, &ramp).unwrap();
+        assert_eq!(hypsometric.entries.len(), ramp.get_stops().len());
+        assert_eq!(hypsometric.entries[1].label, "500 m");
+
+        let slope = Legend::new(ViewMode::Slope, &ramp).unwrap();
+        let labels = slope.entries.iter().map(|entry| entry.label.as_str());
+        assert!(labels.eq(["< 30°", "30–35°", "35–40°", "≥ 40°"]));
+
+        let aspect = Legend::new(ViewMode::Aspect, &ramp).unwrap();
+        assert_eq!(aspect.entries.len(), 9);
+        assert_eq!(aspect.entries[8].color, FLAT_COLOR);
+    }
+
+    #[test]
+    fn analysis_view_modes_color_the_terrain_like_their_legend() {
+        let settings = RendererSettings {
+            view_mode: ViewMode::Slope,
+            // the haze would tint the colors, but it's left out of the analysis view modes
+            atmosphere: AtmosphereSettings::default(),
+            ..test_settings()
+        };
+        let ramp = settings.coloring.elevation_stops.clone();
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, _| {
+            let (slope, gbuffer) = render_start_view(renderer).await;
+            renderer.data_mut().camera.view_mode = ViewMode::Aspect;
+            let aspect = renderer.render().await.unwrap();
+            renderer.data_mut().camera.view_mode = ViewMode::Hypsometric;
+            let hypsometric = renderer.render().await.unwrap();
+
+            let pixels = |image: &RgbaImage, elevations: std::ops::Range<f32>| {
+                gbuffer_pixels(image, &gbuffer)
+                    .filter(|pixel| {
+                        elevations.contains(&pixel.elevation)
+                            && (1_000.0..9_000.0).contains(&pixel.distance)
+                    })
+                    .map(|pixel| pixel.color)
+                    .collect_vec()
+            };
+            let count = |pixels: &[[u8; 3]], color: [u8; 3]| {
+                pixels
+                    .iter()
+                    .filter(|pixel| (0..3).all(|i| pixel[i].abs_diff(color[i]) <= 3))
+                    .count()
+            };
+
+            // the plain is gentler than 30°, the sides of the block are steeper than 40°
+            let plain = pixels(&slope, 480.0..520.0);
+            assert!(count(&plain, SLOPE_CLASSES[0].1) > plain.len() * 9 / 10);
+            let sides = pixels(&slope, 800.0..2200.0);
+            assert!(!sides.is_empty());
+            assert!(count(&sides, SLOPE_CLASSES[3].1) > sides.len() * 9 / 10);
+
+            // the camera looks at the sides of the narrow block facing south-east, south and south-west
+            let sides = pixels(&aspect, 800.0..2200.0);
+            let facing_south = (3..6)
+                .map(|i| count(&sides, ASPECT_COLORS[i].0))
+                .sum::<usize>();
+            assert!(facing_south > sides.len() * 9 / 10);
+            assert!(count(&sides, ASPECT_COLORS[4].0) > 0);
+            let plain = pixels(&aspect, 480.0..520.0);
+            assert!(count(&plain, FLAT_COLOR) > plain.len() * 9 / 10);
+
+            let plain = pixels(&hypsometric, 480.0..520.0);
+            let color_at_500m = ramp.get_stops()[1].color;
+            assert!(count(&plain, color_at_500m) > plain.len() * 9 / 10);
+        });
+    }
+}
@@ -0,0 +1,485 @@
+use std::io;
+
+use color_eyre::{Result, eyre::Context};
+use lyon::{
+    math::point,
+    path::Path,
+    tessellation::{
+        BuffersBuilder, FillOptions, FillRule, FillTessellator, FillVertex, VertexBuffers,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::render::{data::LakeVertex, geometry::transform};
+
+/// Degrees, longer edges of the lake triangles get split so that the surface follows the
+/// earth's curvature instead of sagging below the terrain around it
+const MAX_LAKE_EDGE: f32 = 0.01;
+
+/// The sea and the lakes, see [`GeoLocation::lakes_file_path`]
+///
+/// [`GeoLocation::lakes_file_path`]: topo_common::GeoLocation::lakes_file_path
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WaterSettings {
+    /// Cover the ground below sea level and the loaded tiles without terrain with the sea
+    pub sea: bool,
+    /// Load the lake polygons and draw them as flat water
+    pub lakes: bool,
+    /// sRGB color of the light scattered back from within the water
+    pub color: [u8; 3],
+    /// Mirror the terrain in the lakes, otherwise they only reflect the sky
+    pub reflections: bool,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            sea: true,
+            lakes: true,
+            color: [20, 55, 70],
+            reflections: true,
+        }
+    }
+}
+
+/// Lake surface, flat at its elevation within its outline
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lake {
+    pub name: Option<String>,
+    /// Meters above sea level
+    pub elevation: f32,
+    /// The outline followed by the outlines of the islands, as (longitude, latitude) points
+    pub rings: Vec<Vec<(f32, f32)>>,
+}
+
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Option<Geometry>,
+    #[serde(default)]
+    properties: LakeProperties,
+}
+
+#[derive(Default, Deserialize)]
+struct LakeProperties {
+    name: Option<String>,
+    elevation: Option<f32>,
+}
+
+/// Positions are (longitude, latitude, optionally altitude)
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    Polygon {
+        coordinates: Vec<Vec<Vec<f32>>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Vec<f32>>>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl Lake {
+    /// Reads the polygons and multipolygons of a GeoJSON feature collection, each with its
+    /// `elevation` (in meters above sea level) among the properties, the other features are skipped
+    pub fn read_geojson<R: io::Read>(reader: R) -> Result<Vec<Self>> {
+        let collection: FeatureCollection =
+            serde_json::from_reader(reader).wrap_err("Error parsing the lakes GeoJSON")?;
+
+        let to_rings = |polygon: Vec<Vec<Vec<f32>>>| {
+            polygon
+                .into_iter()
+                .map(|ring| {
+                    ring.into_iter()
+                        .filter(|position| position.len() >= 2)
+                        .map(|position| (position[0], position[1]))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut lakes = vec![];
+        for feature in collection.features {
+            let polygons = match feature.geometry {
+                Some(Geometry::Polygon { coordinates }) => vec![coordinates],
+                Some(Geometry::MultiPolygon { coordinates }) => coordinates,
+                Some(Geometry::Other) | None => continue,
+            };
+            let Some(elevation) = feature.properties.elevation else {
+                log::warn!(
+                    "Skipping the lake {:?} without an elevation",
+                    feature.properties.name
+                );
+                continue;
+            };
+
+            lakes.extend(polygons.into_iter().map(|polygon| Lake {
+                name: feature.properties.name.clone(),
+                elevation,
+                rings: to_rings(polygon),
+            }));
+        }
+
+        Ok(lakes)
+    }
+}
+
+/// Triangles covering the lakes at their elevations, in world space
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LakeMesh {
+    pub vertices: Vec<LakeVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl LakeMesh {
+    pub fn new(lakes: &[Lake]) -> Self {
+        let mut mesh = Self::default();
+        let mut tessellator = FillTessellator::new();
+
+        for lake in lakes {
+            let mut builder = Path::builder();
+            for ring in lake.rings.iter().filter(|ring| ring.len() >= 3) {
+                let mut points = ring.iter().map(|&(x, y)| point(x, y));
+                builder.begin(points.next().unwrap());
+                for point in points {
+                    builder.line_to(point);
+                }
+                builder.close();
+            }
+            let path = builder.build();
+
+            // the islands are holes in the outline
+            let mut outline: VertexBuffers<(f32, f32), u32> = VertexBuffers::new();
+            let result = tessellator.tessellate_path(
+                &path,
+                &FillOptions::default().with_fill_rule(FillRule::EvenOdd),
+                &mut BuffersBuilder::new(&mut outline, |vertex: FillVertex| {
+                    vertex.position().to_tuple()
+                }),
+            );
+            if let Err(err) = result {
+                log::warn!("Skipping the lake {:?}: {err:?}", lake.name);
+                continue;
+            }
+
+            for triangle in outline.indices.chunks_exact(3) {
+                let corners = [0, 1, 2].map(|i| outline.vertices[triangle[i] as usize]);
+                mesh.add_subdivided_triangle(corners, lake.elevation);
+            }
+        }
+
+        mesh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Adds the triangle split into a grid of ones with edges up to [`MAX_LAKE_EDGE`] long
+    fn add_subdivided_triangle(&mut self, [a, b, c]: [(f32, f32); 3], elevation: f32) {
+        let length = |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| (x1 - x0).hypot(y1 - y0);
+        let longest = length(a, b).max(length(b, c)).max(length(c, a));
+        let n = ((longest / MAX_LAKE_EDGE).ceil() as u32).max(1);
+
+        // the vertices row by row, from the one at `a` towards the edge between `b` and `c`
+        let first = self.vertices.len() as u32;
+        for i in 0..=n {
+            for j in 0..=i {
+                let u = (i - j) as f32 / n as f32;
+                let v = j as f32 / n as f32;
+                let longitude = a.0 + u * (b.0 - a.0) + v * (c.0 - a.0);
+                let latitude = a.1 + u * (b.1 - a.1) + v * (c.1 - a.1);
+                self.vertices.push(LakeVertex::new(
+                    transform(elevation, longitude, latitude),
+                    elevation,
+                ));
+            }
+        }
+
+        let index = |i: u32, j: u32| first + i * (i + 1) / 2 + j;
+        for i in 0..n {
+            for j in 0..=i {
+                self.indices
+                    .extend([index(i, j), index(i + 1, j), index(i + 1, j + 1)]);
+                if j < i {
+                    self.indices
+                        .extend([index(i, j), index(i + 1, j + 1), index(i, j + 1)]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use approx::assert_abs_diff_eq;
+    use glam::Vec3;
+    use image::RgbaImage;
+    use itertools::Itertools;
+    use topo_common::{GeoCoord, GeoLocation};
+
+    use crate::{
+        data::camera::StartView,
+        export::gbuffer::render_gbuffer,
+        headless::{
+            HeadlessRenderer,
+            tests::{
+                TILE_SIZE, gbuffer_pixels, render_start_view, test_settings, with_test_renderer,
+                write_test_dem,
+            },
+        },
+        render::{data::PostprocessingUniforms, geometry::inverse_transform},
+        settings::{AtmosphereSettings, PostprocessingSettings, RendererSettings, SunSettings},
+    };
+
+    use super::*;
+
+    const LAKES: &str = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    {
+      "type": "Feature",
+      "properties": { "name": "Square Lake", "elevation": 500 },
+      "geometry": {
+        "type": "Polygon",
+        "coordinates": [
+          [[20.0, 49.0], [20.02, 49.0], [20.02, 49.02], [20.0, 49.02], [20.0, 49.0]],
+          [[20.005, 49.005], [20.005, 49.006], [20.006, 49.006], [20.006, 49.005], [20.005, 49.005]]
+        ]
+      }
+    },
+    {
+      "type": "Feature",
+      "properties": { "elevation": 1200.5 },
+      "geometry": {
+        "type": "MultiPolygon",
+        "coordinates": [
+          [[[20.5, 49.5, 0.0], [20.501, 49.5, 0.0], [20.5, 49.501, 0.0]]],
+          [[[20.6, 49.5], [20.601, 49.5], [20.6, 49.501]]]
+        ]
+      }
+    },
+    {
+      "type": "Feature",
+      "properties": { "name": "Unknown Level" },
+      "geometry": { "type": "Polygon", "coordinates": [[[20.0, 49.0], [20.1, 49.0], [20.0, 49.1]]] }
+    },
+    {
+      "type": "Feature",
+      "properties": { "elevation": 300 },
+      "geometry": { "type": "Point", "coordinates": [20.0, 49.0] }
+    }
+  ]
+}"#;
+
+    #[test]
+    fn reads_lake_polygons() {
+        let lakes = Lake::read_geojson(LAKES.as_bytes()).unwrap();
+
+        assert_eq!(lakes.len(), 3);
+        assert_eq!(lakes[0].name.as_deref(), Some("Square Lake"));
+        assert_eq!(lakes[0].elevation, 500.0);
+        assert_eq!(lakes[0].rings.len(), 2);
+        assert_eq!(lakes[0].rings[1][2], (20.006, 49.006));
+        assert_eq!(lakes[1].elevation, 1200.5);
+        assert_eq!(
+            lakes[2].rings,
+            [vec![(20.6, 49.5), (20.601, 49.5), (20.6, 49.501)]]
+        );
+        assert!(Lake::read_geojson("[]".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn meshes_lakes_at_their_elevation() {
+        let lakes = Lake::read_geojson(LAKES.as_bytes()).unwrap();
+        let mesh = LakeMesh::new(&lakes[..1]);
+
+        assert!(!mesh.is_empty());
+        for vertex in &mesh.vertices {
+            let (elevation, coord) = inverse_transform(Vec3::from_array(vertex.position));
+            assert_abs_diff_eq!(elevation, 500.0, epsilon = 1.0);
+            assert!((48.9999..=49.0201).contains(&coord.latitude));
+            // not on the island
+            assert!(
+                !(49.0051..49.0059).contains(&coord.latitude)
+                    || !(20.0051..20.0059).contains(&coord.longitude)
+            );
+        }
+
+        // the triangles' edges are split to follow the earth's curvature
+        let max_edge = mesh
+            .indices
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                let corner =
+                    |i: usize| Vec3::from_array(mesh.vertices[triangle[i] as usize].position);
+                [corner(0).distance(corner(1)), corner(1).distance(corner(2))]
+            })
+            .fold(0.0, f32::max);
+        assert!(max_edge < 1200.0, "{max_edge}");
+    }
+
+    /// Puts the camera 3km above the location, looking south with the pitch (in degrees)
+    fn look_south(renderer: &mut HeadlessRenderer, location: GeoCoord, pitch: f32) {
+        let camera = &mut renderer.data_mut().camera;
+        camera.reset(location, 3000.0);
+        camera.apply_start_view(StartView {
+            altitude: None,
+            yaw: Some(180.0),
+            pitch: Some(pitch),
+        });
+    }
+
+    /// Renders the current view again without the sea, i.e. with the sky wherever there's no terrain
+    async fn render_without_sea(renderer: &mut HeadlessRenderer) -> RgbaImage {
+        let size = renderer.size();
+        let data = renderer.data_mut();
+        data.postprocessing_uniforms = PostprocessingUniforms::new(
+            (size.width as f32, size.height as f32).into(),
+            &data.camera,
+            data.sun.direction(),
+            &PostprocessingSettings::default(),
+            &AtmosphereSettings {
+                visibility: 0.0,
+                ..Default::default()
+            },
+            &WaterSettings {
+                sea: false,
+                ..Default::default()
+            },
+        );
+        renderer.render().await.unwrap()
+    }
+
+    #[test]
+    fn water_reflects_the_sky_and_the_terrain() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.42, 20.5),
+            view_distance: 15_000.0,
+            sun: SunSettings {
+                time: Some("2024-06-21T10:00:00Z".parse().unwrap()),
+                ..Default::default()
+            },
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, data_dir| {
+            // a lake on the plain south of the block, at the plain's elevation
+            let location = GeoLocation::from(GeoCoord::new(49.5, 20.5));
+            let lakes_path = data_dir.join(location.lakes_file_path());
+            fs::create_dir_all(lakes_path.parent().unwrap()).unwrap();
+            fs::write(
+                lakes_path,
+                r#"{"type": "FeatureCollection", "features": [{
+                    "type": "Feature",
+                    "properties": {"name": "Test Lake", "elevation": 500},
+                    "geometry": {"type": "Polygon", "coordinates": [[
+                        [20.44, 49.44], [20.56, 49.44], [20.56, 49.48], [20.44, 49.48], [20.44, 49.44]
+                    ]]}
+                }]}"#,
+            )
+            .unwrap();
+
+            let (lake, lake_gbuffer) = render_start_view(renderer).await;
+
+            // looking south over the tile's edge, the data has no terrain beyond it, as if it was
+            // the sea, and the tile there is within the view distance
+            let location = GeoCoord::new(49.05, 20.5);
+            renderer.load_location(location).await.unwrap();
+            look_south(renderer, location, -10.0);
+            let sea = renderer.render().await.unwrap();
+            let sea_gbuffer = render_gbuffer(renderer).await.unwrap();
+            let sky = render_without_sea(renderer).await;
+
+            let bluish = |color: [u8; 3]| color[2] as i32 - color[0] as i32 > 10;
+
+            // the lake pixels are mostly the water's color, possibly with the block mirrored in them
+            let (lake_pixels, lake_water) = gbuffer_pixels(&lake, &lake_gbuffer)
+                .filter(|pixel| {
+                    (49.445..49.475).contains(&pixel.latitude)
+                        && (20.45..20.55).contains(&pixel.longitude)
+                })
+                .fold((0, 0), |(pixels, water), pixel| {
+                    (pixels + 1, water + bluish(pixel.color) as u32)
+                });
+            assert!(lake_pixels > 100, "{lake_pixels}");
+            assert!(
+                lake_water * 2 > lake_pixels,
+                "{lake_water} of {lake_pixels}"
+            );
+
+            // while the plain around it stays gray
+            let plain_water = gbuffer_pixels(&lake, &lake_gbuffer)
+                .filter(|pixel| {
+                    pixel.elevation < 600.0 && !(49.43..49.49).contains(&pixel.latitude)
+                })
+                .filter(|pixel| bluish(pixel.color))
+                .count();
+            assert_eq!(plain_water, 0);
+
+            // below the horizon, where there's no terrain, the sea darkens towards the camera
+            let (width, height) = sea.dimensions();
+            let column = width / 2;
+            let sea_rows = (height / 2..height)
+                .filter(|&row| sea_gbuffer.distance[(row * width + column) as usize].is_infinite())
+                .collect::<Vec<_>>();
+            assert!(sea_rows.len() > 20, "{}", sea_rows.len());
+            for &row in &sea_rows[1..sea_rows.len() - 1] {
+                let pixel = sea.get_pixel(column, row);
+                let sky = sky.get_pixel(column, row);
+                assert!(bluish([pixel[0], pixel[1], pixel[2]]), "{row}");
+                // darker than the sky the view shows there without the sea
+                assert!(pixel[2] + 10 < sky[2], "{row} {pixel:?} {sky:?}");
+            }
+            let far = sea.get_pixel(column, sea_rows[1]);
+            let near = sea.get_pixel(column, sea_rows[sea_rows.len() - 2]);
+            assert!(near[2] < far[2], "{near:?} {far:?}");
+        });
+    }
+
+    #[test]
+    fn land_beyond_the_view_distance_stays_sky() {
+        let location = GeoCoord::new(49.42, 20.5);
+        let settings = RendererSettings {
+            start_location: location,
+            view_distance: 15_000.0,
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, data_dir| {
+            // land south of the tile, too far away to get loaded
+            let land = GeoLocation::from(GeoCoord::new(48.5, 20.5));
+            write_test_dem(data_dir, land, TILE_SIZE, |_, _| 300.0);
+
+            renderer.load_location(location).await.unwrap();
+            // level, the view's lower half reaches from the horizon to the loaded terrain
+            look_south(renderer, location, 0.0);
+            let image = renderer.render().await.unwrap();
+            let gbuffer = render_gbuffer(renderer).await.unwrap();
+            let sky = render_without_sea(renderer).await;
+
+            // below the horizon, beyond the loaded terrain, the sky is left as it is
+            let (width, height) = image.dimensions();
+            let beyond = gbuffer_pixels(&image, &gbuffer)
+                .zip(sky.pixels())
+                .enumerate()
+                .filter(|(i, (pixel, _))| {
+                    *i as u32 / width > height / 2 && pixel.distance.is_infinite()
+                })
+                .map(|(_, pixels)| pixels)
+                .collect_vec();
+            assert!(beyond.len() > 100, "{}", beyond.len());
+            for (pixel, sky) in beyond {
+                assert_eq!(pixel.color, [sky[0], sky[1], sky[2]]);
+            }
+        });
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fs, path::Path, time::Duration};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use topo_common::GeoLocation;
+
+use crate::{control::clock::Clock, data::camera_path::CameraPath, headless::HeadlessRenderer};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FlyThroughOptions {
+    /// Frames per second of the exported sequence
+    pub frame_rate: f32,
+}
+
+impl Default for FlyThroughOptions {
+    fn default() -> Self {
+        Self { frame_rate: 30.0 }
+    }
+}
+
+/// Name of the numbered frame file, e.g. `frame_00042.png`
+pub fn frame_file_name(frame: usize) -> String {
+    format!("frame_{frame:05}.png")
+}
+
+/// Plays the camera path back and saves every frame to the directory as a numbered PNG
+///
+/// Time advances by a fixed step per frame, no matter how long rendering takes. Terrain
+/// gets loaded around the camera whenever it moves to another tile, every frame is
+/// captured only after all of it is ready. Returns the number of saved frames
+pub async fn render_fly_through(
+    renderer: &mut HeadlessRenderer,
+    path: &CameraPath,
+    options: &FlyThroughOptions,
+    output_dir: &Path,
+) -> Result<usize> {
+    if !options.frame_rate.is_finite() || options.frame_rate <= 0.0 {
+        return Err(eyre!("Frame rate must be positive"));
+    }
+    if path.points.is_empty() {
+        return Err(eyre!("The camera path is empty"));
+    }
+    fs::create_dir_all(output_dir)
+        .wrap_err_with(|| format!("Unable to create {}", output_dir.display()))?;
+
+    let mut clock = Clock::fixed(options.frame_rate);
+    let duration = Duration::from_secs_f32(path.duration());
+    let mut time = Duration::ZERO;
+    let mut frame = 0;
+
+    while time <= duration {
+        let Some(pose) = path.sample(time.as_secs_f32()) else {
+            break;
+        };
+
+        if renderer.data().current_location.map(GeoLocation::from)
+            != Some(GeoLocation::from(pose.location))
+        {
+            renderer.load_location(pose.location).await?;
+        }
+        pose.apply(&mut renderer.data_mut().camera);
+
+        let frame_path = output_dir.join(frame_file_name(frame));
+        renderer
+            .render()
+            .await?
+            .save(&frame_path)
+            .wrap_err_with(|| format!("Unable to save frame to {}", frame_path.display()))?;
+
+        frame += 1;
+        time += clock.tick();
+    }
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use topo_common::GeoCoord;
+
+    use crate::{
+        data::camera_path::{CameraPathPoint, Easing},
+        headless::tests::with_test_renderer,
+        settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[test]
+    fn saves_numbered_frames_at_fixed_rate() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+        let point = |time, yaw| CameraPathPoint {
+            time,
+            location: GeoCoord::new(49.47, 20.5),
+            altitude: 3000.0,
+            yaw,
+            pitch: -10.0,
+            fov: 45.0,
+            easing: Easing::Linear,
+            lift: 0.0,
+        };
+        let path = CameraPath {
+            points: vec![point(3.0, 0.0), point(4.0, 90.0)],
+        };
+        let options = FlyThroughOptions { frame_rate: 2.0 };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, data_dir| {
+            let output_dir = data_dir.join("frames");
+            let frames = render_fly_through(renderer, &path, &options, &output_dir)
+                .await
+                .unwrap();
+            let images = (0..frames)
+                .map(|frame| image::open(output_dir.join(frame_file_name(frame))).unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(frames, 3);
+            assert!(renderer.engine_mut().is_terrain_ready());
+            assert_ne!(images[0], images[2]);
+            assert!((renderer.data().camera.azimuth() - 90.0).abs() < 0.1);
+        });
+    }
+}
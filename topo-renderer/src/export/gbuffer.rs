@@ -0,0 +1,268 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, OptionExt},
+};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage,
+};
+use glam::{DVec3, Mat4, Vec3};
+use strum::{Display, EnumString};
+use tiff::{
+    encoder::{
+        TiffEncoder,
+        colortype::{Gray32Float, RGB32Float},
+    },
+    tags::Tag,
+};
+
+use crate::{
+    data::{Size, camera::dist_from_depth, refraction::refraction_lift},
+    headless::HeadlessRenderer,
+    render::geometry::R0,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum GBufferFormat {
+    Exr,
+    #[strum(serialize = "tiff", serialize = "tif")]
+    Tiff,
+}
+
+impl GBufferFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// Per pixel geometry of a rendered view, laid out row by row
+///
+/// Pixels without terrain have an infinite distance, zero normals
+/// and their latitude, longitude and elevation are NaN
+#[derive(Clone, Debug, PartialEq)]
+pub struct GBuffer {
+    pub size: Size<u32>,
+    /// Distance from the camera along its viewing direction (in meters)
+    pub distance: Vec<f32>,
+    /// Unit normals in world space, i.e. with the z axis pointing to the north pole
+    /// and the x axis to the intersection of the equator and the prime meridian
+    pub normals: Vec<Vec3>,
+    /// Degrees
+    pub latitude: Vec<f32>,
+    /// Degrees
+    pub longitude: Vec<f32>,
+    /// Meters above sea level
+    pub elevation: Vec<f32>,
+}
+
+impl GBuffer {
+    /// Saves all the layers as 32-bit floats, the format follows from the extension
+    ///
+    /// EXR files get the channels `Z`, `N.X`, `N.Y`, `N.Z`, `latitude`, `longitude`
+    /// and `elevation`. TIFF files get three pages: the distance, the normals (as RGB)
+    /// and the latitude, longitude and elevation (as RGB)
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let format = GBufferFormat::from_path(path)
+            .ok_or_eyre("Expected a .exr, .tif or .tiff file extension")?;
+        match format {
+            GBufferFormat::Exr => self.save_exr(path),
+            GBufferFormat::Tiff => self.save_tiff(path),
+        }
+        .wrap_err_with(|| format!("Unable to save the G-buffer to {}", path.display()))
+    }
+
+    fn save_exr(&self, path: &Path) -> Result<()> {
+        let normal_component =
+            |i: usize| FlatSamples::F32(self.normals.iter().map(|normal| normal[i]).collect());
+        let channels = AnyChannels::sort(
+            vec![
+                AnyChannel::new("Z", FlatSamples::F32(self.distance.clone())),
+                AnyChannel::new("N.X", normal_component(0)),
+                AnyChannel::new("N.Y", normal_component(1)),
+                AnyChannel::new("N.Z", normal_component(2)),
+                AnyChannel::new("latitude", FlatSamples::F32(self.latitude.clone())),
+                AnyChannel::new("longitude", FlatSamples::F32(self.longitude.clone())),
+                AnyChannel::new("elevation", FlatSamples::F32(self.elevation.clone())),
+            ]
+            .into(),
+        );
+        let layer = Layer::new(
+            (self.size.width as usize, self.size.height as usize),
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            channels,
+        );
+
+        Image::from_layer(layer).write().to_file(path)?;
+
+        Ok(())
+    }
+
+    fn save_tiff(&self, path: &Path) -> Result<()> {
+        let (width, height) = (self.size.width, self.size.height);
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(path)?))?;
+
+        let mut image = encoder.new_image::<Gray32Float>(width, height)?;
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, "distance")?;
+        image.write_data(&self.distance)?;
+
+        let normals = self
+            .normals
+            .iter()
+            .flat_map(|normal| normal.to_array())
+            .collect::<Vec<_>>();
+        let mut image = encoder.new_image::<RGB32Float>(width, height)?;
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, "normals")?;
+        image.write_data(&normals)?;
+
+        let coordinates = (0..self.distance.len())
+            .flat_map(|i| [self.latitude[i], self.longitude[i], self.elevation[i]])
+            .collect::<Vec<_>>();
+        let mut image = encoder.new_image::<RGB32Float>(width, height)?;
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, "latitude longitude elevation")?;
+        image.write_data(&coordinates)?;
+
+        Ok(())
+    }
+}
+
+/// Renders the G-buffer of the current view
+///
+/// Positions are reconstructed from the depth buffer in double precision,
+/// the normals are rendered separately in half precision
+pub async fn render_gbuffer(renderer: &mut HeadlessRenderer) -> Result<GBuffer> {
+    let size = renderer.size();
+    let depth = renderer.read_depth().await?;
+    let normals = renderer.read_normals().await?;
+
+    let camera = renderer.data().camera;
+    let inverse_projection = camera
+        .build_proj_matrix(size.width as f32, size.height as f32)
+        .as_dmat4()
+        .inverse();
+    let view_to_world = Mat4::look_to_rh(Vec3::ZERO, camera.direction(), camera.up())
+        .as_dmat4()
+        .inverse();
+    let eye = camera.eye.as_dvec3();
+
+    let pixels = (size.width * size.height) as usize;
+    let mut gbuffer = GBuffer {
+        size,
+        distance: Vec::with_capacity(pixels),
+        normals,
+        latitude: Vec::with_capacity(pixels),
+        longitude: Vec::with_capacity(pixels),
+        elevation: Vec::with_capacity(pixels),
+    };
+
+    for (i, depth) in depth.into_iter().enumerate() {
+        if depth >= 1.0 {
+            gbuffer.distance.push(f32::INFINITY);
+            gbuffer.latitude.push(f32::NAN);
+            gbuffer.longitude.push(f32::NAN);
+            gbuffer.elevation.push(f32::NAN);
+            continue;
+        }
+
+        let (x, y) = (i as u32 % size.width, i as u32 / size.width);
+        let ndc = DVec3::new(
+            2.0 * (x as f64 + 0.5) / size.width as f64 - 1.0,
+            1.0 - 2.0 * (y as f64 + 0.5) / size.height as f64,
+            depth as f64,
+        );
+        let position =
+            eye + view_to_world.transform_vector3(inverse_projection.project_point3(ndc));
+        let r = position.length();
+
+        gbuffer.distance.push(dist_from_depth(depth));
+        gbuffer
+            .latitude
+            .push((position.z / r).asin().to_degrees() as f32);
+        gbuffer
+            .longitude
+            .push(position.y.atan2(position.x).to_degrees() as f32);
+        // the terrain appears raised by the refraction
+        let lift = refraction_lift(position.distance(eye) as f32, camera.refraction);
+        gbuffer.elevation.push((r - R0 as f64) as f32 - lift);
+    }
+
+    Ok(gbuffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use topo_common::GeoCoord;
+
+    use crate::{headless::tests::with_test_renderer, settings::RendererSettings};
+
+    use super::*;
+
+    #[rstest]
+    #[case("gbuffer.exr", Some(GBufferFormat::Exr))]
+    #[case("gbuffer.TIF", Some(GBufferFormat::Tiff))]
+    #[case("gbuffer.tiff", Some(GBufferFormat::Tiff))]
+    #[case("gbuffer.png", None)]
+    fn format_from_path(#[case] path: &str, #[case] expected: Option<GBufferFormat>) {
+        assert_eq!(GBufferFormat::from_path(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn gbuffer_matches_terrain() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(180.0),
+            start_pitch: Some(-10.0),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+
+        with_test_renderer(settings, (64, 48).into(), async |renderer, data_dir| {
+            renderer
+                .load_location(GeoCoord::new(49.47, 20.5))
+                .await
+                .unwrap();
+            let gbuffer = render_gbuffer(renderer).await.unwrap();
+
+            let pixels = 64 * 48;
+            assert_eq!(gbuffer.distance.len(), pixels);
+            assert_eq!(gbuffer.normals.len(), pixels);
+
+            // the top row is sky
+            assert_eq!(gbuffer.distance[0], f32::INFINITY);
+            assert!(gbuffer.latitude[0].is_nan());
+
+            // the bottom row is the flat plain south of the camera, facing up
+            let bottom = pixels - 32;
+            let up = crate::render::geometry::transform(
+                gbuffer.elevation[bottom],
+                gbuffer.longitude[bottom],
+                gbuffer.latitude[bottom],
+            )
+            .normalize();
+            assert!(gbuffer.distance[bottom].is_finite());
+            assert!((gbuffer.elevation[bottom] - 500.0).abs() < 5.0);
+            assert!(gbuffer.latitude[bottom] < 49.47 && gbuffer.latitude[bottom] > 49.4);
+            assert!((gbuffer.longitude[bottom] - 20.5).abs() < 0.01);
+            assert!(gbuffer.normals[bottom].dot(up) > 0.99);
+
+            for file in ["gbuffer.exr", "gbuffer.tif"] {
+                let path = data_dir.join(file);
+                gbuffer.save(&path).unwrap();
+                assert!(std::fs::metadata(&path).unwrap().len() > 0);
+            }
+            let image =
+                exr::prelude::read_all_flat_layers_from_file(data_dir.join("gbuffer.exr")).unwrap();
+            assert_eq!(image.layer_data[0].channel_data.list.len(), 7);
+        });
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+
+use glyphon::SwashCache;
+use image::RgbaImage;
+use topo_common::GeoLocation;
+
+use crate::{
+    data::application_data::ApplicationData,
+    render::text_renderer::{
+        LABEL_PADDING_LEFT, LINE_HEIGHT, LINE_PADDING, LabelId, LabelLayout, LabelPositions,
+        TextRenderer, layout_labels,
+    },
+};
+
+/// Peaks marked as visible by the last render
+pub(crate) fn visible_peak_ids(
+    data: &ApplicationData,
+) -> impl Iterator<Item = (GeoLocation, LabelId)> + '_ {
+    data.peaks.iter().flat_map(|(location, peaks)| {
+        peaks
+            .iter()
+            .enumerate()
+            .filter(|(_, peak)| peak.visible)
+            .map(|(i, _)| (*location, LabelId(i as u32)))
+    })
+}
+
+/// Marks exactly the given peaks as visible, e.g. when they were collected from multiple renders
+pub(crate) fn mark_visible_peaks(
+    data: &mut ApplicationData,
+    visible_peaks: &BTreeSet<(GeoLocation, LabelId)>,
+) {
+    for (location, peaks) in data.peaks.iter_mut() {
+        for (i, peak) in peaks.iter_mut().enumerate() {
+            peak.visible = visible_peaks.contains(&(*location, LabelId(i as u32)));
+        }
+    }
+}
+
+/// Lays out the labels of the peaks at the given pixel positions and draws them
+/// the same way as the label renderers do
+///
+/// With `wrap_around` the labels crossing the left or right edge continue on the other side
+pub(crate) fn draw_labels(
+    image: &mut RgbaImage,
+    positions: LabelPositions,
+    data: &ApplicationData,
+    wrap_around: bool,
+) {
+    let laid_out_labels = layout_labels(
+        positions,
+        |location, id| {
+            data.peak_labels
+                .get(&location)
+                .and_then(|labels| labels.get(id.0 as usize))
+                .map(|label| label.width)
+        },
+        LINE_HEIGHT + LINE_PADDING,
+    );
+
+    let mut put_pixel = |x: i32, y: i32, color: [u8; 4]| {
+        if y < 0 || y >= image.height() as i32 {
+            return;
+        }
+        let x = if wrap_around {
+            x.rem_euclid(image.width() as i32)
+        } else if (0..image.width() as i32).contains(&x) {
+            x
+        } else {
+            return;
+        };
+        let pixel = image.get_pixel_mut(x as u32, y as u32);
+        let alpha = color[3] as f32 / 255.0;
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha).round() as u8;
+        }
+    };
+
+    let mut swash_cache = SwashCache::new();
+
+    for LabelLayout {
+        location,
+        id,
+        label_x,
+        label_y,
+        label_width,
+        peak_x,
+        peak_y,
+    } in laid_out_labels
+    {
+        for y in label_y as i32..=peak_y as i32 {
+            put_pixel(peak_x as i32, y, [0, 0, 0, 255]);
+        }
+
+        for y in label_y as i32..(label_y + LINE_HEIGHT) as i32 {
+            for x in label_x as i32..(label_x + label_width).ceil() as i32 {
+                put_pixel(x, y, [255, 255, 255, 255]);
+            }
+        }
+
+        let label = &data.peak_labels[&location][id.0 as usize];
+        let (left, top) = ((label_x + LABEL_PADDING_LEFT) as i32, label_y as i32);
+        TextRenderer::draw_peak_label(label, &mut swash_cache, |x, y, w, h, color| {
+            for dy in 0..h as i32 {
+                for dx in 0..w as i32 {
+                    put_pixel(
+                        left + x + dx,
+                        top + y + dy,
+                        [color.r(), color.g(), color.b(), color.a()],
+                    );
+                }
+            }
+        });
+    }
+}
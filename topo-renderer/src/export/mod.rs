@@ -0,0 +1,13 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fly_through;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod gbuffer;
+#[cfg(not(target_arch = "wasm32"))]
+mod labels;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod panorama;
+pub mod peaks;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod poster;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod vector;
@@ -0,0 +1,549 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    f32::consts::{FRAC_PI_2, PI, TAU},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, eyre},
+};
+use image::{Rgba, RgbaImage};
+use strum::{Display, EnumString};
+use topo_common::GeoLocation;
+
+use crate::{
+    data::{
+        Size,
+        application_data::ApplicationData,
+        camera::{MAX_FOV, MIN_FOV},
+    },
+    export::labels::{draw_labels, mark_visible_peaks, visible_peak_ids},
+    headless::HeadlessRenderer,
+    render::text_renderer::LabelId,
+};
+
+/// Largest texture side guaranteed by the default wgpu limits
+const MAX_SLICE_SIZE: u32 = 8192;
+/// Pixels by which neighboring slices overlap on each side, so that peaks right
+/// at the seams are still within the view when checking their visibility
+const SLICE_MARGIN: u32 = 8;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum PanoramaProjection {
+    /// Rows spaced evenly in the elevation angle
+    #[default]
+    Equirectangular,
+    /// Rows spaced evenly on a cylinder around the viewer
+    Cylindrical,
+}
+
+impl PanoramaProjection {
+    /// Height on a panorama of radius 1 of the given elevation angle (in radians)
+    fn project(self, elevation: f32) -> f32 {
+        match self {
+            PanoramaProjection::Equirectangular => elevation,
+            PanoramaProjection::Cylindrical => elevation.tan(),
+        }
+    }
+
+    fn unproject(self, height: f32) -> f32 {
+        match self {
+            PanoramaProjection::Equirectangular => height,
+            PanoramaProjection::Cylindrical => height.atan(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PanoramaOptions {
+    /// Width of the whole 360° panorama in pixels, the height follows from the elevation range
+    pub width: u32,
+    /// Elevation angle at the bottom edge (in degrees above the horizon)
+    pub min_elevation: f32,
+    /// Elevation angle at the top edge (in degrees above the horizon)
+    pub max_elevation: f32,
+    pub projection: PanoramaProjection,
+    /// Number of perspective views rendered around the viewer and stitched together
+    pub slices: u32,
+    pub labels: bool,
+}
+
+impl Default for PanoramaOptions {
+    fn default() -> Self {
+        Self {
+            width: 8192,
+            min_elevation: -20.0,
+            max_elevation: 30.0,
+            projection: PanoramaProjection::default(),
+            slices: 8,
+            labels: true,
+        }
+    }
+}
+
+/// Mapping between the panorama pixels, viewing directions and the rendered slices
+///
+/// Azimuths are in radians clockwise from the left edge of the panorama,
+/// which is at `heading - 180°`
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PanoramaGeometry {
+    projection: PanoramaProjection,
+    /// Azimuth of the middle of the panorama (radians clockwise from north)
+    heading: f32,
+    size: Size<u32>,
+    /// Pixels per radian along the horizon
+    scale: f32,
+    /// Projected height of the top edge
+    top: f32,
+    slices: u32,
+    slice_size: Size<u32>,
+    /// Tangents of half of the fields of view of the slices
+    slice_tan_half_fov_x: f32,
+    slice_tan_half_fov_y: f32,
+}
+
+impl PanoramaGeometry {
+    fn new(options: &PanoramaOptions, heading: f32) -> Result<Self> {
+        let PanoramaOptions {
+            width,
+            min_elevation,
+            max_elevation,
+            projection,
+            slices,
+            labels: _,
+        } = *options;
+
+        if width == 0 {
+            return Err(eyre!("Panorama width must be positive"));
+        }
+        if slices < 3 {
+            return Err(eyre!("At least 3 slices are needed for a panorama"));
+        }
+        if !(-90.0 < min_elevation && min_elevation < max_elevation && max_elevation < 90.0) {
+            return Err(eyre!(
+                "Invalid elevation range {min_elevation}°..{max_elevation}°, it must be within -90°..90°"
+            ));
+        }
+
+        let scale = width as f32 / TAU;
+        let top = projection.project(max_elevation.to_radians());
+        let bottom = projection.project(min_elevation.to_radians());
+        let height = (((top - bottom) * scale).round() as u32).max(1);
+
+        // the slices need to cover the elevation range at their edges too,
+        // where it's stretched the most by the perspective projection
+        let tan_half_angle = (PI / slices as f32).tan();
+        let max_abs_elevation = max_elevation.abs().max(min_elevation.abs()).to_radians();
+        let tan_half_fov_y =
+            (max_abs_elevation.tan() / (PI / slices as f32).cos()).max((0.5 * MIN_FOV).tan());
+        if tan_half_fov_y > (0.5 * MAX_FOV).tan() {
+            return Err(eyre!(
+                "Elevation range too large for {slices} slices, try using more of them"
+            ));
+        }
+
+        let slice_width = (2.0 * scale * tan_half_angle).ceil() as u32 + 2 * SLICE_MARGIN;
+        let tan_half_fov_x =
+            tan_half_angle * slice_width as f32 / (slice_width - 2 * SLICE_MARGIN) as f32;
+        let slice_height = (slice_width as f32 * tan_half_fov_y / tan_half_fov_x).ceil() as u32;
+        if slice_width > MAX_SLICE_SIZE || slice_height > MAX_SLICE_SIZE {
+            return Err(eyre!(
+                "Slices of {slice_width}x{slice_height} pixels are too large, use more slices or a smaller width"
+            ));
+        }
+
+        Ok(Self {
+            projection,
+            heading,
+            size: (width, height).into(),
+            scale,
+            top,
+            slices,
+            slice_size: (slice_width, slice_height).into(),
+            slice_tan_half_fov_x: tan_half_fov_x,
+            // rounding the height up makes the field of view slightly larger
+            slice_tan_half_fov_y: tan_half_fov_x * slice_height as f32 / slice_width as f32,
+        })
+    }
+
+    fn slice_angle(&self) -> f32 {
+        TAU / self.slices as f32
+    }
+
+    fn slice_fov_y(&self) -> f32 {
+        2.0 * self.slice_tan_half_fov_y.atan()
+    }
+
+    /// Direction of the middle of the slice (radians clockwise from north)
+    fn slice_heading(&self, slice: u32) -> f32 {
+        self.heading - PI + (slice as f32 + 0.5) * self.slice_angle()
+    }
+
+    /// Converts an azimuth clockwise from north to one relative to the left edge
+    fn relative_azimuth(&self, azimuth: f32) -> f32 {
+        (azimuth - self.heading + PI).rem_euclid(TAU)
+    }
+
+    /// Position on the panorama (in pixels) of a direction given by its
+    /// azimuth (radians clockwise from north) and elevation angle
+    fn pixel_at(&self, azimuth: f32, elevation: f32) -> (f32, f32) {
+        (
+            self.relative_azimuth(azimuth) * self.scale,
+            (self.top - self.projection.project(elevation)) * self.scale,
+        )
+    }
+
+    /// Relative azimuth and elevation angle of the center of the pixel
+    fn direction_at(&self, x: u32, y: u32) -> (f32, f32) {
+        (
+            (x as f32 + 0.5) / self.scale,
+            self.projection
+                .unproject(self.top - (y as f32 + 0.5) / self.scale),
+        )
+    }
+
+    /// Slice showing the direction and the position on it (in pixels)
+    fn slice_pixel_at(&self, relative_azimuth: f32, elevation: f32) -> (usize, f32, f32) {
+        let slice = ((relative_azimuth / self.slice_angle()) as u32).min(self.slices - 1);
+        let offset = relative_azimuth - (slice as f32 + 0.5) * self.slice_angle();
+
+        let x = offset.tan() / self.slice_tan_half_fov_x;
+        let y = elevation.tan() / offset.cos() / self.slice_tan_half_fov_y;
+
+        (
+            slice as usize,
+            0.5 * (x + 1.0) * self.slice_size.width as f32,
+            0.5 * (1.0 - y) * self.slice_size.height as f32,
+        )
+    }
+
+    /// Height and top offset of the cropped image within the full panorama, as described by GPano,
+    /// which only knows the equirectangular projection
+    fn full_height_and_top(&self) -> Option<(u32, u32)> {
+        match self.projection {
+            PanoramaProjection::Equirectangular => Some((
+                (PI * self.scale).round() as u32,
+                ((FRAC_PI_2 - self.top) * self.scale).round() as u32,
+            )),
+            PanoramaProjection::Cylindrical => None,
+        }
+    }
+}
+
+/// 360° panorama around the camera, with north wherever the camera heading puts it
+pub struct Panorama {
+    pub image: RgbaImage,
+    geometry: PanoramaGeometry,
+}
+
+impl Panorama {
+    /// Photo Sphere (GPano) metadata, so that panorama viewers recognize the image,
+    /// none for the cylindrical projection which they don't support
+    pub fn xmp(&self) -> Option<String> {
+        let geometry = &self.geometry;
+        let (full_height, top) = geometry.full_height_and_top()?;
+        Some(format!(
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:GPano="http://ns.google.com/photos/1.0/panorama/">
+   <GPano:UsePanoramaViewer>True</GPano:UsePanoramaViewer>
+   <GPano:ProjectionType>equirectangular</GPano:ProjectionType>
+   <GPano:PoseHeadingDegrees>{heading:.1}</GPano:PoseHeadingDegrees>
+   <GPano:CroppedAreaImageWidthPixels>{width}</GPano:CroppedAreaImageWidthPixels>
+   <GPano:CroppedAreaImageHeightPixels>{height}</GPano:CroppedAreaImageHeightPixels>
+   <GPano:FullPanoWidthPixels>{width}</GPano:FullPanoWidthPixels>
+   <GPano:FullPanoHeightPixels>{full_height}</GPano:FullPanoHeightPixels>
+   <GPano:CroppedAreaLeftPixels>0</GPano:CroppedAreaLeftPixels>
+   <GPano:CroppedAreaTopPixels>{top}</GPano:CroppedAreaTopPixels>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#,
+            heading = geometry.heading.to_degrees().rem_euclid(360.0),
+            width = geometry.size.width,
+            height = geometry.size.height,
+        ))
+    }
+
+    /// Saves the panorama as PNG with the metadata from [`Self::xmp`] embedded
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).wrap_err_with(|| format!("Unable to create {}", path.display()))?;
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            self.image.width(),
+            self.image.height(),
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some(xmp) = self.xmp() {
+            encoder.add_itxt_chunk("XML:com.adobe.xmp".to_string(), xmp)?;
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.image.as_raw())?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Renders a 360° panorama from the camera position, centered on its current heading
+///
+/// The view is rendered as a number of perspective slices around the camera, which
+/// get resampled into the panorama's projection. Labels of the peaks visible in any of
+/// the slices are laid out once for the whole panorama. The renderer's size and camera
+/// are restored afterwards
+pub async fn render_panorama(
+    renderer: &mut HeadlessRenderer,
+    options: &PanoramaOptions,
+) -> Result<Panorama> {
+    let camera = renderer.data().camera;
+    let size = renderer.size();
+    let geometry = PanoramaGeometry::new(options, camera.azimuth().to_radians())?;
+
+    renderer.engine_mut().set_show_labels(false);
+    let slices = render_slices(renderer, &geometry).await;
+    renderer.engine_mut().set_show_labels(true);
+    renderer.data_mut().camera = camera;
+    renderer.resize(size);
+    let (slices, visible_peaks) = slices?;
+
+    // until the next render, the peaks are marked as visible if they're visible on the panorama
+    mark_visible_peaks(renderer.data_mut(), &visible_peaks);
+
+    let mut image = RgbaImage::new(geometry.size.width, geometry.size.height);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (azimuth, elevation) = geometry.direction_at(x, y);
+        let (slice, slice_x, slice_y) = geometry.slice_pixel_at(azimuth, elevation);
+        *pixel = sample_bilinear(&slices[slice], slice_x, slice_y);
+    }
+
+    if options.labels {
+        draw_panorama_labels(&mut image, &geometry, renderer.data(), &visible_peaks);
+    }
+
+    Ok(Panorama { image, geometry })
+}
+
+async fn render_slices(
+    renderer: &mut HeadlessRenderer,
+    geometry: &PanoramaGeometry,
+) -> Result<(Vec<RgbaImage>, BTreeSet<(GeoLocation, LabelId)>)> {
+    renderer.resize(geometry.slice_size);
+
+    let mut slices = Vec::with_capacity(geometry.slices as usize);
+    let mut visible_peaks = BTreeSet::new();
+
+    for slice in 0..geometry.slices {
+        let camera = &mut renderer.data_mut().camera;
+        camera.set_pitch(0.0);
+        camera.set_fovy(geometry.slice_fov_y());
+        camera.set_azimuth(geometry.slice_heading(slice).to_degrees());
+
+        slices.push(renderer.render().await?);
+
+        visible_peaks.extend(visible_peak_ids(renderer.data()));
+    }
+
+    Ok((slices, visible_peaks))
+}
+
+fn sample_bilinear(image: &RgbaImage, x: f32, y: f32) -> Rgba<u8> {
+    // pixel centers are at half coordinates
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let pixel = |dx: f32, dy: f32| {
+        let x = (x0 + dx).clamp(0.0, (image.width() - 1) as f32) as u32;
+        let y = (y0 + dy).clamp(0.0, (image.height() - 1) as f32) as u32;
+        image.get_pixel(x, y).0
+    };
+    let (p00, p10, p01, p11) = (
+        pixel(0.0, 0.0),
+        pixel(1.0, 0.0),
+        pixel(0.0, 1.0),
+        pixel(1.0, 1.0),
+    );
+
+    Rgba(std::array::from_fn(|c| {
+        let top = p00[c] as f32 * (1.0 - fx) + p10[c] as f32 * fx;
+        let bottom = p01[c] as f32 * (1.0 - fx) + p11[c] as f32 * fx;
+        (top * (1.0 - fy) + bottom * fy).round() as u8
+    }))
+}
+
+/// Lays out the labels of the peaks visible on the panorama, wrapping around its edges
+fn draw_panorama_labels(
+    image: &mut RgbaImage,
+    geometry: &PanoramaGeometry,
+    data: &ApplicationData,
+    visible_peaks: &BTreeSet<(GeoLocation, LabelId)>,
+) {
+    let eye = data.camera.eye;
+    let up = data.camera.up();
+    let (north, east) = data.camera.north_east();
+
+    let mut positions = BTreeMap::<_, Vec<_>>::new();
+    for &(location, id) in visible_peaks {
+        let Some(peak) = data
+            .peaks
+            .get(&location)
+            .and_then(|peaks| peaks.get(id.0 as usize))
+        else {
+            continue;
+        };
+
+        let direction = (data.camera.apparent_position(peak.position) - eye).normalize();
+        let (x, y) = geometry.pixel_at(
+            direction.dot(east).atan2(direction.dot(north)),
+            direction.dot(up).asin(),
+        );
+        if (0.0..geometry.size.height as f32).contains(&y) {
+            positions
+                .entry(location)
+                .or_default()
+                .push((id, (x as u32, y as u32)));
+        }
+    }
+
+    draw_labels(image, positions, data, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use rstest::rstest;
+    use topo_common::GeoCoord;
+
+    use crate::{
+        headless::tests::with_test_renderer,
+        render::text_renderer::{LINE_HEIGHT, LINE_PADDING},
+        settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[rstest]
+    #[case(PanoramaProjection::Equirectangular)]
+    #[case(PanoramaProjection::Cylindrical)]
+    fn pixels_map_back_to_their_directions(#[case] projection: PanoramaProjection) {
+        let options = PanoramaOptions {
+            width: 3600,
+            projection,
+            ..Default::default()
+        };
+        let geometry = PanoramaGeometry::new(&options, 90f32.to_radians()).unwrap();
+
+        for (x, y) in [(0, 0), (1800, 200), (3599, geometry.size.height - 1)] {
+            let (azimuth, elevation) = geometry.direction_at(x, y);
+            let (pixel_x, pixel_y) = geometry.pixel_at(azimuth + geometry.heading - PI, elevation);
+            assert_abs_diff_eq!(pixel_x, x as f32 + 0.5, epsilon = 1e-2);
+            assert_abs_diff_eq!(pixel_y, y as f32 + 0.5, epsilon = 1e-2);
+        }
+    }
+
+    #[rstest]
+    #[case(PanoramaProjection::Equirectangular)]
+    #[case(PanoramaProjection::Cylindrical)]
+    fn elevation_range_fits_in_slices(#[case] projection: PanoramaProjection) {
+        let options = PanoramaOptions {
+            width: 4000,
+            min_elevation: -10.0,
+            max_elevation: 40.0,
+            projection,
+            ..Default::default()
+        };
+        let geometry = PanoramaGeometry::new(&options, 0.0).unwrap();
+
+        // the middle of the horizon is the middle of the first slice
+        let (slice, x, y) = geometry.slice_pixel_at(0.5 * geometry.slice_angle(), 0.0);
+        assert_eq!(slice, 0);
+        assert_abs_diff_eq!(x, 0.5 * geometry.slice_size.width as f32, epsilon = 1e-2);
+        assert_abs_diff_eq!(y, 0.5 * geometry.slice_size.height as f32, epsilon = 1e-2);
+
+        // top corners are the most stretched
+        for x in [0, geometry.size.width - 1] {
+            let (azimuth, elevation) = geometry.direction_at(x, 0);
+            let (_, slice_x, slice_y) = geometry.slice_pixel_at(azimuth, elevation);
+            assert!((0.0..=geometry.slice_size.width as f32).contains(&slice_x));
+            assert!((0.0..=geometry.slice_size.height as f32).contains(&slice_y));
+        }
+    }
+
+    #[rstest]
+    #[case(PanoramaProjection::Equirectangular, true)]
+    #[case(PanoramaProjection::Cylindrical, false)]
+    fn only_equirectangular_panoramas_are_photo_spheres(
+        #[case] projection: PanoramaProjection,
+        #[case] has_xmp: bool,
+    ) {
+        let options = PanoramaOptions {
+            width: 3600,
+            projection,
+            ..Default::default()
+        };
+        let geometry = PanoramaGeometry::new(&options, 90f32.to_radians()).unwrap();
+        let panorama = Panorama {
+            image: RgbaImage::new(geometry.size.width, geometry.size.height),
+            geometry,
+        };
+
+        let xmp = panorama.xmp();
+        assert_eq!(xmp.is_some(), has_xmp);
+        if let Some(xmp) = xmp {
+            assert!(xmp.contains("<GPano:ProjectionType>equirectangular<"));
+            assert!(xmp.contains("<GPano:PoseHeadingDegrees>90.0<"));
+            assert!(xmp.contains("<GPano:FullPanoHeightPixels>1800<"));
+            // 30° above the horizon
+            assert!(xmp.contains("<GPano:CroppedAreaTopPixels>600<"));
+        }
+    }
+
+    #[test]
+    fn renders_panorama_with_labels() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(0.0),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+        let options = PanoramaOptions {
+            width: 1024,
+            projection: PanoramaProjection::Cylindrical,
+            ..Default::default()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, _| {
+            renderer
+                .load_location(GeoCoord::new(49.47, 20.5))
+                .await
+                .unwrap();
+            let panorama = render_panorama(renderer, &options).await.unwrap();
+
+            assert_eq!(panorama.image.width(), 1024);
+            assert_eq!(renderer.size(), (160, 120).into());
+            // panorama viewers only know equirectangular photo spheres
+            assert_eq!(panorama.xmp(), None);
+            // the peak is due north, in the middle of the panorama, with its label above it
+            assert!(
+                renderer
+                    .data()
+                    .peaks
+                    .values()
+                    .flatten()
+                    .all(|peak| peak.visible)
+            );
+            let label_background = panorama.image.get_pixel(
+                512 + LINE_HEIGHT as u32,
+                (LINE_HEIGHT + LINE_PADDING) as u32 / 2 + 1,
+            );
+            assert_eq!(label_background.0, [255, 255, 255, 255]);
+        });
+    }
+}
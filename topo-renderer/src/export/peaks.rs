@@ -0,0 +1,233 @@
+use std::path::Path;
+
+use color_eyre::Result;
+use serde::Serialize;
+use serde_json::json;
+use strum::{Display, EnumString};
+use topo_common::GeoCoord;
+
+use crate::{
+    data::{Size, application_data::ApplicationData, camera::Camera},
+    render::{
+        data::PeakInstance,
+        geometry::{bearing, inverse_transform, surface_distance},
+    },
+};
+
+/// File formats the visible peaks can be exported to
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum PeaksFormat {
+    #[default]
+    Json,
+    Csv,
+    GeoJson,
+}
+
+impl PeaksFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// A peak as seen from the camera
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VisiblePeak {
+    pub name: String,
+    pub coord: GeoCoord,
+    /// Meters above sea level
+    pub elevation: f32,
+    /// Distance along the ground (in meters)
+    pub distance: f32,
+    /// Direction of the peak (in degrees clockwise from north)
+    pub azimuth: f32,
+    /// Angle of the peak (in degrees above the horizon)
+    pub elevation_angle: f32,
+    /// Pixels from the top left corner of the view, `None` if the peak is outside of it
+    pub screen_position: Option<(f32, f32)>,
+}
+
+impl VisiblePeak {
+    pub fn new(peak: &PeakInstance, camera: &Camera, bounds: Size<f32>) -> Self {
+        let (_, eye_coord) = inverse_transform(camera.eye);
+        // seen where the refracted rays come from
+        let position = camera.apparent_position(peak.position);
+        let direction = (position - camera.eye).normalize();
+
+        let projected = camera
+            .build_view_proj_matrix(bounds.width, bounds.height)
+            .project_point3(position);
+        let screen_position =
+            (projected.x.abs() < 1.0 && projected.y.abs() < 1.0 && projected.z < 1.0).then(|| {
+                (
+                    0.5 * (projected.x + 1.0) * bounds.width,
+                    -0.5 * (projected.y - 1.0) * bounds.height,
+                )
+            });
+
+        Self {
+            name: peak.name.clone(),
+            coord: peak.coord,
+            elevation: peak.elevation,
+            distance: surface_distance(eye_coord, peak.coord),
+            azimuth: bearing(eye_coord, peak.coord),
+            elevation_angle: direction.dot(camera.up()).asin().to_degrees(),
+            screen_position,
+        }
+    }
+}
+
+/// Peaks found visible when the labels were last laid out, nearest first
+pub fn visible_peaks(data: &ApplicationData, bounds: Size<f32>) -> Vec<VisiblePeak> {
+    let mut peaks = data
+        .peaks
+        .values()
+        .flatten()
+        .filter(|peak| peak.visible)
+        .map(|peak| VisiblePeak::new(peak, &data.camera, bounds))
+        .collect::<Vec<_>>();
+    peaks.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    peaks
+}
+
+/// Flat record for the CSV export, the screen position is empty for peaks outside of the view
+#[derive(Serialize)]
+struct CsvPeak<'a> {
+    name: &'a str,
+    latitude: f32,
+    longitude: f32,
+    elevation: f32,
+    distance: f32,
+    azimuth: f32,
+    elevation_angle: f32,
+    screen_x: Option<f32>,
+    screen_y: Option<f32>,
+}
+
+/// Serializes the peaks to the given format
+pub fn export_peaks(peaks: &[VisiblePeak], format: PeaksFormat) -> Result<String> {
+    match format {
+        PeaksFormat::Json => Ok(serde_json::to_string_pretty(peaks)?),
+        PeaksFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for peak in peaks {
+                writer.serialize(CsvPeak {
+                    name: &peak.name,
+                    latitude: peak.coord.latitude,
+                    longitude: peak.coord.longitude,
+                    elevation: peak.elevation,
+                    distance: peak.distance,
+                    azimuth: peak.azimuth,
+                    elevation_angle: peak.elevation_angle,
+                    screen_x: peak.screen_position.map(|(x, _)| x),
+                    screen_y: peak.screen_position.map(|(_, y)| y),
+                })?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        PeaksFormat::GeoJson => {
+            let features = peaks
+                .iter()
+                .map(|peak| {
+                    json!({
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Point",
+                            "coordinates": [peak.coord.longitude, peak.coord.latitude, peak.elevation],
+                        },
+                        "properties": {
+                            "name": peak.name,
+                            "elevation": peak.elevation,
+                            "distance": peak.distance,
+                            "azimuth": peak.azimuth,
+                            "elevation_angle": peak.elevation_angle,
+                            "screen_position": peak.screen_position,
+                        },
+                    })
+                })
+                .collect::<Vec<_>>();
+            Ok(serde_json::to_string_pretty(&json!({
+                "type": "FeatureCollection",
+                "features": features,
+            }))?)
+        }
+    }
+}
+
+/// Writes the peaks to a file, the format follows from its extension (JSON if unknown)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_peaks(peaks: &[VisiblePeak], path: &Path) -> Result<()> {
+    use color_eyre::eyre::Context;
+
+    let format = PeaksFormat::from_path(path).unwrap_or_default();
+    std::fs::write(path, export_peaks(peaks, format)?)
+        .wrap_err_with(|| format!("Unable to save peaks to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn peaks() -> Vec<VisiblePeak> {
+        vec![
+            VisiblePeak {
+                name: "Near, Peak".to_string(),
+                coord: GeoCoord::new(49.5, 20.5),
+                elevation: 2500.0,
+                distance: 3300.0,
+                azimuth: 0.0,
+                elevation_angle: -8.5,
+                screen_position: Some((80.0, 60.5)),
+            },
+            VisiblePeak {
+                name: "Far Peak".to_string(),
+                coord: GeoCoord::new(49.6, 20.25),
+                elevation: 1800.0,
+                distance: 20000.0,
+                azimuth: 300.0,
+                elevation_angle: -3.0,
+                screen_position: None,
+            },
+        ]
+    }
+
+    #[rstest]
+    #[case("peaks.json", Some(PeaksFormat::Json))]
+    #[case("peaks.CSV", Some(PeaksFormat::Csv))]
+    #[case("peaks.geojson", Some(PeaksFormat::GeoJson))]
+    #[case("peaks.txt", None)]
+    fn format_from_path(#[case] path: &str, #[case] expected: Option<PeaksFormat>) {
+        assert_eq!(PeaksFormat::from_path(Path::new(path)), expected);
+    }
+
+    #[test]
+    fn exports_csv_with_empty_screen_position_outside_of_view() {
+        let csv = export_peaks(&peaks(), PeaksFormat::Csv).unwrap();
+
+        assert_eq!(
+            csv,
+            "name,latitude,longitude,elevation,distance,azimuth,elevation_angle,screen_x,screen_y\n\
+             \"Near, Peak\",49.5,20.5,2500.0,3300.0,0.0,-8.5,80.0,60.5\n\
+             Far Peak,49.6,20.25,1800.0,20000.0,300.0,-3.0,,\n"
+        );
+    }
+
+    #[test]
+    fn exports_geojson_points_with_lon_lat_order() {
+        let geojson: serde_json::Value =
+            serde_json::from_str(&export_peaks(&peaks(), PeaksFormat::GeoJson).unwrap()).unwrap();
+
+        assert_eq!(geojson["type"], "FeatureCollection");
+        assert_eq!(
+            geojson["features"][0]["geometry"]["coordinates"],
+            json!([20.5, 49.5, 2500.0])
+        );
+        assert_eq!(geojson["features"][0]["properties"]["name"], "Near, Peak");
+        assert_eq!(
+            geojson["features"][1]["properties"]["screen_position"],
+            serde_json::Value::Null
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use color_eyre::{Result, eyre::eyre};
+use glam::Vec2;
+use image::{GenericImage, GenericImageView, RgbaImage};
+use topo_common::GeoLocation;
+
+use crate::{
+    data::{Size, camera::ViewTile},
+    export::labels::{draw_labels, mark_visible_peaks, visible_peak_ids},
+    headless::HeadlessRenderer,
+    render::text_renderer::LabelId,
+};
+
+/// Largest texture side guaranteed by the default wgpu limits
+const MAX_TILE_SIZE: u32 = 8192;
+/// Pixels by which neighboring tiles overlap on each side, so that peaks right
+/// at the seams are still within the view when checking their visibility
+const TILE_MARGIN: u32 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PosterOptions {
+    /// Size of the whole image in pixels
+    pub size: Size<u32>,
+    /// Largest side of the separately rendered tiles in pixels, their margins included
+    pub max_tile_size: u32,
+    pub labels: bool,
+}
+
+impl Default for PosterOptions {
+    fn default() -> Self {
+        Self {
+            size: (20000, 4000).into(),
+            max_tile_size: 4096,
+            labels: true,
+        }
+    }
+}
+
+/// Split of the poster into equally sized tiles rendered with off-center projections
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PosterTiles {
+    size: Size<u32>,
+    columns: u32,
+    rows: u32,
+    /// Pixels of the poster covered by each tile, without the margins
+    tile_size: Size<u32>,
+}
+
+impl PosterTiles {
+    fn new(options: &PosterOptions) -> Result<Self> {
+        let PosterOptions {
+            size,
+            max_tile_size,
+            labels: _,
+        } = *options;
+
+        if size.width == 0 || size.height == 0 {
+            return Err(eyre!("Poster size must be positive"));
+        }
+        if !(2 * TILE_MARGIN < max_tile_size && max_tile_size <= MAX_TILE_SIZE) {
+            return Err(eyre!(
+                "Tile size must be between {} and {MAX_TILE_SIZE} pixels",
+                2 * TILE_MARGIN + 1
+            ));
+        }
+
+        let max_inner_size = max_tile_size - 2 * TILE_MARGIN;
+        let columns = size.width.div_ceil(max_inner_size);
+        let rows = size.height.div_ceil(max_inner_size);
+
+        Ok(Self {
+            size,
+            columns,
+            rows,
+            tile_size: (size.width.div_ceil(columns), size.height.div_ceil(rows)).into(),
+        })
+    }
+
+    /// Size of the rendered tiles, margins included
+    fn render_size(&self) -> Size<u32> {
+        (
+            self.tile_size.width + 2 * TILE_MARGIN,
+            self.tile_size.height + 2 * TILE_MARGIN,
+        )
+            .into()
+    }
+
+    /// Position of the tile's top left corner (without the margin) on the poster
+    fn tile_origin(&self, column: u32, row: u32) -> (u32, u32) {
+        (column * self.tile_size.width, row * self.tile_size.height)
+    }
+
+    /// Part of the whole view rendered for the tile, margins included
+    fn view_tile(&self, column: u32, row: u32) -> ViewTile {
+        let (x, y) = self.tile_origin(column, row);
+        let render_size = self.render_size();
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+
+        let left = x as f32 - TILE_MARGIN as f32;
+        let right = left + render_size.width as f32;
+        let top = y as f32 - TILE_MARGIN as f32;
+        let bottom = top + render_size.height as f32;
+
+        ViewTile {
+            aspect_ratio: width / height,
+            min: Vec2::new(2.0 * left / width - 1.0, 1.0 - 2.0 * bottom / height),
+            max: Vec2::new(2.0 * right / width - 1.0, 1.0 - 2.0 * top / height),
+        }
+    }
+}
+
+/// Renders the current view at a resolution beyond the limits of a single render target
+///
+/// The view is split into tiles, each rendered with the matching off-center projection
+/// and copied into the poster. Labels of the peaks visible in any of the tiles are laid
+/// out once for the whole poster. The renderer's size and camera are restored afterwards
+pub async fn render_poster(
+    renderer: &mut HeadlessRenderer,
+    options: &PosterOptions,
+) -> Result<RgbaImage> {
+    let camera = renderer.data().camera;
+    let size = renderer.size();
+    let tiles = PosterTiles::new(options)?;
+
+    let mut image = RgbaImage::new(tiles.size.width, tiles.size.height);
+
+    renderer.engine_mut().set_show_labels(false);
+    let visible_peaks = render_tiles(renderer, &tiles, &mut image).await;
+    renderer.engine_mut().set_show_labels(true);
+    renderer.data_mut().camera = camera;
+    renderer.resize(size);
+    let visible_peaks = visible_peaks?;
+
+    // until the next render, the peaks are marked as visible if they're visible on the poster
+    mark_visible_peaks(renderer.data_mut(), &visible_peaks);
+
+    if options.labels {
+        let data = renderer.data();
+        let projection =
+            camera.build_view_proj_matrix(tiles.size.width as f32, tiles.size.height as f32);
+
+        let mut positions = BTreeMap::<_, Vec<_>>::new();
+        for &(location, id) in &visible_peaks {
+            let Some(peak) = data
+                .peaks
+                .get(&location)
+                .and_then(|peaks| peaks.get(id.0 as usize))
+            else {
+                continue;
+            };
+
+            let projected = projection.project_point3(camera.apparent_position(peak.position));
+            let x = 0.5 * (projected.x + 1.0) * tiles.size.width as f32;
+            let y = 0.5 * (1.0 - projected.y) * tiles.size.height as f32;
+            if (0.0..tiles.size.width as f32).contains(&x)
+                && (0.0..tiles.size.height as f32).contains(&y)
+            {
+                positions
+                    .entry(location)
+                    .or_default()
+                    .push((id, (x as u32, y as u32)));
+            }
+        }
+
+        draw_labels(&mut image, positions, data, false);
+    }
+
+    Ok(image)
+}
+
+async fn render_tiles(
+    renderer: &mut HeadlessRenderer,
+    tiles: &PosterTiles,
+    image: &mut RgbaImage,
+) -> Result<BTreeSet<(GeoLocation, LabelId)>> {
+    renderer.resize(tiles.render_size());
+
+    let mut visible_peaks = BTreeSet::new();
+
+    for row in 0..tiles.rows {
+        for column in 0..tiles.columns {
+            renderer.data_mut().camera.tile = Some(tiles.view_tile(column, row));
+            let tile = renderer.render().await?;
+            visible_peaks.extend(visible_peak_ids(renderer.data()));
+
+            // the last row and column may reach past the poster's edges
+            let (x, y) = tiles.tile_origin(column, row);
+            let width = tiles.tile_size.width.min(tiles.size.width - x);
+            let height = tiles.tile_size.height.min(tiles.size.height - y);
+            image.copy_from(&*tile.view(TILE_MARGIN, TILE_MARGIN, width, height), x, y)?;
+        }
+    }
+
+    Ok(visible_peaks)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use topo_common::GeoCoord;
+
+    use crate::{headless::tests::with_test_renderer, settings::RendererSettings};
+
+    use super::*;
+
+    #[test]
+    fn tiles_cover_the_whole_view() {
+        let tiles = PosterTiles::new(&PosterOptions {
+            size: (20000, 4000).into(),
+            max_tile_size: 4096,
+            labels: true,
+        })
+        .unwrap();
+
+        assert_eq!((tiles.columns, tiles.rows), (5, 1));
+        assert!(tiles.render_size().width <= 4096);
+        let (x, y) = tiles.tile_origin(tiles.columns - 1, tiles.rows - 1);
+        assert!(x + tiles.tile_size.width >= 20000);
+        assert!(y + tiles.tile_size.height >= 4000);
+
+        // neighboring tiles overlap by the margins on both sides
+        let first = tiles.view_tile(0, 0);
+        let second = tiles.view_tile(1, 0);
+        let pixel = 2.0 / 20000.0;
+        assert_abs_diff_eq!(
+            first.min.x,
+            -1.0 - TILE_MARGIN as f32 * pixel,
+            epsilon = 1e-6
+        );
+        assert_abs_diff_eq!(
+            first.max.x - second.min.x,
+            2.0 * TILE_MARGIN as f32 * pixel,
+            epsilon = 1e-6
+        );
+        assert_abs_diff_eq!(first.max.y, 1.0 + TILE_MARGIN as f32 * 2.0 / 4000.0);
+    }
+
+    #[test]
+    fn tiled_render_matches_single_render() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(0.0),
+            start_pitch: Some(-10.0),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+        let options = PosterOptions {
+            size: (320, 240).into(),
+            max_tile_size: 96,
+            labels: true,
+        };
+
+        with_test_renderer(settings, (320, 240).into(), async |renderer, _| {
+            renderer
+                .load_location(GeoCoord::new(49.47, 20.5))
+                .await
+                .unwrap();
+            renderer.engine_mut().set_show_labels(false);
+            let single = renderer.render().await.unwrap();
+            renderer.engine_mut().set_show_labels(true);
+            renderer.resize((160, 120).into());
+            let poster = render_poster(renderer, &options).await.unwrap();
+
+            assert_eq!(poster.dimensions(), (320, 240));
+            assert_eq!(renderer.size(), (160, 120).into());
+            assert_eq!(renderer.data().camera.tile, None);
+            assert!(
+                renderer
+                    .data()
+                    .peaks
+                    .values()
+                    .flatten()
+                    .all(|peak| peak.visible)
+            );
+
+            // apart from the label, the tiles line up with the single render
+            let differing = single
+                .pixels()
+                .zip(poster.pixels())
+                .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > 8))
+                .count();
+            assert!(differing > 0);
+            assert!(differing < 320 * 240 / 20, "{differing} pixels differ");
+        });
+    }
+}
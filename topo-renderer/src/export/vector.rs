@@ -0,0 +1,603 @@
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::Path as FilePath};
+
+use color_eyre::{
+    Result,
+    eyre::{Context, OptionExt},
+};
+use glam::{Vec2, vec2};
+use lyon::path::{Path, PathEvent};
+use strum::{Display, EnumString};
+
+use crate::{
+    data::{Size, application_data::ApplicationData, camera::dist_from_depth},
+    export::peaks::VisiblePeak,
+    headless::HeadlessRenderer,
+    render::text_renderer::{
+        LABEL_PADDING_LEFT, LINE_HEIGHT, LINE_PADDING, LabelId, LabelLayout, TextRenderer,
+        layout_labels,
+    },
+};
+
+const NAME_FONT_SIZE: f32 = 12.0;
+const DETAILS_FONT_SIZE: f32 = 9.0;
+const DETAILS_LINE_HEIGHT: f32 = 12.0;
+const LABEL_HEIGHT: f32 = LINE_HEIGHT + DETAILS_LINE_HEIGHT;
+/// Silhouettes traced over fewer pixels are considered noise
+const MIN_SILHOUETTE_PIXELS: usize = 4;
+/// Maximum distance (in pixels) of the simplified silhouettes from the traced ones
+const SIMPLIFY_TOLERANCE: f32 = 1.0;
+const SILHOUETTE_WIDTH: f32 = 1.5;
+const LEADER_LINE_WIDTH: f32 = 1.0;
+const MM_TO_PT: f32 = 72.0 / 25.4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum VectorFormat {
+    Svg,
+    Pdf,
+}
+
+impl VectorFormat {
+    pub fn from_path(path: &FilePath) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VectorOptions {
+    /// Width of the drawing (in millimeters), the height follows from the view's aspect ratio
+    pub width_mm: f32,
+    /// Relative jump of the distance between neighboring pixels that counts as a silhouette
+    pub silhouette_threshold: f32,
+}
+
+impl Default for VectorOptions {
+    fn default() -> Self {
+        Self {
+            width_mm: 1000.0,
+            silhouette_threshold: 0.05,
+        }
+    }
+}
+
+/// Peak label with its text converted to outlines
+pub struct VectorLabel {
+    pub peak: VisiblePeak,
+    pub label_x: f32,
+    pub label_y: f32,
+    pub label_width: f32,
+    pub peak_x: f32,
+    pub peak_y: f32,
+    name: Path,
+    details: Path,
+}
+
+impl VectorLabel {
+    /// Elevation, distance and bearing, as shown under the name
+    pub fn details(peak: &VisiblePeak) -> String {
+        format!(
+            "{:.0} m · {:.1} km · {:.0}°",
+            peak.elevation,
+            peak.distance / 1000.0,
+            peak.azimuth
+        )
+    }
+}
+
+/// Terrain silhouettes and peak labels of a view, in pixels of the rendered view
+pub struct LabeledView {
+    pub size: Size<u32>,
+    pub silhouettes: Vec<Vec<Vec2>>,
+    pub labels: Vec<VectorLabel>,
+}
+
+/// Renders the current view and converts it to vector paths
+///
+/// The silhouettes are traced along the depth discontinuities, the same ones the
+/// postprocessing outlines, so a larger renderer size gives more detailed paths
+pub async fn render_labeled_view(
+    renderer: &mut HeadlessRenderer,
+    options: &VectorOptions,
+) -> Result<LabeledView> {
+    // lays out the labels, which marks the visible peaks
+    renderer.render().await?;
+    let distances = renderer
+        .read_depth()
+        .await?
+        .into_iter()
+        .map(dist_from_depth)
+        .collect::<Vec<_>>();
+
+    let size = renderer.size();
+    Ok(LabeledView {
+        size,
+        silhouettes: extract_silhouettes(&distances, size, options.silhouette_threshold),
+        labels: layout_vector_labels(renderer.data(), size),
+    })
+}
+
+fn layout_vector_labels(data: &ApplicationData, size: Size<u32>) -> Vec<VectorLabel> {
+    let bounds = (size.width as f32, size.height as f32).into();
+
+    let mut positions = BTreeMap::<_, Vec<_>>::new();
+    let mut peaks = BTreeMap::new();
+    for (location, location_peaks) in &data.peaks {
+        for (i, peak) in location_peaks.iter().enumerate() {
+            if !peak.visible {
+                continue;
+            }
+            let peak = VisiblePeak::new(peak, &data.camera, bounds);
+            if let Some((x, y)) = peak.screen_position {
+                let id = LabelId(i as u32);
+                positions
+                    .entry(*location)
+                    .or_default()
+                    .push((id, (x as u32, y as u32)));
+                let (name, name_width) =
+                    TextRenderer::text_outline(&peak.name, NAME_FONT_SIZE, LINE_HEIGHT);
+                let (details, details_width) = TextRenderer::text_outline(
+                    &VectorLabel::details(&peak),
+                    DETAILS_FONT_SIZE,
+                    DETAILS_LINE_HEIGHT,
+                );
+                let width = name_width.max(details_width) + 2.0 * LABEL_PADDING_LEFT;
+                peaks.insert((*location, id), (peak, name, details, width));
+            }
+        }
+    }
+
+    let laid_out_labels = layout_labels(
+        positions,
+        |location, id| peaks.get(&(location, id)).map(|(.., width)| *width),
+        LABEL_HEIGHT + LINE_PADDING,
+    );
+
+    laid_out_labels
+        .into_iter()
+        .filter_map(
+            |LabelLayout {
+                 location,
+                 id,
+                 label_x,
+                 label_y,
+                 label_width,
+                 peak_x,
+                 peak_y,
+             }| {
+                let (peak, name, details, _) = peaks.remove(&(location, id))?;
+                Some(VectorLabel {
+                    peak,
+                    label_x,
+                    label_y,
+                    label_width,
+                    peak_x,
+                    peak_y,
+                    name,
+                    details,
+                })
+            },
+        )
+        .collect()
+}
+
+/// Traces the pixels in front of a jump in the distance to the terrain into polylines
+///
+/// The pixels only get traced on the nearer side of the jump, which keeps the lines one pixel wide
+fn extract_silhouettes(distances: &[f32], size: Size<u32>, threshold: f32) -> Vec<Vec<Vec2>> {
+    const NEIGHBORS: [(i32, i32); 8] = [
+        (1, 0),
+        (-1, 0),
+        (0, 1),
+        (0, -1),
+        (1, 1),
+        (1, -1),
+        (-1, 1),
+        (-1, -1),
+    ];
+    let (width, height) = (size.width as i32, size.height as i32);
+    let index = |x: i32, y: i32| {
+        ((0..width).contains(&x) && (0..height).contains(&y)).then_some((y * width + x) as usize)
+    };
+
+    let edges = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let distance = distances[index(x, y).expect("pixel within the view")];
+            NEIGHBORS[..4].iter().any(|(dx, dy)| {
+                let Some(farther) = index(x + dx, y + dy).map(|i| distances[i]) else {
+                    return false;
+                };
+                // at grazing angles the distance grows quickly without anything being occluded,
+                // so the jump also needs to stand out from the change on the other side
+                let gradient = index(x - dx, y - dy)
+                    .map(|i| distance - distances[i])
+                    .unwrap_or(0.0)
+                    .max(0.0);
+                let jump = farther - distance;
+                jump > threshold * distance && jump > 2.0 * gradient
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut visited = vec![false; edges.len()];
+
+    let follow = |visited: &mut Vec<bool>, (mut x, mut y): (i32, i32)| {
+        let mut chain = vec![];
+        while let Some((next_x, next_y, next)) = NEIGHBORS.iter().find_map(|(dx, dy)| {
+            index(x + dx, y + dy)
+                .filter(|&i| edges[i] && !visited[i])
+                .map(|i| (x + dx, y + dy, i))
+        }) {
+            visited[next] = true;
+            chain.push((next_x, next_y));
+            (x, y) = (next_x, next_y);
+        }
+        chain
+    };
+
+    let mut silhouettes = vec![];
+    for start in 0..edges.len() {
+        if !edges[start] || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let start = (start as i32 % width, start as i32 / width);
+        let forward = follow(&mut visited, start);
+        let backward = follow(&mut visited, start);
+
+        let chain = backward
+            .into_iter()
+            .rev()
+            .chain(std::iter::once(start))
+            .chain(forward)
+            .map(|(x, y)| vec2(x as f32 + 0.5, y as f32 + 0.5))
+            .collect::<Vec<_>>();
+        if chain.len() >= MIN_SILHOUETTE_PIXELS {
+            silhouettes.push(simplify(&chain, SIMPLIFY_TOLERANCE));
+        }
+    }
+
+    silhouettes
+}
+
+/// Ramer–Douglas–Peucker simplification of a polyline
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+        return vec![];
+    };
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let segment = last - first;
+    let distance_from_segment = |point: Vec2| {
+        let t = if segment.length_squared() > 0.0 {
+            ((point - first).dot(segment) / segment.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        point.distance(first + t * segment)
+    };
+
+    let (farthest, distance) = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i + 1, distance_from_segment(*point)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("at least one point between the ends");
+
+    if distance > tolerance {
+        let mut simplified = simplify(&points[..=farthest], tolerance);
+        simplified.pop();
+        simplified.extend(simplify(&points[farthest..], tolerance));
+        simplified
+    } else {
+        vec![first, last]
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn svg_path_data(path: &Path) -> String {
+    let mut data = String::new();
+    for event in path.iter() {
+        let _ = match event {
+            PathEvent::Begin { at } => write!(data, "M{:.2} {:.2}", at.x, at.y),
+            PathEvent::Line { to, .. } => write!(data, "L{:.2} {:.2}", to.x, to.y),
+            PathEvent::Quadratic { ctrl, to, .. } => {
+                write!(data, "Q{:.2} {:.2} {:.2} {:.2}", ctrl.x, ctrl.y, to.x, to.y)
+            }
+            PathEvent::Cubic {
+                ctrl1, ctrl2, to, ..
+            } => write!(
+                data,
+                "C{:.2} {:.2} {:.2} {:.2} {:.2} {:.2}",
+                ctrl1.x, ctrl1.y, ctrl2.x, ctrl2.y, to.x, to.y
+            ),
+            PathEvent::End { close: true, .. } => write!(data, "Z"),
+            PathEvent::End { close: false, .. } => Ok(()),
+        };
+    }
+    data
+}
+
+fn pdf_path_operators(content: &mut String, path: &Path) {
+    for event in path.iter() {
+        let _ = match event {
+            PathEvent::Begin { at } => writeln!(content, "{:.2} {:.2} m", at.x, at.y),
+            PathEvent::Line { to, .. } => writeln!(content, "{:.2} {:.2} l", to.x, to.y),
+            PathEvent::Quadratic { from, ctrl, to } => {
+                // pdf only has cubic curves
+                let ctrl1 = from + (ctrl - from) * (2.0 / 3.0);
+                let ctrl2 = to + (ctrl - to) * (2.0 / 3.0);
+                writeln!(
+                    content,
+                    "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+                    ctrl1.x, ctrl1.y, ctrl2.x, ctrl2.y, to.x, to.y
+                )
+            }
+            PathEvent::Cubic {
+                ctrl1, ctrl2, to, ..
+            } => writeln!(
+                content,
+                "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+                ctrl1.x, ctrl1.y, ctrl2.x, ctrl2.y, to.x, to.y
+            ),
+            PathEvent::End { close: true, .. } => writeln!(content, "h"),
+            PathEvent::End { close: false, .. } => Ok(()),
+        };
+    }
+}
+
+impl LabeledView {
+    fn height_mm(&self, options: &VectorOptions) -> f32 {
+        options.width_mm * self.size.height as f32 / self.size.width as f32
+    }
+
+    pub fn to_svg(&self, options: &VectorOptions) -> String {
+        let (width, height) = (self.size.width, self.size.height);
+        let mut svg = String::new();
+        let _ = writeln!(svg, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.1}mm" height="{:.1}mm" viewBox="0 0 {width} {height}">"#,
+            options.width_mm,
+            self.height_mm(options),
+        );
+
+        let _ = writeln!(
+            svg,
+            r##"<g id="silhouettes" fill="none" stroke="#000" stroke-width="{SILHOUETTE_WIDTH}" stroke-linecap="round" stroke-linejoin="round">"##
+        );
+        for silhouette in &self.silhouettes {
+            let points = silhouette
+                .iter()
+                .map(|point| format!("{:.1},{:.1}", point.x, point.y))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let _ = writeln!(svg, r#"<polyline points="{points}"/>"#);
+        }
+        let _ = writeln!(svg, "</g>");
+
+        let _ = writeln!(svg, r#"<g id="labels">"#);
+        for label in &self.labels {
+            let peak = &label.peak;
+            let _ = writeln!(
+                svg,
+                r#"<g class="peak" data-name="{name}" data-latitude="{:.5}" data-longitude="{:.5}" data-elevation="{:.0}" data-distance="{:.0}" data-bearing="{:.1}">"#,
+                peak.coord.latitude,
+                peak.coord.longitude,
+                peak.elevation,
+                peak.distance,
+                peak.azimuth,
+                name = escape_xml(&peak.name),
+            );
+            let _ = writeln!(
+                svg,
+                "<title>{}, {}</title>",
+                escape_xml(&peak.name),
+                VectorLabel::details(peak)
+            );
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x:.1}" y1="{:.1}" x2="{x:.1}" y2="{:.1}" stroke="#000" stroke-width="{LEADER_LINE_WIDTH}"/>"##,
+                label.label_y + LABEL_HEIGHT,
+                label.peak_y,
+                x = label.peak_x,
+            );
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{LABEL_HEIGHT}" fill="#fff"/>"##,
+                label.label_x, label.label_y, label.label_width,
+            );
+            let text_x = label.label_x + LABEL_PADDING_LEFT;
+            let _ = writeln!(
+                svg,
+                r#"<path transform="translate({text_x:.1} {:.1})" d="{}"/>"#,
+                label.label_y,
+                svg_path_data(&label.name),
+            );
+            let _ = writeln!(
+                svg,
+                r#"<path transform="translate({text_x:.1} {:.1})" d="{}"/>"#,
+                label.label_y + LINE_HEIGHT,
+                svg_path_data(&label.details),
+            );
+            let _ = writeln!(svg, "</g>");
+        }
+        let _ = writeln!(svg, "</g>");
+        let _ = writeln!(svg, "</svg>");
+
+        svg
+    }
+
+    pub fn to_pdf(&self, options: &VectorOptions) -> Vec<u8> {
+        let page_width = options.width_mm * MM_TO_PT;
+        let page_height = self.height_mm(options) * MM_TO_PT;
+        let scale = page_width / self.size.width as f32;
+
+        // drawn in pixels of the view, with y pointing down like in the view
+        let mut content = String::new();
+        let _ = writeln!(content, "q {scale} 0 0 {} 0 {page_height} cm", -scale);
+
+        let _ = writeln!(content, "0 0 0 RG {SILHOUETTE_WIDTH} w 1 J 1 j");
+        for silhouette in &self.silhouettes {
+            for (i, point) in silhouette.iter().enumerate() {
+                let operator = if i == 0 { "m" } else { "l" };
+                let _ = writeln!(content, "{:.2} {:.2} {operator}", point.x, point.y);
+            }
+            let _ = writeln!(content, "S");
+        }
+
+        let _ = writeln!(content, "{LEADER_LINE_WIDTH} w");
+        for label in &self.labels {
+            let _ = writeln!(
+                content,
+                "{x:.2} {:.2} m {x:.2} {:.2} l S",
+                label.label_y + LABEL_HEIGHT,
+                label.peak_y,
+                x = label.peak_x,
+            );
+            let _ = writeln!(
+                content,
+                "1 1 1 rg {:.2} {:.2} {:.2} {LABEL_HEIGHT} re f",
+                label.label_x, label.label_y, label.label_width,
+            );
+
+            let _ = writeln!(content, "0 0 0 rg");
+            let text_x = label.label_x + LABEL_PADDING_LEFT;
+            for (path, y) in [
+                (&label.name, label.label_y),
+                (&label.details, label.label_y + LINE_HEIGHT),
+            ] {
+                let _ = writeln!(content, "q 1 0 0 1 {text_x:.2} {y:.2} cm");
+                pdf_path_operators(&mut content, path);
+                let _ = writeln!(content, "f Q");
+            }
+        }
+        let _ = writeln!(content, "Q");
+
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {page_width:.2} {page_height:.2}] /Contents 4 0 R /Resources << >> >>"
+            ),
+            format!(
+                "<< /Length {} >>\nstream\n{content}endstream",
+                content.len()
+            ),
+        ];
+
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            let _ = write!(pdf, "{} 0 obj\n{object}\nendobj\n", i + 1);
+        }
+        let xref_offset = pdf.len();
+        let _ = write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(pdf, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            pdf,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+            objects.len() + 1
+        );
+
+        pdf.into_bytes()
+    }
+
+    /// Saves the view as SVG or PDF, depending on the file extension
+    pub fn save(&self, path: &FilePath, options: &VectorOptions) -> Result<()> {
+        let format =
+            VectorFormat::from_path(path).ok_or_eyre("Expected a .svg or .pdf file extension")?;
+        let contents = match format {
+            VectorFormat::Svg => self.to_svg(options).into_bytes(),
+            VectorFormat::Pdf => self.to_pdf(options),
+        };
+        fs::write(path, contents).wrap_err_with(|| format!("Unable to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use topo_common::GeoCoord;
+
+    use crate::{
+        data::camera::FAR, headless::tests::with_test_renderer, settings::RendererSettings,
+    };
+
+    use super::*;
+
+    #[test]
+    fn traces_skyline_and_ridge_in_front_of_terrain() {
+        let size = Size {
+            width: 40,
+            height: 30,
+        };
+        // sky above row 10, terrain 20km away below, with a ridge 5km away in the lower left
+        let distances = (0..size.height)
+            .flat_map(|y| (0..size.width).map(move |x| (x, y)))
+            .map(|(x, y)| match (x, y) {
+                (_, 0..10) => FAR,
+                (0..20, 20..) => 5000.0,
+                _ => 20_000.0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut silhouettes = extract_silhouettes(&distances, size, 0.05);
+        silhouettes.sort_by(|a, b| a[0].y.total_cmp(&b[0].y));
+
+        assert_eq!(silhouettes.len(), 2);
+        assert_eq!(silhouettes[0], vec![vec2(0.5, 10.5), vec2(39.5, 10.5)]);
+        // the ridge goes along its top and down its right side
+        assert_eq!(
+            silhouettes[1],
+            vec![vec2(0.5, 20.5), vec2(19.5, 20.5), vec2(19.5, 29.5)]
+        );
+    }
+
+    #[test]
+    fn exports_silhouettes_and_labels() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(0.0),
+            start_pitch: Some(-10.0),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+        let options = VectorOptions::default();
+
+        with_test_renderer(settings, (320, 240).into(), async |renderer, _| {
+            renderer
+                .load_location(GeoCoord::new(49.47, 20.5))
+                .await
+                .unwrap();
+            let view = render_labeled_view(renderer, &options).await.unwrap();
+
+            assert!(!view.silhouettes.is_empty());
+            let [label] = &view.labels[..] else {
+                panic!("expected a single label");
+            };
+            assert_eq!(label.peak.name, "Test Peak");
+            assert_eq!(label.peak.elevation, 2500.0);
+            // the camera is 0.03° (~3.3km) south of the peak
+            assert!((label.peak.distance - 3336.0).abs() < 10.0);
+            assert!(label.peak.azimuth < 0.1 || label.peak.azimuth > 359.9);
+
+            let svg = view.to_svg(&options);
+            assert!(svg.contains(r#"data-name="Test Peak""#));
+            assert!(svg.contains("<polyline"));
+            let pdf = view.to_pdf(&options);
+            assert!(pdf.starts_with(b"%PDF-1.4"));
+            assert!(pdf.ends_with(b"%%EOF\n"));
+        });
+    }
+}
@@ -0,0 +1,384 @@
+use std::sync::Arc;
+
+use color_eyre::{Result, eyre::OptionExt};
+use futures::{future::join_all, join};
+use glam::Vec3;
+use image::RgbaImage;
+use itertools::Itertools;
+use topo_common::GeoCoord;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    control::{
+        background_runner::{fetch_imagery, fetch_lakes, fetch_terrain, imagery_and_lake_events},
+        ui_controller::UiController,
+    },
+    data::{Size, application_data::ApplicationData, contours::contour_anchors},
+    render::{
+        render_engine::{RenderEngine, RenderEvent},
+        text_renderer::TextRenderer,
+    },
+    settings::RendererSettings,
+};
+
+/// Renders views without a window or an event loop, e.g. on a server or in tests
+///
+/// Data gets loaded synchronously with [`Self::load_location`] instead of
+/// in the background, so that every rendered frame is complete
+pub struct HeadlessRenderer {
+    engine: RenderEngine,
+    data: ApplicationData,
+    settings: RendererSettings,
+}
+
+impl HeadlessRenderer {
+    pub async fn new(size: Size<u32>, settings: RendererSettings) -> Result<Self> {
+        let engine = RenderEngine::new_headless(size).await?;
+        let data = ApplicationData::new((size.width as f32, size.height as f32).into(), &settings);
+
+        Ok(Self {
+            engine,
+            data,
+            settings,
+        })
+    }
+
+    pub fn data(&self) -> &ApplicationData {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut ApplicationData {
+        &mut self.data
+    }
+
+    pub fn engine_mut(&mut self) -> &mut RenderEngine {
+        &mut self.engine
+    }
+
+    pub fn size(&self) -> Size<u32> {
+        self.engine.size().into()
+    }
+
+    /// Changes the size of the rendered images, returns false if the size is empty
+    pub fn resize(&mut self, size: Size<u32>) -> bool {
+        self.engine
+            .resize(PhysicalSize::new(size.width, size.height), &mut self.data)
+    }
+
+    /// Loads terrain and peaks within the view distance and places the camera at the location
+    ///
+    /// The start view from the settings is applied on the first call. Tiles
+    /// the backend has no terrain for are covered with the sea, the ones
+    /// that fail to load are skipped, unless it's the tile of the location
+    /// itself. Returns once the normals of the loaded terrain are computed
+    pub async fn load_location(&mut self, location: GeoCoord) -> Result<()> {
+        let locations = UiController::get_locations_range(location, self.settings.view_distance);
+
+        let to_unload = self
+            .data
+            .loaded_locations
+            .iter()
+            .filter(|loaded| !locations.contains(loaded))
+            .copied()
+            .collect_vec();
+        for unloaded in to_unload {
+            self.data.loaded_locations.remove(&unloaded);
+            self.data.peaks.remove(&unloaded);
+            self.data.peak_labels.remove(&unloaded);
+            self.data.height_maps.remove(&unloaded);
+            self.data.contour_anchors.remove(&unloaded);
+            self.engine
+                .renderers_mut()
+                .terrain
+                .unload_terrain(&unloaded);
+            self.data.scene_changed();
+        }
+
+        let to_load = locations
+            .into_iter()
+            .filter(|requested| !self.data.loaded_locations.contains(requested))
+            .collect_vec();
+        let results = join_all(to_load.iter().map(|requested| async {
+            join!(
+                fetch_terrain(*requested, &self.settings.application),
+                fetch_imagery(*requested, &self.settings.application),
+                fetch_lakes(*requested, &self.settings.application)
+            )
+        }))
+        .await;
+
+        for (requested, (result, imagery, lakes)) in to_load.into_iter().zip(results) {
+            let (peaks, height_map) = match result {
+                Ok(Some(loaded)) => loaded,
+                Ok(None) => {
+                    self.engine
+                        .process_event(RenderEvent::NoTerrain(requested), &mut self.data);
+                    continue;
+                }
+                Err(err) => {
+                    log::warn!("Skipping location {requested:?}: {err:?}");
+                    continue;
+                }
+            };
+
+            TextRenderer::load_additional_fonts(TextRenderer::get_scripts(
+                peaks.iter().map(|peak| peak.name.as_str()),
+            ))
+            .await?;
+            let labels = TextRenderer::prepare_peak_labels(&peaks);
+
+            self.data.peaks.insert(requested, peaks);
+            self.data.peak_labels.insert(requested, labels);
+            let height_map = Arc::new(height_map);
+            let contour_interval = self.data.contours.label_interval();
+            let anchors = contour_anchors(&height_map, contour_interval);
+            self.engine.process_event(
+                RenderEvent::TerrainReady(requested, height_map),
+                &mut self.data,
+            );
+            self.data
+                .set_contour_anchors(requested, contour_interval, anchors);
+            for event in imagery_and_lake_events(requested, imagery, lakes) {
+                self.engine.process_event(event, &mut self.data);
+            }
+        }
+
+        let camera_height = self
+            .data
+            .get_ground_height(location)
+            .ok_or_eyre("Unable to get the location's height from the height map")?;
+
+        self.data.current_location = Some(location);
+        self.engine.process_event(
+            RenderEvent::ResetCamera(location, camera_height),
+            &mut self.data,
+        );
+
+        self.engine.wait_for_terrain(&mut self.data)
+    }
+
+    /// Renders the current view, labels included
+    pub async fn render(&mut self) -> Result<RgbaImage> {
+        self.engine.render_to_image(&mut self.data).await
+    }
+
+    /// Renders the current view and reads back its depth buffer, see [`RenderEngine::read_depth`]
+    pub async fn read_depth(&mut self) -> Result<Vec<f32>> {
+        self.engine.read_depth(&mut self.data).await
+    }
+
+    /// Renders the world space normals of the current view, see [`RenderEngine::read_normals`]
+    pub async fn read_normals(&mut self) -> Result<Vec<Vec3>> {
+        self.engine.read_normals(&mut self.data).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use tiff::{encoder::TiffEncoder, encoder::colortype::Gray32Float, tags::Tag};
+    use topo_common::GeoLocation;
+
+    use crate::{
+        export::gbuffer::{GBuffer, render_gbuffer},
+        settings::AtmosphereSettings,
+    };
+
+    use super::*;
+
+    /// Texels along each side of the test tiles, a degree wide
+    pub(crate) const TILE_SIZE: u32 = 120;
+
+    /// Flat tile with a steep, flat topped mountain in its middle and a peak on top of it
+    pub(crate) fn write_test_data(data_dir: &Path) {
+        let location = GeoLocation::from(GeoCoord::new(49.5, 20.5));
+
+        let center = TILE_SIZE / 2;
+        write_test_dem(data_dir, location, TILE_SIZE, |x, y| {
+            if x.abs_diff(center) <= 1 && y.abs_diff(center) <= 1 {
+                2500.0
+            } else {
+                500.0
+            }
+        });
+
+        let peaks_path = data_dir.join(location.peaks_file_path());
+        fs::create_dir_all(peaks_path.parent().unwrap()).unwrap();
+        fs::write(
+            peaks_path,
+            "latitude,longitude,name,elevation\n49.5,20.5,Test Peak,2500.0\n",
+        )
+        .unwrap();
+    }
+
+    /// Writes the location's height map with the height of each texel, starting from the top left,
+    /// the tile is [`TILE_SIZE`] texels tall and narrower than that e.g. like COP90 tiles north of 50°
+    pub(crate) fn write_test_dem(
+        data_dir: &Path,
+        location: GeoLocation,
+        width: u32,
+        height: impl Fn(u32, u32) -> f32,
+    ) {
+        let dem_path = data_dir.join(location.dem_file_path());
+        fs::create_dir_all(dem_path.parent().unwrap()).unwrap();
+        let heights = (0..width * TILE_SIZE)
+            .map(|i| height(i % width, i / width))
+            .collect_vec();
+        let mut encoder = TiffEncoder::new(fs::File::create(dem_path).unwrap()).unwrap();
+        let mut image = encoder.new_image::<Gray32Float>(width, TILE_SIZE).unwrap();
+        image
+            .encoder()
+            .write_tag(
+                Tag::ModelPixelScaleTag,
+                &[1.0 / width as f64, 1.0 / TILE_SIZE as f64, 0.0][..],
+            )
+            .unwrap();
+        let (latitude, longitude) = location.to_numerical();
+        image
+            .encoder()
+            .write_tag(
+                Tag::ModelTiepointTag,
+                &[0.0, 0.0, 0.0, longitude as f64, latitude as f64 + 1.0, 0.0][..],
+            )
+            .unwrap();
+        image.write_data(&heights).unwrap();
+    }
+
+    /// Set to fail the GPU tests instead of skipping them when there's no adapter to render with
+    const REQUIRE_GPU: &str = "TOPO_REQUIRE_GPU";
+
+    /// Temporary data directory, removed once the test is done with it
+    struct TestDataDir(PathBuf);
+
+    impl Drop for TestDataDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Runs the test with a renderer of the given size reading the data from [`write_test_data`]
+    /// in a temporary directory, which the test gets for any additional data
+    ///
+    /// Returns `None` when there's no adapter, unless [`REQUIRE_GPU`] is set, any other error
+    /// creating the renderer fails the test
+    pub(crate) fn with_test_renderer<T>(
+        mut settings: RendererSettings,
+        size: Size<u32>,
+        test: impl AsyncFnOnce(&mut HeadlessRenderer, &Path) -> T,
+    ) -> Option<T> {
+        static DATA_DIRS: AtomicUsize = AtomicUsize::new(0);
+        let data_dir = TestDataDir(std::env::temp_dir().join(format!(
+            "topo-test-{}-{}",
+            std::process::id(),
+            DATA_DIRS.fetch_add(1, Ordering::Relaxed)
+        )));
+        write_test_data(&data_dir.0);
+        settings.application.data_dir = Some(data_dir.0.clone());
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let mut renderer = match HeadlessRenderer::new(size, settings).await {
+                Ok(renderer) => renderer,
+                Err(err)
+                    if err.downcast_ref::<wgpu::RequestAdapterError>().is_some()
+                        && std::env::var_os(REQUIRE_GPU).is_none() =>
+                {
+                    eprintln!("Skipping, no adapter available ({REQUIRE_GPU} is not set): {err}");
+                    return None;
+                }
+                Err(err) => panic!("Unable to create the renderer: {err:?}"),
+            };
+            Some(test(&mut renderer, &data_dir.0).await)
+        })
+    }
+
+    /// Looks north at the block of [`write_test_data`] from 3km south of it, without the haze
+    pub(crate) fn test_settings() -> RendererSettings {
+        RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(0.0),
+            start_pitch: Some(-10.0),
+            view_distance: 10_000.0,
+            atmosphere: AtmosphereSettings {
+                visibility: 0.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Loads the location the settings start at, renders the view and its G-buffer
+    pub(crate) async fn render_start_view(renderer: &mut HeadlessRenderer) -> (RgbaImage, GBuffer) {
+        renderer
+            .load_location(renderer.settings.start_location)
+            .await
+            .unwrap();
+        let image = renderer.render().await.unwrap();
+        let gbuffer = render_gbuffer(renderer).await.unwrap();
+        (image, gbuffer)
+    }
+
+    /// Color of a rendered pixel along with what the G-buffer has for it
+    pub(crate) struct GBufferPixel {
+        pub color: [u8; 3],
+        pub latitude: f32,
+        pub longitude: f32,
+        pub elevation: f32,
+        pub distance: f32,
+    }
+
+    pub(crate) fn gbuffer_pixels<'a>(
+        image: &'a RgbaImage,
+        gbuffer: &'a GBuffer,
+    ) -> impl Iterator<Item = GBufferPixel> + 'a {
+        image.pixels().enumerate().map(|(i, pixel)| GBufferPixel {
+            color: [pixel.0[0], pixel.0[1], pixel.0[2]],
+            latitude: gbuffer.latitude[i],
+            longitude: gbuffer.longitude[i],
+            elevation: gbuffer.elevation[i],
+            distance: gbuffer.distance[i],
+        })
+    }
+
+    #[test]
+    fn renders_terrain_and_labels_without_window() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.47, 20.5),
+            start_altitude: Some(3000.0),
+            start_yaw: Some(0.0),
+            start_pitch: Some(-10.0),
+            view_distance: 10_000.0,
+            ..Default::default()
+        };
+
+        with_test_renderer(settings, (160, 120).into(), async |renderer, _| {
+            renderer
+                .load_location(GeoCoord::new(49.47, 20.5))
+                .await
+                .unwrap();
+            let image = renderer.render().await.unwrap();
+
+            assert_eq!(image.dimensions(), (160, 120));
+            let first_pixel = image.get_pixel(0, 0);
+            assert!(image.pixels().any(|pixel| pixel != first_pixel));
+            assert!(
+                renderer
+                    .data()
+                    .peaks
+                    .values()
+                    .flatten()
+                    .any(|peak| peak.visible && peak.name == "Test Peak")
+            );
+        });
+    }
+}
@@ -2,4 +2,8 @@ pub mod app;
 pub mod common;
 pub mod control;
 pub mod data;
+pub mod export;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod headless;
 pub mod render;
+pub mod settings;
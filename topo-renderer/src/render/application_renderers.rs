@@ -17,7 +17,6 @@ impl ApplicationRenderers {
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        config: &wgpu::SurfaceConfiguration,
         format: TextureFormat,
         target_size: Size<u32>,
     ) -> Self {
@@ -26,12 +25,12 @@ impl ApplicationRenderers {
         let text = TextRenderer::new(
             device,
             queue,
-            config,
+            format,
             Pipeline::get_postprocessing_depth_stencil_state(),
         );
 
         let mut line = LineRenderer::new(device, format);
-        line.prepare(device, queue, vec![]);
+        line.prepare(device, queue, &[], &[]);
 
         Self {
             terrain,
@@ -1,7 +1,9 @@
 use wgpu::util::DeviceExt;
-use winit::event_loop::EventLoopProxy;
 
-use crate::{app::ApplicationEvent, data::DepthState};
+use crate::{
+    data::DepthState,
+    render::render_engine::{RenderEvent, RenderEventSender},
+};
 
 // A custom buffer container for dynamic resizing.
 pub struct Buffer {
@@ -72,16 +74,10 @@ impl Buffer {
         }
     }
 
-    pub fn map(
-        &mut self,
-        sender: EventLoopProxy<ApplicationEvent>,
-        new_depth_state: DepthState,
-    ) -> bool {
+    pub fn map(&mut self, sender: RenderEventSender, new_depth_state: DepthState) -> bool {
         if !self.mapped {
             self.raw.slice(..).map_async(wgpu::MapMode::Read, move |_| {
-                let _ = sender.send_event(ApplicationEvent::RenderEvent(
-                    super::render_engine::RenderEvent::DepthBufferReady(new_depth_state),
-                ));
+                sender.send(RenderEvent::DepthBufferReady(new_depth_state));
             });
             self.mapped = true;
             true
@@ -1,9 +1,11 @@
 use topo_common::GeoLocation;
-use winit::event_loop::EventLoopProxy;
 
-use crate::{
-    app::ApplicationEvent,
-    render::{buffer::Buffer, render_engine::RenderEvent, texture::Texture},
+use crate::render::{
+    buffer::Buffer,
+    data::ShadowUniforms,
+    render_buffer::RenderBuffer,
+    render_engine::{RenderEvent, RenderEventSender},
+    texture::Texture,
 };
 
 pub struct ComputePipeline {
@@ -38,7 +40,7 @@ impl ComputePipeline {
         normal_texture: &Texture,
         uniforms: &Buffer,
         (width, height): (u32, u32),
-        event_loop_proxy: EventLoopProxy<ApplicationEvent>,
+        event_sender: RenderEventSender,
     ) {
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Compute normals texture bind group"),
@@ -75,15 +77,120 @@ impl ComputePipeline {
         }
 
         encoder.on_submitted_work_done(move || {
-            let _ = event_loop_proxy.send_event(ApplicationEvent::RenderEvent(
-                RenderEvent::NormalsComputed(location),
-            ));
+            event_sender.send(RenderEvent::NormalsComputed(location));
         });
 
         queue.submit([encoder.finish()]);
     }
 }
 
+/// Computes how much of the sun is visible from each point of a tile, see `compute_shadows_shader.wgsl`
+pub struct ComputePipelineShadows {
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl ComputePipelineShadows {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let compute_shadows_shader = device.create_shader_module(wgpu::include_wgsl!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../resources/shaders/compute_shadows_shader.wgsl"
+        )));
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("compute shadows pipeline"),
+            layout: None,
+            module: &compute_shadows_shader,
+            entry_point: Some("compute_shadows"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self { pipeline }
+    }
+
+    /// The neighbours go from the top left to the bottom right one, the terrain
+    /// of the ones that are loaded casts shadows on the tile too
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_buffer: &RenderBuffer,
+        neighbours: [Option<&RenderBuffer>; 8],
+        shadow_uniforms: &ShadowUniforms,
+    ) {
+        let heightmap_texture = render_buffer.get_height_map_texture();
+        let shadow_texture = render_buffer.get_shadow_texture();
+        let terrain_uniforms = render_buffer.get_uniforms();
+
+        let shadow_uniforms = Buffer::new_init(
+            device,
+            "shadow uniform buffer",
+            bytemuck::bytes_of(&shadow_uniforms.with_neighbours(neighbours.map(|n| n.is_some()))),
+            wgpu::BufferUsages::UNIFORM,
+        );
+        // the shader skips the missing neighbours, but something has to be bound in their place
+        let neighbours = neighbours.map(|neighbour| neighbour.unwrap_or(render_buffer));
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(heightmap_texture.get_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(shadow_texture.get_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: terrain_uniforms.raw.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: shadow_uniforms.raw.as_entire_binding(),
+            },
+        ];
+        entries.extend(neighbours.iter().zip(4..).map(|(neighbour, binding)| {
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(
+                    neighbour.get_height_map_texture().get_view(),
+                ),
+            }
+        }));
+        entries.extend(neighbours.iter().zip(12..).map(|(neighbour, binding)| {
+            wgpu::BindGroupEntry {
+                binding,
+                resource: neighbour.get_uniforms().raw.as_entire_binding(),
+            }
+        }));
+
+        let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute shadows texture bind group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+
+        let size = heightmap_texture.get_size();
+        let (dispatch_width, dispatch_height) =
+            compute_work_group_count((size.width, size.height), (16, 16));
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compute shadows pass"),
+                ..Default::default()
+            });
+
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &texture_bind_group, &[]);
+            compute_pass.dispatch_workgroups(dispatch_width, dispatch_height, 1);
+        }
+
+        queue.submit([encoder.finish()]);
+    }
+}
+
 fn compute_work_group_count(
     (width, height): (u32, u32),
     (workgroup_width, workgroup_height): (u32, u32),
@@ -143,7 +250,7 @@ impl ComputePipelineEdge {
         normal_texture_right: &Texture,
         uniforms: &Buffer,
         (width, height): (u32, u32),
-        event_loop_proxy: EventLoopProxy<ApplicationEvent>,
+        event_sender: RenderEventSender,
     ) {
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Compute normals edge texture bind group"),
@@ -196,9 +303,7 @@ impl ComputePipelineEdge {
         }
 
         encoder.on_submitted_work_done(move || {
-            let _ = event_loop_proxy.send_event(ApplicationEvent::RenderEvent(
-                RenderEvent::NormalsComputed(location),
-            ));
+            event_sender.send(RenderEvent::NormalsComputed(location));
         });
 
         queue.submit([encoder.finish()]);
@@ -242,7 +347,7 @@ impl ComputePipelineCorner {
         normal_texture_bottom_left: &Texture,
         normal_texture_bottom_right: &Texture,
         uniforms: &Buffer,
-        event_loop_proxy: EventLoopProxy<ApplicationEvent>,
+        event_sender: RenderEventSender,
     ) {
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Compute normals corner texture bind group"),
@@ -317,9 +422,7 @@ impl ComputePipelineCorner {
         }
 
         encoder.on_submitted_work_done(move || {
-            let _ = event_loop_proxy.send_event(ApplicationEvent::RenderEvent(
-                RenderEvent::NormalsComputed(location),
-            ));
+            event_sender.send(RenderEvent::NormalsComputed(location));
         });
 
         queue.submit([encoder.finish()]);
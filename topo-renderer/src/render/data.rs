@@ -1,8 +1,19 @@
 use glam::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use topo_common::GeoCoord;
 
 use crate::{
     common::coordinate_transform::CoordinateTransform,
-    data::{Size, camera::Camera},
+    data::{
+        Size,
+        camera::Camera,
+        terrain_coloring::{
+            ASPECT_COLORS, ElevationRamp, FLAT_COLOR, FLAT_SLOPE, MAX_ELEVATION_STOPS,
+            SLOPE_CLASSES, gpu_aspect_colors, gpu_slope_classes, srgb_to_linear,
+        },
+        water::WaterSettings,
+    },
+    render::geometry::{inverse_transform, north_east, transform},
+    settings::{AtmosphereSettings, ContourSettings, PostprocessingSettings, ShadowSettings},
 };
 
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -30,6 +41,41 @@ impl Vertex {
     }
 }
 
+/// Point of a lake's surface, see [`LakeMesh`]
+///
+/// [`LakeMesh`]: crate::data::water::LakeMesh
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct LakeVertex {
+    pub position: [f32; 3],
+    /// Meters above sea level
+    pub elevation: f32,
+}
+
+impl LakeVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        // position
+        0 => Float32x3,
+        // elevation
+        1 => Float32,
+    ];
+
+    pub fn new(position: Vec3, elevation: f32) -> Self {
+        Self {
+            position: position.to_array(),
+            elevation,
+        }
+    }
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct Uniforms {
@@ -38,10 +84,43 @@ pub struct Uniforms {
     camera_pos: Vec4,
     pub sun_direction: Vec3,
     pub view_mode: i32,
+    refraction: f32,
+    elevation_stop_count: u32,
+    /// See [`ImagerySettings::blend`]
+    ///
+    /// [`ImagerySettings::blend`]: crate::data::imagery::ImagerySettings::blend
+    imagery_blend: f32,
+    /// 1 covers the ground below sea level with the sea, see [`WaterSettings::sea`]
+    sea: u32,
+    /// See [`ElevationRamp::gpu_stops`]
+    elevation_stops: [Vec4; MAX_ELEVATION_STOPS],
+    /// See [`ContourSettings`], with the color linearized
+    contour_color: Vec3,
+    contour_interval: f32,
+    contour_width: f32,
+    contour_index_width: f32,
+    contour_index_every: u32,
+    _contour_padding: f32,
+    /// See [`gpu_slope_classes`]
+    slope_classes: [Vec4; SLOPE_CLASSES.len()],
+    /// See [`gpu_aspect_colors`]
+    aspect_colors: [Vec4; ASPECT_COLORS.len()],
+    /// See [`FLAT_COLOR`], linearized
+    flat_color: Vec3,
+    /// See [`FLAT_SLOPE`]
+    flat_slope: f32,
 }
 
 impl Uniforms {
-    pub fn new(camera: &Camera, bounds: Size<f32>) -> Self {
+    pub fn new(
+        camera: &Camera,
+        sun_direction: Vec3,
+        bounds: Size<f32>,
+        elevation_ramp: &ElevationRamp,
+        imagery_blend: f32,
+        contours: &ContourSettings,
+        water: &WaterSettings,
+    ) -> Self {
         let camera_proj = camera.build_view_proj_matrix(bounds.width, bounds.height);
         let normal_proj = camera.build_view_normal_matrix();
         let view_mode = camera.view_mode as i32;
@@ -50,14 +129,45 @@ impl Uniforms {
             camera_proj,
             normal_proj,
             camera_pos: camera.position(),
-            sun_direction: camera.sun_angle.to_vec3(),
+            sun_direction,
             view_mode,
+            refraction: camera.refraction,
+            elevation_stop_count: elevation_ramp.get_stops().len() as u32,
+            imagery_blend,
+            sea: water.sea as u32,
+            elevation_stops: elevation_ramp.gpu_stops(),
+            contour_color: Vec3::ZERO,
+            contour_interval: 0.0,
+            contour_width: 0.0,
+            contour_index_width: 0.0,
+            contour_index_every: 1,
+            _contour_padding: 0.0,
+            slope_classes: gpu_slope_classes(),
+            aspect_colors: gpu_aspect_colors(),
+            flat_color: srgb_to_linear(FLAT_COLOR),
+            flat_slope: FLAT_SLOPE,
         };
 
-        new_uniforms
+        new_uniforms.with_contours(contours)
     }
 
-    pub fn update_projection(&self, camera: &Camera, bounds: Size<f32>) -> Self {
+    pub fn with_contours(&self, contours: &ContourSettings) -> Self {
+        Self {
+            contour_color: srgb_to_linear(contours.color),
+            contour_interval: contours.interval.max(0.0),
+            contour_width: contours.width,
+            contour_index_width: contours.index_width,
+            contour_index_every: contours.index_every.max(1),
+            ..*self
+        }
+    }
+
+    pub fn update_projection(
+        &self,
+        camera: &Camera,
+        sun_direction: Vec3,
+        bounds: Size<f32>,
+    ) -> Self {
         let camera_proj = camera.build_view_proj_matrix(bounds.width, bounds.height);
         let normal_proj = camera.build_view_normal_matrix();
 
@@ -65,8 +175,10 @@ impl Uniforms {
             camera_proj,
             normal_proj,
             camera_pos: camera.position(),
-            sun_direction: camera.sun_angle.to_vec3(),
+            sun_direction,
             view_mode: camera.view_mode as i32,
+            refraction: camera.refraction,
+            ..*self
         }
     }
 }
@@ -74,36 +186,160 @@ impl Uniforms {
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct PostprocessingUniforms {
-    viewport: [f32; 2],
+    /// Maps the screen to the viewing directions, ignoring the camera's position
+    inverse_view_rotation_proj: Mat4,
+    camera_direction: Vec3,
+    /// Meters above sea level
+    camera_altitude: f32,
+    camera_up: Vec3,
     pixelize_n: f32,
-    _padding: f32,
+    sun_direction: Vec3,
+    /// Scattering coefficient (per meter) of the aerosols at sea level
+    mie: f32,
+    /// Scattering coefficients (per meter) of the air at sea level for red, green and blue
+    rayleigh: Vec3,
+    /// See [`AtmosphereSettings::turbidity`]
+    turbidity: f32,
+    viewport: [f32; 2],
+    _padding_viewport: [f32; 2],
+    /// Maps the directions relative to the camera to the screen, the inverse of the above
+    view_rotation_proj: Mat4,
+    /// Linear, see [`WaterSettings::color`]
+    water_color: Vec3,
+    /// See [`WaterSettings::sea`]
+    sea: u32,
+    /// See [`Camera::refraction`]
+    refraction: f32,
+    /// See [`WaterSettings::reflections`]
+    water_reflections: u32,
+    /// Only the default view mode gets the haze and the water, the others show the terrain's colors
+    view_mode: i32,
+    _padding_water: f32,
 }
 
 impl PostprocessingUniforms {
-    pub fn new(viewport: Size<f32>, pixelize_n: f32) -> Self {
+    /// Wavelengths (in nanometers) the red, green and blue channels stand for
+    const WAVELENGTHS: Vec3 = Vec3::new(680.0, 550.0, 440.0);
+    /// Extinction coefficient times the visibility, i.e. -ln(0.02)
+    const KOSCHMIEDER_CONSTANT: f32 = 3.912;
+
+    pub fn new(
+        viewport: Size<f32>,
+        camera: &Camera,
+        sun_direction: Vec3,
+        postprocessing: &PostprocessingSettings,
+        atmosphere: &AtmosphereSettings,
+        water: &WaterSettings,
+    ) -> Self {
+        let extinction = if atmosphere.visibility > 0.0 {
+            Self::KOSCHMIEDER_CONSTANT / atmosphere.visibility
+        } else {
+            0.0
+        };
+        let turbidity = atmosphere.turbidity.max(1.0);
+        // the green channel's extinction is split between the air and the aerosols,
+        // the air scatters shorter wavelengths more with the fourth power
+        let rayleigh = extinction / turbidity * (Self::WAVELENGTHS.y / Self::WAVELENGTHS).powf(4.0);
+
         Self {
+            inverse_view_rotation_proj: Mat4::ZERO,
+            camera_direction: Vec3::ZERO,
+            camera_altitude: 0.0,
+            camera_up: Vec3::Z,
+            pixelize_n: postprocessing.pixelize_n,
+            sun_direction,
+            mie: extinction - extinction / turbidity,
+            rayleigh,
+            turbidity,
             viewport: [viewport.width, viewport.height],
-            pixelize_n,
-            _padding: 0.0,
+            _padding_viewport: [0.0; 2],
+            view_rotation_proj: Mat4::ZERO,
+            water_color: srgb_to_linear(water.color),
+            sea: water.sea as u32,
+            refraction: 0.0,
+            water_reflections: water.reflections as u32,
+            view_mode: 0,
+            _padding_water: 0.0,
         }
+        .update_view(camera, sun_direction, viewport)
     }
 
-    pub fn with_new_viewport(&self, viewport: Size<f32>) -> Self {
-        PostprocessingUniforms::new(viewport, self.pixelize_n)
+    pub fn update_view(&self, camera: &Camera, sun_direction: Vec3, viewport: Size<f32>) -> Self {
+        let view_rotation = Mat4::look_to_rh(Vec3::ZERO, camera.direction(), camera.up());
+        let proj = camera.build_proj_matrix(viewport.width, viewport.height);
+        let (camera_altitude, _) = inverse_transform(camera.position().truncate());
+
+        Self {
+            inverse_view_rotation_proj: (proj * view_rotation).inverse(),
+            view_rotation_proj: proj * view_rotation,
+            refraction: camera.refraction,
+            view_mode: camera.view_mode as i32,
+            camera_direction: camera.direction(),
+            camera_altitude,
+            camera_up: camera.position().truncate().normalize_or(Vec3::Z),
+            sun_direction,
+            viewport: [viewport.width, viewport.height],
+            ..*self
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct ShadowUniforms {
+    sun_direction: Vec3,
+    max_distance: f32,
+    /// Half of the angle (in radians) over which the shadows fade out
+    penumbra: f32,
+    /// Bit for each of the loaded neighbouring tiles, from the top left to the bottom right one
+    neighbours: u32,
+    _padding: [f32; 2],
+}
+
+impl ShadowUniforms {
+    /// Smallest penumbra (in degrees), as the shadow edges can't be infinitely sharp
+    const MIN_PENUMBRA: f32 = 0.01;
+
+    pub fn new(sun_direction: Vec3, settings: &ShadowSettings) -> Self {
+        Self {
+            sun_direction,
+            max_distance: settings.max_distance,
+            penumbra: 0.5 * settings.penumbra.max(Self::MIN_PENUMBRA).to_radians(),
+            neighbours: 0,
+            _padding: [0.0; 2],
+        }
+    }
+
+    /// Lets the terrain of the loaded neighbours cast shadows on the tile,
+    /// ordered from the top left to the bottom right one
+    pub fn with_neighbours(self, loaded: [bool; 8]) -> Self {
+        Self {
+            neighbours: loaded
+                .iter()
+                .enumerate()
+                .filter(|(_, loaded)| **loaded)
+                .fold(0, |bits, (i, _)| bits | 1 << i),
+            ..self
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct PeakInstance {
     pub position: Vec3,
+    pub coord: GeoCoord,
+    /// Elevation listed in the peaks data (in meters above sea level)
+    pub elevation: f32,
     pub name: String,
     pub visible: bool,
 }
 
 impl PeakInstance {
-    pub fn new(position: Vec3, name: String) -> Self {
+    pub fn new(position: Vec3, coord: GeoCoord, elevation: f32, name: String) -> Self {
         Self {
             position,
+            coord,
+            elevation,
             name,
             visible: false,
         }
@@ -118,6 +354,12 @@ pub struct TerrainUniforms {
     pixel_scale: Vec2,
     size: Vec2,
     normal_to_world_rot: Mat4,
+    /// Georeferencing of the draped imagery, the same way as the height map's, its size is 0
+    /// when there's no imagery
+    imagery_raster_point: Vec2,
+    imagery_model_point: Vec2,
+    imagery_pixel_scale: Vec2,
+    imagery_size: Vec2,
 }
 
 impl TerrainUniforms {
@@ -125,12 +367,10 @@ impl TerrainUniforms {
         let latitude = coordinate_transform.model_point.1;
         let longitude = coordinate_transform.model_point.0;
 
-        let normal_to_world_rot = Mat4::from_mat3(Mat3::from_euler(
-            glam::EulerRot::XYZEx,
-            0.0,
-            (90.0 - latitude).to_radians(),
-            (longitude).to_radians(),
-        ));
+        // the normals are computed with x pointing east, y north and z up
+        let up = transform(0.0, longitude, latitude).normalize();
+        let (north, east) = north_east(up);
+        let normal_to_world_rot = Mat4::from_mat3(Mat3::from_cols(east, north, up));
 
         Self {
             raster_point: Vec2::new(
@@ -147,6 +387,24 @@ impl TerrainUniforms {
             ),
             size: Vec2::new(width as f32, height as f32),
             normal_to_world_rot,
+            imagery_raster_point: Vec2::ZERO,
+            imagery_model_point: Vec2::ZERO,
+            imagery_pixel_scale: Vec2::ONE,
+            imagery_size: Vec2::ZERO,
+        }
+    }
+
+    pub fn with_imagery(
+        self,
+        coordinate_transform: CoordinateTransform,
+        (width, height): (u32, u32),
+    ) -> Self {
+        Self {
+            imagery_raster_point: coordinate_transform.raster_point.into(),
+            imagery_model_point: coordinate_transform.model_point.into(),
+            imagery_pixel_scale: coordinate_transform.pixel_scale.into(),
+            imagery_size: Vec2::new(width as f32, height as f32),
+            ..self
         }
     }
 }
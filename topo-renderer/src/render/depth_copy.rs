@@ -0,0 +1,153 @@
+use crate::data::pad_256;
+
+use super::texture::Texture;
+
+/// Copies the depth texture into a buffer through an intermediate color texture
+/// (holding the bits of the depth values, as float formats aren't always renderable),
+/// for adapters that can't copy depth textures directly (e.g. OpenGL ones)
+pub struct DepthCopy {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    target: Option<Texture>,
+}
+
+impl DepthCopy {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    /// Returns `None` if the adapter supports copying depth textures directly
+    pub fn new_if_needed(device: &wgpu::Device, adapter: &wgpu::Adapter) -> Option<Self> {
+        if adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES)
+        {
+            return None;
+        }
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("depth copy bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Copy Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../resources/shaders/depth_copy_shader.wgsl"
+        )));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Copy Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Self::FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        Some(Self {
+            pipeline,
+            bind_group_layout,
+            target: None,
+        })
+    }
+
+    pub fn copy_to_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_texture: &Texture,
+        buffer: &wgpu::Buffer,
+    ) {
+        let size = *depth_texture.get_size();
+        if self
+            .target
+            .as_ref()
+            .is_none_or(|target| *target.get_size() != size)
+        {
+            self.target = Some(Texture::create_capture_texture(
+                device,
+                Self::FORMAT,
+                (size.width, size.height),
+                "depth copy texture",
+            ));
+        }
+        let target = self
+            .target
+            .as_ref()
+            .expect("depth copy texture created above");
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth copy bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_texture.get_view()),
+            }],
+        });
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Copy Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target.get_view(),
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+                multiview_mask: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        encoder.copy_texture_to_buffer(
+            target.get_texture().as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    bytes_per_row: Some(pad_256(size.width * 4)),
+                    ..Default::default()
+                },
+            },
+            size,
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use color_eyre::{Result, eyre::eyre};
+use futures::channel::oneshot;
+use image::RgbaImage;
+
+use crate::data::{Size, pad_256};
+
+use super::texture::Texture;
+
+/// Offscreen color target together with the buffer its contents get copied to,
+/// used for reading rendered frames back to the cpu
+pub struct FrameCapture {
+    texture: Texture,
+    read_buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    size: Size<u32>,
+}
+
+impl FrameCapture {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: Size<u32>) -> Self {
+        let texture = Texture::create_capture_texture(
+            device,
+            format,
+            (size.width, size.height),
+            "frame capture texture",
+        );
+
+        let read_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame capture read buffer"),
+            size: (pad_256(size.width * 4) * size.height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            texture,
+            read_buffer,
+            format,
+            size,
+        }
+    }
+
+    pub fn get_view(&self) -> &wgpu::TextureView {
+        self.texture.get_view()
+    }
+
+    pub fn size(&self) -> Size<u32> {
+        self.size
+    }
+
+    pub fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            self.texture.get_texture().as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.read_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    bytes_per_row: Some(pad_256(self.size.width * 4)),
+                    ..Default::default()
+                },
+            },
+            *self.texture.get_size(),
+        );
+    }
+
+    /// Waits for the copy submitted with [`Self::copy_to_buffer`] and converts it to an RGBA image
+    pub async fn read(&self, device: &wgpu::Device) -> Result<RgbaImage> {
+        let (sender, receiver) = oneshot::channel();
+        self.read_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        #[cfg(target_arch = "wasm32")]
+        let _ = device;
+
+        receiver
+            .await
+            .map_err(|_| eyre!("Frame capture buffer mapping was canceled"))??;
+
+        let image = {
+            let padded_bytes_per_row = pad_256(self.size.width * 4) as usize;
+            let bytes_per_row = self.size.width as usize * 4;
+            let mapped = self.read_buffer.slice(..).get_mapped_range();
+
+            let mut pixels = Vec::with_capacity(bytes_per_row * self.size.height as usize);
+            for row in mapped
+                .chunks(padded_bytes_per_row)
+                .take(self.size.height as usize)
+            {
+                pixels.extend_from_slice(&row[..bytes_per_row]);
+            }
+
+            if matches!(
+                self.format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ) {
+                pixels
+                    .chunks_exact_mut(4)
+                    .for_each(|pixel| pixel.swap(0, 2));
+            }
+
+            RgbaImage::from_raw(self.size.width, self.size.height, pixels)
+                .ok_or_else(|| eyre!("Frame capture buffer too small"))?
+        };
+        self.read_buffer.unmap();
+
+        Ok(image)
+    }
+}
@@ -1,6 +1,7 @@
 use super::data::Vertex;
 
-use glam::Vec3;
+use glam::{DVec3, Vec3};
+use topo_common::GeoCoord;
 
 pub const R0: f32 = 6_371_000.0;
 
@@ -18,3 +19,122 @@ pub fn transform(h: f32, longitude_deg: f32, latitude_deg: f32) -> Vec3 {
     let z = r * latitude.sin();
     Vec3::new(x, y, z)
 }
+
+/// Unit vectors pointing north and east in the plane tangent to the sphere below the position
+pub fn north_east(position: Vec3) -> (Vec3, Vec3) {
+    let up = position.normalize();
+    let east = Vec3::Z.cross(up).normalize();
+    let north = up.cross(east);
+    (north, east)
+}
+
+/// Inverse of [`transform`], returns the height and the coordinates of the position
+pub fn inverse_transform(position: Vec3) -> (f32, GeoCoord) {
+    let (x, y, z) = (position.x as f64, position.y as f64, position.z as f64);
+    let r = (x * x + y * y + z * z).sqrt();
+    let latitude = (z / r).asin().to_degrees();
+    let longitude = y.atan2(x).to_degrees();
+    (
+        (r - R0 as f64) as f32,
+        GeoCoord::new(latitude as f32, longitude as f32),
+    )
+}
+
+fn to_radians(coord: GeoCoord) -> (f64, f64) {
+    (
+        (coord.latitude as f64).to_radians(),
+        (coord.longitude as f64).to_radians(),
+    )
+}
+
+/// Great circle distance at sea level (in meters)
+pub fn surface_distance(from: GeoCoord, to: GeoCoord) -> f32 {
+    let (lat1, lon1) = to_radians(from);
+    let (lat2, lon2) = to_radians(to);
+    let a = (0.5 * (lat2 - lat1)).sin().powi(2)
+        + lat1.cos() * lat2.cos() * (0.5 * (lon2 - lon1)).sin().powi(2);
+    (2.0 * R0 as f64 * a.sqrt().asin()) as f32
+}
+
+/// Initial direction of the great circle path (in degrees clockwise from north)
+pub fn bearing(from: GeoCoord, to: GeoCoord) -> f32 {
+    let (lat1, lon1) = to_radians(from);
+    let (lat2, lon2) = to_radians(to);
+    let dlon = lon2 - lon1;
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    (y.atan2(x).to_degrees().rem_euclid(360.0)) as f32
+}
+
+/// Point at the given fraction of the great circle path between the coordinates
+pub fn great_circle_point(from: GeoCoord, to: GeoCoord, fraction: f32) -> GeoCoord {
+    let unit = |coord: GeoCoord| {
+        let (lat, lon) = to_radians(coord);
+        DVec3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin())
+    };
+    let (a, b) = (unit(from), unit(to));
+    let angle = a.angle_between(b);
+    if angle < 1e-9 {
+        return from;
+    }
+
+    let fraction = fraction as f64;
+    let point = (a * ((1.0 - fraction) * angle).sin() + b * (fraction * angle).sin()) / angle.sin();
+    GeoCoord::new(
+        point.z.asin().to_degrees() as f32,
+        point.y.atan2(point.x).to_degrees() as f32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn inverse_transform_gives_back_coordinates() {
+        let (height, coord) = inverse_transform(transform(2655.0, 20.088, 49.179));
+        assert_abs_diff_eq!(height, 2655.0, epsilon = 1.0);
+        assert_abs_diff_eq!(coord.latitude, 49.179, epsilon = 1e-5);
+        assert_abs_diff_eq!(coord.longitude, 20.088, epsilon = 1e-5);
+    }
+
+    #[rstest]
+    #[case(GeoCoord::new(50.0, 20.0), 0.0, 111_195.0)]
+    #[case(GeoCoord::new(49.0, 21.0), 90.0, 72_950.0)]
+    #[case(GeoCoord::new(48.0, 20.0), 180.0, 111_195.0)]
+    #[case(GeoCoord::new(49.0, 19.0), 270.0, 72_950.0)]
+    fn bearing_and_distance(
+        #[case] to: GeoCoord,
+        #[case] expected_bearing: f32,
+        #[case] expected_distance: f32,
+    ) {
+        let from = GeoCoord::new(49.0, 20.0);
+        assert_abs_diff_eq!(bearing(from, to), expected_bearing, epsilon = 0.5);
+        assert_abs_diff_eq!(
+            surface_distance(from, to),
+            expected_distance,
+            epsilon = 50.0
+        );
+    }
+
+    #[test]
+    fn great_circle_point_follows_the_shorter_arc() {
+        let from = GeoCoord::new(0.0, 170.0);
+        let to = GeoCoord::new(0.0, -170.0);
+        let middle = great_circle_point(from, to, 0.5);
+        assert_abs_diff_eq!(middle.latitude, 0.0, epsilon = 1e-4);
+        assert_abs_diff_eq!(middle.longitude.abs(), 180.0, epsilon = 1e-4);
+
+        // north of the rhumb line between two points at the same latitude
+        let middle = great_circle_point(GeoCoord::new(49.0, 0.0), GeoCoord::new(49.0, 40.0), 0.5);
+        assert_abs_diff_eq!(middle.latitude, 50.75, epsilon = 1e-2);
+        assert_abs_diff_eq!(middle.longitude, 20.0, epsilon = 1e-4);
+
+        assert_eq!(great_circle_point(from, from, 0.3), from);
+        let end = great_circle_point(from, to, 1.0);
+        assert_abs_diff_eq!(end.longitude, -170.0, epsilon = 1e-4);
+    }
+}
@@ -3,6 +3,7 @@ use std::ops::Range;
 use super::buffer::Buffer;
 use super::pipeline::Pipeline;
 use super::text_renderer::{LINE_HEIGHT, LabelLayout};
+use crate::data::contours::ContourLabelLayout;
 use bytemuck::{Pod, Zeroable};
 use glam::Vec3;
 use lyon::algorithms::rounded_polygon;
@@ -98,7 +99,8 @@ impl LineRenderer {
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        laid_out_labels: Vec<LabelLayout>,
+        laid_out_labels: &[LabelLayout],
+        contour_labels: &[ContourLabelLayout],
     ) {
         let lines_path = {
             let mut builder = Path::builder();
@@ -124,16 +126,17 @@ impl LineRenderer {
 
         let mut label_background_tessellator = FillTessellator::new();
 
-        laid_out_labels.iter().for_each(
-            |&LabelLayout {
-                 location: _,
-                 id: _,
-                 label_x,
-                 label_y,
-                 label_width,
-                 peak_x: _,
-                 peak_y: _,
-             }| {
+        // the contour labels hide the lines under them, like on printed maps
+        let contour_label_rects = contour_labels.iter().map(|label| {
+            let (left, top) = label.get_top_left();
+            (left, top, label.width)
+        });
+
+        laid_out_labels
+            .iter()
+            .map(|label| (label.label_x, label.label_y, label.label_width))
+            .chain(contour_label_rects)
+            .for_each(|(label_x, label_y, label_width)| {
                 let label_backgrounds_path = {
                     let mut builder = Path::builder();
                     let label_rect = Polygon {
@@ -167,8 +170,7 @@ impl LineRenderer {
                         ),
                     )
                     .unwrap();
-            },
-        );
+            });
 
         let mut line_tessellator = StrokeTessellator::new();
 
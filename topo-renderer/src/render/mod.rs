@@ -3,6 +3,8 @@ pub mod bound_texture_view;
 pub mod buffer;
 pub mod compute_pipeline;
 pub mod data;
+pub mod depth_copy;
+pub mod frame_capture;
 pub mod geometry;
 pub mod line_renderer;
 pub mod pipeline;
@@ -1,5 +1,5 @@
 use super::{
-    data::{PostprocessingUniforms, Uniforms, Vertex},
+    data::{LakeVertex, PostprocessingUniforms, Uniforms, Vertex},
     texture::Texture,
 };
 
@@ -36,6 +36,7 @@ impl Pipeline {
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
         texture_bind_group_layout: &wgpu::BindGroupLayout,
+        sea_mask: &Texture,
     ) -> Self {
         let postprocessing_uniforms = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("postprocessing uniform buffer"),
@@ -47,26 +48,44 @@ impl Pipeline {
         let postprocessing_uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("postprocessing uniform bind group layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         let postprocessing_uniform_bind_group =
             device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("postprocessing uniform bind group"),
                 layout: &postprocessing_uniform_bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: postprocessing_uniforms.as_entire_binding(),
-                }],
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: postprocessing_uniforms.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(sea_mask.get_view()),
+                    },
+                ],
             });
 
         let postprocessing_pipeline_layout =
@@ -120,14 +139,125 @@ impl Pipeline {
             uniform_bind_group: postprocessing_uniform_bind_group,
         }
     }
+
+    /// Draws the sky over the whole target at the start of the terrain pass, it shares the
+    /// camera, sun and atmosphere parameters with the postprocessing
+    pub fn create_sky_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        // the terrain drawn afterwards covers it, so the depth is left cleared
+        let depth_stencil = wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        Self::create_sky_shader_pipeline(device, format, "fs_main", Some(depth_stencil))
+    }
+
+    /// Draws the sky above the camera's horizon into a small texture by the azimuth and
+    /// the elevation, which the postprocessing looks the reflections on the water up in
+    pub fn create_sky_view_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        Self::create_sky_shader_pipeline(device, format, "fs_sky_view", None)
+    }
+
+    fn create_sky_shader_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        fragment_entry_point: &str,
+        depth_stencil: Option<wgpu::DepthStencilState>,
+    ) -> Self {
+        let sky_uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sky uniform buffer"),
+            size: std::mem::size_of::<PostprocessingUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sky_uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("sky uniform bind group layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let sky_uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sky uniform bind group"),
+            layout: &sky_uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: sky_uniforms.as_entire_binding(),
+            }],
+        });
+
+        let sky_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&sky_uniform_bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let sky_shader = device.create_shader_module(wgpu::include_wgsl!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../resources/shaders/sky_shader.wgsl"
+        )));
+
+        let sky_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(&sky_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &sky_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &sky_shader,
+                entry_point: Some(fragment_entry_point),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline: sky_pipeline,
+            uniforms: sky_uniforms,
+            uniform_bind_group: sky_uniform_bind_group,
+        }
+    }
 }
 
 pub struct TerrainRenderPipeline {
     pipeline: Pipeline,
+    /// Draws the lakes, see [`LakeVertex`], with the terrain's uniforms
+    lake_pipeline: wgpu::RenderPipeline,
     height_map_bind_group_layout: wgpu::BindGroupLayout,
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
 }
 
 impl TerrainRenderPipeline {
+    pub const NORMALS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
     pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
         let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("uniform buffer"),
@@ -177,7 +307,7 @@ impl TerrainRenderPipeline {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -185,6 +315,32 @@ impl TerrainRenderPipeline {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
                 ],
             });
 
@@ -209,11 +365,46 @@ impl TerrainRenderPipeline {
             "/../resources/shaders/render_shader.wgsl"
         )));
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &render_shader,
+            "fs_main",
+            format,
+            Some(wgpu::BlendState::REPLACE),
+        );
+
+        let lake_pipeline =
+            Self::create_lake_pipeline(device, &uniform_bind_group_layout, &render_shader, format);
+
+        let pipeline = Pipeline {
+            pipeline: render_pipeline,
+            uniforms,
+            uniform_bind_group,
+        };
+
+        Self {
+            pipeline,
+            lake_pipeline,
+            height_map_bind_group_layout,
+            layout: render_pipeline_layout,
+            shader: render_shader,
+        }
+    }
+
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        fragment_entry_point: &str,
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &render_shader,
+                module: shader,
                 entry_point: Some("vs_main"),
                 buffers: &[Vertex::desc()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -236,29 +427,80 @@ impl TerrainRenderPipeline {
                 alpha_to_coverage_enabled: false,
             },
             fragment: Some(wgpu::FragmentState {
-                module: &render_shader,
-                entry_point: Some("fs_main"),
+                module: shader,
+                entry_point: Some(fragment_entry_point),
                 targets: &[Some(wgpu::ColorTargetState {
                     format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             }),
             multiview_mask: None,
             cache: None,
+        })
+    }
+
+    fn create_lake_pipeline(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Lake Pipeline Layout"),
+            bind_group_layouts: &[uniform_bind_group_layout],
+            immediate_size: 0,
         });
 
-        let pipeline = Pipeline {
-            pipeline: render_pipeline,
-            uniforms,
-            uniform_bind_group,
-        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Lake Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: Some("vs_lake"),
+                buffers: &[LakeVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_lake"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            multiview_mask: None,
+            cache: None,
+        })
+    }
 
-        Self {
-            pipeline,
-            height_map_bind_group_layout,
-        }
+    /// Pipeline writing the world space normals to a [`Self::NORMALS_FORMAT`] target
+    /// instead of shading the terrain, created on demand as not every device supports it
+    pub fn create_normals_pipeline(&self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        Self::create_render_pipeline(
+            device,
+            &self.layout,
+            &self.shader,
+            "fs_normals",
+            Self::NORMALS_FORMAT,
+            None,
+        )
     }
 
     pub fn get_height_map_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
@@ -268,4 +510,131 @@ impl TerrainRenderPipeline {
     pub fn get_pipeline(&self) -> &Pipeline {
         &self.pipeline
     }
+
+    pub fn get_lake_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.lake_pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use jiff::Timestamp;
+    use topo_common::GeoCoord;
+
+    use crate::{
+        data::{camera::ViewMode, sun::SunPosition, water::WaterSettings},
+        headless::tests::{gbuffer_pixels, render_start_view, test_settings, with_test_renderer},
+        settings::{AtmosphereSettings, PostprocessingSettings, RendererSettings, SunSettings},
+    };
+
+    use super::*;
+
+    #[test]
+    fn haze_fades_the_distant_terrain() {
+        let settings = RendererSettings {
+            start_location: GeoCoord::new(49.2, 20.5),
+            start_altitude: Some(1_500.0),
+            start_pitch: Some(-5.0),
+            view_distance: 60_000.0,
+            sun: SunSettings {
+                // early morning, the terrain is darker than the sunlit haze
+                time: Some("2024-06-21T04:00:00Z".parse().unwrap()),
+                ..Default::default()
+            },
+            atmosphere: AtmosphereSettings {
+                visibility: 20_000.0,
+                ..Default::default()
+            },
+            ..test_settings()
+        };
+
+        with_test_renderer(settings, (128, 96).into(), async |renderer, _| {
+            let (hazy, gbuffer) = render_start_view(renderer).await;
+
+            // the same view without the haze
+            let size = renderer.size();
+            let data = renderer.data_mut();
+            data.postprocessing_uniforms = PostprocessingUniforms::new(
+                (size.width as f32, size.height as f32).into(),
+                &data.camera,
+                data.sun.direction(),
+                &PostprocessingSettings::default(),
+                &AtmosphereSettings {
+                    visibility: 0.0,
+                    ..Default::default()
+                },
+                &WaterSettings::default(),
+            );
+            let clear = renderer.render().await.unwrap();
+
+            let mean_difference = |distances: std::ops::Range<f32>| {
+                let differences = gbuffer_pixels(&hazy, &gbuffer)
+                    .zip(clear.pixels())
+                    .filter(|(hazy, _)| distances.contains(&hazy.distance))
+                    .map(|(hazy, clear)| {
+                        (0..3)
+                            .map(|i| (hazy.color[i] as f32 - clear.0[i] as f32).abs())
+                            .sum::<f32>()
+                    })
+                    .collect_vec();
+                assert!(!differences.is_empty());
+                differences.iter().sum::<f32>() / differences.len() as f32
+            };
+            let (near, far) = (
+                mean_difference(0.0..3_000.0),
+                mean_difference(15_000.0..60_000.0),
+            );
+            assert!(far > 2.0 * near);
+            assert!(far > 60.0);
+        });
+    }
+
+    #[test]
+    fn sky_follows_the_sun_in_every_view_mode() {
+        let location = GeoCoord::new(49.2, 20.5);
+        let morning: Timestamp = "2024-06-21T04:00:00Z".parse().unwrap();
+        let sun = SunPosition::new(morning, location);
+        let settings = RendererSettings {
+            start_location: location,
+            start_altitude: Some(1_500.0),
+            // looking right at the sun
+            start_yaw: Some(sun.azimuth),
+            start_pitch: Some(sun.altitude),
+            fov: 90.0,
+            sun: SunSettings {
+                time: Some(morning),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        with_test_renderer(settings, (128, 96).into(), async |renderer, _| {
+            renderer.load_location(location).await.unwrap();
+            let day = renderer.render().await.unwrap();
+            renderer.data_mut().camera.view_mode = ViewMode::Normals;
+            let normals = renderer.render().await.unwrap();
+            renderer
+                .data_mut()
+                .sun
+                .set_time("2024-06-21T23:00:00Z".parse().unwrap());
+            let night = renderer.render().await.unwrap();
+
+            let brightness = |image: &image::RgbaImage, x: u32, y: u32| {
+                image.get_pixel(x, y).0[..3]
+                    .iter()
+                    .map(|&c| c as u32)
+                    .sum::<u32>()
+                    / 3
+            };
+            // the sun's glow in the middle, a blue sky further away from it
+            assert!(brightness(&day, 64, 48) > 220);
+            let corner = day.get_pixel(2, 2).0;
+            assert!(corner[2] > corner[0]);
+            assert!(brightness(&day, 2, 2) < brightness(&day, 64, 48));
+
+            assert_eq!(normals.get_pixel(64, 48), day.get_pixel(64, 48));
+            assert!(brightness(&night, 64, 48) < 30);
+        });
+    }
 }
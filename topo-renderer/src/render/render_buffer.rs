@@ -1,9 +1,12 @@
 use std::{cell::OnceCell, sync::Arc};
 
+use glam::Vec3;
+use image::{Rgba, RgbaImage};
 use thiserror::Error;
 
 use crate::{
     common::coordinate_transform::CoordinateTransform,
+    data::imagery::Imagery,
     render::{data::TerrainUniforms, pipeline::TerrainRenderPipeline, texture::Texture},
 };
 
@@ -26,7 +29,14 @@ pub struct RenderBuffer {
     indices_len: usize,
     height_map_texture: Texture,
     normal_texture: Texture,
+    /// How much of the sun is visible from each point, 0 in the terrain's shadow
+    shadow_texture: Texture,
+    /// Sun direction the shadows were last computed for
+    shadows_sun_direction: Option<Vec3>,
+    /// Draped on the terrain, a single white texel until the imagery is loaded
+    imagery_texture: Texture,
     height_map_texture_bind_group: wgpu::BindGroup,
+    terrain_uniforms: TerrainUniforms,
     uniforms: Buffer,
 }
 
@@ -90,11 +100,21 @@ impl RenderBuffer {
         );
 
         let normal_texture = Self::create_normals_texture(device, (width, height));
+        let shadow_texture =
+            Texture::create_storage_texture(device, (width, height), "terrain shadow texture");
 
+        let imagery_texture = Texture::create_imagery_texture(
+            device,
+            queue,
+            &[RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 255]))],
+            "terrain imagery texture",
+        );
+
+        let terrain_uniforms = TerrainUniforms::new(coordinate_transform, (width, height));
         let uniforms = Buffer::new_init(
             device,
             "terrain uniform buffer",
-            bytemuck::bytes_of(&TerrainUniforms::new(coordinate_transform, (width, height))),
+            bytemuck::bytes_of(&terrain_uniforms),
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         );
 
@@ -103,6 +123,8 @@ impl RenderBuffer {
             pipeline,
             &height_map_texture,
             &normal_texture,
+            &shadow_texture,
+            &imagery_texture,
             &uniforms,
         );
 
@@ -112,11 +134,48 @@ impl RenderBuffer {
             indices_len,
             height_map_texture,
             normal_texture,
+            shadow_texture,
+            shadows_sun_direction: None,
+            imagery_texture,
             height_map_texture_bind_group,
+            terrain_uniforms,
             uniforms,
         }
     }
 
+    /// Drapes the imagery on the terrain, replacing the previous one
+    pub fn set_imagery(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline: &TerrainRenderPipeline,
+        imagery: &Imagery,
+    ) {
+        let mip_levels = imagery.mip_chain(device.limits().max_texture_dimension_2d);
+        self.imagery_texture =
+            Texture::create_imagery_texture(device, queue, &mip_levels, "terrain imagery texture");
+
+        // the georeferencing doesn't change with the size of the texture
+        self.terrain_uniforms = self
+            .terrain_uniforms
+            .with_imagery(imagery.coordinate_transform, imagery.image.dimensions());
+        queue.write_buffer(
+            &self.uniforms.raw,
+            0,
+            bytemuck::bytes_of(&self.terrain_uniforms),
+        );
+
+        self.height_map_texture_bind_group = Self::create_bind_group(
+            device,
+            pipeline,
+            &self.height_map_texture,
+            &self.normal_texture,
+            &self.shadow_texture,
+            &self.imagery_texture,
+            &self.uniforms,
+        );
+    }
+
     pub fn get_vertices(&self) -> &Buffer {
         &self.vertices
     }
@@ -137,6 +196,23 @@ impl RenderBuffer {
         &self.normal_texture
     }
 
+    pub fn get_shadow_texture(&self) -> &Texture {
+        &self.shadow_texture
+    }
+
+    pub fn get_shadows_sun_direction(&self) -> Option<Vec3> {
+        self.shadows_sun_direction
+    }
+
+    pub fn set_shadows_sun_direction(&mut self, sun_direction: Vec3) {
+        self.shadows_sun_direction = Some(sun_direction);
+    }
+
+    /// Makes the shadows stale whatever the sun direction, for them to be computed again
+    pub fn invalidate_shadows(&mut self) {
+        self.shadows_sun_direction = None;
+    }
+
     pub fn get_height_map_texture_bind_group(&self) -> &wgpu::BindGroup {
         &self.height_map_texture_bind_group
     }
@@ -159,6 +235,8 @@ impl RenderBuffer {
         pipeline: &TerrainRenderPipeline,
         height_map_texture: &Texture,
         normals_texture: &Texture,
+        shadow_texture: &Texture,
+        imagery_texture: &Texture,
         uniforms: &Buffer,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -177,6 +255,23 @@ impl RenderBuffer {
                     binding: 2,
                     resource: uniforms.raw.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(shadow_texture.get_view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(imagery_texture.get_view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(
+                        imagery_texture
+                            .get_sampler()
+                            .as_ref()
+                            .expect("imagery textures have a sampler"),
+                    ),
+                },
             ],
         })
     }
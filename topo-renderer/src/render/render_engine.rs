@@ -1,46 +1,102 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, mpsc},
+};
 
 use bytes::Buf;
-use color_eyre::Result;
-use glam::Mat4;
+use color_eyre::{Result, eyre::eyre};
+use futures::channel::oneshot;
+use glam::{Mat4, Vec3};
+use half::f16;
+use image::RgbaImage;
 use itertools::Itertools;
-use tiff::decoder::DecodingResult;
 use topo_common::{GeoCoord, GeoLocation};
 use wgpu::{BufferView, TexelCopyBufferInfo, TexelCopyBufferLayout};
 use winit::{dpi::PhysicalSize, event_loop::EventLoopProxy, window::Window};
 
 use crate::{
     app::ApplicationEvent,
-    common::coordinate_transform::CoordinateTransform,
-    data::{DepthState, Size, application_data::ApplicationData, camera::dist_from_depth, pad_256},
+    data::{
+        DepthState, Size, application_data::ApplicationData, camera::dist_from_depth,
+        contours::ContourLabelLayout, height_map::HeightMap, imagery::Imagery, pad_256,
+        water::LakeMesh,
+    },
     render::{
-        data::{PeakInstance, Uniforms},
-        text_renderer::LabelId,
+        data::PeakInstance,
+        depth_copy::DepthCopy,
+        frame_capture::FrameCapture,
+        pipeline::TerrainRenderPipeline,
+        text_renderer::{LabelId, LabelLayout},
+        texture::Texture,
     },
 };
 
 use super::application_renderers::ApplicationRenderers;
 
 pub enum RenderEvent {
-    TerrainReady(GeoLocation, DecodingResult, CoordinateTransform, (u32, u32)),
+    TerrainReady(GeoLocation, Arc<HeightMap>),
+    /// The backend has no terrain for the location, it's covered with the sea
+    NoTerrain(GeoLocation),
+    /// Imagery to drape on the already loaded terrain of the location
+    ImageryReady(GeoLocation, Imagery),
+    /// Lakes on the already loaded terrain of the location
+    LakesReady(GeoLocation, LakeMesh),
     DepthBufferReady(DepthState),
     FrameFinished(DepthState),
     NormalsComputed(GeoLocation),
     ResetCamera(GeoCoord, f32),
 }
 
+/// Where the [`RenderEvent`]s produced by gpu callbacks get delivered
+#[derive(Clone, Debug)]
+pub enum RenderEventSender {
+    EventLoop(EventLoopProxy<ApplicationEvent>),
+    /// Used when there's no event loop, the events are processed with
+    /// [`RenderEngine::process_pending_events`]
+    Channel(mpsc::Sender<RenderEvent>),
+}
+
+impl RenderEventSender {
+    pub fn send(&self, event: RenderEvent) {
+        match self {
+            RenderEventSender::EventLoop(event_loop_proxy) => {
+                let _ = event_loop_proxy.send_event(ApplicationEvent::RenderEvent(event));
+            }
+            RenderEventSender::Channel(sender) => {
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
+
+enum RenderTarget {
+    Surface {
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        config: wgpu::SurfaceConfiguration,
+    },
+    Offscreen(FrameCapture),
+}
+
 /// This struct handles logic that necessarily requires access to wgpu primitives
 /// and so must be done synchronously in a tight loop
 pub struct RenderEngine {
-    window: Arc<Window>,
-    surface: wgpu::Surface<'static>,
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
     size: PhysicalSize<u32>,
     renderers: ApplicationRenderers,
+    depth_copy: Option<DepthCopy>,
     depth_state: Option<DepthState>,
-    event_loop_proxy: EventLoopProxy<ApplicationEvent>,
+    event_sender: RenderEventSender,
+    event_receiver: Option<mpsc::Receiver<RenderEvent>>,
+    show_labels: bool,
+    /// Labels of the last frame, for picking peaks
+    label_layouts: Vec<LabelLayout>,
+    contour_label_layouts: Vec<ContourLabelLayout>,
+    /// Normal computations dispatched for the loaded terrain that didn't report back yet
+    pending_normal_computations: usize,
 }
 
 impl RenderEngine {
@@ -61,16 +117,8 @@ impl RenderEngine {
                 force_fallback_adapter: false,
             })
             .await?;
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-                experimental_features: Default::default(),
-            })
-            .await?;
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let depth_copy = DepthCopy::new_if_needed(&device, &adapter);
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -94,23 +142,136 @@ impl RenderEngine {
             desired_maximum_frame_latency: 2,
         };
 
-        let renderers = ApplicationRenderers::new(&device, &queue, &config, format, size.into());
+        let renderers = ApplicationRenderers::new(&device, &queue, format, size.into());
 
         Ok(Self {
-            window,
-            surface,
+            target: RenderTarget::Surface {
+                window,
+                surface,
+                config,
+            },
             device,
             queue,
-            config,
+            format,
             size,
             renderers,
+            depth_copy,
             depth_state: None,
-            event_loop_proxy,
+            label_layouts: vec![],
+            contour_label_layouts: vec![],
+            event_sender: RenderEventSender::EventLoop(event_loop_proxy),
+            event_receiver: None,
+            show_labels: true,
+            pending_normal_computations: 0,
         })
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    /// Creates an engine rendering into an offscreen texture, without the need for a window
+    ///
+    /// Any adapter will do, including software ones like llvmpipe, the choice
+    /// can be narrowed down with the `WGPU_BACKEND` and `WGPU_ADAPTER_NAME` environment variables
+    pub async fn new_headless(size: Size<u32>) -> Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all().with_env(),
+            ..Default::default()
+        });
+        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, None).await?;
+        log::info!("Rendering headless with {:?}", adapter.get_info());
+        let (device, queue) = Self::request_device(&adapter).await?;
+        let depth_copy = DepthCopy::new_if_needed(&device, &adapter);
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let (sender, receiver) = mpsc::channel();
+
+        let mut engine = Self {
+            target: RenderTarget::Offscreen(FrameCapture::new(&device, format, size)),
+            renderers: ApplicationRenderers::new(&device, &queue, format, size),
+            depth_copy,
+            device,
+            queue,
+            format,
+            size: PhysicalSize::new(size.width, size.height),
+            depth_state: None,
+            label_layouts: vec![],
+            contour_label_layouts: vec![],
+            event_sender: RenderEventSender::Channel(sender),
+            event_receiver: Some(receiver),
+            show_labels: true,
+            pending_normal_computations: 0,
+        };
+        engine.update_resolution();
+
+        Ok(engine)
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let device_and_queue = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::default(),
+                memory_hints: Default::default(),
+                trace: wgpu::Trace::Off,
+                experimental_features: Default::default(),
+            })
+            .await?;
+
+        Ok(device_and_queue)
+    }
+
+    /// Window of the surface the engine renders to, `None` for headless engines
+    pub fn window(&self) -> Option<&Window> {
+        match &self.target {
+            RenderTarget::Surface { window, .. } => Some(window),
+            RenderTarget::Offscreen(_) => None,
+        }
+    }
+
+    pub fn request_redraw(&self) {
+        if let Some(window) = self.window() {
+            window.request_redraw();
+        }
+    }
+
+    /// Labels still get laid out (and peaks marked as visible) when hidden, they just aren't drawn
+    pub fn set_show_labels(&mut self, show_labels: bool) {
+        self.show_labels = show_labels;
+    }
+
+    /// Peak whose label of the last frame is at the position (in pixels), the topmost one if several
+    pub fn get_label_at(&self, position: (f32, f32)) -> Option<(GeoLocation, LabelId)> {
+        self.label_layouts
+            .iter()
+            .rev()
+            .find(|label| label.contains(position))
+            .map(|label| (label.location, label.id))
+    }
+
+    /// Contour labels of the last frame
+    pub fn get_contour_labels(&self) -> &[ContourLabelLayout] {
+        &self.contour_label_layouts
+    }
+
+    /// Whether the normals of all the loaded terrain are computed
+    pub fn is_terrain_ready(&self) -> bool {
+        self.pending_normal_computations == 0
+    }
+
+    /// Blocks until the gpu finishes the normal computations of the loaded terrain
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_for_terrain(&mut self, data: &mut ApplicationData) -> Result<()> {
+        if !self.is_terrain_ready() {
+            self.device.poll(wgpu::PollType::wait_indefinitely())?;
+            self.process_pending_events(data);
+        }
+        if !self.is_terrain_ready() {
+            return Err(eyre!(
+                "{} normal computations didn't finish",
+                self.pending_normal_computations
+            ));
+        }
+
+        Ok(())
     }
 
     pub fn size(&self) -> PhysicalSize<u32> {
@@ -125,15 +286,32 @@ impl RenderEngine {
         DepthState {
             size: self.size.into(),
             camera: data.camera,
+            scene_version: data.scene_version,
         }
     }
 
+    /// Whether the labels were laid out for the current camera, size and scene
+    pub fn labels_up_to_date(&self, data: &ApplicationData) -> bool {
+        self.depth_state == Some(self.new_depth_state(data))
+    }
+
     pub fn update_size(&mut self, new_size: PhysicalSize<u32>, data: &mut ApplicationData) {
-        self.surface.configure(&self.device, &self.config);
+        match &mut self.target {
+            RenderTarget::Surface {
+                surface, config, ..
+            } => surface.configure(&self.device, config),
+            RenderTarget::Offscreen(frame_capture) => {
+                *frame_capture = FrameCapture::new(&self.device, self.format, new_size.into());
+            }
+        }
         self.size = new_size;
         let bounds = (new_size.width as f32, new_size.height as f32).into();
-        data.uniforms = data.uniforms.update_projection(&data.camera, bounds);
-        data.postprocessing_uniforms = data.postprocessing_uniforms.with_new_viewport(bounds);
+        data.uniforms = data
+            .uniforms
+            .update_projection(&data.camera, data.sun.direction(), bounds);
+        data.postprocessing_uniforms =
+            data.postprocessing_uniforms
+                .update_view(&data.camera, data.sun.direction(), bounds);
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>, data: &mut ApplicationData) -> bool {
@@ -141,21 +319,12 @@ impl RenderEngine {
             // TODO: Might be a better way to do this; buffer gets touched during resize
             // so we unmap it so that there's no chance of crashing
             self.renderers.terrain.get_depth_read_buffer_mut().unmap();
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
+            if let RenderTarget::Surface { config, .. } = &mut self.target {
+                config.width = new_size.width;
+                config.height = new_size.height;
+            }
             self.update_size(new_size, data);
-
-            self.renderers.text.viewport.update(
-                &self.queue,
-                glyphon::Resolution {
-                    width: self.config.width,
-                    height: self.config.height,
-                },
-            );
-
-            self.renderers
-                .line
-                .update_resolution(self.config.width, self.config.height);
+            self.update_resolution();
 
             self.renderers.terrain.update(
                 &self.device,
@@ -170,6 +339,20 @@ impl RenderEngine {
         }
     }
 
+    fn update_resolution(&mut self) {
+        self.renderers.text.viewport.update(
+            &self.queue,
+            glyphon::Resolution {
+                width: self.size.width,
+                height: self.size.height,
+            },
+        );
+
+        self.renderers
+            .line
+            .update_resolution(self.size.width, self.size.height);
+    }
+
     pub fn poll(&self) -> Result<()> {
         self.device.poll(wgpu::PollType::Poll)?;
         Ok(())
@@ -177,9 +360,13 @@ impl RenderEngine {
 
     pub fn update(&mut self, data: &mut ApplicationData) {
         let size: Size<u32> = self.size.into();
+        let bounds = (size.width as f32, size.height as f32).into();
         data.uniforms = data
             .uniforms
-            .update_projection(&data.camera, (size.width as f32, size.height as f32).into());
+            .update_projection(&data.camera, data.sun.direction(), bounds);
+        data.postprocessing_uniforms =
+            data.postprocessing_uniforms
+                .update_view(&data.camera, data.sun.direction(), bounds);
         self.renderers.terrain.update(
             &self.device,
             &self.queue,
@@ -187,15 +374,42 @@ impl RenderEngine {
             &data.uniforms,
             &data.postprocessing_uniforms,
         );
+        self.update_shadows(data, data.shadows.tiles_per_frame);
+    }
+
+    /// Follows the sun with the terrain's shadows, updating up to `max_tiles` tiles
+    fn update_shadows(&mut self, data: &ApplicationData, max_tiles: usize) {
+        self.renderers.terrain.update_shadows(
+            &self.device,
+            &self.queue,
+            data.sun.direction(),
+            &data.shadows,
+            max_tiles,
+        );
+    }
+
+    /// Whether some of the shadows still have to catch up with the sun, which takes
+    /// a few frames as only [`tiles_per_frame`](crate::settings::ShadowSettings::tiles_per_frame) tiles get updated in each
+    pub fn has_stale_shadows(&self, data: &ApplicationData) -> bool {
+        self.renderers
+            .terrain
+            .has_stale_shadows(data.sun.direction(), &data.shadows)
     }
 
     pub fn render(
         &mut self,
         data: &ApplicationData,
     ) -> std::result::Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let RenderTarget::Surface {
+            surface, config, ..
+        } = &self.target
+        else {
+            return Err(wgpu::SurfaceError::Other);
+        };
+
+        let output = surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
-            format: Some(self.config.format),
+            format: Some(config.format),
             ..Default::default()
         });
 
@@ -207,45 +421,18 @@ impl RenderEngine {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let mut pass = self
-                .renderers
-                .terrain
-                .render(&view, &mut encoder, self.size.into());
-            self.renderers.line.render(&mut pass);
-            self.renderers.text.render(&mut pass);
-        }
+        Self::render_scene(
+            &mut self.renderers,
+            self.size,
+            &view,
+            &mut encoder,
+            self.show_labels,
+        );
 
-        if !self.renderers.terrain.get_depth_read_buffer().mapped
-            && self
-                .depth_state
-                .is_none_or(|depth_state| depth_state != self.new_depth_state(data))
-        {
+        if !self.renderers.terrain.get_depth_read_buffer().mapped && !self.labels_up_to_date(data) {
             copying_depth_texture = true;
-            let depth_texture = self
-                .renderers
-                .terrain
-                .get_texture_view()
-                .get_textures()
-                .get(1)
-                .expect("missing depth texture")
-                .get_texture();
-
-            let bytes_per_row_unpadded = depth_texture.width() * 4;
-
-            let depth_read_buffer_info = TexelCopyBufferInfo {
-                buffer: &self.renderers.terrain.get_depth_read_buffer().raw,
-                layout: TexelCopyBufferLayout {
-                    bytes_per_row: Some(pad_256(bytes_per_row_unpadded)),
-                    ..Default::default()
-                },
-            };
-
-            encoder.copy_texture_to_buffer(
-                depth_texture.as_image_copy(),
-                depth_read_buffer_info,
-                depth_texture.size(),
-            );
+            let depth_read_buffer = self.renderers.terrain.get_depth_read_buffer().raw.clone();
+            self.copy_depth_texture(&mut encoder, &depth_read_buffer);
         }
 
         self.queue.submit(Some(encoder.finish()));
@@ -253,36 +440,319 @@ impl RenderEngine {
         self.renderers.text.atlas.trim();
 
         if copying_depth_texture {
-            let event_loop_proxy = self.event_loop_proxy.clone();
+            let event_sender = self.event_sender.clone();
             let new_depth_state = self.new_depth_state(data);
 
             self.queue.on_submitted_work_done(move || {
-                event_loop_proxy
-                    .send_event(ApplicationEvent::RenderEvent(RenderEvent::FrameFinished(
-                        new_depth_state,
-                    )))
-                    .ok();
+                event_sender.send(RenderEvent::FrameFinished(new_depth_state));
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Renders a frame of a headless engine and reads it back
+    ///
+    /// If the view changed since the last frame, the labels are laid out again
+    /// before rendering, which requires an extra pass for getting the depth buffer.
+    /// Waiting for the gpu is only possible on native targets
+    pub async fn render_to_image(&mut self, data: &mut ApplicationData) -> Result<RgbaImage> {
+        if !matches!(self.target, RenderTarget::Offscreen(_)) {
+            return Err(eyre!("Only headless engines can render to an image"));
+        }
+
+        self.update(data);
+        // a single frame has to show all the shadows
+        self.update_shadows(data, usize::MAX);
+
+        if !self.renderers.terrain.get_depth_read_buffer().mapped && !self.labels_up_to_date(data) {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Depth Encoder"),
+                });
+            let target = self.offscreen_view();
+            Self::render_scene(
+                &mut self.renderers,
+                self.size,
+                &target,
+                &mut encoder,
+                self.show_labels,
+            );
+            let depth_read_buffer = self.renderers.terrain.get_depth_read_buffer().raw.clone();
+            self.copy_depth_texture(&mut encoder, &depth_read_buffer);
+            self.queue.submit(Some(encoder.finish()));
+
+            let new_depth_state = self.new_depth_state(data);
+            self.renderers
+                .terrain
+                .get_depth_read_buffer_mut()
+                .map(self.event_sender.clone(), new_depth_state);
+
+            #[cfg(not(target_arch = "wasm32"))]
+            self.device.poll(wgpu::PollType::wait_indefinitely())?;
+            self.process_pending_events(data);
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render To Image Encoder"),
             });
+        let target = self.offscreen_view();
+        Self::render_scene(
+            &mut self.renderers,
+            self.size,
+            &target,
+            &mut encoder,
+            self.show_labels,
+        );
+
+        let RenderTarget::Offscreen(frame_capture) = &self.target else {
+            unreachable!("checked at the beginning");
+        };
+        frame_capture.copy_to_buffer(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        self.renderers.text.atlas.trim();
+
+        frame_capture.read(&self.device).await
+    }
+
+    /// Renders the current view of a headless engine and reads back its depth buffer
+    ///
+    /// The values (in 0..1, see [`dist_from_depth`]) are laid out row by row
+    pub async fn read_depth(&mut self, data: &mut ApplicationData) -> Result<Vec<f32>> {
+        if !matches!(self.target, RenderTarget::Offscreen(_)) {
+            return Err(eyre!(
+                "Only headless engines can read back the depth buffer"
+            ));
+        }
+
+        self.update(data);
+
+        let padded_bytes_per_row = pad_256(self.size.width * 4);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("depth export buffer"),
+            size: (padded_bytes_per_row * self.size.height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Depth Export Encoder"),
+            });
+        let target = self.offscreen_view();
+        Self::render_scene(
+            &mut self.renderers,
+            self.size,
+            &target,
+            &mut encoder,
+            self.show_labels,
+        );
+        self.copy_depth_texture(&mut encoder, &buffer);
+        self.queue.submit(Some(encoder.finish()));
+
+        self.map_for_reading(&buffer).await?;
+
+        let depth = buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| {
+                row[..self.size.width as usize * 4]
+                    .chunks_exact(4)
+                    .map(|mut value| value.get_f32_le())
+            })
+            .collect();
+        buffer.unmap();
+
+        Ok(depth)
+    }
+
+    /// Renders the world space normals of the terrain in the current view of a headless engine
+    ///
+    /// The normals are laid out row by row, they're zero where there's no terrain
+    pub async fn read_normals(&mut self, data: &mut ApplicationData) -> Result<Vec<Vec3>> {
+        if !matches!(self.target, RenderTarget::Offscreen(_)) {
+            return Err(eyre!("Only headless engines can read back the normals"));
         }
 
+        self.update(data);
+
+        let size: Size<u32> = self.size.into();
+        let texture = Texture::create_capture_texture(
+            &self.device,
+            TerrainRenderPipeline::NORMALS_FORMAT,
+            (size.width, size.height),
+            "normals capture texture",
+        );
+        let padded_bytes_per_row = pad_256(size.width * 8);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("normals export buffer"),
+            size: (padded_bytes_per_row * size.height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Normals Export Encoder"),
+            });
+        self.renderers
+            .terrain
+            .render_normals(&self.device, &mut encoder, texture.get_view(), size);
+        encoder.copy_texture_to_buffer(
+            texture.get_texture().as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    ..Default::default()
+                },
+            },
+            *texture.get_size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        self.map_for_reading(&buffer).await?;
+
+        let normals = buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| {
+                row[..size.width as usize * 8]
+                    .chunks_exact(8)
+                    .map(|mut value| {
+                        let mut component = || f16::from_bits(value.get_u16_le()).to_f32();
+                        Vec3::new(component(), component(), component()).normalize_or_zero()
+                    })
+            })
+            .collect();
+        buffer.unmap();
+
+        Ok(normals)
+    }
+
+    async fn map_for_reading(&self, buffer: &wgpu::Buffer) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        #[cfg(not(target_arch = "wasm32"))]
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver
+            .await
+            .map_err(|_| eyre!("Buffer mapping was canceled"))??;
+
         Ok(())
     }
 
+    fn offscreen_view(&self) -> wgpu::TextureView {
+        match &self.target {
+            RenderTarget::Offscreen(frame_capture) => frame_capture.get_view().clone(),
+            RenderTarget::Surface { .. } => unreachable!("not a headless engine"),
+        }
+    }
+
+    /// Processes the events sent by the gpu callbacks of a headless engine
+    pub fn process_pending_events(&mut self, data: &mut ApplicationData) {
+        while let Some(event) = self
+            .event_receiver
+            .as_ref()
+            .and_then(|receiver| receiver.try_recv().ok())
+        {
+            self.process_event(event, data);
+        }
+    }
+
+    fn render_scene(
+        renderers: &mut ApplicationRenderers,
+        size: PhysicalSize<u32>,
+        target: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+        show_labels: bool,
+    ) {
+        let mut pass = renderers.terrain.render(target, encoder, size.into());
+        if show_labels {
+            renderers.line.render(&mut pass);
+            renderers.text.render(&mut pass);
+        }
+    }
+
+    fn copy_depth_texture(&mut self, encoder: &mut wgpu::CommandEncoder, buffer: &wgpu::Buffer) {
+        let renderers = &self.renderers;
+        let depth_texture = renderers
+            .terrain
+            .get_texture_view()
+            .get_textures()
+            .get(1)
+            .expect("missing depth texture");
+
+        if let Some(depth_copy) = &mut self.depth_copy {
+            depth_copy.copy_to_buffer(&self.device, encoder, depth_texture, buffer);
+            return;
+        }
+
+        let depth_texture = depth_texture.get_texture();
+
+        let bytes_per_row_unpadded = depth_texture.width() * 4;
+
+        let depth_read_buffer_info = TexelCopyBufferInfo {
+            buffer,
+            layout: TexelCopyBufferLayout {
+                bytes_per_row: Some(pad_256(bytes_per_row_unpadded)),
+                ..Default::default()
+            },
+        };
+
+        encoder.copy_texture_to_buffer(
+            depth_texture.as_image_copy(),
+            depth_read_buffer_info,
+            depth_texture.size(),
+        );
+    }
+
     /// Returns whether scene changed and needs to be rerendered
     pub fn process_event(&mut self, event: RenderEvent, data: &mut ApplicationData) -> bool {
         use RenderEvent::*;
         match event {
-            TerrainReady(location, mut height_map_data, coordinate_transform, size) => {
-                self.renderers.terrain.add_terrain(
+            TerrainReady(location, height_map) => {
+                self.pending_normal_computations += self.renderers.terrain.add_terrain(
                     &self.device,
                     &self.queue,
                     location,
-                    height_map_data.as_buffer(0).as_bytes(),
-                    coordinate_transform,
-                    size,
-                    self.event_loop_proxy.clone(),
+                    height_map.as_bytes(),
+                    *height_map.get_coordinate_transform(),
+                    height_map.get_size(),
+                    data.sun.direction(),
+                    &data.shadows,
+                    self.event_sender.clone(),
                 );
+                data.height_maps.insert(location, height_map);
                 data.loaded_locations.insert(location);
+                data.scene_changed();
+            }
+            NoTerrain(location) => {
+                self.renderers.terrain.add_sea(location);
+                data.loaded_locations.insert(location);
+                data.scene_changed();
+            }
+            ImageryReady(location, imagery) => {
+                self.renderers
+                    .terrain
+                    .set_imagery(&self.device, &self.queue, location, &imagery);
+            }
+            LakesReady(location, lakes) => {
+                self.renderers
+                    .terrain
+                    .set_lakes(&self.device, location, &lakes);
+                data.scene_changed();
             }
             DepthBufferReady(depth_state) => {
                 let depth_buffer = self.renderers.terrain.get_depth_read_buffer();
@@ -304,16 +774,28 @@ impl RenderEngine {
                         &depth_buffer_view,
                     );
 
-                    let laid_out_labels = self.renderers.text.prepare(
+                    let contour_labels = Self::get_visible_contour_labels(
+                        data,
+                        &projection,
+                        self.size,
+                        depth_state,
+                        &depth_buffer_view,
+                    );
+
+                    (self.label_layouts, self.contour_label_layouts) = self.renderers.text.prepare(
                         &self.device,
                         &self.queue,
                         visible_labels,
+                        contour_labels,
                         data,
                     );
 
-                    self.renderers
-                        .line
-                        .prepare(&self.device, &self.queue, laid_out_labels);
+                    self.renderers.line.prepare(
+                        &self.device,
+                        &self.queue,
+                        &self.label_layouts,
+                        &self.contour_label_layouts,
+                    );
                 }
                 self.renderers.terrain.get_depth_read_buffer_mut().unmap();
             }
@@ -321,14 +803,23 @@ impl RenderEngine {
                 self.renderers
                     .terrain
                     .get_depth_read_buffer_mut()
-                    .map(self.event_loop_proxy.clone(), depth_state);
+                    .map(self.event_sender.clone(), depth_state);
             }
             ResetCamera(current_location, height) => {
-                data.camera.reset(current_location, height + 50.0);
-                data.uniforms = Uniforms::new(&data.camera, self.bounds());
+                let start_view = data.start_view.take().unwrap_or_default();
+                let altitude = start_view.altitude.unwrap_or(height + 50.0);
+                data.camera.reset(current_location, altitude);
+                data.camera.apply_start_view(start_view);
+                data.uniforms = data.uniforms.update_projection(
+                    &data.camera,
+                    data.sun.direction(),
+                    self.bounds(),
+                );
             }
             NormalsComputed(_) => {
                 log::debug!("Finished normal computation");
+                self.pending_normal_computations =
+                    self.pending_normal_computations.saturating_sub(1);
             }
         }
 
@@ -349,37 +840,15 @@ impl RenderEngine {
                     .iter_mut()
                     .enumerate()
                     .map(|(i, peak)| {
-                        let projected_point = projection.project_point3(peak.position);
-                        if projected_point.x > -1.0
-                            && projected_point.x < 1.0
-                            && projected_point.y > -1.0
-                            && projected_point.y < 1.0
-                            && projected_point.z < 1.0
-                        {
-                            let (x_pos, y_pos) = (
-                                (0.5 * (projected_point.x + 1.0) * size.width as f32) as u32,
-                                (-0.5 * (projected_point.y - 1.0) * size.height as f32) as u32,
-                            );
-
-                            let pos =
-                                (x_pos * 4 + y_pos * pad_256(depth_state.size.width * 4)) as usize;
-
-                            let depth_value = depth_buffer_view
-                                .get(pos..pos + 4)
-                                .expect("Failed depth buffer lookup")
-                                .get_f32_le();
-
-                            let terrain_distance = dist_from_depth(depth_value);
-                            let peak_distance = dist_from_depth(projected_point.z);
-                            if peak_distance - 10.0 < terrain_distance {
-                                peak.visible = true;
-                                (i, peak, Some((x_pos, y_pos)))
-                            } else {
-                                (i, peak, None)
-                            }
-                        } else {
-                            (i, peak, None)
-                        }
+                        let screen_position = Self::get_visible_screen_position(
+                            depth_state.camera.apparent_position(peak.position),
+                            projection,
+                            size,
+                            depth_state,
+                            depth_buffer_view,
+                            10.0,
+                        );
+                        (i, peak, screen_position)
                     })
                     .update(|(_, peak, vis_pos)| match vis_pos {
                         Some(_) => peak.visible = true,
@@ -395,6 +864,84 @@ impl RenderEngine {
         visible_labels
     }
 
+    /// Labels at the visible contour anchors, the nearest ones first
+    fn get_visible_contour_labels(
+        data: &ApplicationData,
+        projection: &Mat4,
+        size: PhysicalSize<u32>,
+        depth_state: DepthState,
+        depth_buffer_view: &BufferView,
+    ) -> Vec<ContourLabelLayout> {
+        if data.contours.interval <= 0.0 {
+            return vec![];
+        }
+
+        let eye = depth_state.camera.position().truncate();
+        data.contour_anchors
+            .values()
+            .flatten()
+            .map(|anchor| (anchor, anchor.position.distance(eye)))
+            .sorted_by(|(_, a), (_, b)| a.total_cmp(b))
+            .filter_map(|(anchor, distance)| {
+                let elevation = anchor.elevation.round() as i32;
+                let label = data.contour_labels.get(&elevation)?;
+                // the anchors are interpolated between the height map's points, so they can
+                // be off the rendered triangles by more than the peaks' labels are
+                let (x, y) = Self::get_visible_screen_position(
+                    depth_state.camera.apparent_position(anchor.position),
+                    projection,
+                    size,
+                    depth_state,
+                    depth_buffer_view,
+                    10.0 + 0.01 * distance,
+                )?;
+                Some(ContourLabelLayout {
+                    elevation,
+                    x: x as f32,
+                    y: y as f32,
+                    width: label.width,
+                })
+            })
+            .collect()
+    }
+
+    /// Pixel position of the point, `None` if it's off the screen or further than
+    /// the tolerance (in meters) behind the terrain
+    fn get_visible_screen_position(
+        point: Vec3,
+        projection: &Mat4,
+        size: PhysicalSize<u32>,
+        depth_state: DepthState,
+        depth_buffer_view: &BufferView,
+        tolerance: f32,
+    ) -> Option<(u32, u32)> {
+        let projected_point = projection.project_point3(point);
+        if !(projected_point.x > -1.0
+            && projected_point.x < 1.0
+            && projected_point.y > -1.0
+            && projected_point.y < 1.0
+            && projected_point.z < 1.0)
+        {
+            return None;
+        }
+
+        let (x_pos, y_pos) = (
+            (0.5 * (projected_point.x + 1.0) * size.width as f32) as u32,
+            (-0.5 * (projected_point.y - 1.0) * size.height as f32) as u32,
+        );
+
+        let pos = (x_pos * 4 + y_pos * pad_256(depth_state.size.width * 4)) as usize;
+
+        let depth_value = depth_buffer_view
+            .get(pos..pos + 4)
+            .expect("Failed depth buffer lookup")
+            .get_f32_le();
+
+        let terrain_distance = dist_from_depth(depth_value);
+        let point_distance = dist_from_depth(projected_point.z);
+        (point_distance - tolerance < terrain_distance).then_some((x_pos, y_pos))
+    }
+
     pub fn renderers_mut(&mut self) -> &mut ApplicationRenderers {
         &mut self.renderers
     }
@@ -1,33 +1,58 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
+use glam::Vec3;
+use itertools::Itertools;
 use topo_common::GeoLocation;
 use wgpu::RenderPass;
-use winit::event_loop::EventLoopProxy;
 
 use crate::{
-    app::ApplicationEvent,
     common::coordinate_transform::CoordinateTransform,
-    data::{Size, pad_256},
+    data::{Size, imagery::Imagery, pad_256, water::LakeMesh},
     render::{
         compute_pipeline::{
             ComputeEdgePatchesOrientation, ComputePipeline, ComputePipelineCorner,
-            ComputePipelineEdge,
+            ComputePipelineEdge, ComputePipelineShadows,
         },
+        data::ShadowUniforms,
         pipeline::TerrainRenderPipeline,
+        render_engine::RenderEventSender,
     },
+    settings::ShadowSettings,
 };
 
+/// Radians the sun has to move by for the shadows of a tile to be computed again
+const SHADOW_UPDATE_ANGLE: f32 = 0.002;
+/// Texels of the sky above the camera's horizon by the azimuth and the elevation,
+/// `SKY_VIEW_SIZE` in the sky shader has to match it
+const SKY_VIEW_SIZE: (u32, u32) = (256, 64);
+/// Texels of the sea mask, one per tile by the longitude and the latitude from -180° and -90°
+const SEA_MASK_SIZE: (u32, u32) = (360, 180);
+
 use super::{
     bound_texture_view::BoundTextureView, buffer::Buffer, data::PostprocessingUniforms,
     data::Uniforms, pipeline::Pipeline, render_buffer::RenderBuffer, texture::Texture,
 };
 
+struct LakeBuffer {
+    vertices: Buffer,
+    indices: Buffer,
+    indices_len: u32,
+}
+
 pub struct TerrainRenderer {
     first_pass_pipeline: TerrainRenderPipeline,
     postprocessing_pipeline: Pipeline,
+    sky_pipeline: Pipeline,
+    sky_view_pipeline: Pipeline,
+    shadow_pipeline: ComputePipelineShadows,
     texture_view: BoundTextureView,
     postprocessing_depth_texture_view: BoundTextureView,
     render_buffers: BTreeMap<GeoLocation, RenderBuffer>,
+    lake_buffers: BTreeMap<GeoLocation, LakeBuffer>,
+    /// Loaded tiles the backend has no terrain for, the postprocessing covers them with the sea
+    sea_locations: BTreeSet<GeoLocation>,
+    sea_mask: Texture,
+    sea_mask_stale: bool,
     depth_read_buffer: Buffer,
     format: wgpu::TextureFormat,
     target_size: Size<u32>,
@@ -41,10 +66,12 @@ impl TerrainRenderer {
         let postprocessing_depth_texture_view =
             Self::create_postprocessing_depth_texture_view(device, target_size);
 
+        let sea_mask = Texture::create_mask_texture(device, SEA_MASK_SIZE, "sea_mask_texture");
         let postprocessing_pipeline = Pipeline::create_postprocessing_pipeline(
             device,
             format,
             &texture_view.get_texture_bind_group_layout(),
+            &sea_mask,
         );
 
         let x = pad_256(target_size.width) * target_size.height * 4;
@@ -59,9 +86,17 @@ impl TerrainRenderer {
         Self {
             first_pass_pipeline,
             postprocessing_pipeline,
+            sky_pipeline: Pipeline::create_sky_pipeline(device, format),
+            sky_view_pipeline: Pipeline::create_sky_view_pipeline(device, format),
+            shadow_pipeline: ComputePipelineShadows::new(device),
             texture_view,
             postprocessing_depth_texture_view,
             render_buffers: BTreeMap::new(),
+            lake_buffers: BTreeMap::new(),
+            sea_locations: BTreeSet::new(),
+            sea_mask,
+            // the texture starts out zeroed, i.e. without the sea
+            sea_mask_stale: false,
             depth_read_buffer,
             format,
             target_size,
@@ -101,7 +136,13 @@ impl TerrainRenderer {
                 | wgpu::TextureUsages::COPY_SRC,
         );
 
-        BoundTextureView::create(device, vec![render_texture, depth_texture])
+        let sky_view_texture =
+            Texture::create_render_texture(device, format, SKY_VIEW_SIZE, "sky_view_texture");
+
+        BoundTextureView::create(
+            device,
+            vec![render_texture, depth_texture, sky_view_texture],
+        )
     }
 
     fn create_postprocessing_depth_texture_view(
@@ -168,8 +209,52 @@ impl TerrainRenderer {
             0,
             bytemuck::bytes_of(postprocessing_uniforms),
         );
+        queue.write_buffer(
+            self.sky_pipeline.get_uniforms(),
+            0,
+            bytemuck::bytes_of(postprocessing_uniforms),
+        );
+        queue.write_buffer(
+            self.sky_view_pipeline.get_uniforms(),
+            0,
+            bytemuck::bytes_of(postprocessing_uniforms),
+        );
+
+        if self.sea_mask_stale {
+            self.write_sea_mask(queue);
+            self.sea_mask_stale = false;
+        }
     }
 
+    fn write_sea_mask(&self, queue: &wgpu::Queue) {
+        let (width, height) = SEA_MASK_SIZE;
+        let mut mask = vec![0u8; (width * height) as usize];
+        for location in &self.sea_locations {
+            let (latitude, longitude) = location.to_numerical();
+            let x = (longitude.floor() as i32 + 180).clamp(0, width as i32 - 1) as u32;
+            let y = (latitude.floor() as i32 + 90).clamp(0, height as i32 - 1) as u32;
+            mask[(y * width + x) as usize] = u8::MAX;
+        }
+
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: self.sea_mask.get_texture(),
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &mask,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width),
+                rows_per_image: Some(height),
+            },
+            *self.sea_mask.get_size(),
+        );
+    }
+
+    /// Returns the number of the dispatched normal computations, each of
+    /// them reports back with a `NormalsComputed` render event
     pub fn add_terrain(
         &mut self,
         device: &wgpu::Device,
@@ -178,8 +263,10 @@ impl TerrainRenderer {
         height_map_data: &[u8],
         coordinate_transform: CoordinateTransform,
         size: (u32, u32),
-        event_loop_proxy: EventLoopProxy<ApplicationEvent>,
-    ) {
+        sun_direction: Vec3,
+        shadows: &ShadowSettings,
+        event_sender: RenderEventSender,
+    ) -> usize {
         let render_buffer = RenderBuffer::new(
             device,
             queue,
@@ -198,7 +285,7 @@ impl TerrainRenderer {
             render_buffer.get_normal_texture(),
             render_buffer.get_uniforms(),
             size,
-            event_loop_proxy.clone(),
+            event_sender.clone(),
         );
 
         let location_left =
@@ -261,6 +348,8 @@ impl TerrainRenderer {
             ));
         }
 
+        let mut dispatched = 1 + selected_buffers_edge.len();
+
         for (lt_buffer, rb_buffer, orientation) in selected_buffers_edge {
             let compute_pipeline_edge = ComputePipelineEdge::new(device, orientation);
 
@@ -274,7 +363,7 @@ impl TerrainRenderer {
                 rb_buffer.get_normal_texture(),
                 render_buffer.get_uniforms(),
                 size,
-                event_loop_proxy.clone(),
+                event_sender.clone(),
             );
         }
 
@@ -324,6 +413,8 @@ impl TerrainRenderer {
             ));
         }
 
+        dispatched += selected_buffers_corner.len();
+
         for (selected_top_left, selected_top_right, selected_bottom_left, selected_bottom_right) in
             selected_buffers_corner
         {
@@ -342,11 +433,126 @@ impl TerrainRenderer {
                 selected_bottom_left.get_normal_texture(),
                 selected_bottom_right.get_normal_texture(),
                 render_buffer.get_uniforms(),
-                event_loop_proxy.clone(),
+                event_sender.clone(),
             );
         }
 
         self.render_buffers.insert(location, render_buffer);
+        self.compute_shadows(device, queue, location, sun_direction, shadows);
+        // the new tile's terrain casts shadows on the neighbours too
+        self.invalidate_neighbours_shadows(location);
+
+        dispatched
+    }
+
+    /// Whether any tile's shadows were computed for a sun direction too far from the given one
+    pub fn has_stale_shadows(&self, sun_direction: Vec3, shadows: &ShadowSettings) -> bool {
+        self.render_buffers
+            .values()
+            .any(|render_buffer| Self::are_shadows_stale(render_buffer, sun_direction, shadows))
+    }
+
+    /// Computes the shadows again for up to `max_tiles` tiles lit from another direction,
+    /// starting with the ones the sun moved the most for, so that following it spreads over frames
+    pub fn update_shadows(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sun_direction: Vec3,
+        shadows: &ShadowSettings,
+        max_tiles: usize,
+    ) {
+        let stale = self
+            .render_buffers
+            .iter()
+            .filter(|(_, render_buffer)| {
+                Self::are_shadows_stale(render_buffer, sun_direction, shadows)
+            })
+            .map(|(location, render_buffer)| {
+                let angle = render_buffer
+                    .get_shadows_sun_direction()
+                    .map_or(f32::INFINITY, |direction| {
+                        direction.angle_between(sun_direction)
+                    });
+                (*location, angle)
+            })
+            .sorted_by(|(_, a), (_, b)| b.total_cmp(a))
+            .take(max_tiles)
+            .map(|(location, _)| location)
+            .collect_vec();
+
+        for location in stale {
+            self.compute_shadows(device, queue, location, sun_direction, shadows);
+        }
+    }
+
+    fn are_shadows_stale(
+        render_buffer: &RenderBuffer,
+        sun_direction: Vec3,
+        shadows: &ShadowSettings,
+    ) -> bool {
+        // without shadows the sun is visible from everywhere, whatever its direction
+        shadows.max_distance > 0.0
+            && render_buffer
+                .get_shadows_sun_direction()
+                .is_none_or(|direction| {
+                    direction.angle_between(sun_direction) > SHADOW_UPDATE_ANGLE
+                })
+    }
+
+    fn compute_shadows(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        location: GeoLocation,
+        sun_direction: Vec3,
+        shadows: &ShadowSettings,
+    ) {
+        let Some(render_buffer) = self.render_buffers.get(&location) else {
+            return;
+        };
+        let neighbours = Self::get_neighbour_locations(location)
+            .map(|neighbour| self.render_buffers.get(&neighbour));
+
+        self.shadow_pipeline.dispatch(
+            device,
+            queue,
+            render_buffer,
+            neighbours,
+            &ShadowUniforms::new(sun_direction, shadows),
+        );
+
+        if let Some(render_buffer) = self.render_buffers.get_mut(&location) {
+            render_buffer.set_shadows_sun_direction(sun_direction);
+        }
+    }
+
+    /// Has the shadows of the tiles around the location computed again, as
+    /// they depend on the terrain of the location after it's loaded or unloaded
+    fn invalidate_neighbours_shadows(&mut self, location: GeoLocation) {
+        for neighbour in Self::get_neighbour_locations(location) {
+            if let Some(render_buffer) = self.render_buffers.get_mut(&neighbour) {
+                render_buffer.invalidate_shadows();
+            }
+        }
+    }
+
+    /// The eight tiles around the location, from the top left to the bottom right one
+    fn get_neighbour_locations(location: GeoLocation) -> [GeoLocation; 8] {
+        let (latitude, longitude) = location.to_numerical();
+        let (latitude, longitude) = (latitude as i32, longitude as i32);
+
+        [
+            (1, -1),
+            (1, 0),
+            (1, 1),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (-1, 0),
+            (-1, 1),
+        ]
+        .map(|(north, east)| GeoLocation::from_coord(latitude + north, longitude + east))
     }
 
     pub fn get_render_buffer_mut_with_pipeline(
@@ -358,8 +564,55 @@ impl TerrainRenderer {
             .map(|buffer| (buffer, &mut self.first_pass_pipeline))
     }
 
+    /// Drapes the imagery on the location's terrain, if it's still loaded
+    pub fn set_imagery(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        location: GeoLocation,
+        imagery: &Imagery,
+    ) {
+        if let Some(render_buffer) = self.render_buffers.get_mut(&location) {
+            render_buffer.set_imagery(device, queue, &self.first_pass_pipeline, imagery);
+        }
+    }
+
+    /// Puts the lakes on the location's terrain, if it's still loaded
+    pub fn set_lakes(&mut self, device: &wgpu::Device, location: GeoLocation, mesh: &LakeMesh) {
+        if !self.render_buffers.contains_key(&location) || mesh.is_empty() {
+            return;
+        }
+
+        self.lake_buffers.insert(
+            location,
+            LakeBuffer {
+                vertices: Buffer::new_init(
+                    device,
+                    "lake vertices",
+                    bytemuck::cast_slice(&mesh.vertices),
+                    wgpu::BufferUsages::VERTEX,
+                ),
+                indices: Buffer::new_init(
+                    device,
+                    "lake indices",
+                    bytemuck::cast_slice(&mesh.indices),
+                    wgpu::BufferUsages::INDEX,
+                ),
+                indices_len: mesh.indices.len() as u32,
+            },
+        );
+    }
+
+    /// Covers the location, which the backend has no terrain for, with the sea
+    pub fn add_sea(&mut self, location: GeoLocation) {
+        self.sea_mask_stale |= self.sea_locations.insert(location);
+    }
+
     pub fn unload_terrain(&mut self, location: &GeoLocation) {
         self.render_buffers.remove(&location);
+        self.lake_buffers.remove(location);
+        self.sea_mask_stale |= self.sea_locations.remove(location);
+        self.invalidate_neighbours_shadows(*location);
     }
 
     pub fn render<'a>(
@@ -369,6 +622,33 @@ impl TerrainRenderer {
         viewport: Size<u32>,
     ) -> Box<RenderPass<'a>> {
         {
+            {
+                let mut sky_view_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("sky_view.pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: self.texture_view.get_textures()[2].get_view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                    multiview_mask: None,
+                });
+
+                sky_view_pass.set_pipeline(self.sky_view_pipeline.get_pipeline());
+                sky_view_pass.set_bind_group(
+                    0,
+                    self.sky_view_pipeline.get_uniform_bind_group(),
+                    &[],
+                );
+                sky_view_pass.draw(0..6, 0..1);
+            }
+
             {
                 let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: Some("render.pass"),
@@ -376,12 +656,8 @@ impl TerrainRenderer {
                         view: &self.texture_view.get_textures()[0].get_view(),
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.71,
-                                b: 0.885,
-                                a: 1.0,
-                            }),
+                            // the sky covers the whole target
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                             store: wgpu::StoreOp::Store,
                         },
                         depth_slice: None,
@@ -399,25 +675,15 @@ impl TerrainRenderer {
                     multiview_mask: None,
                 });
 
+                render_pass.set_pipeline(self.sky_pipeline.get_pipeline());
+                render_pass.set_bind_group(0, self.sky_pipeline.get_uniform_bind_group(), &[]);
+                render_pass.draw(0..6, 0..1);
+
                 let pipeline = self.first_pass_pipeline.get_pipeline();
 
                 render_pass.set_pipeline(pipeline.get_pipeline());
-                render_pass.set_bind_group(0, pipeline.get_uniform_bind_group(), &[]);
-
-                self.render_buffers.iter().for_each(|(_, render_buffer)| {
-                    render_pass.set_vertex_buffer(0, render_buffer.get_vertices().raw.slice(..));
-                    render_pass.set_index_buffer(
-                        render_buffer.get_indices().raw.slice(..),
-                        wgpu::IndexFormat::Uint32,
-                    );
-                    render_pass.set_bind_group(
-                        1,
-                        render_buffer.get_height_map_texture_bind_group(),
-                        &[],
-                    );
-
-                    render_pass.draw_indexed(0..(render_buffer.get_indices_len() as u32), 0, 0..1);
-                });
+                self.draw_terrain(&mut render_pass);
+                self.draw_lakes(&mut render_pass);
             }
 
             let mut postprocessing_pass =
@@ -450,4 +716,86 @@ impl TerrainRenderer {
             postprocessing_pass
         }
     }
+
+    fn draw_terrain(&self, render_pass: &mut RenderPass) {
+        let pipeline = self.first_pass_pipeline.get_pipeline();
+        render_pass.set_bind_group(0, pipeline.get_uniform_bind_group(), &[]);
+
+        self.render_buffers.iter().for_each(|(_, render_buffer)| {
+            render_pass.set_vertex_buffer(0, render_buffer.get_vertices().raw.slice(..));
+            render_pass.set_index_buffer(
+                render_buffer.get_indices().raw.slice(..),
+                wgpu::IndexFormat::Uint32,
+            );
+            render_pass.set_bind_group(1, render_buffer.get_height_map_texture_bind_group(), &[]);
+
+            render_pass.draw_indexed(0..(render_buffer.get_indices_len() as u32), 0, 0..1);
+        });
+    }
+
+    fn draw_lakes(&self, render_pass: &mut RenderPass) {
+        if self.lake_buffers.is_empty() {
+            return;
+        }
+
+        render_pass.set_pipeline(self.first_pass_pipeline.get_lake_pipeline());
+        render_pass.set_bind_group(
+            0,
+            self.first_pass_pipeline
+                .get_pipeline()
+                .get_uniform_bind_group(),
+            &[],
+        );
+
+        for lake_buffer in self.lake_buffers.values() {
+            render_pass.set_vertex_buffer(0, lake_buffer.vertices.raw.slice(..));
+            render_pass
+                .set_index_buffer(lake_buffer.indices.raw.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..lake_buffer.indices_len, 0, 0..1);
+        }
+    }
+
+    /// Renders the world space normals of the terrain, see [`TerrainRenderPipeline::create_normals_pipeline`]
+    pub fn render_normals(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        target_size: Size<u32>,
+    ) {
+        let pipeline = self.first_pass_pipeline.create_normals_pipeline(device);
+        let depth_texture = Texture::create_depth_texture(
+            device,
+            (target_size.width, target_size.height),
+            "normals_depth_texture",
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("normals.pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_texture.get_view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&pipeline);
+        self.draw_terrain(&mut render_pass);
+    }
 }
@@ -1,13 +1,16 @@
 use crate::data::application_data::{ApplicationData, PeakLabel};
+use crate::data::contours::{ContourLabelLayout, layout_contour_labels};
 use crate::render::data::PeakInstance;
 
 use color_eyre::eyre::Result;
 use futures::stream::FuturesUnordered;
 use glyphon::fontdb::{Database, Source};
 use glyphon::{
-    Attrs, Buffer, Cache, Family, FontSystem, Metrics, Shaping, SwashCache, TextArea, TextAtlas,
-    TextBounds, Viewport,
+    Attrs, Buffer, Cache, Command, Family, FontSystem, Metrics, Shaping, SwashCache, TextArea,
+    TextAtlas, TextBounds, Viewport,
 };
+use lyon::math::point;
+use lyon::path::Path;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Bound::{Included, Unbounded};
@@ -103,6 +106,14 @@ pub struct LabelLayout {
     pub peak_y: f32,
 }
 
+impl LabelLayout {
+    /// Whether the point (in pixels) is on the label's background
+    pub fn contains(&self, (x, y): (f32, f32)) -> bool {
+        (self.label_x..=self.label_x + self.label_width).contains(&x)
+            && (self.label_y..=self.label_y + LINE_HEIGHT).contains(&y)
+    }
+}
+
 pub struct TextRenderer {
     pub swash_cache: SwashCache,
     pub viewport: Viewport,
@@ -114,11 +125,9 @@ impl TextRenderer {
     pub fn new(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        config: &wgpu::SurfaceConfiguration,
+        swapchain_format: wgpu::TextureFormat,
         depth_stencil: Option<wgpu::DepthStencilState>,
     ) -> Self {
-        let swapchain_format = config.format;
-
         let swash_cache = SwashCache::new();
         let cache = Cache::new(device);
         let viewport = Viewport::new(device, &cache);
@@ -214,39 +223,136 @@ impl TextRenderer {
     }
 
     pub fn prepare_peak_labels(peaks: &Vec<PeakInstance>) -> Vec<PeakLabel> {
-        let metric = Metrics::new(12.0, LINE_HEIGHT as f32);
-        Self::with_font_system(|mut font_system| {
+        Self::with_font_system(|font_system| {
             peaks
                 .iter()
-                .map(|peak| {
-                    let mut buffer = Buffer::new(&mut font_system, metric);
-                    buffer.set_size(&mut font_system, None, None);
-                    buffer.set_text(
-                        &mut font_system,
-                        peak.name.as_str(),
-                        &Attrs::new().family(Family::SansSerif),
-                        Shaping::Advanced,
-                        None,
-                    );
-                    buffer.shape_until_scroll(&mut font_system, false);
-                    let width = buffer
-                        .layout_runs()
-                        .next()
-                        .expect("Unable to layout peak label")
-                        .line_w;
-                    PeakLabel { buffer, width }
-                })
+                .map(|peak| Self::shape_label(font_system, peak.name.as_str()))
                 .collect::<Vec<_>>()
         })
     }
 
+    /// Label with a single line of text, e.g. a contour's elevation
+    pub fn prepare_label(text: &str) -> PeakLabel {
+        Self::with_font_system(|font_system| Self::shape_label(font_system, text))
+    }
+
+    fn shape_label(font_system: &mut FontSystem, text: &str) -> PeakLabel {
+        let metric = Metrics::new(12.0, LINE_HEIGHT);
+        let mut buffer = Buffer::new(font_system, metric);
+        buffer.set_size(font_system, None, None);
+        buffer.set_text(
+            font_system,
+            text,
+            &Attrs::new().family(Family::SansSerif),
+            Shaping::Advanced,
+            None,
+        );
+        buffer.shape_until_scroll(font_system, false);
+        let width = buffer
+            .layout_runs()
+            .next()
+            .expect("Unable to layout peak label")
+            .line_w;
+        PeakLabel { buffer, width }
+    }
+
+    /// Rasterizes a label on the cpu, `draw` gets called with rectangles relative to the label's top left corner
+    pub fn draw_peak_label(
+        label: &PeakLabel,
+        swash_cache: &mut SwashCache,
+        draw: impl FnMut(i32, i32, u32, u32, glyphon::Color),
+    ) {
+        Self::with_font_system(|font_system| {
+            label
+                .buffer
+                .draw(font_system, swash_cache, glyphon::Color::rgb(0, 0, 0), draw)
+        });
+    }
+
+    /// Glyph outlines of a line of text in pixels, relative to the line's top left corner
+    ///
+    /// Returns the outlines along with the width of the text
+    pub fn text_outline(text: &str, font_size: f32, line_height: f32) -> (Path, f32) {
+        let mut swash_cache = SwashCache::new();
+        let mut builder = Path::builder();
+
+        let width = Self::with_font_system(|font_system| {
+            let mut buffer = Buffer::new(font_system, Metrics::new(font_size, line_height));
+            buffer.set_size(font_system, None, None);
+            buffer.set_text(
+                font_system,
+                text,
+                &Attrs::new().family(Family::SansSerif),
+                Shaping::Advanced,
+                None,
+            );
+            buffer.shape_until_scroll(font_system, false);
+
+            let mut width = 0.0f32;
+            for run in buffer.layout_runs() {
+                width = width.max(run.line_w);
+                for glyph in run.glyphs {
+                    let physical = glyph.physical((0.0, 0.0), 1.0);
+                    let Some(commands) =
+                        swash_cache.get_outline_commands(font_system, physical.cache_key)
+                    else {
+                        continue;
+                    };
+                    // outlines have y pointing up from the glyph's origin on the baseline
+                    let (x, y) = (physical.x as f32, run.line_y + physical.y as f32);
+                    let to_point = |x_offset: f32, y_offset: f32| point(x + x_offset, y - y_offset);
+
+                    let mut open = false;
+                    for command in commands {
+                        match *command {
+                            Command::MoveTo(p) => {
+                                if open {
+                                    builder.end(false);
+                                }
+                                builder.begin(to_point(p.x, p.y));
+                                open = true;
+                            }
+                            Command::LineTo(p) => {
+                                builder.line_to(to_point(p.x, p.y));
+                            }
+                            Command::QuadTo(c, p) => {
+                                builder.quadratic_bezier_to(to_point(c.x, c.y), to_point(p.x, p.y));
+                            }
+                            Command::CurveTo(c1, c2, p) => {
+                                builder.cubic_bezier_to(
+                                    to_point(c1.x, c1.y),
+                                    to_point(c2.x, c2.y),
+                                    to_point(p.x, p.y),
+                                );
+                            }
+                            Command::Close => {
+                                if open {
+                                    builder.end(true);
+                                    open = false;
+                                }
+                            }
+                        }
+                    }
+                    if open {
+                        builder.end(false);
+                    }
+                }
+            }
+            width
+        });
+
+        (builder.build(), width)
+    }
+
+    /// Lays out the labels of the visible peaks and the contour labels fitting around them
     pub fn prepare(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         peak_labels: BTreeMap<GeoLocation, Vec<(LabelId, (u32, u32))>>,
+        contour_labels: Vec<ContourLabelLayout>,
         data: &ApplicationData,
-    ) -> Vec<LabelLayout> {
+    ) -> (Vec<LabelLayout>, Vec<ContourLabelLayout>) {
         let laid_out_labels = layout_labels(
             peak_labels.clone(),
             |location, id| {
@@ -278,6 +384,22 @@ impl TextRenderer {
                 },
             )
             .collect::<Vec<_>>();
+
+        let laid_out_contour_labels = layout_contour_labels(contour_labels, &laid_out_labels);
+        let [red, green, blue] = data.contours.color;
+        let contour_text_areas = laid_out_contour_labels.iter().filter_map(|label| {
+            let (left, top) = label.get_top_left();
+            Some(TextArea {
+                buffer: &data.contour_labels.get(&label.elevation)?.buffer,
+                left,
+                top,
+                scale: 1.0,
+                bounds: TextBounds::default(),
+                default_color: glyphon::Color::rgb(red, green, blue),
+                custom_glyphs: &[],
+            })
+        });
+        let text_areas = text_areas.into_iter().chain(contour_text_areas);
         Self::with_font_system(|mut font_system| {
             self.text_renderer
                 .prepare_with_depth(
@@ -293,7 +415,7 @@ impl TextRenderer {
                 .unwrap();
         });
 
-        laid_out_labels
+        (laid_out_labels, laid_out_contour_labels)
     }
 }
 
@@ -336,8 +458,12 @@ fn process_label_layout(edges: &mut Vec<BTreeSet<LabelEdge>>, x: u32, width: f32
     }
 }
 
-fn layout_labels(
-    peak_labels: BTreeMap<GeoLocation, Vec<(LabelId, (u32, u32))>>,
+/// Pixel positions of the peaks to be labeled, grouped by their location
+pub(crate) type LabelPositions = BTreeMap<GeoLocation, Vec<(LabelId, (u32, u32))>>;
+
+/// Places labels in rows at the top of the screen, the ones that don't fit in any row get dropped
+pub(crate) fn layout_labels(
+    peak_labels: LabelPositions,
     widths: impl Fn(GeoLocation, LabelId) -> Option<f32>,
     line_height: f32,
 ) -> Vec<LabelLayout> {
@@ -1,3 +1,4 @@
+use image::RgbaImage;
 use wgpu::{Sampler, TextureView};
 
 pub enum TextureType {
@@ -86,6 +87,43 @@ impl Texture {
         }
     }
 
+    pub fn create_capture_texture(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(format),
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler: None,
+            t_type: TextureType::Render,
+            size,
+        }
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         (width, height): (u32, u32),
@@ -159,6 +197,76 @@ impl Texture {
         }
     }
 
+    /// Single channel texture written by a compute pass, e.g. the terrain's sun visibility
+    pub fn create_storage_texture(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler: None,
+            t_type: TextureType::Depth,
+            size,
+        }
+    }
+
+    /// Single channel texture written from the cpu and read texel by texel, e.g. the sea mask
+    pub fn create_mask_texture(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler: None,
+            t_type: TextureType::Render,
+            size,
+        }
+    }
+
     pub fn create_normal_texture(
         device: &wgpu::Device,
         (width, height): (u32, u32),
@@ -196,4 +304,73 @@ impl Texture {
             size,
         }
     }
+
+    /// Color texture with the given mip levels, sampled with trilinear filtering
+    pub fn create_imagery_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mip_levels: &[RgbaImage],
+        label: &str,
+    ) -> Self {
+        let (width, height) = mip_levels[0].dimensions();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: mip_levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        for (mip_level, level) in mip_levels.iter().enumerate() {
+            let (width, height) = level.dimensions();
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                level.as_raw(),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Linear,
+            anisotropy_clamp: 16,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+            t_type: TextureType::Render,
+            size,
+        }
+    }
 }
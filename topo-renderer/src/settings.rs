@@ -0,0 +1,417 @@
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use config::Config;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use topo_common::GeoCoord;
+
+use crate::{
+    app::ApplicationSettings,
+    control::key_bindings::KeyBindings,
+    data::{camera::ViewMode, refraction::Refraction, terrain_coloring::ElevationRamp},
+};
+
+/// Runtime configuration of the renderer
+///
+/// Every field is optional when deserializing, missing ones fall back to defaults
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RendererSettings {
+    #[serde(flatten)]
+    pub application: ApplicationSettings,
+    pub start_location: GeoCoord,
+    /// Altitude of the camera at the start location (in meters above sea level),
+    /// 50m above the ground if not set
+    pub start_altitude: Option<f32>,
+    /// Initial viewing direction (in degrees clockwise from north)
+    pub start_yaw: Option<f32>,
+    /// Initial viewing angle (in degrees above the horizon)
+    pub start_pitch: Option<f32>,
+    /// Distance (in meters) from the current location within which terrain gets loaded
+    pub view_distance: f32,
+    /// Vertical field of view (in degrees)
+    pub fov: f32,
+    pub postprocessing: PostprocessingSettings,
+    pub ground: GroundSettings,
+    pub movement: MovementSettings,
+    pub sun: SunSettings,
+    pub shadows: ShadowSettings,
+    pub atmosphere: AtmosphereSettings,
+    /// Bending of the light rays, see [`Refraction`]
+    pub refraction: Refraction,
+    /// How the terrain gets colored at the start, see [`ViewMode`]
+    pub view_mode: ViewMode,
+    pub coloring: ColoringSettings,
+    pub contours: ContourSettings,
+    pub key_bindings: KeyBindings,
+    /// File the visible peaks get written to with the export key (not supported on wasm),
+    /// its extension selects the format: json, csv or geojson
+    pub peaks_export_file: PathBuf,
+    /// File the camera path gets saved to when its recording is stopped (not supported on wasm)
+    pub camera_path_file: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PostprocessingSettings {
+    /// Number of "pixels" along each screen axis, 100 or more disables pixelization
+    pub pixelize_n: f32,
+}
+
+/// How the camera keeps clear of the terrain
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GroundSettings {
+    /// Keep the camera at least `min_height` above the ground
+    pub collision: bool,
+    /// Meters
+    pub min_height: f32,
+    /// Height of the camera above the ground in walk mode (in meters)
+    pub walk_height: f32,
+}
+
+/// How the free moving camera speeds up, slows down and turns
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MovementSettings {
+    /// Meters per second for each meter the camera is above the ground
+    pub speed_per_height: f32,
+    /// Meters per second
+    pub min_speed: f32,
+    /// Meters per second
+    pub max_speed: f32,
+    /// Speed factor while the sprint key is held
+    pub sprint_multiplier: f32,
+    /// Time constant (in seconds) of speeding up while a movement key is held
+    pub acceleration_time: f32,
+    /// Time constant (in seconds) of coasting to a stop once the keys are released
+    pub damping_time: f32,
+    /// Time constant (in seconds) of turning after mouse or touch input, 0 turns at once
+    pub rotation_smoothing: f32,
+}
+
+/// Date and time the terrain is lit for
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SunSettings {
+    /// UTC time (e.g. `2024-06-21T12:00:00Z`) the sun position is computed for, now if not set
+    pub time: Option<Timestamp>,
+    /// Simulated seconds per real second in time-lapse mode
+    pub time_lapse_speed: f64,
+}
+
+/// Shadows the terrain casts, computed for each tile separately
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShadowSettings {
+    /// Distance (in meters) up to which the terrain casts shadows, 0 disables them
+    pub max_distance: f32,
+    /// Angle (in degrees) the sun has to sink by for the light to fade into a shadow
+    pub penumbra: f32,
+    /// Tiles whose shadows get updated per frame after the sun moved
+    pub tiles_per_frame: usize,
+}
+
+/// Haze that fades the distant terrain into the sky
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AtmosphereSettings {
+    /// Distance (in meters) at sea level at which the contrast drops to 2%, 0 disables the haze
+    pub visibility: f32,
+    /// Ratio of all the scattering to that of the clean air, 1 is a perfectly clear sky
+    /// and higher values add whiter haze (aerosols)
+    pub turbidity: f32,
+}
+
+/// Colors of the terrain in the analysis view modes
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ColoringSettings {
+    /// Colors of the hypsometric and hillshade view modes at the given elevations
+    pub elevation_stops: ElevationRamp,
+}
+
+/// Lines of equal elevation drawn over the terrain in every view mode
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContourSettings {
+    /// Elevation difference (in meters) between neighboring contour lines, 0 disables them
+    pub interval: f32,
+    /// Every how manieth contour is an index contour, drawn thicker and labeled
+    pub index_every: u32,
+    /// Width of the contour lines (in pixels)
+    pub width: f32,
+    /// Width of the index contour lines (in pixels)
+    pub index_width: f32,
+    /// sRGB
+    pub color: [u8; 3],
+    /// Label the index contours with their elevation
+    pub labels: bool,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            application: ApplicationSettings::default(),
+            start_location: GeoCoord::new(49.35135, 20.21139),
+            start_altitude: None,
+            start_yaw: None,
+            start_pitch: None,
+            view_distance: 100_000.0,
+            fov: 45.0,
+            postprocessing: PostprocessingSettings::default(),
+            ground: GroundSettings::default(),
+            movement: MovementSettings::default(),
+            sun: SunSettings::default(),
+            shadows: ShadowSettings::default(),
+            atmosphere: AtmosphereSettings::default(),
+            refraction: Refraction::default(),
+            view_mode: ViewMode::default(),
+            coloring: ColoringSettings::default(),
+            contours: ContourSettings::default(),
+            key_bindings: KeyBindings::default(),
+            peaks_export_file: PathBuf::from("visible_peaks.json"),
+            camera_path_file: PathBuf::from("camera_path.json"),
+        }
+    }
+}
+
+impl Default for PostprocessingSettings {
+    fn default() -> Self {
+        Self { pixelize_n: 100.0 }
+    }
+}
+
+impl Default for GroundSettings {
+    fn default() -> Self {
+        Self {
+            collision: true,
+            min_height: 10.0,
+            walk_height: 1.8,
+        }
+    }
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            speed_per_height: 1.0,
+            min_speed: 5.0,
+            max_speed: 50_000.0,
+            sprint_multiplier: 4.0,
+            acceleration_time: 0.15,
+            damping_time: 0.3,
+            rotation_smoothing: 0.05,
+        }
+    }
+}
+
+impl Default for SunSettings {
+    fn default() -> Self {
+        Self {
+            time: None,
+            time_lapse_speed: 600.0,
+        }
+    }
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            max_distance: 30_000.0,
+            penumbra: 1.0,
+            tiles_per_frame: 2,
+        }
+    }
+}
+
+impl Default for ContourSettings {
+    fn default() -> Self {
+        Self {
+            interval: 0.0,
+            index_every: 5,
+            width: 1.0,
+            index_width: 2.0,
+            color: [150, 85, 40],
+            labels: true,
+        }
+    }
+}
+
+impl ContourSettings {
+    /// Elevation difference (in meters) between neighboring index contours
+    pub fn index_interval(&self) -> f32 {
+        self.interval * self.index_every.max(1) as f32
+    }
+
+    /// Elevation difference (in meters) between the labeled contours, 0 without labels
+    pub fn label_interval(&self) -> f32 {
+        if self.labels {
+            self.index_interval()
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for AtmosphereSettings {
+    fn default() -> Self {
+        Self {
+            visibility: 150_000.0,
+            turbidity: 2.0,
+        }
+    }
+}
+
+impl RendererSettings {
+    pub fn from_config(config: Config) -> Result<Self> {
+        let settings = config.try_deserialize()?;
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{File, FileFormat};
+    use winit::keyboard::KeyCode;
+
+    use crate::{
+        control::camera_controller::Control,
+        data::{imagery::ImagerySource, terrain_coloring::ColorStop},
+    };
+
+    use super::*;
+
+    #[test]
+    fn empty_config_gives_defaults() {
+        let config = Config::builder().build().unwrap();
+
+        assert_eq!(
+            RendererSettings::from_config(config).unwrap(),
+            RendererSettings::default()
+        );
+    }
+
+    #[test]
+    fn partial_config_overrides_only_given_fields() {
+        let toml = r#"
+backend_url = "https://example.com/api"
+fov = 60
+refraction = 0.25
+
+[start_location]
+latitude = 46.5
+longitude = 7.9
+
+[sun]
+time = "2024-06-21T12:00:00Z"
+
+[contours]
+interval = 50
+
+[imagery]
+source = "xyz"
+tile_url = "https://tile.example.com/{z}/{x}/{y}.png"
+
+[water]
+sea = false
+
+[key_bindings]
+forward = ["KeyZ"]
+"#;
+        let config = Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .set_override("postprocessing.pixelize_n", "50")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let settings = RendererSettings::from_config(config).unwrap();
+
+        assert_eq!(settings.application.backend_url, "https://example.com/api");
+        assert_eq!(settings.fov, 60.0);
+        assert_eq!(settings.start_location, GeoCoord::new(46.5, 7.9));
+        assert_eq!(settings.view_distance, 100_000.0);
+        assert_eq!(settings.postprocessing.pixelize_n, 50.0);
+        assert_eq!(
+            settings.sun.time,
+            Some("2024-06-21T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(settings.sun.time_lapse_speed, 600.0);
+        assert_eq!(settings.refraction, Refraction::Coefficient(0.25));
+        assert_eq!(settings.application.imagery.source, ImagerySource::Xyz);
+        assert_eq!(settings.contours.interval, 50.0);
+        assert_eq!(settings.contours.index_interval(), 250.0);
+        assert_eq!(settings.application.imagery.zoom, 11);
+        assert!(!settings.application.water.sea);
+        assert!(settings.application.water.lakes);
+        assert_eq!(
+            settings.key_bindings.get_bindings(Control::Forward),
+            &[KeyCode::KeyZ.into()]
+        );
+        assert_eq!(
+            settings.key_bindings.get_bindings(Control::Backward),
+            &[KeyCode::KeyS.into(), KeyCode::ArrowDown.into()]
+        );
+    }
+
+    #[test]
+    fn refraction_preset_overrides_config() {
+        let config = Config::builder()
+            .set_override("refraction", "strong_inversion")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let settings = RendererSettings::from_config(config).unwrap();
+        assert_eq!(settings.refraction, Refraction::StrongInversion);
+    }
+
+    #[test]
+    fn elevation_stops_replace_the_default_ramp() {
+        let toml = r#"
+view_mode = "hypsometric"
+
+[coloring]
+elevation_stops = [
+    { elevation = 200, color = [40, 120, 60] },
+    { elevation = 2000, color = [255, 255, 255] },
+]
+"#;
+        let config = Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap();
+
+        let settings = RendererSettings::from_config(config).unwrap();
+        assert_eq!(settings.view_mode, ViewMode::Hypsometric);
+        assert_eq!(
+            settings.coloring.elevation_stops.get_stops(),
+            &[
+                ColorStop {
+                    elevation: 200.0,
+                    color: [40, 120, 60]
+                },
+                ColorStop {
+                    elevation: 2000.0,
+                    color: [255, 255, 255]
+                },
+            ]
+        );
+
+        let unordered = r#"
+[coloring]
+elevation_stops = [
+    { elevation = 2000, color = [255, 255, 255] },
+    { elevation = 200, color = [40, 120, 60] },
+]
+"#;
+        let config = Config::builder()
+            .add_source(File::from_str(unordered, FileFormat::Toml))
+            .build()
+            .unwrap();
+        assert!(RendererSettings::from_config(config).is_err());
+    }
+}